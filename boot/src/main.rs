@@ -6,11 +6,12 @@ use std::{
 
 use clap::Parser;
 use fern::colors::{Color, ColoredLevelConfig};
-use log::{debug, info};
+use log::{debug, info, warn};
 
 const TEST_TIMEOUT_SECS: u64 = 300; // 5 minutes
 const QEMU_COMMAND: &str = "qemu-system-x86_64";
 
+mod symbols;
 mod test;
 
 #[derive(Parser, Debug)]
@@ -44,6 +45,11 @@ fn main() {
     configure_logging(args.verbose);
 
     let kernel_binary_path = args.binary.canonicalize().unwrap();
+    match symbols::embed_symbols(&kernel_binary_path) {
+        Ok(Some(count)) => debug!("embedded {} kernel symbols", count),
+        Ok(None) => warn!("kernel has no symbols, backtraces won't be symbolized"),
+        Err(e) => warn!("unable to embed the kernel symbols: {}", e),
+    }
     let image = create_disk_images(&kernel_binary_path);
 
     if args.no_run {
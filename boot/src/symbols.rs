@@ -0,0 +1,244 @@
+//! Copies the function symbols of the kernel into its `.kernel_symbols` section, since
+//! the bootloader doesn't load the symbol table. The layout of the section is defined
+//! in `src/crash/symbols.rs` of the kernel.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+const SECTION_NAME: &[u8] = b".kernel_symbols";
+const MAGIC: &[u8; 8] = b"KSYMTAB1";
+const CAPACITY: usize = 512 * 1024;
+/// Longer names are cut, mostly generic parameters, so that the symbols fit into the
+/// section.
+const MAX_NAME_LEN: usize = 96;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+const ENTRY_SIZE: usize = 24;
+
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: u32,
+}
+
+struct Symbol {
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+/// Writes the function symbols of the kernel binary into its symbol section. Returns the
+/// number of symbols, or `None` if the binary has no symbol section or no symbol table.
+pub fn embed_symbols(kernel: &Path) -> Result<Option<usize>> {
+    let mut elf = fs::read(kernel)?;
+    let sections = section_headers(&elf)?;
+    let names = sections
+        .get(u16_at(&elf, 0x3e)? as usize)
+        .ok_or_else(|| invalid("invalid section name table index"))?;
+
+    let target = sections.iter().find(|section| {
+        string_at(&elf, names, section.name) == Some(SECTION_NAME)
+            && elf.get(section.offset..section.offset + MAGIC.len()) == Some(&MAGIC[..])
+    });
+    let symtab = sections.iter().find(|section| section.kind == SHT_SYMTAB);
+    let (target, symtab) = match (target, symtab) {
+        (Some(target), Some(symtab)) => (target, symtab),
+        _ => return Ok(None),
+    };
+    let strtab = sections
+        .get(symtab.link as usize)
+        .ok_or_else(|| invalid("invalid string table index"))?;
+
+    let symbols = function_symbols(&elf, symtab, strtab)?;
+    let table = encode(&symbols)?;
+    if target.size < 16 + CAPACITY {
+        return Err(invalid("symbol section is too small"));
+    }
+    // the magic stays in place, the table starts with the number of symbols
+    let start = target.offset + MAGIC.len();
+    elf[start..start + table.len()].copy_from_slice(&table);
+    fs::write(kernel, elf)?;
+    Ok(Some(symbols.len()))
+}
+
+fn section_headers(elf: &[u8]) -> Result<Vec<Section>> {
+    if elf.get(0..4) != Some(b"\x7fELF") || elf.get(4) != Some(&2) || elf.get(5) != Some(&1) {
+        return Err(invalid("not a 64 bit little endian elf file"));
+    }
+    let offset = u64_at(elf, 0x28)? as usize;
+    if u16_at(elf, 0x3a)? as usize != SECTION_HEADER_SIZE {
+        return Err(invalid("unexpected section header size"));
+    }
+    (0..u16_at(elf, 0x3c)? as usize)
+        .map(|index| {
+            let header = offset + index * SECTION_HEADER_SIZE;
+            Ok(Section {
+                name: u32_at(elf, header)?,
+                kind: u32_at(elf, header + 4)?,
+                offset: u64_at(elf, header + 24)? as usize,
+                size: u64_at(elf, header + 32)? as usize,
+                link: u32_at(elf, header + 40)?,
+            })
+        })
+        .collect()
+}
+
+fn string_at<'a>(elf: &'a [u8], table: &Section, offset: u32) -> Option<&'a [u8]> {
+    let strings = elf.get(table.offset..table.offset + table.size)?;
+    let string = strings.get(offset as usize..)?;
+    let end = string.iter().position(|&b| b == 0)?;
+    Some(&string[..end])
+}
+
+/// The function symbols of the symbol table, sorted by their address.
+fn function_symbols(elf: &[u8], symtab: &Section, strtab: &Section) -> Result<Vec<Symbol>> {
+    let mut symbols = Vec::new();
+    for index in 0..symtab.size / SYMBOL_SIZE {
+        let symbol = symtab.offset + index * SYMBOL_SIZE;
+        let info = *elf
+            .get(symbol + 4)
+            .ok_or_else(|| invalid("truncated symbol table"))?;
+        let address = u64_at(elf, symbol + 8)?;
+        if info & 0xf != STT_FUNC || address == 0 {
+            continue;
+        }
+        let name = string_at(elf, strtab, u32_at(elf, symbol)?)
+            .ok_or_else(|| invalid("invalid symbol name"))?;
+        symbols.push(Symbol {
+            address,
+            size: u64_at(elf, symbol + 16)?,
+            name: truncate(demangle(&String::from_utf8_lossy(name))),
+        });
+    }
+    symbols.sort_by_key(|symbol| symbol.address);
+    Ok(symbols)
+}
+
+/// The count, the length of the names, the entries and the names.
+fn encode(symbols: &[Symbol]) -> Result<Vec<u8>> {
+    let mut entries = Vec::with_capacity(symbols.len() * ENTRY_SIZE);
+    let mut names = Vec::new();
+    for symbol in symbols {
+        entries.extend_from_slice(&symbol.address.to_le_bytes());
+        entries.extend_from_slice(&symbol.size.to_le_bytes());
+        entries.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        entries.extend_from_slice(&(symbol.name.len() as u32).to_le_bytes());
+        names.extend_from_slice(symbol.name.as_bytes());
+    }
+    if entries.len() + names.len() > CAPACITY {
+        return Err(invalid("too many symbols for the symbol section"));
+    }
+
+    let mut table = Vec::with_capacity(8 + entries.len() + names.len());
+    table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    table.extend_from_slice(&(names.len() as u32).to_le_bytes());
+    table.extend_from_slice(&entries);
+    table.extend_from_slice(&names);
+    Ok(table)
+}
+
+/// Demangles symbols of the legacy Rust mangling scheme, like
+/// `_ZN6martim5crash6report17h0123456789abcdefE`. Other names are returned unchanged.
+fn demangle(name: &str) -> String {
+    let mut rest = match name
+        .strip_prefix("_ZN")
+        .and_then(|name| name.strip_suffix('E'))
+    {
+        Some(rest) => rest,
+        None => return name.to_string(),
+    };
+    let mut components = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len = match rest[..digits].parse::<usize>() {
+            Ok(len) if digits + len <= rest.len() => len,
+            _ => return name.to_string(),
+        };
+        components.push(&rest[digits..digits + len]);
+        rest = &rest[digits + len..];
+    }
+    // the last component is the hash of the symbol
+    if let Some(hash) = components.last() {
+        if hash.len() == 17
+            && hash.starts_with('h')
+            && hash[1..].bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            components.pop();
+        }
+    }
+    components
+        .iter()
+        .map(|component| unescape(component))
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+fn truncate(mut name: String) -> String {
+    if name.len() > MAX_NAME_LEN {
+        let mut end = MAX_NAME_LEN - "...".len();
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+        name.push_str("...");
+    }
+    name
+}
+
+fn unescape(component: &str) -> String {
+    const ESCAPES: &[(&str, &str)] = &[
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$RF$", "&"),
+        ("$BP$", "*"),
+        ("$C$", ","),
+        ("$SP$", "@"),
+        ("$LP$", "("),
+        ("$RP$", ")"),
+        ("$u20$", " "),
+        ("$u27$", "'"),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("$u7e$", "~"),
+    ];
+    // components that start with an escape are prefixed with an underscore
+    let component = match component.strip_prefix('_') {
+        Some(stripped) if stripped.starts_with('$') => stripped,
+        _ => component,
+    };
+    let mut unescaped = component.replace("..", "::");
+    for (escape, replacement) in ESCAPES {
+        unescaped = unescaped.replace(escape, replacement);
+    }
+    unescaped
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn bytes_at<const N: usize>(elf: &[u8], offset: usize) -> Result<[u8; N]> {
+    elf.get(offset..offset + N)
+        .map(|bytes| bytes.try_into().unwrap())
+        .ok_or_else(|| invalid("unexpected end of file"))
+}
+
+fn u16_at(elf: &[u8], offset: usize) -> Result<u16> {
+    bytes_at(elf, offset).map(u16::from_le_bytes)
+}
+
+fn u32_at(elf: &[u8], offset: usize) -> Result<u32> {
+    bytes_at(elf, offset).map(u32::from_le_bytes)
+}
+
+fn u64_at(elf: &[u8], offset: usize) -> Result<u64> {
+    bytes_at(elf, offset).map(u64::from_le_bytes)
+}
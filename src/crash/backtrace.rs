@@ -0,0 +1,107 @@
+//! Stack walking with frame pointers. The kernel is compiled with frame pointers, so
+//! every function saves the frame pointer of its caller at the address in `rbp`,
+//! followed by its return address.
+
+use core::arch::asm;
+use core::fmt;
+
+use x86_64::VirtAddr;
+
+use crate::crash::symbols;
+use crate::memory;
+
+/// The largest number of frames in a backtrace.
+pub const MAX_FRAMES: usize = 32;
+
+/// The return addresses of the functions on the stack, innermost first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backtrace {
+    frames: [u64; MAX_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// The backtrace of the caller.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+        Self::walk(rbp)
+    }
+
+    /// Follows the chain of frame pointers that starts at `rbp`, until it ends, leaves
+    /// the mapped memory or [`MAX_FRAMES`] are found.
+    pub fn walk(mut rbp: u64) -> Self {
+        let mut backtrace = Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        };
+        while backtrace.len < MAX_FRAMES && is_readable(rbp) && is_readable(rbp + 8) {
+            let frame = rbp as *const u64;
+            let (caller_rbp, return_address) = unsafe { (frame.read(), frame.add(1).read()) };
+            if return_address == 0 {
+                break;
+            }
+            backtrace.frames[backtrace.len] = return_address;
+            backtrace.len += 1;
+            // the stack grows down, so the frames of callers are at higher addresses
+            if caller_rbp <= rbp {
+                break;
+            }
+            rbp = caller_rbp;
+        }
+        backtrace
+    }
+
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+}
+
+/// Whether a `u64` can be read at the given address.
+fn is_readable(addr: u64) -> bool {
+    addr % 8 == 0
+        && VirtAddr::try_new(addr)
+            .map_or(false, |addr| addr.as_u64() != 0 && memory::is_mapped(addr))
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, &address) in self.frames().iter().enumerate() {
+            write!(f, "{:>3}: {:#018x}", index, address)?;
+            // the return address belongs to the instruction after the call, which may be
+            // the first instruction of the next function
+            match symbols::lookup(address - 1) {
+                Some(symbol) => writeln!(f, " {}+{:#x}", symbol.name, symbol.offset + 1)?,
+                None => writeln!(f, " <unknown>")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[inline(never)]
+    fn capture_nested() -> Backtrace {
+        Backtrace::capture()
+    }
+
+    #[test_case]
+    fn test_capture_walks_through_callers() {
+        let backtrace = capture_nested();
+        // this test, the test runner and the entry point are on the stack as well
+        assert!(backtrace.frames().len() >= 3);
+        if !symbols::is_empty() {
+            assert!(symbols::lookup(backtrace.frames()[0] - 1).is_some());
+        }
+    }
+
+    #[test_case]
+    fn test_walk_stops_at_invalid_frame_pointer() {
+        assert!(Backtrace::walk(0).frames().is_empty());
+        assert!(Backtrace::walk(0x8000_0000_0000).frames().is_empty());
+    }
+}
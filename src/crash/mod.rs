@@ -0,0 +1,99 @@
+//! Reports of kernel panics: the panic message, a register dump and a symbolized
+//! backtrace. They are written to the serial port, and to the framebuffer if the kernel
+//! halts afterwards.
+//!
+//! Exception handlers call [`record_exception`] before they panic, so that the report
+//! shows the registers of the code that caused the exception.
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::crash::backtrace::Backtrace;
+use crate::crash::registers::Registers;
+use crate::{serial, vga_buffer};
+
+pub mod backtrace;
+pub mod registers;
+pub mod symbols;
+
+/// The registers at the last exception, which are reported by the next panic.
+static mut EXCEPTION_REGISTERS: Option<Registers> = None;
+
+/// Set while a report is written, so that a panic during the report doesn't write
+/// another one.
+static REPORTING: AtomicBool = AtomicBool::new(false);
+
+/// Records the registers of the code that caused an exception, for the report of the
+/// panic that follows.
+pub fn record_exception(stack_frame: &InterruptStackFrame) {
+    // exception handlers run with interrupts disabled
+    unsafe { EXCEPTION_REGISTERS = Some(Registers::from_exception(stack_frame)) };
+}
+
+/// Writes a report of the given panic to the serial port and to the framebuffer, which
+/// is cleared first. Only for panics after which the kernel halts, since the report
+/// overwrites the screen without the lock of the console writer.
+pub fn report(info: &PanicInfo) {
+    write_report(info, true);
+}
+
+/// Writes a report of the given panic to the serial port only, for panics of tasks that
+/// are terminated while the kernel keeps running. Interrupts stay disabled afterwards,
+/// so callers have to enable them again.
+pub fn report_to_serial(info: &PanicInfo) {
+    write_report(info, false);
+}
+
+fn write_report(info: &PanicInfo, use_screen: bool) {
+    interrupts::disable();
+    if REPORTING.swap(true, Ordering::SeqCst) {
+        // the report itself panicked, only the new message can be shown
+        unsafe { serial::_print_unlocked(format_args!("panic during panic report: {}\n", info)) };
+        return;
+    }
+    let registers = unsafe { EXCEPTION_REGISTERS.take() }.unwrap_or_else(Registers::capture);
+    let backtrace = Backtrace::capture();
+
+    let mut output = ReportWriter {
+        screen: if use_screen {
+            unsafe { vga_buffer::panic_writer() }
+        } else {
+            None
+        },
+    };
+    if let Some(screen) = output.screen.as_mut() {
+        screen.clear_screen();
+    }
+    let _ = write!(
+        output,
+        "KERNEL PANIC: {}\n\nregisters:\n{}\nbacktrace:\n{}",
+        info, registers, backtrace
+    );
+    if symbols::is_empty() {
+        let _ = writeln!(
+            output,
+            "(no kernel symbols, start the kernel with the runner)"
+        );
+    }
+    REPORTING.store(false, Ordering::SeqCst);
+}
+
+/// Writes to the serial port and to the framebuffer, without taking any locks.
+struct ReportWriter {
+    screen: Option<vga_buffer::Writer<'static>>,
+}
+
+impl Write for ReportWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // `report` disabled interrupts
+        unsafe { serial::_print_unlocked(format_args!("{}", s)) };
+        if let Some(screen) = self.screen.as_mut() {
+            screen.write_string(s);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use core::arch::asm;
+use core::fmt;
+
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::model_specific::Efer;
+use x86_64::registers::rflags;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::crash::symbols;
+
+/// The registers that describe the state of the processor when something went wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64,
+    pub cs: u64,
+    pub ss: u64,
+    pub cr0: u64,
+    /// The address that caused the last page fault.
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub efer: u64,
+}
+
+impl Registers {
+    /// The registers of the caller.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let (rip, rsp, cs, ss): (u64, u64, u64, u64);
+        unsafe {
+            asm!(
+                "lea {rip}, [rip]",
+                "mov {rsp}, rsp",
+                "mov {cs:x}, cs",
+                "mov {ss:x}, ss",
+                rip = out(reg) rip,
+                rsp = out(reg) rsp,
+                cs = out(reg) cs,
+                ss = out(reg) ss,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        Self::with_control_registers(rip, rsp, rflags::read_raw(), cs & 0xffff, ss & 0xffff)
+    }
+
+    /// The registers of the code that was interrupted by an exception.
+    pub fn from_exception(stack_frame: &InterruptStackFrame) -> Self {
+        Self::with_control_registers(
+            stack_frame.instruction_pointer.as_u64(),
+            stack_frame.stack_pointer.as_u64(),
+            stack_frame.cpu_flags,
+            stack_frame.code_segment,
+            stack_frame.stack_segment,
+        )
+    }
+
+    fn with_control_registers(rip: u64, rsp: u64, rflags: u64, cs: u64, ss: u64) -> Self {
+        Self {
+            rip,
+            rsp,
+            rflags,
+            cs,
+            ss,
+            cr0: Cr0::read_raw(),
+            cr2: Cr2::read().as_u64(),
+            cr3: Cr3::read().0.start_address().as_u64(),
+            cr4: Cr4::read_raw(),
+            efer: Efer::read_raw(),
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rip:    {:#018x}", self.rip)?;
+        if let Some(symbol) = symbols::lookup(self.rip) {
+            write!(f, " {}", symbol)?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "rsp:    {:#018x}  rflags: {:#018x}",
+            self.rsp, self.rflags
+        )?;
+        writeln!(
+            f,
+            "cs:     {:#06x}              ss:     {:#06x}",
+            self.cs, self.ss
+        )?;
+        writeln!(f, "cr0:    {:#018x}  cr2:    {:#018x}", self.cr0, self.cr2)?;
+        writeln!(f, "cr3:    {:#018x}  cr4:    {:#018x}", self.cr3, self.cr4)?;
+        writeln!(f, "efer:   {:#018x}", self.efer)
+    }
+}
@@ -0,0 +1,120 @@
+//! The function symbols of the kernel. The bootloader only loads the segments of the
+//! kernel, not its symbol table, so the runner copies the function symbols into the
+//! `.kernel_symbols` section of the kernel binary before the disk image is created.
+//!
+//! The section starts with [`MAGIC`], the number of symbols and the length of the names.
+//! They are followed by the symbols, sorted by their address, and the names.
+
+use core::fmt;
+use core::mem::size_of;
+use core::ptr::addr_of;
+
+/// Identifies the section for the runner.
+pub const MAGIC: [u8; 8] = *b"KSYMTAB1";
+
+/// The space for the symbols and their names. The section is part of the kernel image,
+/// so it is kept small, and the runner shortens long names to make the symbols fit.
+const CAPACITY: usize = 512 * 1024;
+
+#[repr(C, align(8))]
+struct SymbolTable {
+    magic: [u8; 8],
+    count: u32,
+    names_len: u32,
+    data: [u8; CAPACITY],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    address: u64,
+    size: u64,
+    name_offset: u32,
+    name_len: u32,
+}
+
+// mutable, so that the compiler doesn't assume that the table is empty
+#[used]
+#[link_section = ".kernel_symbols"]
+static mut SYMBOLS: SymbolTable = SymbolTable {
+    magic: MAGIC,
+    count: 0,
+    names_len: 0,
+    data: [0; CAPACITY],
+};
+
+/// A function symbol that contains an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub address: u64,
+    /// The offset of the address within the function.
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// The symbols and the names that the runner wrote into the table. Both are empty if
+/// the table was not filled in, or is invalid.
+fn table() -> (&'static [Entry], &'static [u8]) {
+    let table = unsafe { &*addr_of!(SYMBOLS) };
+    let entries_len = table.count as usize * size_of::<Entry>();
+    let names_len = table.names_len as usize;
+    if entries_len + names_len > CAPACITY {
+        return (&[], &[]);
+    }
+    let entries = unsafe {
+        core::slice::from_raw_parts(table.data.as_ptr() as *const Entry, table.count as usize)
+    };
+    (entries, &table.data[entries_len..entries_len + names_len])
+}
+
+/// Whether the kernel knows its symbols.
+pub fn is_empty() -> bool {
+    table().0.is_empty()
+}
+
+/// Returns the function that contains the given address.
+pub fn lookup(address: u64) -> Option<Symbol> {
+    let (entries, names) = table();
+    let index = entries.partition_point(|entry| entry.address <= address);
+    let entry = entries.get(index.checked_sub(1)?)?;
+    // symbols without a size, like the ones of assembly code, end at the next symbol
+    if entry.size != 0 && address >= entry.address + entry.size {
+        return None;
+    }
+    let start = entry.name_offset as usize;
+    let name = names.get(start..start + entry.name_len as usize)?;
+    Some(Symbol {
+        name: core::str::from_utf8(name).unwrap_or("<invalid name>"),
+        address: entry.address,
+        offset: address - entry.address,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_lookup_finds_function() {
+        if is_empty() {
+            // the kernel was not started by the runner
+            return;
+        }
+        let address = test_lookup_finds_function as usize as u64;
+        let symbol = lookup(address + 1).unwrap();
+        assert!(symbol.name.ends_with("test_lookup_finds_function"));
+        assert_eq!(address, symbol.address);
+        assert_eq!(1, symbol.offset);
+    }
+
+    #[test_case]
+    fn test_lookup_of_null_fails() {
+        assert_eq!(None, lookup(0));
+    }
+}
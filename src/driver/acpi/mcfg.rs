@@ -0,0 +1,74 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+
+use derive_more::Display;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::driver::acpi::SdtHeader;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Display)]
+pub enum Error {
+    #[display(fmt = "table length {_0} is too short")]
+    TooShort(u32),
+}
+
+/// The PCI Express memory mapped configuration space base address description table.
+#[derive(Debug)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+/// Describes the ECAM region of one PCI segment group for a range of buses.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct McfgEntry {
+    pub base_address: PhysAddr,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct RawEntry {
+    base_address: u64,
+    segment_group: u16,
+    start_bus: u8,
+    end_bus: u8,
+    reserved: u32,
+}
+
+impl Mcfg {
+    /// The MCFG has 8 reserved bytes after the header.
+    const ENTRIES_OFFSET: usize = size_of::<SdtHeader>() + 8;
+
+    /// Parses the MCFG at the given (mapped) address.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the complete table is mapped at the given address.
+    pub(in crate::driver::acpi) unsafe fn parse(
+        header: SdtHeader,
+        address: VirtAddr,
+    ) -> Result<Self, Error> {
+        let length = header.length as usize;
+        if length < Self::ENTRIES_OFFSET {
+            return Err(Error::TooShort(header.length));
+        }
+
+        let entry_count = (length - Self::ENTRIES_OFFSET) / size_of::<RawEntry>();
+        let entries = (0..entry_count)
+            .map(|i| {
+                let entry_address = address + Self::ENTRIES_OFFSET + i * size_of::<RawEntry>();
+                let raw = read_unaligned(entry_address.as_ptr::<RawEntry>());
+                McfgEntry {
+                    base_address: PhysAddr::new(raw.base_address),
+                    segment_group: raw.segment_group,
+                    start_bus: raw.start_bus,
+                    end_bus: raw.end_bus,
+                }
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+}
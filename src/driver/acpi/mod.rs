@@ -0,0 +1,180 @@
+//! Minimal parsing of the ACPI tables, as far as the kernel needs them.
+//!
+//! The tables are found through the RSDP, whose address is passed by the bootloader
+//! in [`init`]. Only the RSDT/XSDT and the tables listed there are read, there
+//! is no AML interpreter. The tables lie in normal memory, so they are read through
+//! the mapping of the complete physical memory instead of being mapped as MMIO.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+
+use conquer_once::spin::OnceCell;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::driver::acpi::mcfg::Mcfg;
+use crate::memory::physical_to_virtual;
+use crate::{error, info};
+
+pub mod mcfg;
+
+static RSDP_ADDRESS: OnceCell<PhysAddr> = OnceCell::uninit();
+
+/// Stores the physical address of the RSDP. Tables can only be found after this
+/// was called, and the memory manager is initialized.
+pub fn init(rsdp_address: PhysAddr) {
+    if RSDP_ADDRESS.try_init_once(|| rsdp_address).is_err() {
+        error!("acpi already initialized");
+    }
+}
+
+/// Returns the parsed MCFG table, or `None` if the platform doesn't have one
+/// (e.g. the QEMU `pc` machine, which has no PCIe).
+pub fn mcfg() -> Option<&'static Mcfg> {
+    static MCFG: OnceCell<Option<Mcfg>> = OnceCell::uninit();
+    MCFG.get_or_init(|| {
+        let (header, address) = find_table(b"MCFG")?;
+        match unsafe { Mcfg::parse(header, address) } {
+            Ok(mcfg) => Some(mcfg),
+            Err(e) => {
+                error!("could not parse MCFG: {}", e);
+                None
+            }
+        }
+    })
+    .as_ref()
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // the following fields are only valid with revision 2 or higher
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// The header that every system description table starts with.
+#[derive(Copy, Clone, Debug)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// Returns the header of the table at the given physical address together with the
+/// virtual address of the complete table, or `None` if the table is invalid.
+unsafe fn read_table(address: PhysAddr) -> Option<(SdtHeader, VirtAddr)> {
+    let table_address = physical_to_virtual(address);
+    let header = read_unaligned(table_address.as_ptr::<SdtHeader>());
+    let signature = core::str::from_utf8(&header.signature).unwrap_or("????");
+    if (header.length as usize) < size_of::<SdtHeader>() {
+        error!("acpi table {} is too short", signature);
+        return None;
+    }
+    if !has_valid_checksum(table_address, header.length as usize) {
+        error!("invalid checksum for acpi table {}", signature);
+        return None;
+    }
+    Some((header, table_address))
+}
+
+unsafe fn has_valid_checksum(address: VirtAddr, len: usize) -> bool {
+    core::slice::from_raw_parts(address.as_ptr::<u8>(), len)
+        .iter()
+        .fold(0_u8, |sum, &b| sum.wrapping_add(b))
+        == 0
+}
+
+/// Returns the physical addresses of all tables that are listed in the RSDT or XSDT.
+fn table_addresses() -> &'static [PhysAddr] {
+    static TABLES: OnceCell<Vec<PhysAddr>> = OnceCell::uninit();
+    TABLES.get_or_init(|| {
+        let rsdp_address = match RSDP_ADDRESS.get() {
+            Some(&a) => a,
+            None => {
+                error!("acpi is not initialized, no RSDP address given");
+                return Vec::new();
+            }
+        };
+        unsafe { read_table_addresses(rsdp_address) }.unwrap_or_default()
+    })
+}
+
+unsafe fn read_table_addresses(rsdp_address: PhysAddr) -> Option<Vec<PhysAddr>> {
+    let rsdp = read_unaligned(physical_to_virtual(rsdp_address).as_ptr::<Rsdp>());
+    if &rsdp.signature != b"RSD PTR " {
+        error!("invalid RSDP signature");
+        return None;
+    }
+
+    // the XSDT has 64 bit entries and is preferred if available
+    let (sdt_address, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (PhysAddr::new(rsdp.xsdt_address), 8)
+    } else {
+        (PhysAddr::new(rsdp.rsdt_address as u64), 4)
+    };
+    let (header, address) = read_table(sdt_address)?;
+    info!(
+        "found acpi {}",
+        core::str::from_utf8(&header.signature).unwrap_or("????")
+    );
+
+    // `read_table` checked that the table is at least as long as its header
+    let entry_count = (header.length as usize - size_of::<SdtHeader>()) / entry_size;
+    let entries = address + size_of::<SdtHeader>();
+    Some(
+        (0..entry_count)
+            .map(|i| {
+                let entry = entries + i * entry_size;
+                PhysAddr::new(if entry_size == 8 {
+                    read_unaligned(entry.as_ptr::<u64>())
+                } else {
+                    read_unaligned(entry.as_ptr::<u32>()) as u64
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Searches the table with the given signature, and returns its header and the
+/// virtual address of the table.
+pub fn find_table(signature: &[u8; 4]) -> Option<(SdtHeader, VirtAddr)> {
+    table_addresses().iter().find_map(|&address| unsafe {
+        let header = read_unaligned(physical_to_virtual(address).as_ptr::<SdtHeader>());
+        if &header.signature == signature {
+            read_table(address)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_find_table() {
+        // QEMU always provides a FADT
+        let (header, address) = find_table(b"FACP").unwrap();
+        assert!(header.length as usize > size_of::<SdtHeader>());
+        assert_eq!(*b"FACP", unsafe {
+            read_unaligned(address.as_ptr::<[u8; 4]>())
+        });
+        assert!(find_table(b"NONE").is_none());
+    }
+}
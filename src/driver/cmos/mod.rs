@@ -26,6 +26,32 @@ pub struct CMOSTime {
     pub century: Option<u8>,
 }
 
+impl CMOSTime {
+    /// The full year, assuming the 21st century if the CMOS has no century register.
+    pub fn full_year(&self) -> u64 {
+        self.century.unwrap_or(20) as u64 * 100 + self.year as u64
+    }
+
+    /// Converts this time into the number of seconds since 1970-01-01 00:00:00 UTC,
+    /// assuming that the CMOS clock runs in UTC.
+    pub fn unix_timestamp(&self) -> u64 {
+        // days from civil, see http://howardhinnant.github.io/date_algorithms.html
+        let month = self.month as i64;
+        let year = self.full_year() as i64 - if month <= 2 { 1 } else { 0 };
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + self.day_of_month as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        days as u64 * 86400
+            + self.hours as u64 * 3600
+            + self.minutes as u64 * 60
+            + self.seconds as u64
+    }
+}
+
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
 enum Mode {
     Binary,
@@ -158,3 +184,23 @@ impl CMOS {
 fn bcd_to_binary(bcd: u8) -> u8 {
     ((bcd & 0xF0) >> 1) + ((bcd & 0xF0) >> 3) + (bcd & 0x0F)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_unix_timestamp() {
+        let time = CMOSTime {
+            seconds: 56,
+            minutes: 34,
+            hours: 12,
+            weekday: 1,
+            day_of_month: 29,
+            month: 2,
+            year: 24,
+            century: Some(20),
+        };
+        assert_eq!(1709210096, time.unix_timestamp());
+    }
+}
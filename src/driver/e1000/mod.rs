@@ -0,0 +1,429 @@
+//! A driver for the Intel 8254x family of network cards, of which the 82540EM
+//! is QEMU's default network card. The card signals received frames with a
+//! message signaled interrupt, or with its legacy interrupt line if it doesn't
+//! support MSI (like the 82540EM). Without either, the card is polled.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::driver::pci::device::PCIDevice;
+use crate::driver::pci::header::PCIStandardHeaderDevice;
+use crate::driver::pci::msi;
+use crate::driver::registry::{PciDriver, PciMatch};
+use crate::interrupts::vector::{allocate_legacy_vector, InterruptHandler};
+use crate::io::fs::INode;
+use crate::memory::dma::DmaBuffer;
+use crate::memory::mmio;
+use crate::net::interface::{NetworkDevice, QEMU_USER_NETWORK};
+use crate::net::{self, Error, MacAddress, Result};
+use crate::{debug, info};
+
+const REG_CTRL: usize = 0x0000;
+const REG_EERD: usize = 0x0014;
+const REG_ICR: usize = 0x00C0;
+const REG_IMS: usize = 0x00D0;
+const REG_IMC: usize = 0x00D8;
+const REG_RCTL: usize = 0x0100;
+const REG_TCTL: usize = 0x0400;
+const REG_TIPG: usize = 0x0410;
+const REG_RDBAL: usize = 0x2800;
+const REG_RDBAH: usize = 0x2804;
+const REG_RDLEN: usize = 0x2808;
+const REG_RDH: usize = 0x2810;
+const REG_RDT: usize = 0x2818;
+const REG_TDBAL: usize = 0x3800;
+const REG_TDBAH: usize = 0x3804;
+const REG_TDLEN: usize = 0x3808;
+const REG_TDH: usize = 0x3810;
+const REG_TDT: usize = 0x3818;
+const REG_MTA: usize = 0x5200;
+const REG_RAL: usize = 0x5400;
+const REG_RAH: usize = 0x5404;
+
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+const RCTL_SECRC: u32 = 1 << 26;
+
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x10 << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+
+const ICR_RXDMT0: u32 = 1 << 4;
+const ICR_RXO: u32 = 1 << 6;
+const ICR_RXT0: u32 = 1 << 7;
+/// The interrupts that tell that the card received frames.
+const RX_INTERRUPTS: u32 = ICR_RXDMT0 | ICR_RXO | ICR_RXT0;
+
+const RAH_AV: u32 = 1 << 31;
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+
+const DESC_STATUS_DD: u8 = 1 << 0;
+const RX_STATUS_EOP: u8 = 1 << 1;
+const TX_CMD_EOP: u8 = 1 << 0;
+const TX_CMD_IFCS: u8 = 1 << 1;
+const TX_CMD_RS: u8 = 1 << 3;
+
+const RING_SIZE: usize = 32;
+/// The size of every receive and transmit buffer, which is the default receive
+/// buffer size of the card. Two buffers share a page, so that no buffer crosses
+/// a page boundary.
+const BUFFER_SIZE: usize = 2048;
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct Descriptor {
+    address: u64,
+    length: u16,
+    /// The checksum for receive descriptors, or checksum offset and command for
+    /// transmit descriptors.
+    field1: [u8; 2],
+    status: u8,
+    /// The errors for receive descriptors, or the checksum start for transmit descriptors.
+    field2: u8,
+    _special: u16,
+}
+
+/// A ring of descriptors together with their buffers.
+struct Ring {
+    descriptors: DmaBuffer,
+    buffers: DmaBuffer,
+}
+
+impl Ring {
+    fn allocate() -> Result<Self> {
+        let ring = Self {
+            descriptors: DmaBuffer::allocate(RING_SIZE * core::mem::size_of::<Descriptor>())
+                .map_err(|_| Error::Device)?,
+            buffers: DmaBuffer::allocate(RING_SIZE * BUFFER_SIZE).map_err(|_| Error::Device)?,
+        };
+        for i in 0..RING_SIZE {
+            let address = ring.buffers.phys_addr() + i * BUFFER_SIZE;
+            ring.set(
+                i,
+                Descriptor {
+                    address: address.as_u64(),
+                    ..Default::default()
+                },
+            );
+        }
+        Ok(ring)
+    }
+
+    fn get(&self, index: usize) -> Descriptor {
+        unsafe { read_volatile(self.descriptors.as_ptr::<Descriptor>().add(index)) }
+    }
+
+    fn set(&self, index: usize, descriptor: Descriptor) {
+        unsafe {
+            write_volatile(
+                self.descriptors.as_mut_ptr::<Descriptor>().add(index),
+                descriptor,
+            )
+        }
+    }
+
+    fn buffer(&mut self, index: usize) -> &mut [u8] {
+        &mut self.buffers.as_mut()[index * BUFFER_SIZE..(index + 1) * BUFFER_SIZE]
+    }
+
+    fn physical_address(&self) -> PhysAddr {
+        self.descriptors.phys_addr()
+    }
+}
+
+pub struct E1000 {
+    registers: VirtAddr,
+    mac_address: MacAddress,
+    rx: Ring,
+    tx: Ring,
+    /// The next receive descriptor that the card will fill.
+    rx_next: usize,
+    /// The next transmit descriptor that will be handed to the card.
+    tx_next: usize,
+    /// Whether the card signals received frames with an interrupt.
+    receive_interrupts: bool,
+}
+
+// the registers and rings are only accessed through `&mut self`
+unsafe impl Send for E1000 {}
+
+impl E1000 {
+    /// Resets the card and sets up the receive and transmit rings.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `registers` is the mapped register space of an
+    /// e1000 card, and that no one else uses the card.
+    pub unsafe fn new(registers: VirtAddr) -> Result<Self> {
+        let mut device = Self {
+            registers,
+            mac_address: MacAddress::ZERO,
+            rx: Ring::allocate()?,
+            tx: Ring::allocate()?,
+            rx_next: 0,
+            tx_next: 0,
+            receive_interrupts: false,
+        };
+
+        device.write(REG_IMC, u32::MAX);
+        device.write(REG_CTRL, device.read(REG_CTRL) | CTRL_RST);
+        // the reset bit clears itself after the reset, which takes about a microsecond
+        while device.read(REG_CTRL) & CTRL_RST != 0 {
+            core::hint::spin_loop();
+        }
+        device.write(REG_IMC, u32::MAX);
+        device.write(REG_CTRL, device.read(REG_CTRL) | CTRL_SLU);
+
+        device.mac_address = device.read_mac_address();
+        for i in 0..128 {
+            device.write(REG_MTA + i * 4, 0);
+        }
+        device.init_rx()?;
+        device.init_tx()?;
+        Ok(device)
+    }
+
+    /// Lets the card signal received frames with an interrupt, which has to be set up
+    /// before.
+    pub fn enable_receive_interrupts(&mut self) {
+        // clear interrupts that are still pending from before
+        self.read(REG_ICR);
+        self.write(REG_IMS, RX_INTERRUPTS);
+        self.receive_interrupts = true;
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        read_register(self.registers, register)
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe {
+            write_volatile(
+                (self.registers.as_u64() as usize + register) as *mut u32,
+                value,
+            )
+        }
+    }
+
+    fn read_mac_address(&self) -> MacAddress {
+        let high = self.read(REG_RAH);
+        if high & RAH_AV != 0 {
+            let low = self.read(REG_RAL).to_le_bytes();
+            let high = high.to_le_bytes();
+            return MacAddress([low[0], low[1], low[2], low[3], high[0], high[1]]);
+        }
+
+        // the receive address is not loaded, so read it from the eeprom
+        let mut mac = [0_u8; 6];
+        for word in 0..3 {
+            self.write(REG_EERD, EERD_START | (word << 8));
+            let value = loop {
+                let value = self.read(REG_EERD);
+                if value & EERD_DONE != 0 {
+                    break value;
+                }
+            };
+            let bytes = ((value >> 16) as u16).to_le_bytes();
+            mac[word as usize * 2..word as usize * 2 + 2].copy_from_slice(&bytes);
+        }
+        let mac = MacAddress(mac);
+        self.write(
+            REG_RAL,
+            u32::from_le_bytes([mac.0[0], mac.0[1], mac.0[2], mac.0[3]]),
+        );
+        self.write(
+            REG_RAH,
+            u32::from_le_bytes([mac.0[4], mac.0[5], 0, 0]) | RAH_AV,
+        );
+        mac
+    }
+
+    fn init_rx(&mut self) -> Result<()> {
+        let address = self.rx.physical_address().as_u64();
+        self.write(REG_RDBAL, address as u32);
+        self.write(REG_RDBAH, (address >> 32) as u32);
+        self.write(
+            REG_RDLEN,
+            (RING_SIZE * core::mem::size_of::<Descriptor>()) as u32,
+        );
+        self.write(REG_RDH, 0);
+        // all descriptors except the one at the tail belong to the card
+        self.write(REG_RDT, RING_SIZE as u32 - 1);
+        // buffer size 2048 is the default (BSIZE = 0)
+        self.write(REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+        Ok(())
+    }
+
+    fn init_tx(&mut self) -> Result<()> {
+        // mark all descriptors as done, so that they can be used
+        for i in 0..RING_SIZE {
+            let mut descriptor = self.tx.get(i);
+            descriptor.status = DESC_STATUS_DD;
+            self.tx.set(i, descriptor);
+        }
+
+        let address = self.tx.physical_address().as_u64();
+        self.write(REG_TDBAL, address as u32);
+        self.write(REG_TDBAH, (address >> 32) as u32);
+        self.write(
+            REG_TDLEN,
+            (RING_SIZE * core::mem::size_of::<Descriptor>()) as u32,
+        );
+        self.write(REG_TDH, 0);
+        self.write(REG_TDT, 0);
+        self.write(REG_TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+        // recommended inter packet gap for IEEE 802.3
+        self.write(REG_TIPG, 0x0060_200A);
+        Ok(())
+    }
+}
+
+impl NetworkDevice for E1000 {
+    fn mac_address(&self) -> MacAddress {
+        self.mac_address
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        if frame.len() > BUFFER_SIZE {
+            return Err(Error::PacketTooLarge);
+        }
+        let index = self.tx_next;
+        let mut descriptor = self.tx.get(index);
+        if descriptor.status & DESC_STATUS_DD == 0 {
+            // the card didn't send the frame that was in this descriptor yet
+            return Err(Error::Device);
+        }
+
+        self.tx.buffer(index)[..frame.len()].copy_from_slice(frame);
+        descriptor.length = frame.len() as u16;
+        descriptor.field1 = [0, TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS];
+        descriptor.status = 0;
+        self.tx.set(index, descriptor);
+
+        self.tx_next = (index + 1) % RING_SIZE;
+        self.write(REG_TDT, self.tx_next as u32);
+        Ok(())
+    }
+
+    fn notifies_receive(&self) -> bool {
+        self.receive_interrupts
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let index = self.rx_next;
+            let mut descriptor = self.rx.get(index);
+            if descriptor.status & DESC_STATUS_DD == 0 {
+                return None;
+            }
+
+            // frames that span multiple descriptors are too large for us and are dropped
+            let complete = descriptor.status & RX_STATUS_EOP != 0 && descriptor.field2 == 0;
+            let frame =
+                complete.then(|| self.rx.buffer(index)[..descriptor.length as usize].to_vec());
+
+            descriptor.status = 0;
+            self.rx.set(index, descriptor);
+            self.rx_next = (index + 1) % RING_SIZE;
+            // give the descriptor back to the card
+            self.write(REG_RDT, index as u32);
+
+            if frame.is_some() {
+                return frame;
+            }
+        }
+    }
+}
+
+fn read_register(registers: VirtAddr, register: usize) -> u32 {
+    unsafe { read_volatile((registers.as_u64() as usize + register) as *const u32) }
+}
+
+/// Acknowledges the interrupts of the card with the given registers, and wakes the
+/// futures that wait for the network if the card received frames.
+fn handle_interrupt(registers: VirtAddr) {
+    // reading the interrupt causes clears them
+    if read_register(registers, REG_ICR) & RX_INTERRUPTS != 0 {
+        net::notify_received();
+    }
+}
+
+pub static E1000_DRIVER: E1000Driver = E1000Driver;
+
+/// Registers every supported card as network interface `ethN`. The first interface is
+/// configured for QEMU's user mode networking.
+pub struct E1000Driver;
+
+impl E1000Driver {
+    const ID_TABLE: &'static [PciMatch] = &[
+        PciMatch::id(0x8086, 0x100E), // 82540EM
+        PciMatch::id(0x8086, 0x100F), // 82545EM
+    ];
+}
+
+impl PciDriver for E1000Driver {
+    fn name(&self) -> &'static str {
+        "e1000"
+    }
+
+    fn id_table(&self) -> &'static [PciMatch] {
+        Self::ID_TABLE
+    }
+
+    fn probe(&self, device: &PCIDevice) -> kstd::io::Result<Vec<INode>> {
+        static NEXT_INTERFACE: AtomicUsize = AtomicUsize::new(0);
+
+        let header = PCIStandardHeaderDevice::new(device.clone())
+            .map_err(|_| kstd::io::Error::InvalidArgument)?;
+        let (address, size) = match header.bar(0) {
+            Some(bar) if !bar.is_io() => (bar.address(), bar.size() as usize),
+            _ => return Err(kstd::io::Error::InvalidArgument),
+        };
+
+        device.enable_memory_space();
+        device.enable_bus_master();
+        let registers = unsafe { mmio::map_physical(PhysAddr::new(address), size) }
+            .map_err(|_| kstd::io::Error::from(Error::Device))?;
+        let mut e1000 = unsafe { E1000::new(registers) }?;
+        let handler: InterruptHandler = Arc::new(move || handle_interrupt(registers));
+        let vector = msi::allocate_interrupt(device, handler.clone()).or_else(|e| {
+            debug!(
+                "{} has no message signaled interrupts ({}), using its interrupt line",
+                device, e
+            );
+            device
+                .interrupt_line()
+                .and_then(|irq| allocate_legacy_vector(irq, handler))
+                .ok_or(e)
+        });
+        match vector {
+            Ok(_) => e1000.enable_receive_interrupts(),
+            Err(_) => info!("{} has no usable interrupt and is polled", device),
+        }
+
+        let number = NEXT_INTERFACE.fetch_add(1, Ordering::SeqCst);
+        let name = format!("eth{}", number);
+        info!(
+            "{} at {} with mac address {}",
+            name,
+            device,
+            e1000.mac_address()
+        );
+        let config = (number == 0).then_some(QEMU_USER_NETWORK);
+        net::add_interface(name, Box::new(e1000), config);
+
+        // network interfaces don't appear in /dev
+        Ok(Vec::new())
+    }
+}
@@ -7,6 +7,7 @@ use x86_64::instructions::interrupts::without_interrupts;
 
 use crate::driver::ide::channel::IDEChannel;
 use crate::driver::ide::{is_bit_set, Command, Status, UDMAMode};
+use crate::io::fs::device::FlushableBlockDevice;
 use kstd::io::block::BlockDevice;
 use kstd::io::Result;
 
@@ -255,3 +256,6 @@ impl BlockDevice for &IDEDrive {
         Ok(buffer.len())
     }
 }
+
+// every write is a complete PIO transfer to the drive
+impl FlushableBlockDevice for &IDEDrive {}
@@ -1,7 +1,10 @@
+use alloc::boxed::Box;
+use alloc::format;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Display, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use bitflags::bitflags;
 use kstd::sync::Mutex;
@@ -9,7 +12,13 @@ use kstd::sync::Mutex;
 use crate::driver::ide::channel::IDEChannel;
 use crate::driver::ide::drive::IDEDrive;
 use crate::driver::pci::classes::{InterruptPin, MassStorageSubClass, PCIDeviceClass};
+use crate::driver::pci::device::PCIDevice;
 use crate::driver::pci::header::PCIStandardHeaderDevice;
+use crate::driver::registry::{PciDriver, PciMatch};
+use crate::io::fs::device::block::BlockDeviceFile;
+use crate::io::fs::device::partition::{read_partitions, PartitionBlockDevice};
+use crate::io::fs::INode;
+use crate::{error, info};
 
 pub mod channel;
 pub mod drive;
@@ -60,6 +69,7 @@ pub struct IDEController {
     secondary: Arc<Mutex<IDEChannel>>,
     interrupt_pin: InterruptPin,
     interrupt_line: Option<u8>,
+    pci_device: PCIDevice,
 
     drives: Vec<IDEDrive>,
 }
@@ -68,6 +78,10 @@ impl IDEController {
     pub fn drives(&self) -> &Vec<IDEDrive> {
         &self.drives
     }
+
+    pub fn pci_device(&self) -> &PCIDevice {
+        &self.pci_device
+    }
 }
 
 impl From<PCIStandardHeaderDevice> for IDEController {
@@ -129,6 +143,7 @@ impl From<PCIStandardHeaderDevice> for IDEController {
             secondary: secondary_channel,
             interrupt_pin: device.interrupt_pin(),
             interrupt_line: device.interrupt_line(),
+            pci_device: (*device).clone(),
             drives,
         }
     }
@@ -145,6 +160,108 @@ impl Debug for IDEController {
     }
 }
 
+pub static IDE_DRIVER: IDEDriver = IDEDriver;
+
+/// The controllers that the [`IDE_DRIVER`] was bound to. They are never dropped, since
+/// the nodes of their drives may still be in use after the driver was unbound.
+static CONTROLLERS: Mutex<Vec<&'static IDEController>> = Mutex::new(Vec::new());
+
+/// Returns the controllers that the [`IDE_DRIVER`] was bound to.
+pub fn controllers() -> Vec<&'static IDEController> {
+    CONTROLLERS.lock().clone()
+}
+
+/// Publishes every existing drive of an IDE controller as `/dev/ideN`, and
+/// every partition on it as `/dev/ideNpM`.
+pub struct IDEDriver;
+
+impl IDEDriver {
+    const ID_TABLE: &'static [PciMatch] = &[PciMatch::class(
+        PCIDeviceClass::MassStorageController(MassStorageSubClass::IDEController),
+    )];
+
+    /// Returns the controller of the given device. A device that was bound before keeps
+    /// its controller, so that there is only one controller for its ports.
+    fn controller(device: &PCIDevice) -> kstd::io::Result<&'static IDEController> {
+        let mut controllers = CONTROLLERS.lock();
+        let existing = controllers.iter().find(|c| {
+            let d = c.pci_device();
+            d.bus() == device.bus()
+                && d.slot() == device.slot()
+                && d.function() == device.function()
+        });
+        if let Some(&controller) = existing {
+            return Ok(controller);
+        }
+        let header = PCIStandardHeaderDevice::new(device.clone())
+            .map_err(|_| kstd::io::Error::InvalidArgument)?;
+        let controller: &'static IDEController = Box::leak(Box::new(header.into()));
+        controllers.push(controller);
+        Ok(controller)
+    }
+
+    fn partition_nodes(name: &str, drive: &'static IDEDrive) -> Vec<INode> {
+        let partitions = match read_partitions(&drive) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("could not read partition table of {}: {}", name, e);
+                return Vec::new();
+            }
+        };
+
+        partitions
+            .into_iter()
+            .map(|partition| {
+                let partition_name = format!("{}p{}", name, partition.number);
+                info!(
+                    "partition {} (blocks {}..{}) of {} at /dev/{}",
+                    partition.number,
+                    partition.first_block,
+                    partition.first_block + partition.block_count,
+                    name,
+                    partition_name
+                );
+                let device = PartitionBlockDevice::new(drive, &partition);
+                INode::new_block_device_file(BlockDeviceFile::new(
+                    device,
+                    0_u64.into(),
+                    partition_name,
+                ))
+            })
+            .collect()
+    }
+}
+
+impl PciDriver for IDEDriver {
+    fn name(&self) -> &'static str {
+        "ide"
+    }
+
+    fn id_table(&self) -> &'static [PciMatch] {
+        Self::ID_TABLE
+    }
+
+    fn probe(&self, device: &PCIDevice) -> kstd::io::Result<Vec<INode>> {
+        static NEXT_DRIVE: AtomicUsize = AtomicUsize::new(0);
+
+        let controller = Self::controller(device)?;
+
+        let mut nodes = Vec::new();
+        for drive in controller.drives().iter().filter(|d| d.exists()) {
+            let name = format!("ide{}", NEXT_DRIVE.fetch_add(1, Ordering::SeqCst));
+            info!("{} at /dev/{}", drive, name);
+            let partitions = Self::partition_nodes(&name, drive);
+            nodes.push(INode::new_block_device_file(BlockDeviceFile::new(
+                drive,
+                0_u64.into(),
+                name,
+            )));
+            nodes.extend(partitions);
+        }
+        Ok(nodes)
+    }
+}
+
 fn is_bit_set(haystack: u64, needle: u8) -> bool {
     (haystack & (1 << needle)) > 0
 }
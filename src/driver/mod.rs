@@ -6,13 +6,19 @@ use conquer_once::spin::OnceCell;
 
 use crate::driver::cmos::{CMOSTime, CMOS};
 use crate::driver::ide::drive::IDEDrive;
-use crate::driver::ide::IDEController;
-use crate::driver::pci::classes::{MassStorageSubClass, PCIDeviceClass};
-use crate::driver::pci::header::PCIStandardHeaderDevice;
 
+pub mod acpi;
 pub mod cmos;
+pub mod e1000;
 pub mod ide;
 pub mod pci;
+pub mod registry;
+
+/// Registers all drivers that are built into the kernel with the [`registry`].
+pub fn register_builtin_drivers() {
+    registry::register(&ide::IDE_DRIVER);
+    registry::register(&e1000::E1000_DRIVER);
+}
 
 pub struct Peripherals;
 
@@ -28,29 +34,12 @@ impl Peripherals {
         CMOS.get_or_init(|| Arc::new(Mutex::new(CMOS::new())))
     }
 
-    pub fn ide_controllers() -> &'static [IDEController] {
-        static IDE_CONTROLLERS: OnceCell<Vec<IDEController>> = OnceCell::uninit();
-        IDE_CONTROLLERS.get_or_init(|| {
-            pci::devices()
-                .filter(|dev| {
-                    dev.class()
-                        == PCIDeviceClass::MassStorageController(MassStorageSubClass::IDEController)
-                })
-                .map(|d| PCIStandardHeaderDevice::new(d.clone()).unwrap())
-                .map(Into::<IDEController>::into)
-                .collect::<Vec<IDEController>>()
-        })
-    }
-
-    pub fn ide_drives() -> &'static [&'static IDEDrive] {
-        static IDE_DRIVES: OnceCell<Vec<&'static IDEDrive>> = OnceCell::uninit();
-
-        IDE_DRIVES.get_or_init(|| {
-            Peripherals::ide_controllers()
-                .iter()
-                .flat_map(|c| c.drives())
-                .filter(|d| d.exists())
-                .collect::<Vec<&IDEDrive>>()
-        })
+    /// The existing drives of all IDE controllers that the IDE driver was bound to.
+    pub fn ide_drives() -> Vec<&'static IDEDrive> {
+        ide::controllers()
+            .into_iter()
+            .flat_map(|c| c.drives())
+            .filter(|d| d.exists())
+            .collect()
     }
 }
@@ -0,0 +1,131 @@
+use crate::driver::pci::device::PCIDevice;
+
+/// The maximum number of capabilities that are visited, to avoid looping forever
+/// on a malformed list. The list lives in the 192 bytes after the standard header,
+/// and every capability is at least 4 bytes long.
+const MAX_CAPABILITIES: usize = 48;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CapabilityId {
+    PowerManagement,
+    Agp,
+    VitalProductData,
+    SlotIdentification,
+    Msi,
+    VendorSpecific,
+    PciExpress,
+    MsiX,
+    Other(u8),
+}
+
+impl From<u8> for CapabilityId {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => Self::PowerManagement,
+            0x02 => Self::Agp,
+            0x03 => Self::VitalProductData,
+            0x04 => Self::SlotIdentification,
+            0x05 => Self::Msi,
+            0x09 => Self::VendorSpecific,
+            0x10 => Self::PciExpress,
+            0x11 => Self::MsiX,
+            _ => Self::Other(value),
+        }
+    }
+}
+
+/// An entry in the capabilities list of a [`PCIDevice`]. The offset is the position
+/// of the capability in the configuration space of the device, and can be used to
+/// read the capability specific registers.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Capability {
+    pub id: CapabilityId,
+    pub offset: u16,
+}
+
+pub struct Capabilities<'a> {
+    device: &'a PCIDevice,
+    next: u16,
+    visited: usize,
+}
+
+impl<'a> Capabilities<'a> {
+    pub(in crate::driver::pci) fn new(device: &'a PCIDevice, first: u16) -> Self {
+        Self {
+            device,
+            next: first,
+            visited: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for Capabilities<'a> {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == 0 || self.visited >= MAX_CAPABILITIES {
+            return None;
+        }
+        self.visited += 1;
+
+        let offset = self.next;
+        let header = unsafe { self.device.read_config_word(offset) };
+        self.next = (header >> 8) & 0xFC;
+        Some(Capability {
+            id: CapabilityId::from(header as u8),
+            offset,
+        })
+    }
+}
+
+/// An entry in the extended capabilities list, which starts at offset 0x100 in the
+/// configuration space and is only accessible through ECAM.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    pub offset: u16,
+}
+
+pub struct ExtendedCapabilities<'a> {
+    device: &'a PCIDevice,
+    next: u16,
+    visited: usize,
+}
+
+impl<'a> ExtendedCapabilities<'a> {
+    /// The maximum number of extended capabilities that are visited, see [`MAX_CAPABILITIES`].
+    const MAX_EXTENDED_CAPABILITIES: usize = (4096 - 256) / 4;
+
+    pub(in crate::driver::pci) fn new(device: &'a PCIDevice, first: u16) -> Self {
+        Self {
+            device,
+            next: first,
+            visited: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for ExtendedCapabilities<'a> {
+    type Item = ExtendedCapability;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next < 0x100 || self.visited >= Self::MAX_EXTENDED_CAPABILITIES {
+            return None;
+        }
+        self.visited += 1;
+
+        let offset = self.next;
+        let header = unsafe { self.device.read_config_double_word(offset) };
+        // a header of 0 (no capabilities) or all ones (not accessible) ends the list
+        if header == 0 || header == 0xFFFF_FFFF {
+            return None;
+        }
+        self.next = (header >> 20) as u16 & 0xFFC;
+        Some(ExtendedCapability {
+            id: header as u16,
+            version: (header >> 16) as u8 & 0xF,
+            offset,
+        })
+    }
+}
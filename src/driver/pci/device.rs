@@ -1,13 +1,16 @@
 use alloc::sync::Arc;
+use core::fmt::{Display, Formatter};
 
 use bitflags::bitflags;
 use kstd::sync::RwLock;
 
+use crate::driver::pci::capability::{Capabilities, ExtendedCapabilities};
 use crate::driver::pci::classes::{InterruptPin, PCIDeviceClass};
 use crate::driver::pci::raw::{
-    read_config_half_word, read_config_word, OFFSET_BIST, OFFSET_CLASS_SUBCLASS,
-    OFFSET_HEADER_TYPE, OFFSET_INTERRUPT_LINE, OFFSET_INTERRUPT_PIN, OFFSET_PROG_IF_REVISION_ID,
-    OFFSET_STATUS,
+    has_extended_config_space, read_config_double_word, read_config_half_word, read_config_word,
+    write_config_double_word, write_config_half_word, write_config_word, OFFSET_BIST,
+    OFFSET_CAPABILITIES_POINTER, OFFSET_CLASS_SUBCLASS, OFFSET_COMMAND, OFFSET_HEADER_TYPE,
+    OFFSET_INTERRUPT_LINE, OFFSET_INTERRUPT_PIN, OFFSET_PROG_IF_REVISION_ID, OFFSET_STATUS,
 };
 use crate::driver::pci::Error;
 use crate::error;
@@ -28,6 +31,21 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct Command: u16 {
+        const INTERRUPT_DISABLE = 1 << 10;
+        const FAST_BACK_TO_BACK_ENABLE = 1 << 9;
+        const SERR_ENABLE = 1 << 8;
+        const PARITY_ERROR_RESPONSE = 1 << 6;
+        const VGA_PALETTE_SNOOP = 1 << 5;
+        const MEMORY_WRITE_AND_INVALIDATE_ENABLE = 1 << 4;
+        const SPECIAL_CYCLES = 1 << 3;
+        const BUS_MASTER = 1 << 2;
+        const MEMORY_SPACE = 1 << 1;
+        const IO_SPACE = 1 << 0;
+    }
+}
+
 bitflags! {
     pub struct BIST: u8 {
         const BIST_CAPABLE = 1 << 7;
@@ -176,6 +194,124 @@ impl PCIDevice {
     pub fn interrupt_pin(&self) -> InterruptPin {
         self.inner.read().interrupt_pin
     }
+
+    pub fn command(&self) -> Command {
+        let command = unsafe { self.read_config_word(OFFSET_COMMAND) };
+        Command::from_bits_truncate(command)
+    }
+
+    /// Writes the command register of this device.
+    ///
+    /// # Safety
+    ///
+    /// Changing the command register changes the way that the device
+    /// accesses memory and responds to accesses. The caller has to make sure
+    /// that the device is set up correctly for the new command.
+    pub unsafe fn set_command(&self, command: Command) {
+        // the status register shares the double word with the command register, but
+        // its bits are cleared by writing 1, so we write 0 to leave them untouched
+        self.write_config_double_word(OFFSET_COMMAND, command.bits() as u32);
+    }
+
+    /// Enables the device to act as bus master, which is required for DMA.
+    pub fn enable_bus_master(&self) {
+        unsafe { self.set_command(self.command() | Command::BUS_MASTER) };
+    }
+
+    /// Enables the device to respond to accesses to its memory mapped BARs.
+    pub fn enable_memory_space(&self) {
+        unsafe { self.set_command(self.command() | Command::MEMORY_SPACE) };
+    }
+
+    /// Enables the device to respond to accesses to its I/O port BARs.
+    pub fn enable_io_space(&self) {
+        unsafe { self.set_command(self.command() | Command::IO_SPACE) };
+    }
+
+    /// Returns an iterator over the capabilities list of this device. If the
+    /// device doesn't have a capabilities list, the iterator is empty.
+    pub fn capabilities(&self) -> Capabilities {
+        let first = if self.status().contains(Status::CAPABILITIES_LIST) {
+            unsafe { (self.read_config_half_word(OFFSET_CAPABILITIES_POINTER) & !3) as u16 }
+        } else {
+            0
+        };
+        Capabilities::new(self, first)
+    }
+
+    /// Returns an iterator over the extended capabilities of this device. The iterator
+    /// is empty if the device has none, or if the extended configuration space is not
+    /// accessible because ECAM is not available.
+    pub fn extended_capabilities(&self) -> ExtendedCapabilities {
+        let first = if has_extended_config_space() {
+            0x100
+        } else {
+            0
+        };
+        ExtendedCapabilities::new(self, first)
+    }
+}
+
+// raw config space access
+impl PCIDevice {
+    /// # Safety
+    ///
+    /// Reading from the configuration space may have side effects, depending on the device.
+    pub unsafe fn read_config_double_word(&self, offset: u16) -> u32 {
+        let guard = self.inner.read();
+        read_config_double_word(guard.bus, guard.slot, guard.function, offset)
+    }
+
+    /// # Safety
+    ///
+    /// Reading from the configuration space may have side effects, depending on the device.
+    pub unsafe fn read_config_word(&self, offset: u16) -> u16 {
+        let guard = self.inner.read();
+        read_config_word(guard.bus, guard.slot, guard.function, offset)
+    }
+
+    /// # Safety
+    ///
+    /// Reading from the configuration space may have side effects, depending on the device.
+    pub unsafe fn read_config_half_word(&self, offset: u16) -> u8 {
+        let guard = self.inner.read();
+        read_config_half_word(guard.bus, guard.slot, guard.function, offset)
+    }
+
+    /// # Safety
+    ///
+    /// Writing to the configuration space changes the behavior of the device.
+    pub unsafe fn write_config_double_word(&self, offset: u16, value: u32) {
+        let guard = self.inner.read();
+        write_config_double_word(guard.bus, guard.slot, guard.function, offset, value)
+    }
+
+    /// # Safety
+    ///
+    /// Writing to the configuration space changes the behavior of the device.
+    pub unsafe fn write_config_word(&self, offset: u16, value: u16) {
+        let guard = self.inner.read();
+        write_config_word(guard.bus, guard.slot, guard.function, offset, value)
+    }
+
+    /// # Safety
+    ///
+    /// Writing to the configuration space changes the behavior of the device.
+    pub unsafe fn write_config_half_word(&self, offset: u16, value: u8) {
+        let guard = self.inner.read();
+        write_config_half_word(guard.bus, guard.slot, guard.function, offset, value)
+    }
+}
+
+impl Display for PCIDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let guard = self.inner.read();
+        write!(
+            f,
+            "{:02x}:{:02x}.{} [{:04x}:{:04x}]",
+            guard.bus, guard.slot, guard.function, guard.vendor, guard.device
+        )
+    }
 }
 
 // plain getters
@@ -1,9 +1,54 @@
 use core::ops::Deref;
 
-use crate::driver::pci::device::{PCIDevice, PCIHeaderType};
-use crate::driver::pci::raw::read_config_double_word;
+use crate::driver::pci::device::{Command, PCIDevice, PCIHeaderType};
+use crate::driver::pci::raw::{
+    read_config_double_word, OFFSET_PRIMARY_BUS, OFFSET_SECONDARY_BUS, OFFSET_SUBORDINATE_BUS,
+};
 use crate::driver::pci::Error;
 
+/// A decoded base address register, including the size of the region
+/// that the device decodes.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Bar {
+    Io {
+        port: u32,
+        size: u32,
+    },
+    Memory32 {
+        address: u32,
+        size: u32,
+        prefetchable: bool,
+    },
+    Memory64 {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+    },
+}
+
+impl Bar {
+    pub fn is_io(&self) -> bool {
+        matches!(self, Bar::Io { .. })
+    }
+
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Io { size, .. } => size as u64,
+            Bar::Memory32 { size, .. } => size as u64,
+            Bar::Memory64 { size, .. } => size,
+        }
+    }
+
+    /// The memory address or the port number of this BAR.
+    pub fn address(&self) -> u64 {
+        match *self {
+            Bar::Io { port, .. } => port as u64,
+            Bar::Memory32 { address, .. } => address as u64,
+            Bar::Memory64 { address, .. } => address,
+        }
+    }
+}
+
 pub struct PCIStandardHeaderDevice {
     inner: PCIDevice,
 }
@@ -17,12 +62,12 @@ impl Deref for PCIStandardHeaderDevice {
 }
 
 impl PCIStandardHeaderDevice {
-    const OFFSET_BAR0: u8 = 0x10;
-    const OFFSET_BAR1: u8 = 0x14;
-    const OFFSET_BAR2: u8 = 0x18;
-    const OFFSET_BAR3: u8 = 0x1C;
-    const OFFSET_BAR4: u8 = 0x20;
-    const OFFSET_BAR5: u8 = 0x24;
+    const OFFSET_BAR0: u16 = 0x10;
+    const OFFSET_BAR1: u16 = 0x14;
+    const OFFSET_BAR2: u16 = 0x18;
+    const OFFSET_BAR3: u16 = 0x1C;
+    const OFFSET_BAR4: u16 = 0x20;
+    const OFFSET_BAR5: u16 = 0x24;
 
     pub fn new(inner: PCIDevice) -> Result<Self, Error> {
         let header_type = inner.header_type();
@@ -56,7 +101,7 @@ impl PCIStandardHeaderDevice {
         self.read_bar(Self::OFFSET_BAR5)
     }
 
-    fn read_bar(&self, bar_offset: u8) -> u32 {
+    fn read_bar(&self, bar_offset: u16) -> u32 {
         unsafe {
             read_config_double_word(
                 self.inner.bus(),
@@ -66,6 +111,85 @@ impl PCIStandardHeaderDevice {
             )
         }
     }
+
+    /// Decodes the BAR with the given index (0 through 5) and determines the size of its region.
+    /// Returns `None` if the BAR is not implemented, or if it is the upper half of a 64-bit BAR.
+    ///
+    /// To determine the size, all ones are written into the BAR, so decoding of the
+    /// device is disabled while the BAR is sized.
+    pub fn bar(&self, index: u8) -> Option<Bar> {
+        assert!(index < 6, "a standard header has only 6 BARs");
+        if self.is_upper_half(index) {
+            return None;
+        }
+        let offset = Self::OFFSET_BAR0 + index as u16 * 4;
+
+        let command = self.inner.command();
+        unsafe {
+            self.inner
+                .set_command(command - Command::IO_SPACE - Command::MEMORY_SPACE)
+        };
+        let bar = unsafe { self.decode_bar(offset) };
+        unsafe { self.inner.set_command(command) };
+        bar
+    }
+
+    /// Whether the BAR with the given index holds the upper half of the address of a
+    /// 64-bit memory BAR. The BARs have to be walked from the start, since the value of
+    /// an upper half may look like a 64-bit BAR itself.
+    fn is_upper_half(&self, index: u8) -> bool {
+        let mut current = 0;
+        while current < index {
+            let bar = self.read_bar(Self::OFFSET_BAR0 + current as u16 * 4);
+            let is_64_bit = bar & 1 == 0 && (bar >> 1) & 0x3 == 0x2;
+            current += if is_64_bit { 2 } else { 1 };
+        }
+        current > index
+    }
+
+    unsafe fn decode_bar(&self, offset: u16) -> Option<Bar> {
+        let original = self.inner.read_config_double_word(offset);
+        self.inner.write_config_double_word(offset, 0xFFFF_FFFF);
+        let mask = self.inner.read_config_double_word(offset);
+        self.inner.write_config_double_word(offset, original);
+
+        if original & 1 == 1 {
+            // the upper 16 bits of an I/O BAR may be hardwired to 0
+            let mask = mask & 0xFFFC;
+            return (mask != 0).then_some(Bar::Io {
+                port: original & !0x3,
+                size: (!mask + 1) & 0xFFFF,
+            });
+        }
+
+        let prefetchable = original & (1 << 3) > 0;
+        match (original >> 1) & 0x3 {
+            0x2 => {
+                if offset == Self::OFFSET_BAR5 {
+                    return None; // a 64-bit BAR needs the next BAR as upper half
+                }
+                let original_hi = self.inner.read_config_double_word(offset + 4);
+                self.inner.write_config_double_word(offset + 4, 0xFFFF_FFFF);
+                let mask_hi = self.inner.read_config_double_word(offset + 4);
+                self.inner.write_config_double_word(offset + 4, original_hi);
+
+                let mask = ((mask_hi as u64) << 32) | (mask & !0xF) as u64;
+                (mask != 0).then_some(Bar::Memory64 {
+                    address: ((original_hi as u64) << 32) | (original & !0xF) as u64,
+                    size: !mask + 1,
+                    prefetchable,
+                })
+            }
+            _ => {
+                let mask = mask & !0xF;
+                (mask != 0).then_some(Bar::Memory32 {
+                    address: original & !0xF,
+                    size: !mask + 1,
+                    prefetchable,
+                })
+            }
+        }
+    }
 }
 
 pub struct PCI2PCIBridge {
@@ -88,4 +212,18 @@ impl PCI2PCIBridge {
         }
         Ok(PCI2PCIBridge { inner })
     }
+
+    pub fn primary_bus(&self) -> u8 {
+        unsafe { self.inner.read_config_half_word(OFFSET_PRIMARY_BUS) }
+    }
+
+    /// The bus number of the bus directly behind this bridge.
+    pub fn secondary_bus(&self) -> u8 {
+        unsafe { self.inner.read_config_half_word(OFFSET_SECONDARY_BUS) }
+    }
+
+    /// The highest bus number of all buses that are reachable through this bridge.
+    pub fn subordinate_bus(&self) -> u8 {
+        unsafe { self.inner.read_config_half_word(OFFSET_SUBORDINATE_BUS) }
+    }
 }
@@ -4,11 +4,15 @@ use conquer_once::spin::OnceCell;
 use derive_more::Display;
 use device::PCIDevice;
 
+pub mod capability;
 pub mod classes;
 pub mod device;
 pub mod header;
+pub mod msi;
 mod raw;
 
+pub use raw::has_extended_config_space;
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Display)]
 pub enum Error {
     #[display(fmt = "unknown header type {_0:#x?}")]
@@ -32,6 +36,17 @@ pub enum Error {
     NotStandardHeader(PCIHeaderType),
     #[display(fmt = "not a pci2pci bridge, but a {_0:?}")]
     NotPCI2PCIBridge(PCIHeaderType),
+
+    #[display(fmt = "device has no msi or msi-x capability")]
+    NoMsiCapability,
+    #[display(fmt = "no free interrupt vector")]
+    NoFreeInterruptVector,
+    #[display(fmt = "bar {_0} is not a memory bar")]
+    NotAMemoryBar(u8),
+    #[display(fmt = "msi-x table has no entry {_0}")]
+    InvalidMsiXEntry(u16),
+    #[display(fmt = "mapping device memory failed")]
+    MappingFailed,
 }
 
 impl core::error::Error for Error {}
@@ -42,9 +57,7 @@ pub fn devices() -> impl Iterator<Item = &'static PCIDevice> {
     DEVICES
         .get_or_init(|| {
             let mut devices = Vec::new();
-            for bus in 0..=255 {
-                unsafe { raw::iterate_bus(bus, &mut devices) };
-            }
+            unsafe { raw::iterate_all(&mut devices) };
             Devices { devices }
         })
         .iter()
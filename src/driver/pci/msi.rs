@@ -0,0 +1,241 @@
+//! Message signaled interrupts (MSI and MSI-X).
+//!
+//! Devices that support message signaled interrupts deliver them as memory writes
+//! to the local APIC, so they don't need an interrupt line. Drivers should use
+//! [`allocate_interrupt`], which allocates an interrupt vector, registers the handler
+//! for it and programs the device to use it.
+
+use alloc::collections::BTreeMap;
+use core::ptr::write_volatile;
+
+use kstd::sync::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::debug;
+use crate::driver::pci::capability::CapabilityId;
+use crate::driver::pci::device::{Command, PCIDevice};
+use crate::driver::pci::header::PCIStandardHeaderDevice;
+use crate::driver::pci::Error;
+use crate::interrupts::lapic;
+use crate::interrupts::vector::{allocate_vector, free_vector, InterruptHandler};
+use crate::memory::mmio;
+
+/// Allocates an interrupt vector, registers the given handler for it, and configures
+/// the device to signal its interrupts with that vector. MSI-X is preferred over
+/// MSI, which is used if MSI-X is missing or can't be set up. Returns the allocated vector.
+pub fn allocate_interrupt(device: &PCIDevice, handler: InterruptHandler) -> Result<u8, Error> {
+    let vector = allocate_vector(handler).ok_or(Error::NoFreeInterruptVector)?;
+
+    let result = match MsiX::new(device).and_then(|msix| msix.set_vector(0, vector).map(|_| msix)) {
+        Ok(msix) => {
+            msix.enable();
+            Ok(())
+        }
+        // a device with an unusable MSI-X capability may still support MSI
+        Err(e) => {
+            debug!("msi-x is not usable ({}), trying msi", e);
+            Msi::new(device).map(|msi| msi.enable(vector))
+        }
+    };
+    if let Err(e) = result {
+        free_vector(vector);
+        return Err(e);
+    }
+
+    debug!(
+        "allocated interrupt vector {} for pci device {:04x}:{:04x}",
+        vector,
+        device.vendor(),
+        device.device()
+    );
+    Ok(vector)
+}
+
+/// The MSI-X vector tables that are mapped, by their physical address. MMIO mappings
+/// are never removed, so every table is mapped only once.
+static MSIX_TABLES: Mutex<BTreeMap<PhysAddr, VirtAddr>> = Mutex::new(BTreeMap::new());
+
+/// The address that message signaled interrupts are written to, which targets
+/// the local APIC of the current processor.
+fn message_address() -> u64 {
+    0xFEE0_0000 | ((lapic::id() as u64) << 12)
+}
+
+/// The message data for a fixed, edge triggered interrupt with the given vector.
+fn message_data(vector: u8) -> u32 {
+    vector as u32
+}
+
+/// Disables the legacy interrupt line and enables bus mastering, which is required
+/// for the device to write interrupt messages.
+fn prepare_device(device: &PCIDevice) {
+    unsafe {
+        device.set_command(device.command() | Command::INTERRUPT_DISABLE | Command::BUS_MASTER)
+    };
+}
+
+/// The MSI capability of a device.
+pub struct Msi {
+    device: PCIDevice,
+    offset: u16,
+}
+
+impl Msi {
+    const CONTROL_ENABLE: u16 = 1 << 0;
+    const CONTROL_MULTIPLE_MESSAGE_ENABLE: u16 = 0b111 << 4;
+    const CONTROL_64_BIT: u16 = 1 << 7;
+
+    pub fn new(device: &PCIDevice) -> Result<Self, Error> {
+        let capability = device
+            .capabilities()
+            .find(|c| c.id == CapabilityId::Msi)
+            .ok_or(Error::NoMsiCapability)?;
+        Ok(Self {
+            device: device.clone(),
+            offset: capability.offset,
+        })
+    }
+
+    fn control(&self) -> u16 {
+        unsafe { self.device.read_config_word(self.offset + 2) }
+    }
+
+    /// Programs the device to signal a single message with the given vector and enables MSI.
+    pub fn enable(&self, vector: u8) {
+        let control = self.control();
+        let address = message_address();
+        unsafe {
+            self.device
+                .write_config_double_word(self.offset + 4, address as u32);
+            if control & Self::CONTROL_64_BIT > 0 {
+                self.device
+                    .write_config_double_word(self.offset + 8, (address >> 32) as u32);
+                self.device
+                    .write_config_word(self.offset + 12, message_data(vector) as u16);
+            } else {
+                self.device
+                    .write_config_word(self.offset + 8, message_data(vector) as u16);
+            }
+        }
+        prepare_device(&self.device);
+        unsafe {
+            self.device.write_config_word(
+                self.offset + 2,
+                (control & !Self::CONTROL_MULTIPLE_MESSAGE_ENABLE) | Self::CONTROL_ENABLE,
+            )
+        };
+    }
+
+    pub fn disable(&self) {
+        unsafe {
+            self.device
+                .write_config_word(self.offset + 2, self.control() & !Self::CONTROL_ENABLE)
+        };
+    }
+}
+
+/// The MSI-X capability of a device, together with its mapped vector table.
+pub struct MsiX {
+    device: PCIDevice,
+    offset: u16,
+    table: VirtAddr,
+    table_size: u16,
+}
+
+impl MsiX {
+    const CONTROL_ENABLE: u16 = 1 << 15;
+    const CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+    const CONTROL_TABLE_SIZE: u16 = (1 << 11) - 1;
+
+    const ENTRY_SIZE: usize = 16;
+    const ENTRY_VECTOR_CONTROL_MASKED: u32 = 1 << 0;
+
+    /// Finds the MSI-X capability of the device and maps its vector table, unless it
+    /// was mapped before.
+    pub fn new(device: &PCIDevice) -> Result<Self, Error> {
+        let capability = device
+            .capabilities()
+            .find(|c| c.id == CapabilityId::MsiX)
+            .ok_or(Error::NoMsiCapability)?;
+        let offset = capability.offset;
+
+        let control = unsafe { device.read_config_word(offset + 2) };
+        let table_size = (control & Self::CONTROL_TABLE_SIZE) + 1;
+        let table_location = unsafe { device.read_config_double_word(offset + 4) };
+        let bir = (table_location & 0b111) as u8;
+        let table_offset = (table_location & !0b111) as u64;
+
+        let header = PCIStandardHeaderDevice::new(device.clone())?;
+        let bar_address = match header.bar(bir) {
+            Some(bar) if !bar.is_io() => bar.address(),
+            _ => return Err(Error::NotAMemoryBar(bir)),
+        };
+        device.enable_memory_space();
+
+        let table = Self::map_table(
+            PhysAddr::new(bar_address + table_offset),
+            table_size as usize * Self::ENTRY_SIZE,
+        )?;
+
+        Ok(Self {
+            device: device.clone(),
+            offset,
+            table,
+            table_size,
+        })
+    }
+
+    fn map_table(address: PhysAddr, len: usize) -> Result<VirtAddr, Error> {
+        let mut tables = MSIX_TABLES.lock();
+        if let Some(&table) = tables.get(&address) {
+            return Ok(table);
+        }
+        let table =
+            unsafe { mmio::map_physical(address, len) }.map_err(|_| Error::MappingFailed)?;
+        tables.insert(address, table);
+        Ok(table)
+    }
+
+    pub fn table_size(&self) -> u16 {
+        self.table_size
+    }
+
+    /// Programs the table entry with the given index to signal the given vector, and unmasks it.
+    pub fn set_vector(&self, entry: u16, vector: u8) -> Result<(), Error> {
+        if entry >= self.table_size {
+            return Err(Error::InvalidMsiXEntry(entry));
+        }
+        let address = message_address();
+        let entry_ptr = (self.table + entry as usize * Self::ENTRY_SIZE).as_mut_ptr::<u32>();
+        unsafe {
+            write_volatile(entry_ptr.add(3), Self::ENTRY_VECTOR_CONTROL_MASKED);
+            write_volatile(entry_ptr, address as u32);
+            write_volatile(entry_ptr.add(1), (address >> 32) as u32);
+            write_volatile(entry_ptr.add(2), message_data(vector));
+            write_volatile(entry_ptr.add(3), 0);
+        }
+        Ok(())
+    }
+
+    pub fn enable(&self) {
+        prepare_device(&self.device);
+        let control = self.control();
+        unsafe {
+            self.device.write_config_word(
+                self.offset + 2,
+                (control & !Self::CONTROL_FUNCTION_MASK) | Self::CONTROL_ENABLE,
+            )
+        };
+    }
+
+    pub fn disable(&self) {
+        unsafe {
+            self.device
+                .write_config_word(self.offset + 2, self.control() & !Self::CONTROL_ENABLE)
+        };
+    }
+
+    fn control(&self) -> u16 {
+        unsafe { self.device.read_config_word(self.offset + 2) }
+    }
+}
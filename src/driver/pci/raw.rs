@@ -1,22 +1,49 @@
 use alloc::vec::Vec;
 
+use conquer_once::spin::OnceCell;
 use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
 
-use crate::driver::pci::device::PCIDevice;
-use crate::error;
+use crate::driver::acpi;
+use crate::driver::pci::device::{PCIDevice, PCIHeaderType};
+use crate::memory::mmio;
+use crate::{error, info};
 
 const CONFIG_ADDRESS: u16 = 0xCF8;
 const CONFIG_DATA: u16 = 0xCFC;
 
-const OFFSET_VENDOR_ID: u8 = 0x00;
-const OFFSET_DEVICE: u8 = 0x02;
-pub const OFFSET_STATUS: u8 = 0x06;
-pub const OFFSET_HEADER_TYPE: u8 = 0x0E;
-pub const OFFSET_PROG_IF_REVISION_ID: u8 = 0x08;
-pub const OFFSET_CLASS_SUBCLASS: u8 = 0x0A;
-pub const OFFSET_BIST: u8 = 0x0F;
-pub const OFFSET_INTERRUPT_LINE: u8 = 0x3C;
-pub const OFFSET_INTERRUPT_PIN: u8 = 0x3D;
+const OFFSET_VENDOR_ID: u16 = 0x00;
+const OFFSET_DEVICE: u16 = 0x02;
+pub const OFFSET_COMMAND: u16 = 0x04;
+pub const OFFSET_STATUS: u16 = 0x06;
+pub const OFFSET_HEADER_TYPE: u16 = 0x0E;
+pub const OFFSET_PROG_IF_REVISION_ID: u16 = 0x08;
+pub const OFFSET_CLASS_SUBCLASS: u16 = 0x0A;
+pub const OFFSET_BIST: u16 = 0x0F;
+pub const OFFSET_CAPABILITIES_POINTER: u16 = 0x34;
+pub const OFFSET_INTERRUPT_LINE: u16 = 0x3C;
+pub const OFFSET_INTERRUPT_PIN: u16 = 0x3D;
+
+pub const OFFSET_PRIMARY_BUS: u16 = 0x18;
+pub const OFFSET_SECONDARY_BUS: u16 = 0x19;
+pub const OFFSET_SUBORDINATE_BUS: u16 = 0x1A;
+
+/// Enumerates all devices that are reachable from the host bridge(s), descending into
+/// the secondary bus of every PCI-to-PCI bridge that is found.
+pub unsafe fn iterate_all(devices: &mut Vec<PCIDevice>) {
+    match check_device(0, 0, 0) {
+        // a multi function host bridge means that there are multiple host controllers,
+        // and the function number is the bus number that the controller is responsible for
+        Some(host) if host.is_multi_function() => {
+            for function in 0..8 {
+                if pci_check_vendor(0, 0, function).is_some() {
+                    iterate_bus(function, devices);
+                }
+            }
+        }
+        _ => iterate_bus(0, devices),
+    }
+}
 
 pub unsafe fn iterate_bus(bus: u8, devices: &mut Vec<PCIDevice>) {
     for slot in 0..32 {
@@ -27,7 +54,7 @@ pub unsafe fn iterate_bus(bus: u8, devices: &mut Vec<PCIDevice>) {
             if dev.is_multi_function() {
                 iterate_functions(bus, slot, devices);
             } else {
-                devices.push(dev);
+                add_device(dev, devices);
             }
         }
     }
@@ -36,12 +63,30 @@ pub unsafe fn iterate_bus(bus: u8, devices: &mut Vec<PCIDevice>) {
 unsafe fn iterate_functions(bus: u8, slot: u8, devices: &mut Vec<PCIDevice>) {
     for function in 0..8 {
         if let Some(dev) = check_device(bus, slot, function) {
-            // TODO: if the device is a PCI2PCI bridge, iterate that as bus as well
-            devices.push(dev);
+            add_device(dev, devices);
         }
     }
 }
 
+/// Adds the device to the list of devices. If the device is a PCI2PCI bridge,
+/// the bus behind the bridge is iterated as well.
+unsafe fn add_device(dev: PCIDevice, devices: &mut Vec<PCIDevice>) {
+    let secondary_bus = if dev.header_type() == PCIHeaderType::PCI2PCIBridge {
+        let secondary_bus =
+            read_config_half_word(dev.bus(), dev.slot(), dev.function(), OFFSET_SECONDARY_BUS);
+        // a secondary bus that is not behind the current one means that the bridge is not
+        // configured, descending into it would iterate buses twice
+        Some(secondary_bus).filter(|&b| b > dev.bus())
+    } else {
+        None
+    };
+
+    devices.push(dev);
+    if let Some(bus) = secondary_bus {
+        iterate_bus(bus, devices);
+    }
+}
+
 unsafe fn check_device(bus: u8, slot: u8, function: u8) -> Option<PCIDevice> {
     if let Some((vendor, device)) = pci_check_vendor(bus, slot, function) {
         let dev = match PCIDevice::new(bus, slot, function, vendor, device) {
@@ -67,36 +112,179 @@ unsafe fn pci_check_vendor(bus: u8, slot: u8, function: u8) -> Option<(u16, u16)
     Some((vendor, device))
 }
 
-pub unsafe fn read_config_double_word(bus: u8, slot: u8, function: u8, offset: u8) -> u32 {
-    read_config_word(bus, slot, function, offset) as u32
-        | ((read_config_word(bus, slot, function, offset + 2) as u32) << 16)
+/// The way that the configuration space is accessed. ECAM is used if the firmware
+/// provides an MCFG table, otherwise the legacy I/O ports are used, which can only
+/// access the first 256 bytes of the configuration space of every function.
+enum ConfigSpaceAccess {
+    Legacy,
+    Ecam(Vec<EcamRegion>),
 }
 
-pub unsafe fn read_config_word(bus: u8, slot: u8, function: u8, offset: u8) -> u16 {
-    #[cfg(debug_assertions)]
-    if offset & 1 > 0 {
-        panic!("can not read unaligned word, use read_config_half_word instead");
-    }
+struct EcamRegion {
+    start_bus: u8,
+    end_bus: u8,
+    base: VirtAddr,
+}
 
-    let mut config_address = Port::<u32>::new(CONFIG_ADDRESS);
-    let mut config_data = Port::<u32>::new(CONFIG_DATA);
+fn config_space_access() -> &'static ConfigSpaceAccess {
+    static ACCESS: OnceCell<ConfigSpaceAccess> = OnceCell::uninit();
+    ACCESS.get_or_init(|| {
+        let mcfg = match acpi::mcfg() {
+            Some(mcfg) => mcfg,
+            None => return ConfigSpaceAccess::Legacy,
+        };
+
+        let regions = mcfg
+            .entries
+            .iter()
+            .filter(|entry| entry.segment_group == 0) // we don't support multiple segments
+            .filter_map(|entry| {
+                let bus_count = (entry.end_bus - entry.start_bus) as usize + 1;
+                // every bus has 32 slots with 8 functions with 4KiB of config space each
+                let base = match unsafe { mmio::map_physical(entry.base_address, bus_count << 20) }
+                {
+                    Ok(base) => base,
+                    Err(e) => {
+                        error!("could not map ecam region: {}", e);
+                        return None;
+                    }
+                };
+                info!(
+                    "using pci ecam at {:#x} for buses {}..={}",
+                    entry.base_address.as_u64(),
+                    entry.start_bus,
+                    entry.end_bus
+                );
+                Some(EcamRegion {
+                    start_bus: entry.start_bus,
+                    end_bus: entry.end_bus,
+                    base,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if regions.is_empty() {
+            ConfigSpaceAccess::Legacy
+        } else {
+            ConfigSpaceAccess::Ecam(regions)
+        }
+    })
+}
+
+/// Returns whether the extended configuration space (offsets 256 through 4095) is accessible.
+pub fn has_extended_config_space() -> bool {
+    matches!(config_space_access(), ConfigSpaceAccess::Ecam(_))
+}
 
+fn ecam_address(bus: u8, slot: u8, function: u8, offset: u16) -> Option<*mut u32> {
+    match config_space_access() {
+        ConfigSpaceAccess::Legacy => None,
+        ConfigSpaceAccess::Ecam(regions) => regions
+            .iter()
+            .find(|r| r.start_bus <= bus && bus <= r.end_bus)
+            .map(|r| {
+                let offset = ((bus - r.start_bus) as u64) << 20
+                    | (slot as u64) << 15
+                    | (function as u64) << 12
+                    | (offset & 0xFFC) as u64;
+                (r.base + offset).as_mut_ptr::<u32>()
+            }),
+    }
+}
+
+fn legacy_config_address(bus: u8, slot: u8, function: u8, offset: u16) -> u32 {
     let mut address: u32 = 0;
     address |= 1 << 31; // enable bit
     address |= (bus as u32) << 16;
     address |= (slot as u32) << 11;
     address |= (function as u32) << 8;
     address |= (offset as u32) & 0xFC;
-    config_address.write(address);
+    address
+}
 
-    let i = config_data.read();
+pub unsafe fn read_config_double_word(bus: u8, slot: u8, function: u8, offset: u16) -> u32 {
+    #[cfg(debug_assertions)]
+    if offset & 3 > 0 {
+        panic!("can not read unaligned double word, use read_config_word instead");
+    }
+
+    if let Some(address) = ecam_address(bus, slot, function, offset) {
+        return address.read_volatile();
+    }
+    if offset > 0xFF {
+        // not reachable without ecam, behave like a non-existent register
+        return 0xFFFF_FFFF;
+    }
+
+    let mut config_address = Port::<u32>::new(CONFIG_ADDRESS);
+    let mut config_data = Port::<u32>::new(CONFIG_DATA);
+
+    config_address.write(legacy_config_address(bus, slot, function, offset));
+    config_data.read()
+}
+
+pub unsafe fn read_config_word(bus: u8, slot: u8, function: u8, offset: u16) -> u16 {
+    #[cfg(debug_assertions)]
+    if offset & 1 > 0 {
+        panic!("can not read unaligned word, use read_config_half_word instead");
+    }
+
+    let i = read_config_double_word(bus, slot, function, offset & !3);
     (i >> ((offset & 2) * 8) & 0xFFFF) as u16
 }
 
-pub unsafe fn read_config_half_word(bus: u8, slot: u8, function: u8, offset: u8) -> u8 {
+pub unsafe fn read_config_half_word(bus: u8, slot: u8, function: u8, offset: u16) -> u8 {
     let word = read_config_word(bus, slot, function, offset & (!1));
     if offset & 1 > 0 {
         return (word >> 8) as u8;
     }
     word as u8
 }
+
+pub unsafe fn write_config_double_word(bus: u8, slot: u8, function: u8, offset: u16, value: u32) {
+    #[cfg(debug_assertions)]
+    if offset & 3 > 0 {
+        panic!("can not write unaligned double word, use write_config_word instead");
+    }
+
+    if let Some(address) = ecam_address(bus, slot, function, offset) {
+        address.write_volatile(value);
+        return;
+    }
+    if offset > 0xFF {
+        error!(
+            "can not write config space offset {:#x} without ecam",
+            offset
+        );
+        return;
+    }
+
+    let mut config_address = Port::<u32>::new(CONFIG_ADDRESS);
+    let mut config_data = Port::<u32>::new(CONFIG_DATA);
+
+    config_address.write(legacy_config_address(bus, slot, function, offset));
+    config_data.write(value);
+}
+
+/// Writes a word into the configuration space. Since the configuration space can only
+/// be written in double words, this reads the double word that contains the word first.
+/// Be careful with registers that have write-1-to-clear bits next to the written word,
+/// like the status register next to the command register.
+pub unsafe fn write_config_word(bus: u8, slot: u8, function: u8, offset: u16, value: u16) {
+    #[cfg(debug_assertions)]
+    if offset & 1 > 0 {
+        panic!("can not write unaligned word, use write_config_half_word instead");
+    }
+
+    let shift = (offset & 2) * 8;
+    let old = read_config_double_word(bus, slot, function, offset & !3);
+    let new = (old & !(0xFFFF << shift)) | ((value as u32) << shift);
+    write_config_double_word(bus, slot, function, offset & !3, new);
+}
+
+pub unsafe fn write_config_half_word(bus: u8, slot: u8, function: u8, offset: u16, value: u8) {
+    let shift = (offset & 3) * 8;
+    let old = read_config_double_word(bus, slot, function, offset & !3);
+    let new = (old & !(0xFF << shift)) | ((value as u32) << shift);
+    write_config_double_word(bus, slot, function, offset & !3, new);
+}
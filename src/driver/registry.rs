@@ -0,0 +1,237 @@
+//! A registry of PCI drivers. Drivers declare which devices they support with a
+//! table of [`PciMatch`]es, and are bound to every matching device that is not yet
+//! bound to another driver. The nodes that a driver returns from [`PciDriver::probe`]
+//! are published in `/dev`, and removed from there again when the device is unbound.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use kstd::io::Result;
+use kstd::sync::{Mutex, Once};
+
+use crate::driver::pci;
+use crate::driver::pci::classes::PCIDeviceClass;
+use crate::driver::pci::device::PCIDevice;
+use crate::io::fs::{vfs, INode, INodeBase};
+use crate::{error, info};
+
+static mut REGISTRY: Option<Mutex<Registry>> = None;
+static REGISTRY_INIT: Once = Once::new();
+
+fn get_registry() -> &'static Mutex<Registry> {
+    REGISTRY_INIT.call_once(|| unsafe {
+        REGISTRY = Some(Mutex::new(Registry::new()));
+    });
+    unsafe { REGISTRY.as_ref().unwrap() }
+}
+
+/// Describes a set of PCI devices that a driver supports. Every field that is `None`
+/// matches any value.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PciMatch {
+    pub class: Option<PCIDeviceClass>,
+    pub vendor: Option<u16>,
+    pub device: Option<u16>,
+}
+
+impl PciMatch {
+    /// Matches every device of the given class.
+    pub const fn class(class: PCIDeviceClass) -> Self {
+        Self {
+            class: Some(class),
+            vendor: None,
+            device: None,
+        }
+    }
+
+    /// Matches the device with the given vendor and device id.
+    pub const fn id(vendor: u16, device: u16) -> Self {
+        Self {
+            class: None,
+            vendor: Some(vendor),
+            device: Some(device),
+        }
+    }
+
+    pub fn matches(&self, device: &PCIDevice) -> bool {
+        self.class.map_or(true, |c| c == device.class())
+            && self.vendor.map_or(true, |v| v == device.vendor())
+            && self.device.map_or(true, |d| d == device.device())
+    }
+}
+
+pub trait PciDriver: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The devices that this driver supports.
+    fn id_table(&self) -> &'static [PciMatch];
+
+    /// Initializes the given device and returns the nodes that should be published
+    /// in `/dev`. If this fails, the device stays unbound and may be probed by another
+    /// driver.
+    fn probe(&self, device: &PCIDevice) -> Result<Vec<INode>>;
+
+    /// Called when the device is unbound from this driver, after its nodes have been
+    /// removed from `/dev`.
+    fn remove(&self, _device: &PCIDevice) {}
+
+    fn supports(&self, device: &PCIDevice) -> bool {
+        self.id_table().iter().any(|m| m.matches(device))
+    }
+}
+
+struct Binding {
+    driver: &'static dyn PciDriver,
+    device: PCIDevice,
+    nodes: Vec<String>,
+}
+
+struct Registry {
+    drivers: Vec<&'static dyn PciDriver>,
+    bindings: Vec<Binding>,
+    /// Whether [`probe_all`] has been called. Drivers that are registered after that
+    /// are probed immediately.
+    probing: bool,
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Self {
+            drivers: Vec::new(),
+            bindings: Vec::new(),
+            probing: false,
+        }
+    }
+
+    fn is_bound(&self, device: &PCIDevice) -> bool {
+        self.bindings.iter().any(|b| same_device(&b.device, device))
+    }
+}
+
+fn same_device(a: &PCIDevice, b: &PCIDevice) -> bool {
+    a.bus() == b.bus() && a.slot() == b.slot() && a.function() == b.function()
+}
+
+/// Registers the given driver. If devices have already been probed, the driver is
+/// bound to all matching unbound devices immediately.
+pub fn register(driver: &'static dyn PciDriver) {
+    let probing = {
+        let mut guard = get_registry().lock();
+        guard.drivers.push(driver);
+        guard.probing
+    };
+    if probing {
+        probe_driver(driver);
+    }
+}
+
+/// Binds every registered driver to all matching devices that are not bound yet.
+pub fn probe_all() {
+    let drivers = {
+        let mut guard = get_registry().lock();
+        guard.probing = true;
+        guard.drivers.clone()
+    };
+    for driver in drivers {
+        probe_driver(driver);
+    }
+}
+
+fn probe_driver(driver: &'static dyn PciDriver) {
+    for device in pci::devices().filter(|d| driver.supports(d)) {
+        if get_registry().lock().is_bound(device) {
+            continue;
+        }
+
+        // don't hold the lock while probing, drivers may take a while or register
+        // other drivers
+        let nodes = match driver.probe(device) {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                error!("{} failed to probe {}: {}", driver.name(), device, e);
+                continue;
+            }
+        };
+
+        let mut names = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let name = node.name();
+            match vfs::mount(&"/dev", node) {
+                Ok(_) => names.push(name),
+                Err(e) => error!("could not publish /dev/{}: {}", name, e),
+            }
+        }
+        info!("bound {} to {}", driver.name(), device);
+
+        get_registry().lock().bindings.push(Binding {
+            driver,
+            device: device.clone(),
+            nodes: names,
+        });
+    }
+}
+
+/// Unbinds the given device from its driver, removing the driver's nodes from `/dev`.
+/// Returns `false` if the device was not bound.
+pub fn unbind(device: &PCIDevice) -> bool {
+    let binding = {
+        let mut guard = get_registry().lock();
+        match guard
+            .bindings
+            .iter()
+            .position(|b| same_device(&b.device, device))
+        {
+            Some(index) => guard.bindings.remove(index),
+            None => return false,
+        }
+    };
+
+    for name in binding.nodes.iter() {
+        if let Err(e) = vfs::unmount(&"/dev", name) {
+            error!("could not remove /dev/{}: {}", name, e);
+        }
+    }
+    binding.driver.remove(&binding.device);
+    info!("unbound {} from {}", binding.driver.name(), device);
+    true
+}
+
+/// Returns the name of the driver that the given device is bound to.
+pub fn bound_driver(device: &PCIDevice) -> Option<&'static str> {
+    get_registry()
+        .lock()
+        .bindings
+        .iter()
+        .find(|b| same_device(&b.device, device))
+        .map(|b| b.driver.name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::pci::classes::MassStorageSubClass;
+
+    #[test_case]
+    fn test_match_any() {
+        let any = PciMatch {
+            class: None,
+            vendor: None,
+            device: None,
+        };
+        assert!(pci::devices().all(|d| any.matches(d)));
+    }
+
+    #[test_case]
+    fn test_match_class() {
+        let ide = PciMatch::class(PCIDeviceClass::MassStorageController(
+            MassStorageSubClass::IDEController,
+        ));
+        for device in pci::devices() {
+            assert_eq!(
+                device.class()
+                    == PCIDeviceClass::MassStorageController(MassStorageSubClass::IDEController),
+                ide.matches(device)
+            );
+        }
+    }
+}
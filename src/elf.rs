@@ -0,0 +1,135 @@
+//! Loading of ELF executables. For now, executables are only checked and memory for
+//! their segments is reserved, they are not run yet.
+
+use derive_more::Display;
+use goblin::elf::header::ET_EXEC;
+use goblin::elf::Elf;
+
+use crate::debug;
+use crate::memory::kbuffer::KBuffer;
+use crate::memory::size::Size;
+
+/// The largest amount of memory that the segments of an executable may span.
+const MAX_IMAGE_SIZE: u64 = Size::MiB(256).bytes() as u64;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Display)]
+pub enum Error {
+    #[display(fmt = "not a valid elf file")]
+    Malformed,
+    #[display(fmt = "not a 64 bit elf file")]
+    Not64Bit,
+    #[display(fmt = "not an executable")]
+    NotExecutable,
+    #[display(fmt = "no program headers")]
+    NoProgramHeaders,
+    #[display(fmt = "segments span too much memory")]
+    TooLarge,
+    #[display(fmt = "out of memory")]
+    OutOfMemory,
+}
+
+impl core::error::Error for Error {}
+
+/// An executable whose memory was reserved.
+pub struct LoadedElf {
+    /// The lowest virtual address of all segments.
+    pub base_address: u64,
+    pub memory: KBuffer,
+}
+
+/// Parses the given executable and reserves memory for all of its segments.
+pub fn load(content: &[u8]) -> Result<LoadedElf> {
+    let elf = Elf::parse(content).map_err(|_| Error::Malformed)?;
+    if !elf.is_64 {
+        return Err(Error::Not64Bit);
+    }
+    if elf.header.e_type != ET_EXEC {
+        return Err(Error::NotExecutable);
+    }
+    let base_address = elf
+        .program_headers
+        .iter()
+        .map(|ph| ph.p_vaddr)
+        .min()
+        .ok_or(Error::NoProgramHeaders)?;
+    debug!("base addr: {:#x}", base_address);
+    // the headers are untrusted, so the end of a segment may overflow
+    let required_mem = elf
+        .program_headers
+        .iter()
+        .map(|ph| (ph.p_vaddr - base_address).checked_add(ph.p_memsz))
+        .try_fold(0, |max, end| end.map(|end| max.max(end)))
+        .filter(|&size| size <= MAX_IMAGE_SIZE)
+        .ok_or(Error::TooLarge)?;
+    debug!("required mem: {:#x}", required_mem);
+
+    let memory = KBuffer::try_allocate(required_mem as usize).map_err(|_| Error::OutOfMemory)?;
+    debug!("allocated buffer at {:p}", memory.as_ptr::<usize>());
+    Ok(LoadedElf {
+        base_address,
+        memory,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Returns an executable with a loadable segment for every given virtual address and
+    /// memory size.
+    fn executable(segments: &[(u64, u64)]) -> Vec<u8> {
+        let mut elf = Vec::new();
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01");
+        elf.resize(16, 0);
+        elf.extend_from_slice(&ET_EXEC.to_le_bytes());
+        elf.extend_from_slice(&0x3e_u16.to_le_bytes()); // x86_64
+        elf.extend_from_slice(&1_u32.to_le_bytes()); // version
+        elf.extend_from_slice(&0_u64.to_le_bytes()); // entry
+        elf.extend_from_slice(&64_u64.to_le_bytes()); // program header offset
+        elf.extend_from_slice(&0_u64.to_le_bytes()); // section header offset
+        elf.extend_from_slice(&0_u32.to_le_bytes()); // flags
+        elf.extend_from_slice(&64_u16.to_le_bytes()); // header size
+        elf.extend_from_slice(&56_u16.to_le_bytes()); // program header size
+        elf.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+        elf.extend_from_slice(&64_u16.to_le_bytes()); // section header size
+        elf.extend_from_slice(&[0; 4]); // no section headers
+        for &(vaddr, memsz) in segments {
+            elf.extend_from_slice(&1_u32.to_le_bytes()); // PT_LOAD
+            elf.extend_from_slice(&4_u32.to_le_bytes()); // readable
+            elf.extend_from_slice(&0_u64.to_le_bytes()); // offset
+            elf.extend_from_slice(&vaddr.to_le_bytes());
+            elf.extend_from_slice(&vaddr.to_le_bytes());
+            elf.extend_from_slice(&0_u64.to_le_bytes()); // file size
+            elf.extend_from_slice(&memsz.to_le_bytes());
+            elf.extend_from_slice(&0x1000_u64.to_le_bytes());
+        }
+        elf
+    }
+
+    #[test_case]
+    fn test_load_reserves_memory_of_all_segments() {
+        let loaded = load(&executable(&[(0x40_1000, 0x1000), (0x40_0000, 0x800)])).unwrap();
+        assert_eq!(0x40_0000, loaded.base_address);
+        assert_eq!(0x2000, loaded.memory.len());
+    }
+
+    #[test_case]
+    fn test_load_rejects_oversized_segments() {
+        let overflowing = executable(&[(0, 0x1000), (u64::MAX - 0x10, 0x100)]);
+        assert_eq!(Err(Error::TooLarge), load(&overflowing).map(|_| ()));
+        let huge = executable(&[(0x40_0000, MAX_IMAGE_SIZE + 1)]);
+        assert_eq!(Err(Error::TooLarge), load(&huge).map(|_| ()));
+    }
+
+    #[test_case]
+    fn test_load_rejects_garbage() {
+        assert_eq!(
+            Err(Error::Malformed),
+            load(b"definitely not an elf file").map(|_| ())
+        );
+    }
+}
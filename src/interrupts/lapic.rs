@@ -0,0 +1,69 @@
+//! The local APIC of the current processor.
+//!
+//! The kernel still uses the 8259 PICs for the legacy interrupts, which the local APIC
+//! passes through. The local APIC is needed for interrupts that are delivered as
+//! messages (MSI and MSI-X), which have to be acknowledged at the local APIC.
+
+use conquer_once::spin::OnceCell;
+use x86_64::registers::model_specific::Msr;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::info;
+use crate::memory::mmio;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+
+const REGISTER_ID: usize = 0x20;
+const REGISTER_EOI: usize = 0xB0;
+const REGISTER_SPURIOUS_INTERRUPT_VECTOR: usize = 0xF0;
+
+/// The vector that the local APIC uses for spurious interrupts.
+pub const SPURIOUS_INTERRUPT_VECTOR: u8 = 0xFF;
+
+static BASE: OnceCell<VirtAddr> = OnceCell::uninit();
+
+/// Maps the registers of the local APIC and software-enables it.
+/// Requires the memory manager to be initialized.
+pub fn init() {
+    BASE.get_or_init(|| {
+        let apic_base = unsafe { Msr::new(IA32_APIC_BASE).read() };
+        let physical = PhysAddr::new(apic_base & 0x000F_FFFF_FFFF_F000);
+        let base = unsafe { mmio::map_physical(physical, 0x1000) }
+            .expect("could not map local apic registers");
+        info!("local apic at {:#x}", physical.as_u64());
+        base
+    });
+
+    unsafe {
+        let svr = read_register(REGISTER_SPURIOUS_INTERRUPT_VECTOR);
+        write_register(
+            REGISTER_SPURIOUS_INTERRUPT_VECTOR,
+            (svr & !0xFF) | (1 << 8) | SPURIOUS_INTERRUPT_VECTOR as u32,
+        );
+    }
+}
+
+/// Returns whether [`init`] was called.
+pub fn is_initialized() -> bool {
+    BASE.is_initialized()
+}
+
+/// The APIC ID of the current processor, which is used as destination for messages.
+pub fn id() -> u8 {
+    unsafe { (read_register(REGISTER_ID) >> 24) as u8 }
+}
+
+/// Signals the end of an interrupt that was delivered through the local APIC.
+pub fn end_of_interrupt() {
+    unsafe { write_register(REGISTER_EOI, 0) };
+}
+
+unsafe fn read_register(register: usize) -> u32 {
+    let base = BASE.get().expect("local apic not initialized");
+    (*base + register).as_ptr::<u32>().read_volatile()
+}
+
+unsafe fn write_register(register: usize, value: u32) {
+    let base = BASE.get().expect("local apic not initialized");
+    (*base + register).as_mut_ptr::<u32>().write_volatile(value)
+}
@@ -6,6 +6,9 @@ use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, Pag
 use crate::scheduler::Scheduler;
 use crate::{gdt, serial_println, time, vga_println};
 
+pub mod lapic;
+pub mod vector;
+
 // "Remapped" PICS chosen as 32 to 47
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -34,6 +37,9 @@ lazy_static! {
         idt[46].set_handler_fn(ignore_handler);
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Serial.as_usize()].set_handler_fn(serial_interrupt_handler);
+        vector::set_handlers(&mut idt);
+        idt[lapic::SPURIOUS_INTERRUPT_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
@@ -47,6 +53,7 @@ pub fn init_idt() {
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Serial = PIC_1_OFFSET + crate::serial::COM1_IRQ,
 }
 
 impl InterruptIndex {
@@ -60,7 +67,8 @@ impl InterruptIndex {
 }
 
 extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+    crate::crash::record_exception(&stack_frame);
+    panic!("EXCEPTION: DIVIDE ERROR");
 }
 
 extern "x86-interrupt" fn ignore_handler(_stack_frame: InterruptStackFrame) {
@@ -69,6 +77,10 @@ extern "x86-interrupt" fn ignore_handler(_stack_frame: InterruptStackFrame) {
     }
 }
 
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // spurious interrupts of the local apic must not be acknowledged
+}
+
 extern "x86-interrupt" fn general_protection_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
@@ -81,7 +93,8 @@ extern "x86-interrupt" fn general_protection_fault_handler(
     serial_println!("tbl: {}", (error_code >> 1) & 0b11);
     serial_println!("e: {}", error_code & 1);
 
-    panic!("EXCEPTION: GENERAL PROTECTION FAULT\n{:#?}", stack_frame);
+    crate::crash::record_exception(&stack_frame);
+    panic!("EXCEPTION: GENERAL PROTECTION FAULT");
 }
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
@@ -89,38 +102,36 @@ extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
 }
 
 extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
-    panic!("EXCEPTION: OVERFLOW\n{:#?}", stack_frame);
+    crate::crash::record_exception(&stack_frame);
+    panic!("EXCEPTION: OVERFLOW");
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+    crate::crash::record_exception(&stack_frame);
+    panic!("EXCEPTION: DOUBLE FAULT");
 }
 
 extern "x86-interrupt" fn stack_segment_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    panic!(
-        "EXCEPTION: STACK SEGMENT FAULT\nerror code: {}\n{:#?}",
-        error_code, stack_frame
-    );
+    crate::crash::record_exception(&stack_frame);
+    panic!("EXCEPTION: STACK SEGMENT FAULT\nerror code: {}", error_code);
 }
 
 extern "x86-interrupt" fn segment_not_present_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
+    crate::crash::record_exception(&stack_frame);
     panic!(
         r#"EXCEPTION: SEGMENT NOT PRESENT FAULT
-instruction pointer: {:p}
 error code: {} ({:#b})
 external: {}
-table[index]: {}[{}]
-{:#?}"#,
-        stack_frame.instruction_pointer.as_u64() as *const u8,
+table[index]: {}[{}]"#,
         error_code,
         error_code,
         (error_code & 1) == 1,
@@ -132,7 +143,6 @@ table[index]: {}[{}]
             _ => "unknown",
         },
         ((error_code & ((1 << 14) - 1)) >> 3),
-        stack_frame
     );
 }
 
@@ -153,11 +163,15 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
+    if crate::memory::manager::resolve_page_fault(Cr2::read(), error_code) {
+        return;
+    }
+
+    crate::crash::record_exception(&stack_frame);
     panic!(
-        "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
+        "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}",
         Cr2::read(),
         error_code,
-        stack_frame
     );
 }
 
@@ -173,6 +187,31 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 }
 
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::serial::handle_interrupt();
+
+    unsafe {
+        end_of_interrupt(InterruptIndex::Serial);
+    }
+}
+
+/// Unmasks the given legacy IRQ (0 to 15) at the 8259 PICs.
+pub fn unmask_legacy_irq(irq: u8) {
+    use x86_64::instructions::port::Port;
+
+    assert!(irq < 16, "legacy irq {} out of range", irq);
+    let _guard = PICS.lock();
+    let (mut data, bit) = if irq < 8 {
+        (Port::<u8>::new(0x21), irq)
+    } else {
+        (Port::<u8>::new(0xA1), irq - 8)
+    };
+    unsafe {
+        let mask = data.read();
+        data.write(mask & !(1 << bit));
+    }
+}
+
 #[inline]
 unsafe fn end_of_interrupt(which: InterruptIndex) {
     PICS.lock().notify_end_of_interrupt(which.as_u8());
@@ -180,6 +219,42 @@ unsafe fn end_of_interrupt(which: InterruptIndex) {
 
 #[cfg(test)]
 mod tests {
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use super::vector::{allocate_legacy_vector, allocate_vector, free_vector, DYNAMIC_VECTORS};
+    use super::{InterruptIndex, PIC_1_OFFSET};
+
+    #[test_case]
+    fn test_dynamic_vector() {
+        static CALLED: AtomicBool = AtomicBool::new(false);
+
+        let vector = allocate_vector(Arc::new(|| CALLED.store(true, Ordering::SeqCst)))
+            .expect("no free vector");
+        assert_eq!(
+            DYNAMIC_VECTORS.start, vector,
+            "no other vector should be in use"
+        );
+
+        unsafe { core::arch::asm!("int 48") };
+        assert!(CALLED.load(Ordering::SeqCst));
+
+        free_vector(vector);
+        let handler = Arc::new(|| {});
+        let again = allocate_vector(handler).unwrap();
+        assert_eq!(vector, again, "freed vector should be reused");
+        free_vector(again);
+    }
+
+    #[test_case]
+    fn test_kernel_irqs_can_not_be_allocated() {
+        let timer = InterruptIndex::Timer.as_u8() - PIC_1_OFFSET;
+        assert_eq!(None, allocate_legacy_vector(timer, Arc::new(|| {})));
+        let cascade = 2;
+        assert_eq!(None, allocate_legacy_vector(cascade, Arc::new(|| {})));
+        assert_eq!(None, allocate_legacy_vector(16, Arc::new(|| {})));
+    }
+
     #[test_case]
     fn test_breakpoint_exception() {
         // invoke a breakpoint exception
@@ -0,0 +1,135 @@
+//! Allocation of interrupt vectors at runtime.
+//!
+//! The vectors in [`DYNAMIC_VECTORS`] are not used by the kernel itself. Drivers can
+//! allocate one of them together with a handler, which is called whenever the
+//! interrupt occurs, without having to modify the IDT. Dynamic vectors are expected
+//! to be delivered through the local APIC (e.g. with MSI), so the end of the interrupt
+//! is signaled to the local APIC after the handler returns.
+//!
+//! Devices without message signaled interrupts use a legacy IRQ line of the PICs
+//! instead. A handler can be registered for each line that the kernel doesn't use
+//! itself, and the end of those interrupts is signaled to the PICs.
+
+use alloc::sync::Arc;
+use core::ops::Range;
+
+use kstd::sync::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+use crate::interrupts::{lapic, unmask_legacy_irq, PICS, PIC_1_OFFSET};
+
+/// The vectors that can be allocated. 32-47 are used by the PICs.
+pub const DYNAMIC_VECTORS: Range<u8> = 48..80;
+
+const VECTOR_COUNT: usize = (DYNAMIC_VECTORS.end - DYNAMIC_VECTORS.start) as usize;
+
+pub type InterruptHandler = Arc<dyn Fn() + Send + Sync>;
+
+static HANDLERS: Mutex<[Option<InterruptHandler>; VECTOR_COUNT]> =
+    Mutex::new([NONE_HANDLER; VECTOR_COUNT]);
+
+// needed for the array initializer, since the handler is not Copy
+const NONE_HANDLER: Option<InterruptHandler> = None;
+
+/// The legacy IRQs that have fixed handlers in the IDT: the timer, the keyboard, the
+/// serial port and the primary ATA channel, and the IRQ that the secondary PIC is
+/// cascaded to, which is never raised.
+const KERNEL_IRQS: [u8; 5] = [0, 1, 2, crate::serial::COM1_IRQ, 14];
+
+static LEGACY_HANDLERS: Mutex<[Option<InterruptHandler>; 16]> = Mutex::new([NONE_HANDLER; 16]);
+
+/// Allocates a free vector and registers the given handler for it. Returns
+/// `None` if all dynamic vectors are in use.
+pub fn allocate_vector(handler: InterruptHandler) -> Option<u8> {
+    without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let index = handlers.iter().position(|h| h.is_none())?;
+        handlers[index] = Some(handler);
+        Some(DYNAMIC_VECTORS.start + index as u8)
+    })
+}
+
+/// Unregisters the handler of the given vector, so that the vector can be allocated again.
+/// The caller has to make sure that no device still delivers interrupts to the vector.
+pub fn free_vector(vector: u8) {
+    assert!(
+        DYNAMIC_VECTORS.contains(&vector),
+        "vector {} is not a dynamic vector",
+        vector
+    );
+    without_interrupts(|| {
+        HANDLERS.lock()[(vector - DYNAMIC_VECTORS.start) as usize] = None;
+    });
+}
+
+/// Registers the given handler for a legacy IRQ (0 to 15) and unmasks the IRQ at the
+/// PICs. Returns the vector of the IRQ, or `None` if the IRQ is used by the kernel or
+/// already has a handler.
+pub fn allocate_legacy_vector(irq: u8, handler: InterruptHandler) -> Option<u8> {
+    if irq >= 16 || KERNEL_IRQS.contains(&irq) {
+        return None;
+    }
+    without_interrupts(|| {
+        let mut handlers = LEGACY_HANDLERS.lock();
+        let slot = &mut handlers[irq as usize];
+        if slot.is_some() {
+            return None;
+        }
+        *slot = Some(handler);
+        // the interrupt handlers lock the PICs as well
+        unmask_legacy_irq(irq);
+        Some(PIC_1_OFFSET + irq)
+    })
+}
+
+fn handle(vector: u8) {
+    // clone the handler, so that it may (de)allocate vectors itself
+    let handler = HANDLERS.lock()[(vector - DYNAMIC_VECTORS.start) as usize].clone();
+    if let Some(handler) = handler {
+        handler();
+    }
+    lapic::end_of_interrupt();
+}
+
+extern "x86-interrupt" fn dynamic_handler<const VECTOR: u8>(_stack_frame: InterruptStackFrame) {
+    handle(VECTOR);
+}
+
+fn handle_legacy(irq: u8) {
+    let handler = LEGACY_HANDLERS.lock()[irq as usize].clone();
+    if let Some(handler) = handler {
+        handler();
+    }
+    unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq) };
+}
+
+extern "x86-interrupt" fn legacy_handler<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    handle_legacy(IRQ);
+}
+
+macro_rules! set_legacy_handlers {
+    ($idt:expr, $($irq:literal),+) => {
+        $(
+            $idt[(PIC_1_OFFSET + $irq) as usize].set_handler_fn(legacy_handler::<$irq>);
+        )+
+    };
+}
+
+macro_rules! set_dynamic_handlers {
+    ($idt:expr, $($vector:literal),+) => {
+        $(
+            $idt[$vector].set_handler_fn(dynamic_handler::<$vector>);
+        )+
+    };
+}
+
+/// Installs the handlers for all dynamic vectors and for the legacy IRQs that the
+/// kernel doesn't use in the given IDT.
+pub(in crate::interrupts) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    set_legacy_handlers!(idt, 3, 5, 6, 7, 8, 9, 10, 11, 12, 13, 15);
+    set_dynamic_handlers!(
+        idt, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68,
+        69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79
+    );
+}
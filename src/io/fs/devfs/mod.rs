@@ -5,6 +5,7 @@ use core::sync::atomic::{AtomicU64, Ordering};
 
 use crate::io::fs::devfs::null::Null;
 use crate::io::fs::devfs::serial::Serial;
+use crate::io::fs::devfs::tty::TtyDevice;
 use crate::io::fs::devfs::zero::Zero;
 use crate::io::fs::perm::Permission;
 use crate::io::fs::{CreateNodeType, Fs, IDir, INode, INodeBase, INodeNum, Stat};
@@ -12,6 +13,7 @@ use kstd::io::{Error, Result};
 
 mod null;
 mod serial;
+mod tty;
 mod zero;
 
 pub struct DevFs {
@@ -28,6 +30,16 @@ impl DevFs {
         root.mount(INode::new_file(Null::new(next()))).unwrap();
         root.mount(INode::new_character_device_file(Serial::new(next())))
             .unwrap();
+        root.mount(INode::new_character_device_file(TtyDevice::new(
+            next(),
+            crate::tty::console(),
+        )))
+        .unwrap();
+        root.mount(INode::new_character_device_file(TtyDevice::new(
+            next(),
+            crate::tty::serial(),
+        )))
+        .unwrap();
 
         Self {
             root: INode::new_dir(root),
@@ -83,6 +95,7 @@ impl DevDir {
                     ctime: 0,
                     blksize: 0,
                     blocks: 0,
+                    permission: Permission::empty(),
                 },
             },
             children: BTreeMap::new(),
@@ -133,4 +146,8 @@ impl IDir for DevDir {
         self.children.insert(name, node);
         Ok(())
     }
+
+    fn unmount(&mut self, name: &dyn AsRef<str>) -> Result<INode> {
+        self.children.remove(name.as_ref()).ok_or(Error::NotFound)
+    }
 }
@@ -38,13 +38,11 @@ impl INodeBase for Serial {
 
 impl ICharacterDeviceFile for Serial {
     fn read_at(&self, _: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
-        let mut serial = crate::serial::SERIAL1.lock();
-        let buffer = buf.as_mut();
-        buffer.iter_mut().for_each(|b| *b = serial.receive());
-        Ok(buffer.len())
+        // received bytes are passed to the serial terminal, see `process_serial_input`
+        crate::tty::serial().read(buf.as_mut())
     }
 
-    fn write_at(&mut self, _: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
+    fn write_at(&self, _: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
         let buffer = buf.as_ref();
         let mut serial = crate::serial::SERIAL1.lock();
         for &b in buffer {
@@ -0,0 +1,51 @@
+use alloc::string::String;
+
+use crate::io::fs::devfs::DevFsNodeBase;
+use crate::io::fs::{ICharacterDeviceFile, INodeBase, INodeNum, Stat};
+use crate::tty::Tty;
+use kstd::io::Result;
+
+/// A terminal as a character device. The offset of reads and writes is ignored.
+pub struct TtyDevice {
+    base: DevFsNodeBase,
+    tty: &'static Tty,
+}
+
+impl TtyDevice {
+    pub fn new(inode_num: INodeNum, tty: &'static Tty) -> Self {
+        Self {
+            base: DevFsNodeBase {
+                name: tty.name().into(),
+                stat: Stat {
+                    inode: inode_num,
+                    ..Default::default()
+                },
+            },
+            tty,
+        }
+    }
+}
+
+impl INodeBase for TtyDevice {
+    fn num(&self) -> INodeNum {
+        self.base.num()
+    }
+
+    fn name(&self) -> String {
+        self.base.name()
+    }
+
+    fn stat(&self) -> Stat {
+        self.base.stat()
+    }
+}
+
+impl ICharacterDeviceFile for TtyDevice {
+    fn read_at(&self, _: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
+        self.tty.read(buf.as_mut())
+    }
+
+    fn write_at(&self, _: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
+        Ok(self.tty.write(buf.as_ref()))
+    }
+}
@@ -1,7 +1,9 @@
+use crate::io::fs::device::FlushableBlockDevice;
 use crate::io::fs::{IBlockDeviceFile, INodeBase, INodeNum, Stat};
 use alloc::string::String;
+use alloc::vec;
 use kstd::io::block::BlockDevice;
-use kstd::io::ReadAt;
+use kstd::io::{Error, ReadAt};
 
 pub struct BlockDeviceFile<D>
 where
@@ -50,7 +52,7 @@ where
 
 impl<D> IBlockDeviceFile for BlockDeviceFile<D>
 where
-    D: 'static + BlockDevice,
+    D: 'static + FlushableBlockDevice,
 {
     fn block_count(&self) -> usize {
         self.device.block_count()
@@ -68,7 +70,42 @@ where
         self.device.read_at(offset, buf)
     }
 
-    fn write_at(&mut self, _offset: u64, _buf: &dyn AsRef<[u8]>) -> kstd::io::Result<usize> {
-        todo!("implement write_at for BlockDevice")
+    fn write_block(&mut self, block: u64, buf: &dyn AsRef<[u8]>) -> kstd::io::Result<()> {
+        self.device.write_block(block, buf).map(|_| ())
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &dyn AsRef<[u8]>) -> kstd::io::Result<usize> {
+        let buffer = buf.as_ref();
+        let block_size = self.device.block_size();
+        let size = self.stat.size;
+        if offset > size {
+            return Err(Error::InvalidOffset);
+        }
+        // writes beyond the end of the device are truncated
+        let len = buffer.len().min((size - offset) as usize);
+
+        let mut block_data = vec![0_u8; block_size];
+        let mut written = 0;
+        while written < len {
+            let position = offset + written as u64;
+            let block = position / block_size as u64;
+            let block_offset = (position % block_size as u64) as usize;
+            let chunk_len = (block_size - block_offset).min(len - written);
+            let chunk = &buffer[written..written + chunk_len];
+
+            if chunk_len == block_size {
+                self.device.write_block(block, &chunk)?;
+            } else {
+                self.device.read_block(block, &mut block_data)?;
+                block_data[block_offset..block_offset + chunk_len].copy_from_slice(chunk);
+                self.device.write_block(block, &block_data)?;
+            }
+            written += chunk_len;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> kstd::io::Result<()> {
+        self.device.flush()
     }
 }
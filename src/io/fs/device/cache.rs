@@ -0,0 +1,603 @@
+//! A global block cache that sits between file systems and block devices.
+//!
+//! Devices are wrapped in a [`CachedBlockDevice`], which implements [`BlockDevice`]
+//! itself, so file systems get the cache without knowing about it. Blocks are
+//! kept in a shared LRU cache keyed by (device, block). Writes only go to the cache
+//! and are written back to the device when the block is evicted, or when the
+//! device is flushed with [`CachedBlockDevice::flush`] or [`sync`], which also flush
+//! the device itself. Dirty blocks stay cached until they were written, so a failed
+//! write-back never loses data.
+//!
+//! No device I/O is performed while the cache is locked, so a cached device may
+//! be backed by something that itself reads from a cached device.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+use kstd::io::block::BlockDevice;
+use kstd::io::{Error, Result};
+use kstd::sync::{Mutex, Once, RwLock};
+
+use crate::error;
+use crate::io::fs::device::FlushableBlockDevice;
+use crate::memory::allocator::slab::{self, SlabCache};
+
+/// The maximum amount of bytes of block data that the cache holds.
+pub const BLOCK_CACHE_SIZE: usize = 128 * 1024;
+
+static mut BLOCK_CACHE: Option<Mutex<BlockCache>> = None;
+static BLOCK_CACHE_INIT: Once = Once::new();
+
+/// The entries of the block cache, which are created and dropped on every miss and
+/// eviction.
+static ENTRY_CACHE: SlabCache = SlabCache::for_type::<CacheEntry>("block-cache-entry");
+
+fn get_cache() -> &'static Mutex<BlockCache> {
+    BLOCK_CACHE_INIT.call_once(|| unsafe {
+        slab::register(&ENTRY_CACHE);
+        BLOCK_CACHE = Some(Mutex::new(BlockCache::new(BLOCK_CACHE_SIZE)));
+    });
+    unsafe { BLOCK_CACHE.as_ref().unwrap() }
+}
+
+/// Writes all dirty blocks of all cached devices back to their devices.
+pub fn sync() -> Result<()> {
+    let device_ids = get_cache()
+        .lock()
+        .devices
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
+    for id in device_ids {
+        flush_device(id)?;
+    }
+    Ok(())
+}
+
+/// Returns a snapshot of the counters of the block cache.
+pub fn stats() -> CacheStats {
+    get_cache().lock().stats
+}
+
+pub type DeviceId = u64;
+
+type CacheKey = (DeviceId, u64);
+
+type DeviceHandle = Arc<RwLock<dyn FlushableBlockDevice>>;
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub writebacks: u64,
+}
+
+impl CacheStats {
+    /// The hit rate in percent, or 0 if there were no accesses yet.
+    pub fn hit_rate(&self) -> u64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0
+        } else {
+            self.hits * 100 / total
+        }
+    }
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "hits: {}, misses: {} ({}% hit rate), evictions: {}, writebacks: {}",
+            self.hits,
+            self.misses,
+            self.hit_rate(),
+            self.evictions,
+            self.writebacks
+        )
+    }
+}
+
+type Entry = Box<CacheEntry, &'static SlabCache>;
+
+struct CacheEntry {
+    data: Vec<u8>,
+    dirty: bool,
+    /// Whether the block is written back to be evicted. Accessing the block cancels
+    /// the eviction.
+    evicting: bool,
+    stamp: u64,
+}
+
+/// A copy of a dirty block that still has to be written to its device.
+struct Writeback {
+    key: CacheKey,
+    data: Vec<u8>,
+}
+
+/// The data structure of the cache. This does not perform any device I/O,
+/// callers are responsible for writing back the [`Writeback`]s that it returns.
+struct BlockCache {
+    capacity: usize,
+    used: usize,
+    clock: u64,
+    next_device_id: DeviceId,
+    devices: BTreeMap<DeviceId, DeviceHandle>,
+    entries: BTreeMap<CacheKey, Entry>,
+    /// Maps the access stamp of every entry to its key, the first element
+    /// is the least recently used entry.
+    lru: BTreeMap<u64, CacheKey>,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            clock: 0,
+            next_device_id: 0,
+            devices: BTreeMap::new(),
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn register(&mut self, device: DeviceHandle) -> DeviceId {
+        let id = self.next_device_id;
+        self.next_device_id += 1;
+        self.devices.insert(id, device);
+        id
+    }
+
+    /// Removes the device and all its blocks from the cache. Dirty blocks are discarded.
+    /// The device handle is returned, so that it isn't dropped while the cache is locked.
+    fn unregister(&mut self, device: DeviceId) -> Option<DeviceHandle> {
+        let handle = self.devices.remove(&device);
+        let keys = self
+            .entries
+            .range((device, 0)..=(device, u64::MAX))
+            .map(|(&k, _)| k)
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.remove(&key);
+        }
+        handle
+    }
+
+    fn touch(&mut self, key: &CacheKey) {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key).unwrap();
+        self.lru.remove(&entry.stamp);
+        entry.stamp = self.clock;
+        entry.evicting = false;
+        self.lru.insert(self.clock, *key);
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.lru.remove(&entry.stamp);
+        self.used -= entry.data.len();
+        Some(entry)
+    }
+
+    /// Copies the cached block into the given buffer and returns true, or returns
+    /// false if the block is not cached.
+    fn read(&mut self, key: &CacheKey, buf: &mut [u8]) -> bool {
+        match self.entries.get(key) {
+            Some(entry) => {
+                let len = buf.len().min(entry.data.len());
+                buf[..len].copy_from_slice(&entry.data[..len]);
+                self.stats.hits += 1;
+                self.touch(key);
+                true
+            }
+            None => {
+                self.stats.misses += 1;
+                false
+            }
+        }
+    }
+
+    /// Inserts a block into the cache. If the block is already cached, a clean insert
+    /// is ignored, since the cached data can't be older than what was read from the device.
+    fn insert(&mut self, key: CacheKey, data: &[u8], dirty: bool) -> Vec<Writeback> {
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if dirty {
+                entry.data.clear();
+                entry.data.extend_from_slice(data);
+                entry.dirty = true;
+            }
+            self.touch(&key);
+            return Vec::new();
+        }
+
+        let writebacks = self.evict_for(data.len());
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            Box::new_in(
+                CacheEntry {
+                    data: Vec::from(data),
+                    dirty,
+                    evicting: false,
+                    stamp: self.clock,
+                },
+                &ENTRY_CACHE,
+            ),
+        );
+        self.lru.insert(self.clock, key);
+        self.used += data.len();
+        writebacks
+    }
+
+    /// Writes the given bytes to the start of a cached block and marks it as dirty.
+    /// Returns false if the block is not cached.
+    fn merge(&mut self, key: &CacheKey, data: &[u8]) -> bool {
+        let entry = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let len = data.len().min(entry.data.len());
+        entry.data[..len].copy_from_slice(&data[..len]);
+        entry.dirty = true;
+        self.touch(key);
+        true
+    }
+
+    /// Evicts least recently used entries until the given amount of bytes fits into the
+    /// cache. Dirty entries are only evicted after they were written back, see
+    /// [`BlockCache::finish_eviction`], so the cache exceeds its capacity until then.
+    fn evict_for(&mut self, size: usize) -> Vec<Writeback> {
+        let mut writebacks = Vec::new();
+        if self.used + size <= self.capacity {
+            return writebacks;
+        }
+        // the amount of bytes that will be freed once the write-backs are done
+        let mut pending = 0;
+        let keys = self.lru.values().copied().collect::<Vec<_>>();
+        for key in keys {
+            if self.used - pending + size <= self.capacity {
+                break;
+            }
+            let entry = self.entries.get_mut(&key).unwrap();
+            if entry.evicting {
+                continue;
+            }
+            if entry.dirty {
+                entry.evicting = true;
+                pending += entry.data.len();
+                writebacks.push(Writeback {
+                    key,
+                    data: entry.data.clone(),
+                });
+            } else {
+                self.remove(&key);
+                self.stats.evictions += 1;
+            }
+        }
+        writebacks
+    }
+
+    /// Removes a block that was written back to be evicted, unless it was accessed in
+    /// the meantime. If the write-back failed, the block stays cached and dirty.
+    fn finish_eviction(&mut self, key: &CacheKey, written: &[u8], success: bool) {
+        let entry = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if !success {
+            entry.evicting = false;
+            return;
+        }
+        if entry.evicting && entry.data == written {
+            self.remove(key);
+            self.stats.evictions += 1;
+        } else {
+            self.mark_clean(key, written);
+        }
+    }
+
+    /// Returns copies of all dirty blocks of the given device. The blocks stay dirty
+    /// until they are marked clean with [`BlockCache::mark_clean`].
+    fn take_dirty(&mut self, device: DeviceId) -> Vec<Writeback> {
+        self.entries
+            .range((device, 0)..=(device, u64::MAX))
+            .filter(|(_, e)| e.dirty)
+            .map(|(&key, e)| Writeback {
+                key,
+                data: e.data.clone(),
+            })
+            .collect()
+    }
+
+    /// Marks a cached block as clean after the given data was written to the device,
+    /// unless the block was changed in the meantime.
+    fn mark_clean(&mut self, key: &CacheKey, written: &[u8]) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            if entry.data == written {
+                entry.dirty = false;
+            }
+        }
+    }
+}
+
+/// Writes the blocks that are evicted back to their devices, and evicts them once they
+/// were written. Failures are only logged, since they don't concern the block that the
+/// caller accesses, and the blocks stay cached. Must not be called while the cache is
+/// locked.
+fn evict(writebacks: Vec<Writeback>) {
+    for writeback in writebacks {
+        let (device_id, block) = writeback.key;
+        let device = match get_cache().lock().devices.get(&device_id) {
+            Some(d) => d.clone(),
+            None => continue, // the device was removed in the meantime
+        };
+        let result = device.write().write_block(block, &writeback.data);
+        if let Err(e) = &result {
+            error!(
+                "could not write back block {} of device {}: {}",
+                block, device_id, e
+            );
+        }
+        let mut cache = get_cache().lock();
+        cache.finish_eviction(&writeback.key, &writeback.data, result.is_ok());
+        if result.is_ok() {
+            cache.stats.writebacks += 1;
+        }
+    }
+}
+
+/// Writes the given blocks back to their devices. Must not be called while the cache is locked.
+fn write_back(writebacks: Vec<Writeback>) -> Result<()> {
+    for writeback in writebacks {
+        let (device_id, block) = writeback.key;
+        let device = match get_cache().lock().devices.get(&device_id) {
+            Some(d) => d.clone(),
+            None => continue, // the device was removed in the meantime
+        };
+        device.write().write_block(block, &writeback.data)?;
+        let mut cache = get_cache().lock();
+        cache.mark_clean(&writeback.key, &writeback.data);
+        cache.stats.writebacks += 1;
+    }
+    Ok(())
+}
+
+fn flush_device(device: DeviceId) -> Result<()> {
+    let (writebacks, handle) = {
+        let mut cache = get_cache().lock();
+        (
+            cache.take_dirty(device),
+            cache.devices.get(&device).cloned(),
+        )
+    };
+    write_back(writebacks)?;
+    match handle {
+        Some(handle) => handle.write().flush(),
+        None => Ok(()),
+    }
+}
+
+/// A [`BlockDevice`] that reads and writes through the global block cache.
+pub struct CachedBlockDevice {
+    id: DeviceId,
+    device: DeviceHandle,
+    block_size: usize,
+    block_count: usize,
+}
+
+impl CachedBlockDevice {
+    pub fn new<D>(device: D) -> Self
+    where
+        D: 'static + FlushableBlockDevice,
+    {
+        let block_size = device.block_size();
+        let block_count = device.block_count();
+        let device: DeviceHandle = Arc::new(RwLock::new(device));
+        let id = get_cache().lock().register(device.clone());
+        Self {
+            id,
+            device,
+            block_size,
+            block_count,
+        }
+    }
+
+    /// Writes all dirty blocks of this device back to the device, and flushes the device.
+    pub fn flush(&self) -> Result<()> {
+        flush_device(self.id)
+    }
+}
+
+impl Drop for CachedBlockDevice {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("could not flush block device {}: {}", self.id, e);
+        }
+        let _device = get_cache().lock().unregister(self.id);
+    }
+}
+
+impl FlushableBlockDevice for CachedBlockDevice {
+    fn flush(&mut self) -> Result<()> {
+        flush_device(self.id)
+    }
+}
+
+impl BlockDevice for CachedBlockDevice {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    fn read_block(&self, block: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
+        let buffer = buf.as_mut();
+        let key = (self.id, block);
+        if get_cache().lock().read(&key, buffer) {
+            return Ok(buffer.len());
+        }
+
+        let mut data = vec![0_u8; self.block_size];
+        self.device.read().read_block(block, &mut data)?;
+        let len = buffer.len().min(data.len());
+        buffer[..len].copy_from_slice(&data[..len]);
+
+        let writebacks = get_cache().lock().insert(key, &data, false);
+        evict(writebacks);
+        Ok(buffer.len())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
+        let buffer = buf.as_ref();
+        let key = (self.id, block);
+        if buffer.len() > self.block_size {
+            return Err(Error::InvalidOffset);
+        }
+        if buffer.len() == self.block_size {
+            let writebacks = get_cache().lock().insert(key, buffer, true);
+            evict(writebacks);
+            return Ok(buffer.len());
+        }
+
+        // partial blocks are merged into the cached block, which is read first if needed
+        if get_cache().lock().merge(&key, buffer) {
+            return Ok(buffer.len());
+        }
+        let mut data = vec![0_u8; self.block_size];
+        self.device.read().read_block(block, &mut data)?;
+        data[..buffer.len()].copy_from_slice(buffer);
+        let writebacks = get_cache().lock().insert(key, &data, true);
+        evict(writebacks);
+        Ok(buffer.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::fs::device::testing::VecBlockDevice;
+
+    #[test_case]
+    fn test_lru_eviction() {
+        let mut cache = BlockCache::new(8);
+        assert!(cache.insert((0, 0), &[1; 4], false).is_empty());
+        assert!(cache.insert((0, 1), &[2; 4], true).is_empty());
+
+        let mut buf = [0_u8; 4];
+        assert!(cache.read(&(0, 0), &mut buf)); // block 1 is now the least recently used
+        assert_eq!([1; 4], buf);
+
+        let writebacks = cache.insert((0, 2), &[3; 4], false);
+        assert_eq!(1, writebacks.len());
+        assert_eq!((0, 1), writebacks[0].key);
+        assert_eq!(vec![2; 4], writebacks[0].data);
+        assert!(
+            cache.entries.contains_key(&(0, 1)),
+            "dirty blocks stay cached until they are written"
+        );
+
+        cache.finish_eviction(&writebacks[0].key, &writebacks[0].data, true);
+        assert!(!cache.read(&(0, 1), &mut buf));
+        assert!(cache.read(&(0, 0), &mut buf));
+        assert!(cache.read(&(0, 2), &mut buf));
+        assert_eq!(
+            CacheStats {
+                hits: 3,
+                misses: 1,
+                evictions: 1,
+                writebacks: 0,
+            },
+            cache.stats
+        );
+    }
+
+    #[test_case]
+    fn test_failed_eviction_keeps_dirty_block() {
+        let mut cache = BlockCache::new(4);
+        cache.insert((0, 0), &[1; 4], true);
+        let writebacks = cache.insert((0, 1), &[2; 4], false);
+        assert_eq!(1, writebacks.len());
+
+        cache.finish_eviction(&writebacks[0].key, &writebacks[0].data, false);
+        let mut buf = [0_u8; 4];
+        assert!(cache.read(&(0, 0), &mut buf));
+        assert_eq!([1; 4], buf);
+        assert_eq!(1, cache.take_dirty(0).len());
+        assert_eq!(0, cache.stats.evictions);
+    }
+
+    #[test_case]
+    fn test_accessed_block_is_not_evicted() {
+        let mut cache = BlockCache::new(4);
+        cache.insert((0, 0), &[1; 4], true);
+        let writebacks = cache.insert((0, 1), &[2; 4], false);
+
+        let mut buf = [0_u8; 4];
+        assert!(cache.read(&(0, 0), &mut buf));
+        cache.finish_eviction(&writebacks[0].key, &writebacks[0].data, true);
+        assert!(cache.read(&(0, 0), &mut buf));
+        assert!(cache.take_dirty(0).is_empty(), "the written block is clean");
+    }
+
+    #[test_case]
+    fn test_clean_insert_keeps_dirty_block() {
+        let mut cache = BlockCache::new(8);
+        cache.insert((0, 0), &[1; 4], true);
+        cache.insert((0, 0), &[0; 4], false);
+
+        let mut buf = [0_u8; 4];
+        assert!(cache.read(&(0, 0), &mut buf));
+        assert_eq!([1; 4], buf);
+        let dirty = cache.take_dirty(0);
+        assert_eq!(1, dirty.len());
+        assert_eq!(
+            1,
+            cache.take_dirty(0).len(),
+            "blocks stay dirty until written"
+        );
+        cache.mark_clean(&dirty[0].key, &dirty[0].data);
+        assert!(cache.take_dirty(0).is_empty());
+    }
+
+    #[test_case]
+    fn test_cached_device_write_back() {
+        let disk = VecBlockDevice::new(4, vec![0_u8; 16]);
+        let data = disk.data();
+        let mut device = CachedBlockDevice::new(disk);
+
+        device.write_block(1, &[7_u8; 4]).unwrap();
+        assert_eq!(0, data.read()[4], "write should only go to the cache");
+
+        let mut buf = [0_u8; 4];
+        device.read_block(1, &mut buf).unwrap();
+        assert_eq!([7; 4], buf);
+
+        device.flush().unwrap();
+        assert_eq!([7; 4], data.read()[4..8]);
+    }
+
+    #[test_case]
+    fn test_partial_write_keeps_dirty_block() {
+        let disk = VecBlockDevice::new(4, vec![0_u8; 16]);
+        let data = disk.data();
+        let mut device = CachedBlockDevice::new(disk);
+
+        device.write_block(2, &[1_u8; 4]).unwrap();
+        device.write_block(2, &[9_u8; 2]).unwrap();
+        device.write_block(3, &[5_u8; 2]).unwrap();
+        assert_eq!([0; 8], data.read()[8..16]);
+
+        device.flush().unwrap();
+        assert_eq!([9, 9, 1, 1, 5, 5, 0, 0], data.read()[8..16]);
+    }
+}
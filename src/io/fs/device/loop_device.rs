@@ -0,0 +1,111 @@
+use alloc::format;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kstd::io::block::BlockDevice;
+use kstd::io::{Error, Result};
+
+use crate::io::fs::device::block::BlockDeviceFile;
+use crate::io::fs::device::FlushableBlockDevice;
+use crate::io::fs::{vfs, IFileHandle, INode};
+
+static NEXT_LOOP_DEVICE: AtomicUsize = AtomicUsize::new(0);
+
+/// A [`BlockDevice`] that is backed by a regular file, so that images that are
+/// stored in a file system can be used like any other block device.
+pub struct LoopDevice {
+    file: IFileHandle,
+    block_size: usize,
+}
+
+impl LoopDevice {
+    /// Creates a loop device over the given file. Trailing bytes of the file that
+    /// don't fill a whole block are not accessible through the loop device.
+    pub fn new(file: IFileHandle, block_size: usize) -> Self {
+        assert_ne!(0, block_size, "block size must not be 0");
+        Self { file, block_size }
+    }
+
+    fn check_access(&self, block: u64, len: usize) -> Result<u64> {
+        if block >= self.block_count() as u64 || len > self.block_size {
+            return Err(Error::InvalidOffset);
+        }
+        Ok(block * self.block_size as u64)
+    }
+}
+
+impl BlockDevice for LoopDevice {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> usize {
+        (self.file.read().size() / self.block_size as u64) as usize
+    }
+
+    fn read_block(&self, block: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
+        let offset = self.check_access(block, buf.as_mut().len())?;
+        self.file.read().read_at(offset, buf)
+    }
+
+    fn write_block(&mut self, block: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
+        let offset = self.check_access(block, buf.as_ref().len())?;
+        self.file.write().write_at(offset, buf)
+    }
+}
+
+// writes go directly to the file
+impl FlushableBlockDevice for LoopDevice {}
+
+/// Creates a new loop device over the given file and mounts it at `/dev/loopN`,
+/// where `N` is the next free loop device number. Returns the mounted node.
+pub fn attach(file: IFileHandle, block_size: usize) -> Result<INode> {
+    let num = NEXT_LOOP_DEVICE.fetch_add(1, Ordering::SeqCst);
+    let device = LoopDevice::new(file, block_size);
+    let node = INode::new_block_device_file(BlockDeviceFile::new(
+        device,
+        0_u64.into(),
+        format!("loop{}", num),
+    ));
+    vfs::mount(&"/dev", node.clone())?;
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::fs::perm::Permission;
+    use crate::io::fs::tmpfs::TmpFs;
+    use crate::io::fs::{CreateNodeType, Fs};
+
+    #[test_case]
+    fn test_loop_device_over_file() {
+        let fs = TmpFs::new("tmp".into(), 4096);
+        let file = fs
+            .root_inode()
+            .as_dir()
+            .unwrap()
+            .write()
+            .create(&"image", CreateNodeType::File, Permission::user_rwx())
+            .unwrap()
+            .as_file()
+            .unwrap();
+        file.write().truncate(1000).unwrap();
+
+        let mut device = LoopDevice::new(file.clone(), 256);
+        assert_eq!(
+            3,
+            device.block_count(),
+            "the incomplete last block is not accessible"
+        );
+
+        device.write_block(1, &[0xAB_u8; 256]).unwrap();
+        let mut data = [0_u8; 2];
+        file.read().read_at(255, &mut data).unwrap();
+        assert_eq!([0, 0xAB], data);
+
+        let mut block = [0_u8; 256];
+        device.read_block(1, &mut block).unwrap();
+        assert_eq!([0xAB_u8; 256], block);
+        assert_eq!(Err(Error::InvalidOffset), device.read_block(3, &mut block));
+    }
+}
@@ -1,7 +1,22 @@
 use crate::io::fs::IBlockDeviceHandle;
 use kstd::io::block::BlockDevice;
+use kstd::io::Result;
 
 pub mod block;
+pub mod cache;
+pub mod loop_device;
+pub mod partition;
+pub mod ramdisk;
+#[cfg(test)]
+mod testing;
+
+/// A [`BlockDevice`] that may buffer writes, which [`FlushableBlockDevice::flush`]
+/// writes back to the underlying storage.
+pub trait FlushableBlockDevice: BlockDevice {
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
 
 pub struct FileBlockDevice {
     file: IBlockDeviceHandle,
@@ -11,6 +26,10 @@ impl FileBlockDevice {
     pub fn new(file: IBlockDeviceHandle) -> Self {
         Self { file }
     }
+
+    pub fn flush(&self) -> kstd::io::Result<()> {
+        self.file.write().flush()
+    }
 }
 
 impl BlockDevice for FileBlockDevice {
@@ -27,7 +46,14 @@ impl BlockDevice for FileBlockDevice {
         Ok(buf.as_mut().len())
     }
 
-    fn write_block(&mut self, _block: u64, _buf: &dyn AsRef<[u8]>) -> kstd::io::Result<usize> {
-        todo!()
+    fn write_block(&mut self, block: u64, buf: &dyn AsRef<[u8]>) -> kstd::io::Result<usize> {
+        self.file.write().write_block(block, buf)?;
+        Ok(buf.as_ref().len())
+    }
+}
+
+impl FlushableBlockDevice for FileBlockDevice {
+    fn flush(&mut self) -> Result<()> {
+        FileBlockDevice::flush(self)
     }
 }
@@ -0,0 +1,218 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+use kstd::io::block::BlockDevice;
+use kstd::io::cursor::Cursor;
+use kstd::io::read::Read;
+use kstd::io::{Error, ReadAt, Result};
+use kstd::{read_bytes, read_le_u32};
+
+const SIGNATURE: [u8; 8] = *b"EFI PART";
+/// Offset of the header CRC32 field within the header.
+const HEADER_CRC_OFFSET: usize = 16;
+/// The smallest entry size that is allowed by the spec.
+const MIN_ENTRY_SIZE: u32 = 128;
+/// The largest partition entry array that is read, which is the size that the spec
+/// reserves for it (128 entries of 128 bytes). Larger arrays are rejected, since the
+/// header is read from untrusted disks.
+const MAX_ENTRIES_SIZE: usize = 128 * 128;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Default)]
+pub struct Guid([u8; 16]);
+
+impl Guid {
+    pub const fn new(data: [u8; 16]) -> Self {
+        Self(data)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|&b| b == 0)
+    }
+}
+
+impl Display for Guid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        // the first three groups are stored little endian, the rest big endian
+        let d = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            d[3], d[2], d[1], d[0], d[5], d[4], d[7], d[6], d[8], d[9], d[10], d[11], d[12], d[13], d[14], d[15]
+        )
+    }
+}
+
+impl core::fmt::Debug for Guid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug)]
+pub struct GptHeader {
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub partition_entries_lba: u64,
+    pub num_partition_entries: u32,
+    pub partition_entry_size: u32,
+    pub partition_entries_crc32: u32,
+}
+
+impl GptHeader {
+    pub fn decode(source: &mut impl Read<u8>) -> Result<Self> {
+        let signature = read_bytes!(source, 8);
+        if signature != SIGNATURE {
+            return Err(Error::InvalidMagicNumber);
+        }
+        let revision = read_le_u32!(source);
+        let header_size = read_le_u32!(source);
+        let header_crc32 = read_le_u32!(source);
+        let _ = read_le_u32!(source); // reserved
+        Ok(Self {
+            revision,
+            header_size,
+            header_crc32,
+            current_lba: read_le_u64(source)?,
+            backup_lba: read_le_u64(source)?,
+            first_usable_lba: read_le_u64(source)?,
+            last_usable_lba: read_le_u64(source)?,
+            disk_guid: Guid(read_bytes!(source, 16)),
+            partition_entries_lba: read_le_u64(source)?,
+            num_partition_entries: read_le_u32!(source),
+            partition_entry_size: read_le_u32!(source),
+            partition_entries_crc32: read_le_u32!(source),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct GptPartitionEntry {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    /// Inclusive.
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptPartitionEntry {
+    /// Decodes a single entry. `entry_size` is the size of an entry as declared
+    /// in the [`GptHeader`], all bytes after the name are skipped.
+    pub fn decode(source: &mut impl Read<u8>, entry_size: usize) -> Result<Self> {
+        let type_guid = Guid(read_bytes!(source, 16));
+        let unique_guid = Guid(read_bytes!(source, 16));
+        let first_lba = read_le_u64(source)?;
+        let last_lba = read_le_u64(source)?;
+        let attributes = read_le_u64(source)?;
+        let name_data = read_bytes!(source, 72);
+        let name = char::decode_utf16(
+            name_data
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0),
+        )
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+        let mut rest = vec![0_u8; entry_size - MIN_ENTRY_SIZE as usize];
+        source.read_exact(&mut rest)?;
+        Ok(Self {
+            type_guid,
+            unique_guid,
+            first_lba,
+            last_lba,
+            attributes,
+            name,
+        })
+    }
+
+    /// Entries with a type GUID of all zeros are unused.
+    pub fn is_used(&self) -> bool {
+        !self.type_guid.is_zero()
+    }
+}
+
+/// Reads the primary GPT header and its partition entries from the given device.
+/// Both checksums are verified, and only used entries are returned, together with
+/// their index in the entry array.
+pub fn read_gpt<D>(device: &D) -> Result<(GptHeader, Vec<(usize, GptPartitionEntry)>)>
+where
+    D: BlockDevice,
+{
+    let block_size = device.block_size();
+
+    let mut header_data = vec![0_u8; block_size];
+    device.read_at(block_size as u64, &mut header_data)?; // the primary header is in LBA 1
+    let header = GptHeader::decode(&mut Cursor::new(header_data.clone()))?;
+
+    let header_size = header.header_size as usize;
+    if header_size < 92 || header_size > block_size {
+        return Err(Error::DecodeError);
+    }
+    header_data[HEADER_CRC_OFFSET..HEADER_CRC_OFFSET + 4].fill(0);
+    if crc32(&header_data[..header_size]) != header.header_crc32 {
+        return Err(Error::IncoherentData);
+    }
+
+    let entry_size = header.partition_entry_size;
+    if entry_size < MIN_ENTRY_SIZE || !entry_size.is_power_of_two() {
+        return Err(Error::DecodeError);
+    }
+    let entries_size = (header.num_partition_entries as usize)
+        .checked_mul(entry_size as usize)
+        .filter(|&size| size <= MAX_ENTRIES_SIZE)
+        .ok_or(Error::DecodeError)?;
+    let entries_offset = header
+        .partition_entries_lba
+        .checked_mul(block_size as u64)
+        .ok_or(Error::DecodeError)?;
+    let disk_size = device.block_count() as u64 * block_size as u64;
+    if entries_offset
+        .checked_add(entries_size as u64)
+        .map_or(true, |end| end > disk_size)
+    {
+        return Err(Error::DecodeError);
+    }
+    let mut entry_data = vec![0_u8; entries_size];
+    device.read_at(entries_offset, &mut entry_data)?;
+    if crc32(&entry_data) != header.partition_entries_crc32 {
+        return Err(Error::IncoherentData);
+    }
+
+    let mut cursor = Cursor::new(entry_data);
+    let mut entries = Vec::new();
+    for index in 0..header.num_partition_entries as usize {
+        let entry = GptPartitionEntry::decode(&mut cursor, entry_size as usize)?;
+        if entry.is_used() {
+            entries.push((index, entry));
+        }
+    }
+    Ok((header, entries))
+}
+
+fn read_le_u64(source: &mut impl Read<u8>) -> Result<u64> {
+    let lo = read_le_u32!(source) as u64;
+    let hi = read_le_u32!(source) as u64;
+    Ok(lo | (hi << 32))
+}
+
+/// CRC32 as used by GPT (IEEE 802.3, reflected, polynomial 0x04C11DB7).
+pub(in crate::io::fs::device::partition) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
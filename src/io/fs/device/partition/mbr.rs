@@ -0,0 +1,87 @@
+use kstd::io::read::Read;
+use kstd::io::Result;
+use kstd::{read_bytes, read_le_u16, read_le_u32, read_u8};
+
+/// Offset of the partition table within the first block of a disk.
+const PARTITION_TABLE_OFFSET: usize = 446;
+/// The last two bytes of a valid MBR.
+const BOOT_SIGNATURE: u16 = 0xAA55;
+
+/// Partition type that marks a protective MBR, which means that the
+/// actual partition table is a GPT.
+pub const PARTITION_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+/// Partition types of extended partitions, which are containers for
+/// logical partitions. Logical partitions are not supported yet.
+pub const PARTITION_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+#[derive(Debug)]
+pub struct MasterBootRecord {
+    pub entries: [MbrPartitionEntry; 4],
+    pub signature: u16,
+}
+
+impl MasterBootRecord {
+    /// Decodes the MBR from the first 512 bytes of a disk. The source must
+    /// be positioned at the very start of the disk.
+    pub fn decode(source: &mut impl Read<u8>) -> Result<Self> {
+        let _ = read_bytes!(source, PARTITION_TABLE_OFFSET); // bootstrap code
+        let entries = [
+            MbrPartitionEntry::decode(source)?,
+            MbrPartitionEntry::decode(source)?,
+            MbrPartitionEntry::decode(source)?,
+            MbrPartitionEntry::decode(source)?,
+        ];
+        let signature = read_le_u16!(source);
+        Ok(Self { entries, signature })
+    }
+
+    pub fn has_valid_signature(&self) -> bool {
+        self.signature == BOOT_SIGNATURE
+    }
+
+    /// Whether this MBR only exists to protect a GPT from tools that don't
+    /// understand GPT.
+    pub fn is_protective(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.partition_type == PARTITION_TYPE_GPT_PROTECTIVE)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MbrPartitionEntry {
+    pub status: u8,
+    pub partition_type: u8,
+    pub first_lba: u32,
+    pub sector_count: u32,
+}
+
+impl MbrPartitionEntry {
+    pub fn decode(source: &mut impl Read<u8>) -> Result<Self> {
+        let status = read_u8!(source);
+        let _ = read_bytes!(source, 3); // CHS address of the first sector, we only use LBA
+        let partition_type = read_u8!(source);
+        let _ = read_bytes!(source, 3); // CHS address of the last sector
+        let first_lba = read_le_u32!(source);
+        let sector_count = read_le_u32!(source);
+        Ok(Self {
+            status,
+            partition_type,
+            first_lba,
+            sector_count,
+        })
+    }
+
+    /// Unused entries have a partition type of 0.
+    pub fn is_used(&self) -> bool {
+        self.partition_type != 0 && self.sector_count != 0
+    }
+
+    pub fn is_bootable(&self) -> bool {
+        self.status & 0x80 > 0
+    }
+
+    pub fn is_extended(&self) -> bool {
+        PARTITION_TYPES_EXTENDED.contains(&self.partition_type)
+    }
+}
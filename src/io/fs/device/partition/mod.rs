@@ -0,0 +1,345 @@
+//! Partition table support.
+//!
+//! Partition tables are read from any [`BlockDevice`], and every partition can be
+//! exposed as its own [`BlockDevice`] through a [`PartitionBlockDevice`], which
+//! translates block addresses relative to the start of the partition into block
+//! addresses on the underlying device.
+//!
+//! Supported are MBR (primary partitions only) and GPT. A disk with a protective MBR
+//! is treated as GPT disk.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use kstd::io::block::BlockDevice;
+use kstd::io::cursor::Cursor;
+use kstd::io::{Error, ReadAt, Result};
+
+use crate::io::fs::device::partition::gpt::Guid;
+use crate::io::fs::device::partition::mbr::MasterBootRecord;
+use crate::io::fs::device::FlushableBlockDevice;
+
+pub mod gpt;
+pub mod mbr;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Partition {
+    /// The number of the partition, starting at 1, as used in names like `ide0p1`.
+    pub number: usize,
+    pub first_block: u64,
+    pub block_count: u64,
+    pub kind: PartitionKind,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PartitionKind {
+    Mbr {
+        partition_type: u8,
+        bootable: bool,
+    },
+    Gpt {
+        type_guid: Guid,
+        unique_guid: Guid,
+        name: String,
+    },
+}
+
+/// Reads the partition table of the given device. If the device doesn't have
+/// a partition table, an empty vec is returned. Tables with partitions that end
+/// before they start or extend past the end of the device are rejected.
+pub fn read_partitions<D>(device: &D) -> Result<Vec<Partition>>
+where
+    D: BlockDevice,
+{
+    let mut first_sector = [0_u8; 512];
+    device.read_at(0, &mut first_sector)?;
+    let mbr = MasterBootRecord::decode(&mut Cursor::new(Vec::from(first_sector)))?;
+    if !mbr.has_valid_signature() {
+        return Ok(Vec::new());
+    }
+
+    if mbr.is_protective() {
+        let (_, entries) = gpt::read_gpt(device)?;
+        return entries
+            .into_iter()
+            // like with MBR, the numbering is determined by the index of the entry
+            .map(|(i, e)| {
+                // the last lba is inclusive
+                let block_count = e
+                    .last_lba
+                    .checked_sub(e.first_lba)
+                    .and_then(|count| count.checked_add(1))
+                    .ok_or(Error::DecodeError)?;
+                check_bounds(
+                    device,
+                    Partition {
+                        number: i + 1,
+                        first_block: e.first_lba,
+                        block_count,
+                        kind: PartitionKind::Gpt {
+                            type_guid: e.type_guid,
+                            unique_guid: e.unique_guid,
+                            name: e.name,
+                        },
+                    },
+                )
+            })
+            .collect();
+    }
+
+    mbr.entries
+        .iter()
+        .enumerate()
+        // the numbering of MBR partitions is determined by the slot, not by the order of used entries
+        .filter(|(_, e)| e.is_used() && !e.is_extended())
+        .map(|(i, e)| Partition {
+            number: i + 1,
+            first_block: e.first_lba as u64,
+            block_count: e.sector_count as u64,
+            kind: PartitionKind::Mbr {
+                partition_type: e.partition_type,
+                bootable: e.is_bootable(),
+            },
+        })
+        .map(|partition| check_bounds(device, partition))
+        .collect()
+}
+
+/// Returns the partition if it lies within the given device.
+fn check_bounds<D>(device: &D, partition: Partition) -> Result<Partition>
+where
+    D: BlockDevice,
+{
+    match partition.first_block.checked_add(partition.block_count) {
+        Some(end) if end <= device.block_count() as u64 => Ok(partition),
+        _ => Err(Error::DecodeError),
+    }
+}
+
+/// A [`BlockDevice`] that represents a single partition on another [`BlockDevice`].
+/// Block 0 of this device is the first block of the partition, and accesses outside
+/// of the partition fail.
+pub struct PartitionBlockDevice<D>
+where
+    D: BlockDevice,
+{
+    device: D,
+    first_block: u64,
+    block_count: usize,
+}
+
+impl<D> PartitionBlockDevice<D>
+where
+    D: BlockDevice,
+{
+    pub fn new(device: D, partition: &Partition) -> Self {
+        Self {
+            device,
+            first_block: partition.first_block,
+            block_count: TryInto::<usize>::try_into(partition.block_count)
+                .expect("too many blocks"),
+        }
+    }
+
+    fn translate(&self, block: u64) -> Result<u64> {
+        if block >= self.block_count as u64 {
+            return Err(Error::InvalidOffset);
+        }
+        Ok(self.first_block + block)
+    }
+}
+
+impl<D> BlockDevice for PartitionBlockDevice<D>
+where
+    D: BlockDevice,
+{
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    fn read_block(&self, block: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
+        self.device.read_block(self.translate(block)?, buf)
+    }
+
+    fn write_block(&mut self, block: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
+        let block = self.translate(block)?;
+        self.device.write_block(block, buf)
+    }
+}
+
+impl<D> FlushableBlockDevice for PartitionBlockDevice<D>
+where
+    D: FlushableBlockDevice,
+{
+    fn flush(&mut self) -> Result<()> {
+        self.device.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::gpt::crc32;
+    use super::*;
+    use crate::io::fs::device::testing::VecBlockDevice;
+
+    fn write_mbr_entry(disk: &mut [u8], slot: usize, typ: u8, first_lba: u32, count: u32) {
+        let entry = &mut disk[446 + slot * 16..446 + (slot + 1) * 16];
+        entry[4] = typ;
+        entry[8..12].copy_from_slice(&first_lba.to_le_bytes());
+        entry[12..16].copy_from_slice(&count.to_le_bytes());
+    }
+
+    #[test_case]
+    fn test_no_partition_table() {
+        let disk = VecBlockDevice::new(512, vec![0_u8; 512 * 4]);
+        assert_eq!(Ok(Vec::new()), read_partitions(&disk));
+    }
+
+    #[test_case]
+    fn test_mbr_partitions() {
+        let mut data = vec![0_u8; 512 * 4];
+        write_mbr_entry(&mut data, 0, 0x83, 1, 2);
+        write_mbr_entry(&mut data, 2, 0x83, 3, 1);
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        let disk = VecBlockDevice::new(512, data);
+
+        let partitions = read_partitions(&disk).unwrap();
+        assert_eq!(2, partitions.len());
+        assert_eq!(1, partitions[0].number);
+        assert_eq!(1, partitions[0].first_block);
+        assert_eq!(2, partitions[0].block_count);
+        assert_eq!(
+            3, partitions[1].number,
+            "numbering must follow the mbr slot"
+        );
+        assert_eq!(3, partitions[1].first_block);
+    }
+
+    /// A disk of 8 blocks with a GPT that has a single partition, and an entry array
+    /// of the given number of entries in LBA 2.
+    fn gpt_disk(first_lba: u64, last_lba: u64, num_entries: u32) -> VecBlockDevice {
+        gpt_disk_with_entries(&[(0, first_lba, last_lba)], num_entries)
+    }
+
+    /// Like [`gpt_disk`], but with a partition in every given (index, first lba, last lba)
+    /// entry.
+    fn gpt_disk_with_entries(partitions: &[(usize, u64, u64)], num_entries: u32) -> VecBlockDevice {
+        let mut data = vec![0_u8; 512 * 8];
+        write_mbr_entry(&mut data, 0, 0xEE, 1, 7);
+        data[510] = 0x55;
+        data[511] = 0xAA;
+
+        for &(index, first_lba, last_lba) in partitions {
+            let entry = &mut data[1024 + index * 128..1024 + (index + 1) * 128];
+            entry[0] = 0xAF; // any non-zero type guid
+            entry[16] = 0x01;
+            entry[32..40].copy_from_slice(&first_lba.to_le_bytes());
+            entry[40..48].copy_from_slice(&last_lba.to_le_bytes());
+            for (i, c) in "data".encode_utf16().enumerate() {
+                entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        let entries_end = (1024 + num_entries as usize * 128).min(data.len());
+        let entries_crc = crc32(&data[1024..entries_end]);
+
+        let header = &mut data[512..512 + 92];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x0001_0000_u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92_u32.to_le_bytes());
+        header[24..32].copy_from_slice(&1_u64.to_le_bytes());
+        header[72..80].copy_from_slice(&2_u64.to_le_bytes());
+        header[80..84].copy_from_slice(&num_entries.to_le_bytes());
+        header[84..88].copy_from_slice(&128_u32.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let header_crc = crc32(header);
+        header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+
+        VecBlockDevice::new(512, data)
+    }
+
+    #[test_case]
+    fn test_gpt_partition() {
+        // the table consists of 4 entries (one block)
+        let disk = gpt_disk(4, 6, 4);
+        let partitions = read_partitions(&disk).unwrap();
+        assert_eq!(1, partitions.len());
+        assert_eq!(4, partitions[0].first_block);
+        assert_eq!(3, partitions[0].block_count, "last lba is inclusive");
+        match &partitions[0].kind {
+            PartitionKind::Gpt { name, .. } => assert_eq!("data", name),
+            k => panic!("expected a gpt partition, but got {:?}", k),
+        }
+    }
+
+    #[test_case]
+    fn test_gpt_numbering_follows_the_entry_index() {
+        let disk = gpt_disk_with_entries(&[(0, 4, 4), (2, 6, 6)], 4);
+        let partitions = read_partitions(&disk).unwrap();
+        assert_eq!(2, partitions.len());
+        assert_eq!(1, partitions[0].number);
+        assert_eq!(
+            3, partitions[1].number,
+            "numbering must follow the entry index"
+        );
+        assert_eq!(6, partitions[1].first_block);
+    }
+
+    #[test_case]
+    fn test_invalid_gpt_is_rejected() {
+        assert_eq!(
+            Err(Error::DecodeError),
+            read_partitions(&gpt_disk(6, 4, 4)),
+            "partition ends before it starts"
+        );
+        assert_eq!(
+            Err(Error::DecodeError),
+            read_partitions(&gpt_disk(4, 8, 4)),
+            "partition extends past the end of the disk"
+        );
+        assert_eq!(
+            Err(Error::DecodeError),
+            read_partitions(&gpt_disk(4, 6, 1 << 20)),
+            "entry array is too large"
+        );
+        assert_eq!(
+            Err(Error::DecodeError),
+            read_partitions(&gpt_disk(4, 6, 48)),
+            "entry array extends past the end of the disk"
+        );
+    }
+
+    #[test_case]
+    fn test_partition_block_device() {
+        let mut data = vec![0_u8; 512 * 4];
+        data[1024] = 0xAB;
+        let partition = Partition {
+            number: 1,
+            first_block: 2,
+            block_count: 2,
+            kind: PartitionKind::Mbr {
+                partition_type: 0x83,
+                bootable: false,
+            },
+        };
+        let disk = VecBlockDevice::new(512, data);
+        let data = disk.data();
+        let mut device = PartitionBlockDevice::new(disk, &partition);
+        assert_eq!(2, device.block_count());
+
+        let mut block = [0_u8; 512];
+        device.read_block(0, &mut block).unwrap();
+        assert_eq!(0xAB, block[0]);
+        assert_eq!(Err(Error::InvalidOffset), device.read_block(2, &mut block));
+
+        block[1] = 0xCD;
+        device.write_block(1, &block).unwrap();
+        assert_eq!(0xCD, data.read()[3 * 512 + 1]);
+    }
+}
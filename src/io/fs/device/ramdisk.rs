@@ -0,0 +1,80 @@
+use kstd::io::block::BlockDevice;
+use kstd::io::{Error, Result};
+
+use crate::io::fs::device::FlushableBlockDevice;
+use crate::memory::kbuffer::KBuffer;
+
+/// A [`BlockDevice`] that is backed by memory. The data is lost when the
+/// ram disk is dropped.
+pub struct RamDisk {
+    buffer: KBuffer,
+    block_size: usize,
+}
+
+impl RamDisk {
+    /// Allocates a zeroed ram disk with the given amount of blocks of the given size.
+    pub fn new(block_count: usize, block_size: usize) -> Self {
+        Self {
+            buffer: KBuffer::allocate(block_count * block_size),
+            block_size,
+        }
+    }
+
+    fn block_range(&self, block: u64, len: usize) -> Result<core::ops::Range<usize>> {
+        if block >= self.block_count() as u64 || len > self.block_size {
+            return Err(Error::InvalidOffset);
+        }
+        let start = block as usize * self.block_size;
+        Ok(start..start + len)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.buffer.len() / self.block_size
+    }
+
+    fn read_block(&self, block: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
+        let buffer = buf.as_mut();
+        let range = self.block_range(block, buffer.len())?;
+        buffer.copy_from_slice(&self.buffer.as_ref()[range]);
+        Ok(buffer.len())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
+        let buffer = buf.as_ref();
+        let range = self.block_range(block, buffer.len())?;
+        self.buffer.as_mut()[range].copy_from_slice(buffer);
+        Ok(buffer.len())
+    }
+}
+
+// writes go directly to memory
+impl FlushableBlockDevice for RamDisk {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_read_write_block() {
+        let mut disk = RamDisk::new(4, 512);
+        assert_eq!(4, disk.block_count());
+
+        let mut block = [0_u8; 512];
+        disk.read_block(3, &mut block).unwrap();
+        assert_eq!([0_u8; 512], block);
+
+        disk.write_block(3, &[0xAB_u8; 512]).unwrap();
+        disk.read_block(3, &mut block).unwrap();
+        assert_eq!([0xAB_u8; 512], block);
+        disk.read_block(2, &mut block).unwrap();
+        assert_eq!([0_u8; 512], block);
+
+        assert_eq!(Err(Error::InvalidOffset), disk.read_block(4, &mut block));
+    }
+}
@@ -0,0 +1,55 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use kstd::io::block::BlockDevice;
+use kstd::io::Result;
+use kstd::sync::RwLock;
+
+use crate::io::fs::device::FlushableBlockDevice;
+
+/// A [`BlockDevice`] for tests that is backed by a vector. The data is shared, so that
+/// it can still be inspected after the device was moved into another device.
+pub struct VecBlockDevice {
+    block_size: usize,
+    data: Arc<RwLock<Vec<u8>>>,
+}
+
+impl VecBlockDevice {
+    pub fn new(block_size: usize, data: Vec<u8>) -> Self {
+        Self {
+            block_size,
+            data: Arc::new(RwLock::new(data)),
+        }
+    }
+
+    /// The data of the device.
+    pub fn data(&self) -> Arc<RwLock<Vec<u8>>> {
+        self.data.clone()
+    }
+}
+
+impl BlockDevice for VecBlockDevice {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.data.read().len() / self.block_size
+    }
+
+    fn read_block(&self, block: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
+        let buffer = buf.as_mut();
+        let start = block as usize * self.block_size;
+        buffer.copy_from_slice(&self.data.read()[start..start + buffer.len()]);
+        Ok(buffer.len())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
+        let buffer = buf.as_ref();
+        let start = block as usize * self.block_size;
+        self.data.write()[start..start + buffer.len()].copy_from_slice(buffer);
+        Ok(buffer.len())
+    }
+}
+
+impl FlushableBlockDevice for VecBlockDevice {}
@@ -5,7 +5,7 @@ use alloc::vec::Vec;
 
 use kstd::io::block::BlockDevice;
 use kstd::io::ReadAt;
-use kstd::io::Result;
+use kstd::io::{Error, Result};
 use kstd::sync::RwLock;
 
 use crate::io::fs::ext2::base::Ext2NodeBase;
@@ -38,23 +38,72 @@ where
     /// for the block address. For example, a block index of 0 (the very first block in a file) is
     /// stored in the direct pointers list. A block index of 12 is the first entry in the single
     /// indirect pointers list.
-    fn determine_block_pointer_type(&self, block_index: usize) -> BlockPointerType {
-        let block_size = self.base.fs().read().superblock.block_size;
-        let pointers_per_block = (block_size / 4) as usize;
-        let hi_single_indirect = pointers_per_block;
-        let hi_double_indirect = hi_single_indirect * pointers_per_block;
-        let hi_triple_indirect = hi_double_indirect * pointers_per_block;
+    /// Returns `None` if the block index can't be addressed by any of the pointer lists.
+    fn determine_block_pointer_type(
+        block_index: usize,
+        pointers_per_block: usize,
+    ) -> Option<BlockPointerType> {
+        let hi_single_indirect = 12 + pointers_per_block;
+        let hi_double_indirect = hi_single_indirect + pointers_per_block.pow(2);
+        let hi_triple_indirect = hi_double_indirect + pointers_per_block.pow(3);
         match block_index {
-            0..12 => BlockPointerType::Direct,
-            x if x >= 12 && x < hi_single_indirect => BlockPointerType::SingleIndirect,
-            x if x >= hi_single_indirect && x < hi_double_indirect => {
-                BlockPointerType::DoubleIndirect
-            }
-            x if x >= hi_double_indirect && x < hi_triple_indirect => {
-                BlockPointerType::TripleIndirect
+            0..12 => Some(BlockPointerType::Direct),
+            x if x < hi_single_indirect => Some(BlockPointerType::SingleIndirect),
+            x if x < hi_double_indirect => Some(BlockPointerType::DoubleIndirect),
+            x if x < hi_triple_indirect => Some(BlockPointerType::TripleIndirect),
+            _ => None,
+        }
+    }
+
+    /// Determines the block pointer of the block with the given index within this file,
+    /// following as many levels of indirect pointer blocks as necessary.
+    /// A pointer of 0 means that the block is not allocated (a hole in a sparse file).
+    fn resolve_block_pointer(&self, fs: &Inner<D>, block_index: usize) -> Result<u32> {
+        let pointers_per_block = (fs.superblock.block_size / 4) as usize;
+        let inode = self.base.inode();
+
+        let single_indirect_start = 12;
+        let double_indirect_start = single_indirect_start + pointers_per_block;
+        let triple_indirect_start = double_indirect_start + pointers_per_block.pow(2);
+        let (mut pointer, mut index, depth) =
+            match Self::determine_block_pointer_type(block_index, pointers_per_block)
+                .ok_or(Error::DecodeError)?
+            {
+                BlockPointerType::Direct => return Ok(inode.direct_pointers[block_index]),
+                BlockPointerType::SingleIndirect => (
+                    inode.singly_indirect_pointer,
+                    block_index - single_indirect_start,
+                    1,
+                ),
+                BlockPointerType::DoubleIndirect => (
+                    inode.doubly_indirect_pointer,
+                    block_index - double_indirect_start,
+                    2,
+                ),
+                BlockPointerType::TripleIndirect => (
+                    inode.triply_indirect_pointer,
+                    block_index - triple_indirect_start,
+                    3,
+                ),
+            };
+
+        for level in (0..depth).rev() {
+            if pointer == 0 {
+                // the whole range covered by this pointer block is a hole
+                return Ok(0);
             }
-            _ => unreachable!("too many blocks"),
+
+            // the number of data blocks that every pointer in the current pointer block covers
+            let blocks_per_pointer = pointers_per_block.pow(level);
+            let pointer_index = index / blocks_per_pointer;
+            index %= blocks_per_pointer;
+
+            let mut pointer_data = [0_u8; 4];
+            let pointer_address = fs.get_block_address(pointer) + pointer_index as u64 * 4;
+            fs.device.read_at(pointer_address, &mut pointer_data)?;
+            pointer = u32::from_le_bytes(pointer_data);
         }
+        Ok(pointer)
     }
 }
 
@@ -88,56 +137,44 @@ where
     }
 
     fn read_at(&self, offset: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
-        let buffer = buf.as_mut();
-
-        let block_size = self.base.fs().read().superblock.block_size as usize;
-
-        let start_block = offset as u32 / block_size as u32;
-        let end_block = (offset as u32 + buffer.len() as u32) / block_size as u32;
-        let relative_offset = offset as usize % block_size;
-        let block_count = if buffer.len() % block_size == 0 {
-            end_block - start_block
-        } else {
-            end_block - start_block + 1
-        } as usize;
+        let size = self.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        // don't read past the end of the file
+        let len = buf.as_mut().len().min((size - offset) as usize);
+        let buffer = &mut buf.as_mut()[..len];
+        if buffer.is_empty() {
+            return Ok(0);
+        }
 
-        // read blocks
-        let mut data: Vec<u8> = vec![0_u8; block_count * block_size];
         let guard = self.base.fs().read();
-        for i in 0..block_count {
-            let read_block_index = (start_block + i as u32) as usize;
-            let block_pointer = match self.determine_block_pointer_type(read_block_index) {
-                BlockPointerType::Direct => self.base.inode().direct_pointers[read_block_index],
-                BlockPointerType::SingleIndirect => {
-                    let single_indirect_block_index = read_block_index - 12; // 12 direct pointers, so subtract the 12
-                    let pointer_block = self.base.inode().singly_indirect_pointer;
-                    let pointer_block_address = guard.get_block_address(pointer_block);
-                    let mut pointer_data = [0_u8; 4];
-                    let pointer_start_address =
-                        pointer_block_address + single_indirect_block_index as u64 * 4;
-                    guard
-                        .device
-                        .read_at(pointer_start_address, &mut pointer_data)?;
-                    u32::from_le_bytes(pointer_data)
-                }
-                BlockPointerType::DoubleIndirect => {
-                    todo!("double indirect pointers")
-                }
-                BlockPointerType::TripleIndirect => {
-                    todo!("triple indirect pointers")
-                }
-            };
+        let block_size = guard.superblock.block_size as u64;
+
+        let start_block = offset / block_size;
+        // offset + len <= size and len > 0, so this can't overflow
+        let end_block = (offset + len as u64 - 1) / block_size + 1;
+        let relative_offset = (offset % block_size) as usize;
+        let block_count = usize::try_from(end_block - start_block).or(Err(Error::InvalidOffset))?;
+        let data_len = block_count
+            .checked_mul(block_size as usize)
+            .ok_or(Error::InvalidOffset)?;
+
+        // read blocks, blocks that are not allocated stay zeroed
+        let mut data: Vec<u8> = vec![0_u8; data_len];
+        for (i, block) in data.chunks_exact_mut(block_size as usize).enumerate() {
+            let read_block_index =
+                usize::try_from(start_block + i as u64).or(Err(Error::InvalidOffset))?;
+            let block_pointer = self.resolve_block_pointer(&guard, read_block_index)?;
+            if block_pointer == 0 {
+                continue;
+            }
             let block_address = guard.get_block_address(block_pointer);
-
-            let start_index = i * block_size;
-            let end_index = start_index + block_size;
-            guard
-                .device
-                .read_at(block_address, &mut &mut data[start_index..end_index])?;
+            guard.device.read_at(block_address, &mut &mut *block)?;
         }
-        buffer.copy_from_slice(&data[relative_offset..relative_offset + buffer.len()]);
+        buffer.copy_from_slice(&data[relative_offset..relative_offset + len]);
 
-        Ok(buffer.len())
+        Ok(len)
     }
 
     fn write_at(&mut self, _offset: u64, _buf: &dyn AsRef<[u8]>) -> Result<usize> {
@@ -1,6 +1,6 @@
 use alloc::collections::BTreeMap;
-use alloc::rc::Rc;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, Ordering};
@@ -8,6 +8,7 @@ use core::sync::atomic::{AtomicU64, Ordering};
 use kstd::sync::RwLock;
 
 use crate::io::fs::perm::Permission;
+use crate::io::fs::pipe::Fifo;
 use crate::io::fs::{CreateNodeType, Fs, IDir, IFile, INode, INodeBase, INodeNum, Stat};
 use kstd::io::{Error, Result};
 
@@ -17,7 +18,7 @@ pub struct MemFs {
     root: INode,
 }
 
-type InnerHandle = Rc<RwLock<Inner>>;
+type InnerHandle = Arc<RwLock<Inner>>;
 
 struct Inner {
     nodes: BTreeMap<INodeNum, INode>,
@@ -32,7 +33,7 @@ impl Inner {
 
 impl MemFs {
     pub fn new(root_node_name: String) -> Self {
-        let inner = Rc::new(RwLock::new(Inner {
+        let inner = Arc::new(RwLock::new(Inner {
             nodes: BTreeMap::new(),
             inode_counter: AtomicU64::new(1),
         }));
@@ -53,7 +54,7 @@ impl Fs for MemFs {
 }
 
 struct MemNodeBase {
-    fs: Rc<RwLock<Inner>>,
+    fs: Arc<RwLock<Inner>>,
     stat: Stat,
     name: String,
 }
@@ -214,6 +215,13 @@ impl IDir for MemDir {
                 let d = MemDir::new(self.base.fs.clone(), name, inode_num);
                 INode::new_dir(d)
             }
+            CreateNodeType::Fifo => INode::new_pipe(Fifo::new(
+                name,
+                Stat {
+                    inode: inode_num,
+                    ..Default::default()
+                },
+            )),
         };
         self.mount(inode.clone())?;
         Ok(inode)
@@ -1,11 +1,16 @@
+use alloc::alloc::Global;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::alloc::{AllocError, Allocator, Layout};
 use core::fmt::{Debug, Display, Formatter};
+use core::ptr::NonNull;
 
 use crate::io::fs::perm::Permission;
-use kstd::sync::RwLock;
+use crate::io::fs::pipe::{PipeReader, PipeWriter};
+use crate::memory::allocator::slab::{self, SlabCache};
+use kstd::sync::{Once, RwLock};
 
 use kstd::io::Result;
 use kstd::io::WriteAt;
@@ -18,7 +23,10 @@ pub mod ext2;
 pub mod flags;
 pub mod memfs;
 pub mod perm;
+pub mod pipe;
+pub mod procfs;
 pub mod rootdir;
+pub mod tmpfs;
 pub mod vfs;
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Default)]
@@ -62,13 +70,62 @@ pub struct Stat {
     pub ctime: u32,
     pub blksize: u32,
     pub blocks: u32,
+    pub permission: Permission,
 }
 
-pub type IBlockDeviceHandle = Arc<RwLock<dyn IBlockDeviceFile>>;
-pub type ICharacterDeviceHandle = Arc<RwLock<dyn ICharacterDeviceFile>>;
-pub type IDirHandle = Arc<RwLock<dyn IDir>>;
-pub type IFileHandle = Arc<RwLock<dyn IFile>>;
-pub type ISymlinkHandle = Arc<RwLock<dyn ISymlink>>;
+pub type IBlockDeviceHandle = Arc<RwLock<dyn IBlockDeviceFile>, &'static HandleCaches>;
+pub type ICharacterDeviceHandle = Arc<RwLock<dyn ICharacterDeviceFile>, &'static HandleCaches>;
+pub type IDirHandle = Arc<RwLock<dyn IDir>, &'static HandleCaches>;
+pub type IFileHandle = Arc<RwLock<dyn IFile>, &'static HandleCaches>;
+pub type IPipeHandle = Arc<RwLock<dyn IPipe>, &'static HandleCaches>;
+pub type ISymlinkHandle = Arc<RwLock<dyn ISymlink>, &'static HandleCaches>;
+
+static HANDLE_CACHES: HandleCaches = HandleCaches([
+    SlabCache::new("inode-handle-64", handle_layout(64), None),
+    SlabCache::new("inode-handle-128", handle_layout(128), None),
+    SlabCache::new("inode-handle-256", handle_layout(256), None),
+    SlabCache::new("inode-handle-512", handle_layout(512), None),
+]);
+static HANDLE_CACHES_INIT: Once = Once::new();
+
+const fn handle_layout(size: usize) -> Layout {
+    match Layout::from_size_align(size, 16) {
+        Ok(layout) => layout,
+        Err(_) => panic!("invalid handle layout"),
+    }
+}
+
+/// The allocator of the inode handles. Since the handles are trait objects of different
+/// sizes, they are allocated from the smallest cache that fits them, or from the heap
+/// if they are larger than all caches.
+pub struct HandleCaches([SlabCache; 4]);
+
+impl HandleCaches {
+    fn cache_for(&self, layout: Layout) -> Option<&SlabCache> {
+        self.0.iter().find(|cache| {
+            let object = cache.layout();
+            layout.size() <= object.size() && layout.align() <= object.align()
+        })
+    }
+}
+
+unsafe impl Allocator for HandleCaches {
+    fn allocate(&self, layout: Layout) -> core::result::Result<NonNull<[u8]>, AllocError> {
+        HANDLE_CACHES_INIT.call_once(|| HANDLE_CACHES.0.iter().for_each(slab::register));
+        match self.cache_for(layout) {
+            Some(cache) => cache.allocate(layout),
+            None => Global.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // the layout is the one of the allocation, so it selects the same cache
+        match self.cache_for(layout) {
+            Some(cache) => cache.deallocate(ptr, layout),
+            None => Global.deallocate(ptr, layout),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub enum INode {
@@ -76,6 +133,7 @@ pub enum INode {
     CharacterDevice(ICharacterDeviceHandle),
     Dir(IDirHandle),
     File(IFileHandle),
+    Pipe(IPipeHandle),
     Symlink(ISymlinkHandle),
 }
 
@@ -90,35 +148,42 @@ impl INode {
     where
         F: 'static + IFile,
     {
-        Self::File(Arc::new(RwLock::new(f)))
+        Self::File(Arc::new_in(RwLock::new(f), &HANDLE_CACHES))
     }
 
     pub fn new_block_device_file<F>(f: F) -> Self
     where
         F: 'static + IBlockDeviceFile,
     {
-        Self::BlockDevice(Arc::new(RwLock::new(f)))
+        Self::BlockDevice(Arc::new_in(RwLock::new(f), &HANDLE_CACHES))
     }
 
     pub fn new_character_device_file<F>(f: F) -> Self
     where
         F: 'static + ICharacterDeviceFile,
     {
-        Self::CharacterDevice(Arc::new(RwLock::new(f)))
+        Self::CharacterDevice(Arc::new_in(RwLock::new(f), &HANDLE_CACHES))
     }
 
     pub fn new_dir<D>(d: D) -> Self
     where
         D: 'static + IDir,
     {
-        Self::Dir(Arc::new(RwLock::new(d)))
+        Self::Dir(Arc::new_in(RwLock::new(d), &HANDLE_CACHES))
+    }
+
+    pub fn new_pipe<P>(p: P) -> Self
+    where
+        P: 'static + IPipe,
+    {
+        Self::Pipe(Arc::new_in(RwLock::new(p), &HANDLE_CACHES))
     }
 
     pub fn new_symlink<S>(s: S) -> Self
     where
         S: 'static + ISymlink,
     {
-        Self::Symlink(Arc::new(RwLock::new(s)))
+        Self::Symlink(Arc::new_in(RwLock::new(s), &HANDLE_CACHES))
     }
 
     pub fn as_file(&self) -> Option<IFileHandle> {
@@ -149,6 +214,13 @@ impl INode {
         }
     }
 
+    pub fn as_pipe(&self) -> Option<IPipeHandle> {
+        match self {
+            INode::Pipe(p) => Some(p.clone()),
+            _ => None,
+        }
+    }
+
     pub fn as_symlink(&self) -> Option<ISymlinkHandle> {
         match self {
             INode::Symlink(d) => Some(d.clone()),
@@ -172,6 +244,10 @@ impl INode {
         matches!(self, INode::CharacterDevice(_))
     }
 
+    pub fn is_pipe(&self) -> bool {
+        matches!(self, INode::Pipe(_))
+    }
+
     pub fn is_symlink(&self) -> bool {
         matches!(self, INode::Symlink(_))
     }
@@ -193,6 +269,7 @@ impl Debug for INode {
                     INode::Dir(_) => "Dir",
                     INode::BlockDevice(_) => "BlockDevice",
                     INode::CharacterDevice(_) => "CharacterDevice",
+                    INode::Pipe(_) => "Pipe",
                     INode::Symlink(_) => "Symlink",
                 },
             )
@@ -209,6 +286,7 @@ impl INodeBase for INode {
             INode::Dir(dir) => dir.read().num(),
             INode::BlockDevice(dev) => dev.read().num(),
             INode::CharacterDevice(dev) => dev.read().num(),
+            INode::Pipe(pipe) => pipe.read().num(),
             INode::Symlink(symlink) => symlink.read().num(),
         }
     }
@@ -219,6 +297,7 @@ impl INodeBase for INode {
             INode::Dir(dir) => dir.read().name(),
             INode::BlockDevice(dev) => dev.read().name(),
             INode::CharacterDevice(dev) => dev.read().name(),
+            INode::Pipe(pipe) => pipe.read().name(),
             INode::Symlink(symlink) => symlink.read().name(),
         }
     }
@@ -229,6 +308,7 @@ impl INodeBase for INode {
             INode::Dir(dir) => dir.read().stat(),
             INode::BlockDevice(dev) => dev.read().stat(),
             INode::CharacterDevice(dev) => dev.read().stat(),
+            INode::Pipe(pipe) => pipe.read().stat(),
             INode::Symlink(symlink) => symlink.read().stat(),
         }
     }
@@ -243,13 +323,25 @@ pub trait IBlockDeviceFile: INodeBase {
 
     fn read_at(&self, offset: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize>;
 
+    fn write_block(&mut self, block: u64, buf: &dyn AsRef<[u8]>) -> Result<()>;
+
+    /// Writes the given buffer at the given byte offset. Blocks that are
+    /// only partially written are read, modified and written back.
     fn write_at(&mut self, offset: u64, buf: &dyn AsRef<[u8]>) -> Result<usize>;
+
+    /// Writes any data that is buffered by the device back to the underlying storage.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
+/// A device that is read and written as a stream of bytes. Both reads and writes
+/// only need a shared reference, so that a read that waits for data, like on a
+/// terminal or a socket, doesn't block writes to the same device.
 pub trait ICharacterDeviceFile: INodeBase {
     fn read_at(&self, offset: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize>;
 
-    fn write_at(&mut self, offset: u64, buf: &dyn AsRef<[u8]>) -> Result<usize>;
+    fn write_at(&self, offset: u64, buf: &dyn AsRef<[u8]>) -> Result<usize>;
 }
 
 pub trait IFile: INodeBase {
@@ -304,6 +396,8 @@ impl WriteAt<u8> for dyn IFile {
 pub enum CreateNodeType {
     File,
     Dir,
+    /// A named pipe, see [`pipe::Fifo`].
+    Fifo,
 }
 
 pub trait IDir: INodeBase {
@@ -326,6 +420,20 @@ pub trait IDir: INodeBase {
     /// means, that the given [`INode`] does not necessarily belong to the same file
     /// system or device as this node.
     fn mount(&mut self, node: INode) -> Result<()>;
+
+    /// Removes a previously mounted [`INode`] from the children of this dir and
+    /// returns it. Dirs that don't support unmounting return [`Error::NotImplemented`].
+    fn unmount(&mut self, _name: &dyn AsRef<str>) -> Result<INode> {
+        Err(Error::NotImplemented)
+    }
+}
+
+pub trait IPipe: INodeBase {
+    /// Opens a new read end of the pipe.
+    fn open_read(&self) -> PipeReader;
+
+    /// Opens a new write end of the pipe.
+    fn open_write(&self) -> PipeWriter;
 }
 
 pub trait ISymlink: INodeBase {
@@ -0,0 +1,357 @@
+//! Pipes, through which tasks can stream data to each other.
+//!
+//! A pipe has a bounded buffer of [`PIPE_CAPACITY`] bytes. Reading from an empty
+//! pipe waits until data was written, and writing to a full pipe waits until data
+//! was read. Once all writers are dropped, readers drain the remaining data and then
+//! read 0 bytes. Anonymous pipes are created with [`pipe`], named ones are [`Fifo`]s,
+//! which can be created in a dir with [`CreateNodeType::Fifo`](crate::io::fs::CreateNodeType::Fifo).
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::task::{Context, Poll, Waker};
+
+use kstd::io::{Error, Result};
+use kstd::sync::Mutex;
+
+use crate::io::fs::{INodeBase, INodeNum, IPipe, Stat};
+use crate::scheduler::Scheduler;
+
+/// The amount of bytes that a pipe can hold before writers have to wait.
+pub const PIPE_CAPACITY: usize = 4096;
+
+struct State {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    /// Whether a writer was ever opened. Until then, an empty pipe is not at its end,
+    /// so that readers of a [`Fifo`] can be opened before the writers.
+    had_writer: bool,
+    /// Whether a reader was ever opened. Writes only fail if all readers are gone.
+    had_reader: bool,
+    /// Wakers of futures that wait for the pipe to change.
+    wakers: Vec<Waker>,
+}
+
+impl State {
+    fn wake_all(&mut self) {
+        self.wakers.drain(..).for_each(Waker::wake);
+    }
+
+    fn try_read(&mut self, buf: &mut [u8]) -> Option<Result<usize>> {
+        if buf.is_empty() {
+            return Some(Ok(0));
+        }
+        if self.buffer.is_empty() {
+            return if self.had_writer && self.writers == 0 {
+                Some(Ok(0))
+            } else {
+                None
+            };
+        }
+        let len = buf.len().min(self.buffer.len());
+        for (dst, src) in buf.iter_mut().zip(self.buffer.drain(..len)) {
+            *dst = src;
+        }
+        self.wake_all();
+        Some(Ok(len))
+    }
+
+    fn try_write(&mut self, buf: &[u8]) -> Option<Result<usize>> {
+        if self.had_reader && self.readers == 0 {
+            return Some(Err(Error::PrematureEndOfInput));
+        }
+        if buf.is_empty() {
+            return Some(Ok(0));
+        }
+        let len = buf.len().min(PIPE_CAPACITY - self.buffer.len());
+        if len == 0 {
+            return None;
+        }
+        self.buffer.extend(&buf[..len]);
+        self.wake_all();
+        Some(Ok(len))
+    }
+}
+
+#[derive(Clone)]
+struct Pipe(Arc<Mutex<State>>);
+
+impl Pipe {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(State {
+            buffer: VecDeque::with_capacity(PIPE_CAPACITY),
+            readers: 0,
+            writers: 0,
+            had_writer: false,
+            had_reader: false,
+            wakers: Vec::new(),
+        })))
+    }
+
+    fn reader(&self) -> PipeReader {
+        let mut state = self.0.lock();
+        state.readers += 1;
+        state.had_reader = true;
+        state.wake_all();
+        PipeReader { pipe: self.clone() }
+    }
+
+    fn writer(&self) -> PipeWriter {
+        let mut state = self.0.lock();
+        state.writers += 1;
+        state.had_writer = true;
+        state.wake_all();
+        PipeWriter { pipe: self.clone() }
+    }
+
+    /// Calls `f` until it returns `Some`, and lets other scheduler tasks run in between.
+    fn wait<T, F>(&self, mut f: F) -> T
+    where
+        F: FnMut(&mut State) -> Option<T>,
+    {
+        loop {
+            if let Some(result) = f(&mut self.0.lock()) {
+                return result;
+            }
+            Scheduler::reschedule();
+        }
+    }
+
+    fn poll<T, F>(&self, cx: &mut Context<'_>, f: F) -> Poll<T>
+    where
+        F: FnOnce(&mut State) -> Option<T>,
+    {
+        let mut state = self.0.lock();
+        match f(&mut state) {
+            Some(result) => Poll::Ready(result),
+            None => {
+                if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                    state.wakers.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Creates an anonymous pipe and returns its read and write end.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Pipe::new();
+    (pipe.reader(), pipe.writer())
+}
+
+/// The read end of a pipe.
+pub struct PipeReader {
+    pipe: Pipe,
+}
+
+impl PipeReader {
+    /// Waits for data and reads it into the given buffer. Returns 0 if all writers
+    /// were closed and all data was read.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        self.pipe.wait(|state| state.try_read(buf))
+    }
+
+    /// Like [`PipeReader::read`], but waits asynchronously.
+    pub async fn read_async(&self, buf: &mut [u8]) -> Result<usize> {
+        core::future::poll_fn(|cx| self.pipe.poll(cx, |state| state.try_read(buf))).await
+    }
+
+    /// The amount of bytes that can be read without waiting.
+    pub fn available(&self) -> usize {
+        self.pipe.0.lock().buffer.len()
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut state = self.pipe.0.lock();
+        state.readers -= 1;
+        state.wake_all();
+    }
+}
+
+/// The write end of a pipe.
+pub struct PipeWriter {
+    pipe: Pipe,
+}
+
+impl PipeWriter {
+    /// Writes as much of the given data as fits into the pipe, and waits if nothing
+    /// fits. Returns the number of bytes written, which may be less than the length
+    /// of the data. Fails with [`Error::PrematureEndOfInput`] if all readers were closed.
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.pipe.wait(|state| state.try_write(buf))
+    }
+
+    /// Like [`PipeWriter::write`], but waits asynchronously.
+    pub async fn write_async(&self, buf: &[u8]) -> Result<usize> {
+        core::future::poll_fn(|cx| self.pipe.poll(cx, |state| state.try_write(buf))).await
+    }
+
+    /// Writes all of the given data, waiting for readers to make space as needed.
+    pub fn write_all(&self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let written = self.write(buf)?;
+            buf = &buf[written..];
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let mut state = self.pipe.0.lock();
+        state.writers -= 1;
+        state.wake_all();
+    }
+}
+
+/// A named pipe. All readers and writers that are opened on the same fifo share
+/// one pipe.
+pub struct Fifo {
+    name: String,
+    stat: Stat,
+    pipe: Pipe,
+}
+
+impl Fifo {
+    pub fn new(name: String, stat: Stat) -> Self {
+        Self {
+            name,
+            stat,
+            pipe: Pipe::new(),
+        }
+    }
+}
+
+impl INodeBase for Fifo {
+    fn num(&self) -> INodeNum {
+        self.stat.inode
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            size: self.pipe.0.lock().buffer.len() as u64,
+            ..self.stat
+        }
+    }
+}
+
+impl IPipe for Fifo {
+    fn open_read(&self) -> PipeReader {
+        self.pipe.reader()
+    }
+
+    fn open_write(&self) -> PipeWriter {
+        self.pipe.writer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use crate::io::fs::perm::Permission;
+    use crate::io::fs::tmpfs::TmpFs;
+    use crate::io::fs::{CreateNodeType, Fs};
+    use crate::task::simple_executor::SimpleExecutor;
+    use crate::task::Task;
+
+    use super::*;
+
+    #[test_case]
+    fn test_pipe_read_write() {
+        let (reader, writer) = pipe();
+        assert_eq!(Ok(5), writer.write(b"hello"));
+        assert_eq!(5, reader.available());
+
+        let mut buf = [0_u8; 3];
+        assert_eq!(Ok(3), reader.read(&mut buf));
+        assert_eq!(b"hel", &buf);
+        assert_eq!(Ok(2), reader.read(&mut buf));
+        assert_eq!(b"lo", &buf[..2]);
+    }
+
+    #[test_case]
+    fn test_pipe_eof_on_writer_close() {
+        let (reader, writer) = pipe();
+        writer.write_all(b"abc").unwrap();
+        drop(writer);
+
+        let mut buf = [0_u8; 8];
+        assert_eq!(Ok(3), reader.read(&mut buf));
+        assert_eq!(Ok(0), reader.read(&mut buf));
+    }
+
+    #[test_case]
+    fn test_pipe_broken() {
+        let (reader, writer) = pipe();
+        drop(reader);
+        assert_eq!(Err(Error::PrematureEndOfInput), writer.write(b"abc"));
+    }
+
+    #[test_case]
+    fn test_pipe_capacity() {
+        let (reader, writer) = pipe();
+        let data = vec![7_u8; PIPE_CAPACITY + 10];
+        assert_eq!(Ok(PIPE_CAPACITY), writer.write(&data));
+        assert_eq!(PIPE_CAPACITY, reader.available());
+    }
+
+    #[test_case]
+    fn test_pipe_async() {
+        let (reader, writer) = pipe();
+        let mut executor = SimpleExecutor::default();
+        executor.spawn(Task::new(async move {
+            let mut received = Vec::new();
+            let mut buf = [0_u8; 64];
+            loop {
+                match reader.read_async(&mut buf).await.unwrap() {
+                    0 => break,
+                    n => received.extend_from_slice(&buf[..n]),
+                }
+            }
+            assert_eq!(PIPE_CAPACITY * 2, received.len());
+        }));
+        executor.spawn(Task::new(async move {
+            let data = vec![1_u8; PIPE_CAPACITY * 2];
+            let mut written = 0;
+            while written < data.len() {
+                written += writer.write_async(&data[written..]).await.unwrap();
+            }
+        }));
+        executor.run();
+    }
+
+    #[test_case]
+    fn test_fifo() {
+        let fs = TmpFs::new("tmp".into(), 0);
+        let node = fs
+            .root_inode()
+            .as_dir()
+            .unwrap()
+            .write()
+            .create(&"fifo", CreateNodeType::Fifo, Permission::user_rwx())
+            .unwrap();
+        assert!(node.is_pipe());
+        let fifo = node.as_pipe().unwrap();
+
+        let reader = fifo.read().open_read();
+        let writer = fifo.read().open_write();
+        writer.write_all(b"named").unwrap();
+        assert_eq!(5, node.stat().size);
+        drop(writer);
+
+        let mut buf = [0_u8; 8];
+        assert_eq!(Ok(5), reader.read(&mut buf));
+        assert_eq!(b"named", &buf[..5]);
+        assert_eq!(Ok(0), reader.read(&mut buf));
+    }
+}
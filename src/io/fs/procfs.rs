@@ -0,0 +1,130 @@
+//! A file system of read-only files whose content is generated when they are read,
+//! like `/proc/meminfo`.
+
+use alloc::string::String;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::io::fs::rootdir::RootDir;
+use crate::io::fs::{Fs, IDir, IFile, INode, INodeBase, INodeNum, Stat};
+use crate::memory::info;
+use kstd::io::{Error, Result};
+
+pub struct ProcFs {
+    root: INode,
+}
+
+impl ProcFs {
+    pub fn new(root_node_name: String) -> Self {
+        let cnt = AtomicU64::new(0);
+        let next = || INodeNum::from(cnt.fetch_add(1, Ordering::SeqCst));
+
+        let mut root = RootDir::new(
+            root_node_name,
+            Stat {
+                inode: next(),
+                ..Default::default()
+            },
+        );
+        root.mount(INode::new_file(ProcFile::new(
+            next(),
+            "meminfo",
+            info::meminfo,
+        )))
+        .unwrap();
+        root.mount(INode::new_file(ProcFile::new(
+            next(),
+            "memmap",
+            info::memmap,
+        )))
+        .unwrap();
+
+        Self {
+            root: INode::new_dir(root),
+        }
+    }
+}
+
+impl Fs for ProcFs {
+    fn root_inode(&self) -> INode {
+        self.root.clone()
+    }
+}
+
+/// A file whose content is generated by a function every time that it is accessed.
+pub struct ProcFile {
+    name: &'static str,
+    stat: Stat,
+    generate: fn() -> String,
+}
+
+impl ProcFile {
+    pub fn new(inode_num: INodeNum, name: &'static str, generate: fn() -> String) -> Self {
+        Self {
+            name,
+            stat: Stat {
+                inode: inode_num,
+                ..Default::default()
+            },
+            generate,
+        }
+    }
+}
+
+impl INodeBase for ProcFile {
+    fn num(&self) -> INodeNum {
+        self.stat.inode
+    }
+
+    fn name(&self) -> String {
+        self.name.into()
+    }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            size: self.size(),
+            ..self.stat
+        }
+    }
+}
+
+impl IFile for ProcFile {
+    fn size(&self) -> u64 {
+        (self.generate)().len() as u64
+    }
+
+    fn truncate(&mut self, _: u64) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
+        let content = (self.generate)();
+        let content = content.as_bytes();
+        let start = (offset as usize).min(content.len());
+        let buffer = buf.as_mut();
+        let len = buffer.len().min(content.len() - start);
+        buffer[..len].copy_from_slice(&content[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&mut self, _: u64, _: &dyn AsRef<[u8]>) -> Result<usize> {
+        Err(Error::NotImplemented)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::*;
+
+    #[test_case]
+    fn test_proc_file_reads_generated_content() {
+        let file = ProcFile::new(INodeNum::from(0_u64), "test", || "0123456789".to_string());
+        assert_eq!(10, file.size());
+        let mut buf = vec![0_u8; 4];
+        assert_eq!(Ok(2), file.read_at(8, &mut buf));
+        assert_eq!(b"89", &buf[..2]);
+        assert_eq!(Ok(0), file.read_at(12, &mut buf));
+    }
+}
@@ -68,4 +68,8 @@ impl IDir for RootDir {
         self.children.insert(node.name(), node);
         Ok(())
     }
+
+    fn unmount(&mut self, name: &dyn AsRef<str>) -> Result<INode> {
+        self.children.remove(name.as_ref()).ok_or(Error::NotFound)
+    }
 }
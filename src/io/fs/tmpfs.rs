@@ -0,0 +1,376 @@
+//! A file system that keeps all of its data in memory, similar to [`MemFs`](crate::io::fs::memfs::MemFs),
+//! but with a limit on the amount of file data that it can hold, as well as
+//! permissions and timestamps for every node.
+//!
+//! In contrast to [`MemFs`](crate::io::fs::memfs::MemFs), files grow when
+//! written beyond their end.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use kstd::io::{Error, Result};
+use kstd::sync::RwLock;
+
+use crate::io::fs::perm::Permission;
+use crate::io::fs::pipe::Fifo;
+use crate::io::fs::{CreateNodeType, Fs, IDir, IFile, INode, INodeBase, INodeNum, Stat};
+use crate::time;
+
+pub struct TmpFs {
+    inner: InnerHandle,
+    root: INode,
+}
+
+type InnerHandle = Arc<Inner>;
+
+struct Inner {
+    nodes: RwLock<BTreeMap<INodeNum, INode>>,
+    inode_counter: AtomicU64,
+    /// The maximum amount of bytes that all files in this fs may hold together.
+    size_limit: u64,
+    used: AtomicU64,
+}
+
+impl Inner {
+    fn get_unused_inode_num(&self) -> INodeNum {
+        self.inode_counter.fetch_add(1, Ordering::SeqCst).into()
+    }
+
+    /// Accounts for `additional` bytes of file data, or fails with [`Error::InvalidArgument`]
+    /// if that would exceed the size limit of the fs.
+    fn reserve(&self, additional: u64) -> Result<()> {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(additional)
+                    .filter(|&new_used| new_used <= self.size_limit)
+            })
+            .map(|_| ())
+            .map_err(|_| Error::InvalidArgument)
+    }
+
+    fn release(&self, amount: u64) {
+        self.used.fetch_sub(amount, Ordering::SeqCst);
+    }
+}
+
+impl TmpFs {
+    pub fn new(root_node_name: String, size_limit: u64) -> Self {
+        let inner = Arc::new(Inner {
+            nodes: RwLock::new(BTreeMap::new()),
+            inode_counter: AtomicU64::new(1),
+            size_limit,
+            used: AtomicU64::new(0),
+        });
+
+        let root_inode_num = 0_u64.into();
+        let root_dir = TmpDir::new(
+            inner.clone(),
+            root_node_name,
+            root_inode_num,
+            Permission::user_rwx() | Permission::group_rwx() | Permission::other_rwx(),
+        );
+        let root = INode::new_dir(root_dir);
+        inner.nodes.write().insert(root_inode_num, root.clone());
+
+        Self { inner, root }
+    }
+
+    /// The amount of file data in bytes that is currently stored in this fs.
+    pub fn used(&self) -> u64 {
+        self.inner.used.load(Ordering::SeqCst)
+    }
+
+    pub fn size_limit(&self) -> u64 {
+        self.inner.size_limit
+    }
+}
+
+impl Fs for TmpFs {
+    fn root_inode(&self) -> INode {
+        self.root.clone()
+    }
+}
+
+struct TmpNodeBase {
+    fs: InnerHandle,
+    stat: Stat,
+    name: String,
+}
+
+impl TmpNodeBase {
+    fn new(fs: InnerHandle, name: String, inode_num: INodeNum, permission: Permission) -> Self {
+        let now = time::unix_timestamp() as u32;
+        Self {
+            fs,
+            name,
+            stat: Stat {
+                inode: inode_num,
+                nlink: 1,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                permission,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn touch(&mut self) {
+        let now = time::unix_timestamp() as u32;
+        self.stat.atime = now;
+        self.stat.mtime = now;
+    }
+}
+
+impl INodeBase for TmpNodeBase {
+    fn num(&self) -> INodeNum {
+        self.stat.inode
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn stat(&self) -> Stat {
+        self.stat
+    }
+}
+
+pub struct TmpFile {
+    base: TmpNodeBase,
+    data: Vec<u8>,
+}
+
+impl TmpFile {
+    fn new(fs: InnerHandle, name: String, inode_num: INodeNum, permission: Permission) -> Self {
+        Self {
+            base: TmpNodeBase::new(fs, name, inode_num, permission),
+            data: Vec::new(),
+        }
+    }
+
+    fn resize(&mut self, new_size: usize) -> Result<()> {
+        let old_size = self.data.len();
+        if new_size > old_size {
+            self.base.fs.reserve((new_size - old_size) as u64)?;
+        } else {
+            self.base.fs.release((old_size - new_size) as u64);
+        }
+        self.data.resize(new_size, 0);
+        self.base.stat.size = new_size as u64;
+        Ok(())
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        self.base.fs.release(self.data.len() as u64);
+    }
+}
+
+impl INodeBase for TmpFile {
+    fn num(&self) -> INodeNum {
+        self.base.num()
+    }
+
+    fn name(&self) -> String {
+        self.base.name()
+    }
+
+    fn stat(&self) -> Stat {
+        self.base.stat()
+    }
+}
+
+impl IFile for TmpFile {
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<()> {
+        let new_size = TryInto::<usize>::try_into(size).unwrap(); // u64 -> usize is valid on x86_64
+        self.resize(new_size)?;
+        self.base.touch();
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
+        let buffer = buf.as_mut();
+        let offset = offset as usize;
+        if offset > self.data.len() {
+            return Err(Error::InvalidOffset);
+        }
+        let length = buffer.len().min(self.data.len() - offset);
+        buffer[..length].copy_from_slice(&self.data[offset..offset + length]);
+        Ok(length)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
+        let buffer = buf.as_ref();
+        let offset = offset as usize;
+        let end = offset + buffer.len();
+        if end > self.data.len() {
+            self.resize(end)?;
+        }
+        self.data[offset..end].copy_from_slice(buffer);
+        self.base.touch();
+        Ok(buffer.len())
+    }
+}
+
+pub struct TmpDir {
+    base: TmpNodeBase,
+    children: Vec<INodeNum>,
+}
+
+impl TmpDir {
+    fn new(fs: InnerHandle, name: String, inode_num: INodeNum, permission: Permission) -> Self {
+        Self {
+            base: TmpNodeBase::new(fs, name, inode_num, permission),
+            children: Vec::new(),
+        }
+    }
+}
+
+impl INodeBase for TmpDir {
+    fn num(&self) -> INodeNum {
+        self.base.num()
+    }
+
+    fn name(&self) -> String {
+        self.base.name()
+    }
+
+    fn stat(&self) -> Stat {
+        self.base.stat()
+    }
+}
+
+impl IDir for TmpDir {
+    fn lookup(&self, name: &dyn AsRef<str>) -> Result<INode> {
+        let needle = name.as_ref();
+        let guard = self.base.fs.nodes.read();
+        match self
+            .children
+            .iter()
+            .filter_map(|n| guard.get(n))
+            .find(|n| n.name() == needle)
+        {
+            None => Err(Error::NotFound),
+            Some(n) => Ok(n.clone()),
+        }
+    }
+
+    fn create(
+        &mut self,
+        name: &dyn AsRef<str>,
+        typ: CreateNodeType,
+        permission: Permission,
+    ) -> Result<INode> {
+        if self.lookup(name).is_ok() {
+            return Err(Error::ExistsButShouldNot);
+        }
+
+        let name = name.as_ref().to_string();
+        let inode_num = self.base.fs.get_unused_inode_num();
+        let inode = match typ {
+            CreateNodeType::File => {
+                let f = TmpFile::new(self.base.fs.clone(), name, inode_num, permission);
+                INode::new_file(f)
+            }
+            CreateNodeType::Dir => {
+                let d = TmpDir::new(self.base.fs.clone(), name, inode_num, permission);
+                INode::new_dir(d)
+            }
+            CreateNodeType::Fifo => {
+                let base = TmpNodeBase::new(self.base.fs.clone(), name, inode_num, permission);
+                INode::new_pipe(Fifo::new(base.name, base.stat))
+            }
+        };
+        self.mount(inode.clone())?;
+        Ok(inode)
+    }
+
+    fn children(&self) -> Result<Vec<INode>> {
+        let guard = self.base.fs.nodes.read();
+        Ok(self
+            .children
+            .iter()
+            .filter_map(|n| guard.get(n))
+            .cloned()
+            .collect())
+    }
+
+    fn mount(&mut self, node: INode) -> Result<()> {
+        let inode_num = node.num();
+        self.base.fs.nodes.write().insert(inode_num, node);
+        self.children.push(inode_num);
+        self.base.touch();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn create_file(fs: &TmpFs, name: &str) -> INode {
+        fs.root_inode()
+            .as_dir()
+            .expect("root must be a dir")
+            .write()
+            .create(&name, CreateNodeType::File, Permission::user_rwx())
+            .expect("creating a file should not fail")
+    }
+
+    #[test_case]
+    fn test_write_grows_file() {
+        let fs = TmpFs::new("tmp".into(), 1024);
+        let file = create_file(&fs, "file.txt").as_file().unwrap();
+
+        let data = "Hello, World!";
+        file.write().write_at(2, &data).unwrap();
+        assert_eq!(data.len() as u64 + 2, file.read().size());
+        assert_eq!(data.len() as u64 + 2, fs.used());
+
+        let mut buffer = vec![0_u8; 32];
+        let read = file.read().read_at(2, &mut buffer).unwrap();
+        assert_eq!(data.as_bytes(), &buffer[..read]);
+    }
+
+    #[test_case]
+    fn test_size_limit() {
+        let fs = TmpFs::new("tmp".into(), 16);
+        let first = create_file(&fs, "first").as_file().unwrap();
+        let second = create_file(&fs, "second").as_file().unwrap();
+
+        first.write().truncate(10).unwrap();
+        assert_eq!(Err(Error::InvalidArgument), second.write().truncate(7));
+        assert_eq!(0, second.read().size());
+
+        first.write().truncate(4).unwrap();
+        second.write().truncate(7).unwrap();
+        assert_eq!(11, fs.used());
+    }
+
+    #[test_case]
+    fn test_create_stores_permission() {
+        let fs = TmpFs::new("tmp".into(), 16);
+        let file = create_file(&fs, "file.txt");
+        assert_eq!(Permission::user_rwx(), file.stat().permission);
+        assert_eq!(file.stat().ctime, file.stat().mtime);
+
+        assert!(fs
+            .root_inode()
+            .as_dir()
+            .unwrap()
+            .write()
+            .create(&"file.txt", CreateNodeType::Dir, Permission::user_rwx())
+            .is_err());
+    }
+}
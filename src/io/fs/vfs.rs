@@ -9,7 +9,9 @@ use alloc::vec::Vec;
 use crate::io::fs::rootdir::RootDir;
 #[cfg(debug_assertions)]
 use crate::io::fs::INodeBase;
-use crate::io::fs::{IBlockDeviceHandle, ICharacterDeviceHandle, IFileHandle, INode, Stat};
+use crate::io::fs::{
+    IBlockDeviceHandle, ICharacterDeviceHandle, IFileHandle, INode, IPipeHandle, Stat,
+};
 use crate::{debug, info};
 
 static mut VFS: Option<Mutex<Vfs>> = None;
@@ -32,6 +34,13 @@ pub fn mount(p: &dyn AsRef<Path>, node: INode) -> Result<()> {
     get_vfs().lock().mount(p, node)
 }
 
+/// Removes the child with the given name from the directory at the given path, and
+/// returns the removed node.
+pub fn unmount(p: &dyn AsRef<Path>, name: &dyn AsRef<str>) -> Result<INode> {
+    debug!("unmounting inode '{}' from '{}'", name.as_ref(), p.as_ref());
+    get_vfs().lock().unmount(p, name)
+}
+
 /// Locates the given node and attempts to read it as regular file.
 /// Will return an error if the node is not a regular file.
 pub fn read_file_node(p: &dyn AsRef<Path>) -> Result<Vec<u8>> {
@@ -57,6 +66,7 @@ pub enum OpenResult {
     File(IFileHandle),
     BlockDevice(IBlockDeviceHandle),
     CharacterDevice(ICharacterDeviceHandle),
+    Pipe(IPipeHandle),
 }
 
 /// Attempts to open the given path and return the result as a handle.
@@ -69,6 +79,7 @@ pub fn open(p: &dyn AsRef<Path>) -> Result<OpenResult> {
         INode::Dir(_) => Err(Error::IsDir),
         INode::BlockDevice(f) => Ok(OpenResult::BlockDevice(f)),
         INode::CharacterDevice(f) => Ok(OpenResult::CharacterDevice(f)),
+        INode::Pipe(p) => Ok(OpenResult::Pipe(p)),
         INode::Symlink(symlink) => {
             let guard = symlink.read();
             let target = guard.target_path()?;
@@ -101,6 +112,7 @@ impl Vfs {
             INode::Dir(_) => Err(Error::IsDir),
             INode::BlockDevice(_) => Err(Error::InvalidArgument),
             INode::CharacterDevice(_) => Err(Error::InvalidArgument),
+            INode::Pipe(_) => Err(Error::InvalidArgument),
             INode::Symlink(link) => {
                 let guard = link.read();
                 let target = guard.target_path()?;
@@ -131,6 +143,7 @@ impl Vfs {
                 }
             }
             INode::File(_) => {}
+            INode::Pipe(_) => {}
             INode::Symlink(_) => {} // don't follow symlinks
         }
         Ok(())
@@ -146,6 +159,7 @@ impl Vfs {
             INode::Dir(d) => d,
             INode::BlockDevice(_) => return Err(Error::IsFile),
             INode::CharacterDevice(_) => return Err(Error::IsFile),
+            INode::Pipe(_) => return Err(Error::IsFile),
             INode::Symlink(link) => {
                 let guard = link.read();
                 let target_path = guard.target_path()?;
@@ -161,6 +175,15 @@ impl Vfs {
         guard.mount(node)
     }
 
+    fn unmount(&mut self, p: &dyn AsRef<Path>, name: &dyn AsRef<str>) -> Result<INode> {
+        let dir = match self.find_inode(p)? {
+            INode::Dir(d) => d,
+            _ => return Err(Error::NotFound),
+        };
+        let mut guard = dir.write();
+        guard.unmount(name)
+    }
+
     fn find_inode_from(p: &dyn AsRef<Path>, starting_point: INode) -> Result<INode> {
         let path = p.as_ref().to_owned(); // OwnedPaths are always canonical
         let components = path.components();
@@ -185,6 +208,7 @@ impl Vfs {
                         INode::Dir(d) => d,
                         INode::BlockDevice(_) => return Err(Error::NotFound),
                         INode::CharacterDevice(_) => return Err(Error::NotFound),
+                        INode::Pipe(_) => return Err(Error::NotFound),
                         INode::Symlink(link) => {
                             let guard = link.read();
                             let target_path = guard.target_path()?;
@@ -2,6 +2,7 @@
 #![cfg_attr(test, no_main)]
 #![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
+#![feature(allocator_api)]
 #![feature(const_mut_refs)]
 #![feature(custom_test_frameworks)]
 #![feature(error_in_core)]
@@ -16,6 +17,7 @@ extern crate alloc;
 
 use bootloader::BootInfo;
 use core::panic::PanicInfo;
+use x86_64::PhysAddr;
 
 #[cfg(test)]
 use bootloader::entry_point;
@@ -26,17 +28,22 @@ pub use syscall::Result;
 use crate::driver::Peripherals;
 use crate::io::fs::vfs;
 
+pub mod crash;
 pub mod driver;
+pub mod elf;
 pub mod gdt;
 pub mod interrupts;
 pub mod io;
 pub mod macros;
 pub mod memory;
+pub mod net;
 pub mod scheduler;
 pub mod serial;
+pub mod shell;
 pub mod syscall;
 pub mod task;
 pub mod time;
+pub mod tty;
 pub mod vfs_setup;
 pub mod vga_buffer;
 
@@ -48,7 +55,13 @@ pub fn kernel_init(boot_info: &'static mut BootInfo) {
     x86_64::instructions::interrupts::enable();
 
     // high level
+    let rsdp_addr = boot_info.rsdp_addr.as_ref().copied();
     memory::init_memory(boot_info);
+    serial::init_interrupts();
+    interrupts::lapic::init();
+    if let Some(rsdp_addr) = rsdp_addr {
+        driver::acpi::init(PhysAddr::new(rsdp_addr));
+    }
     scheduler::init();
     let _ = Peripherals::boot_time(); // initialize boot time
     vfs::init();
@@ -69,6 +82,8 @@ pub enum QemuExitCode {
 pub fn exit_qemu(exit_code: QemuExitCode) {
     use x86_64::instructions::port::Port;
 
+    // output that is still queued would be lost
+    serial::flush();
     unsafe {
         let mut port = Port::new(0xf4);
         port.write(exit_code as u32);
@@ -107,6 +122,7 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[\u{001b}[31mfailed\u{001b}[0m]\n");
     serial_println!("\u{001b}[1m\u{001b}[31merror\u{001b}[0m: {}\n", info);
+    crash::report(info);
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
@@ -9,16 +9,12 @@ extern crate alloc;
 use core::panic::PanicInfo;
 
 use bootloader::{entry_point, BootInfo};
-use goblin::elf::header::ET_EXEC;
-use goblin::elf::Elf;
-use x86_64::instructions::hlt;
 
 use martim::driver::Peripherals;
-use martim::io::fs::vfs;
-use martim::memory::kbuffer::KBuffer;
 use martim::scheduler::Scheduler;
 use martim::vfs_setup::init_vfs;
 use martim::{debug, hlt_loop, info, kernel_init};
+use martim::{serial, shell};
 use martim::{
     serial_print, serial_println,
     task::{executor::Executor, keyboard, Task},
@@ -29,16 +25,20 @@ use martim::{
 #[cfg(not(test))]
 #[panic_handler]
 fn panic_handler(info: &PanicInfo) -> ! {
-    martim::info!(
-        "terminating task {}: {}",
-        Scheduler::get_current_tid(),
-        info
-    );
-
     if Scheduler::get_current_tid().as_usize() == 0 {
+        martim::crash::report(info);
         serial_println!("kernel task panicked, halting...");
         hlt_loop()
     } else {
+        // the shell keeps running, so the screen is left alone
+        martim::crash::report_to_serial(info);
+        martim::info!(
+            "terminating task {}: {}",
+            Scheduler::get_current_tid(),
+            info
+        );
+        // the report disabled interrupts, which the scheduler needs
+        x86_64::instructions::interrupts::enable();
         Scheduler::exit()
     }
 }
@@ -95,9 +95,9 @@ fn main() {
     );
 
     Scheduler::spawn_from_c_fn(init_vfs).unwrap();
-    Scheduler::spawn_from_c_fn(just_panic).unwrap();
-    Scheduler::spawn_from_c_fn(elf_stuff).unwrap();
-    Scheduler::spawn_from_c_fn(example_tasks).unwrap();
+    Scheduler::spawn_from_c_fn(input_task).unwrap();
+    Scheduler::spawn_from_c_fn(shell::console_shell).unwrap();
+    Scheduler::spawn_from_c_fn(shell::serial_shell).unwrap();
 
     debug!(
         "kernel task with tid {} is still running",
@@ -105,65 +105,10 @@ fn main() {
     );
 }
 
-extern "C" fn elf_stuff() {
-    let path = "/mnt/block_device0/executables/hello_world";
-    // wait for vfs to be initialized
-    let content = loop {
-        if let Ok(content) = vfs::read_file_node(&path) {
-            break content;
-        }
-        hlt();
-    };
-    let elf = Elf::parse(&content).unwrap();
-    exec_elf(elf).unwrap();
-    info!("executed elf");
-}
-
-fn exec_elf(elf: Elf) -> Result<(), ()> {
-    if !elf.is_64 {
-        return Err(());
-    }
-    if elf.header.e_type != ET_EXEC {
-        return Err(());
-    }
-    let base_addr = elf
-        .program_headers
-        .iter()
-        .map(|ph| ph.p_vaddr)
-        .min()
-        .ok_or(())?;
-    debug!("base addr: {:#x}", base_addr);
-    let required_mem = elf
-        .program_headers
-        .iter()
-        .map(|ph| ph.p_vaddr - base_addr + ph.p_memsz)
-        .max()
-        .ok_or(())?;
-    debug!("required mem: {:#x}", required_mem);
-
-    let _elf_buffer = KBuffer::allocate(required_mem as usize);
-    debug!("allocated buffer at {:p}", _elf_buffer.as_ptr::<usize>());
-    Ok(())
-}
-
-async fn async_number() -> u32 {
-    42
-}
-
-async fn example_task() {
-    let number = async_number().await;
-    serial_println!("async number: {}", number);
-}
-
-extern "C" fn just_panic() {
-    serial_println!("Hi, my name is Tid {} and", Scheduler::get_current_tid());
-    panic!("Welcome to MartimOS");
-}
-
-extern "C" fn example_tasks() {
+extern "C" fn input_task() {
     let mut executor = Executor::default();
-    executor.spawn(Task::new(example_task()));
-    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.spawn(Task::new(keyboard::process_keypresses()));
+    executor.spawn(Task::new(serial::process_serial_input()));
     executor.run();
 }
 
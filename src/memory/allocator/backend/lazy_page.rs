@@ -1,7 +1,10 @@
+use crate::memory::allocator::align_up;
 use crate::memory::allocator::backend::MemoryBackend;
 use crate::memory::manager::{MemoryKind, MemoryManager, UserAccessible};
 use crate::memory::{DefaultPageSize, Result};
 use core::marker::PhantomData;
+use x86_64::align_down;
+use x86_64::structures::paging::page::PageRange;
 use x86_64::structures::paging::{Page, PageSize};
 use x86_64::VirtAddr;
 
@@ -28,16 +31,54 @@ where
     }
 }
 
+/// Returns the pages that contain any part of the given range.
+fn containing_pages(addr: *const u8, size: usize) -> PageRange<DefaultPageSize> {
+    Page::range(
+        Page::containing_address(VirtAddr::new_truncate(addr as u64)),
+        Page::containing_address(VirtAddr::new_truncate(addr as u64 + size as u64 - 1)) + 1,
+    )
+}
+
+fn ensure_is_mapped(addr: *const u8, size: usize) -> Result<()> {
+    if size == 0 {
+        return Ok(());
+    }
+    MemoryManager::lock().map_with_huge_pages(
+        containing_pages(addr, size),
+        MemoryKind::Writable,
+        UserAccessible::No,
+    )
+}
+
 impl MemoryBackend for LazyPageMappingBackend<DefaultPageSize, NoUnmap> {
     fn memory_allocated(&mut self, addr: *const u8, size: usize) -> Result<()> {
-        let page_range = Page::range(
-            Page::containing_address(VirtAddr::new_truncate(addr as u64)),
-            Page::containing_address(VirtAddr::new_truncate(addr as u64 + size as u64 - 1)) + 1,
-        );
-        MemoryManager::lock().ensure_is_mapped(page_range, MemoryKind::Writable, UserAccessible::No)
+        ensure_is_mapped(addr, size)
     }
 
     fn memory_deallocated(&mut self, _addr: *const u8, _size: usize) -> Result<()> {
         Ok(())
     }
 }
+
+/// Unmaps the pages that lie completely inside of deallocated ranges, and returns
+/// their frames. Pages that are only partially deallocated may still be in use, so
+/// allocators should only deallocate whole pages, like the
+/// [`RangeAllocator`](crate::memory::allocator::range::RangeAllocator) does.
+impl MemoryBackend for LazyPageMappingBackend<DefaultPageSize, WithUnmap> {
+    fn memory_allocated(&mut self, addr: *const u8, size: usize) -> Result<()> {
+        ensure_is_mapped(addr, size)
+    }
+
+    fn memory_deallocated(&mut self, addr: *const u8, size: usize) -> Result<()> {
+        let start = align_up(addr as usize, DefaultPageSize::SIZE as usize) as u64;
+        let end = align_down(addr as u64 + size as u64, DefaultPageSize::SIZE);
+        if start >= end {
+            return Ok(());
+        }
+        let pages = Page::range(
+            Page::containing_address(VirtAddr::new_truncate(start)),
+            Page::containing_address(VirtAddr::new_truncate(end)),
+        );
+        MemoryManager::lock().deallocate_and_unmap_page_range(pages)
+    }
+}
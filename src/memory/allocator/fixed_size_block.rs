@@ -3,8 +3,10 @@ use core::{alloc::Layout, mem, ptr, ptr::NonNull};
 
 use x86_64::structures::paging::{PageSize, Size4KiB};
 
+use crate::memory::allocator::align_up;
 use crate::memory::allocator::backend::MemoryBackend;
 use crate::memory::heap::Locked;
+use crate::memory::Result;
 
 struct ListNode {
     next: Option<&'static mut ListNode>,
@@ -27,10 +29,47 @@ const BLOCK_SIZES: &[usize] = &[
     Size4KiB::SIZE as usize,
 ];
 
+/// The minimum amount of bytes by which the heap grows when it is full.
+const MIN_GROWTH: usize = 256 * 1024;
+
+/// Statistics about the blocks of one size.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SizeClassStats {
+    pub block_size: usize,
+    /// Blocks that are handed out.
+    pub in_use: usize,
+    /// Blocks that were freed and can be reused.
+    pub free: usize,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct HeapStats {
+    /// The current size of the heap.
+    pub size: usize,
+    /// The size up to which the heap can grow.
+    pub size_limit: usize,
+    /// Bytes that are handed out, including the unused rest of fixed size blocks.
+    pub used: usize,
+    /// Bytes of the current heap size that are not handed out.
+    pub free: usize,
+    pub size_classes: [SizeClassStats; BLOCK_SIZES.len()],
+}
+
+/// An allocator that serves small allocations from lists of fixed size blocks, and
+/// larger ones from a linked list allocator.
+///
+/// The heap starts at its initial size and grows on demand up to its size limit.
+/// The memory backend is notified whenever the heap grows, so that it can provide
+/// the new memory before it is used.
 pub struct FixedSizeBlockAllocator<M> {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
     memory_backend: M,
+    size_limit: usize,
+    blocks_in_use: [usize; BLOCK_SIZES.len()],
+    blocks_free: [usize; BLOCK_SIZES.len()],
+    /// Bytes of allocations that are too large for a block.
+    large_in_use: usize,
 }
 
 impl<M> FixedSizeBlockAllocator<M>
@@ -44,27 +83,103 @@ where
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
             memory_backend,
+            size_limit: 0,
+            blocks_in_use: [0; BLOCK_SIZES.len()],
+            blocks_free: [0; BLOCK_SIZES.len()],
+            large_in_use: 0,
         }
     }
 
-    /// Initialize the allocator with the given heap bounds.
+    /// Initialize the allocator with the given heap start, the initial heap size and
+    /// the size up to which the heap may grow.
     ///
     /// # Safety
     ///
-    /// This function is unsafe because the caller must guarantee that the given
-    /// heap bounds are valid and that the heap is unused. This method must be
-    /// called only once.
-    pub unsafe fn init(&mut self, heap_start: *mut u8, heap_size: usize) {
-        self.fallback_allocator.init(heap_start, heap_size);
+    /// This function is unsafe because the caller must guarantee that the memory from
+    /// the heap start up to the size limit is unused and reserved for the heap. This
+    /// method must be called only once.
+    pub unsafe fn init(
+        &mut self,
+        heap_start: *mut u8,
+        initial_size: usize,
+        size_limit: usize,
+    ) -> Result<()> {
+        self.memory_backend
+            .memory_allocated(heap_start, initial_size)?;
+        self.fallback_allocator.init(heap_start, initial_size);
+        self.size_limit = size_limit.max(initial_size);
+        Ok(())
+    }
+
+    /// Sets the size up to which the heap may grow. The heap never shrinks, so the
+    /// limit is at least the current size.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the memory up to the new limit is reserved for
+    /// the heap.
+    pub unsafe fn set_size_limit(&mut self, size_limit: usize) {
+        self.size_limit = size_limit.max(self.fallback_allocator.size());
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let mut size_classes = [SizeClassStats::default(); BLOCK_SIZES.len()];
+        for (i, class) in size_classes.iter_mut().enumerate() {
+            *class = SizeClassStats {
+                block_size: BLOCK_SIZES[i],
+                in_use: self.blocks_in_use[i],
+                free: self.blocks_free[i],
+            };
+        }
+        let used = size_classes
+            .iter()
+            .map(|c| c.block_size * c.in_use)
+            .sum::<usize>()
+            + self.large_in_use;
+        let size = self.fallback_allocator.size();
+        HeapStats {
+            size,
+            size_limit: self.size_limit,
+            used,
+            free: size - used,
+            size_classes,
+        }
     }
 
-    /// Allocates using the fallback allocator.
+    /// Allocates using the fallback allocator, and grows the heap if it is full.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        if !self.grow(layout) {
+            return ptr::null_mut();
+        }
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => ptr::null_mut(),
         }
     }
+
+    /// Grows the heap so that it can fit the given layout. Returns false if that
+    /// would exceed the size limit or the memory backend failed.
+    fn grow(&mut self, layout: Layout) -> bool {
+        // the end of the heap may not be aligned, so reserve space for the alignment
+        let required = match layout.size().checked_add(layout.align()) {
+            Some(required) => required,
+            None => return false,
+        };
+        let available = self.size_limit - self.fallback_allocator.size();
+        let growth = align_up(required.max(MIN_GROWTH), Size4KiB::SIZE as usize).min(available);
+        if growth < required {
+            return false;
+        }
+        let top = self.fallback_allocator.top();
+        if self.memory_backend.memory_allocated(top, growth).is_err() {
+            return false;
+        }
+        unsafe { self.fallback_allocator.extend(growth) };
+        true
+    }
 }
 
 unsafe impl<M> GlobalAlloc for Locked<FixedSizeBlockAllocator<M>>
@@ -74,12 +189,12 @@ where
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
         let required_block_size = layout.size().max(layout.align());
-        let index = BLOCK_SIZES.iter().position(|&s| s >= required_block_size);
-        let pointer = match index {
+        match BLOCK_SIZES.iter().position(|&s| s >= required_block_size) {
             Some(index) => {
-                match allocator.list_heads[index].take() {
+                let pointer = match allocator.list_heads[index].take() {
                     Some(node) => {
                         allocator.list_heads[index] = node.next.take();
+                        allocator.blocks_free[index] -= 1;
                         node as *mut ListNode as *mut u8
                     }
                     None => {
@@ -90,15 +205,20 @@ where
                         let layout = Layout::from_size_align(block_size, block_align).unwrap();
                         allocator.fallback_alloc(layout)
                     }
+                };
+                if !pointer.is_null() {
+                    allocator.blocks_in_use[index] += 1;
                 }
+                pointer
             }
-            None => allocator.fallback_alloc(layout),
-        };
-        allocator
-            .memory_backend
-            .memory_allocated(pointer as *const u8, required_block_size)
-            .expect("memory allocation via backend failed");
-        pointer
+            None => {
+                let pointer = allocator.fallback_alloc(layout);
+                if !pointer.is_null() {
+                    allocator.large_in_use += layout.size();
+                }
+                pointer
+            }
+        }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
@@ -115,16 +235,15 @@ where
                 let new_node_ptr = ptr as *mut ListNode;
                 new_node_ptr.write(new_node);
                 allocator.list_heads[index] = Some(&mut *new_node_ptr);
+                allocator.blocks_in_use[index] -= 1;
+                allocator.blocks_free[index] += 1;
             }
             None => {
                 let ptr = NonNull::new(ptr)
                     .unwrap_or_else(|| panic!("invalid pointer {:p} passed to deallocate", ptr));
                 allocator.fallback_allocator.deallocate(ptr, layout);
+                allocator.large_in_use -= layout.size();
             }
         };
-        allocator
-            .memory_backend
-            .memory_deallocated(ptr as *const u8, required_block_size)
-            .expect("memory deallocation via backend failed");
     }
 }
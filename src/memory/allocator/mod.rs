@@ -2,6 +2,8 @@ pub mod backend;
 pub mod bump;
 pub mod fixed_size_block;
 pub mod linked_list;
+pub mod range;
+pub mod slab;
 
 /// Align the given address `addr` upwards to alignment `align`.
 ///
@@ -0,0 +1,198 @@
+use alloc::collections::BTreeMap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use crate::memory::allocator::align_up;
+use crate::memory::allocator::backend::MemoryBackend;
+use crate::memory::heap::Locked;
+
+/// An allocator that hands out ranges of a fixed granularity, and reuses freed ranges.
+///
+/// In contrast to the other allocators, the free ranges are kept on the kernel heap
+/// and not in the managed memory itself, so the memory backend can unmap freed
+/// ranges. Every allocation covers whole granules, so no two allocations share a
+/// granule. With a granularity of a page, the backend is only notified about whole
+/// pages.
+pub struct RangeAllocator<M>
+where
+    M: MemoryBackend,
+{
+    /// The free ranges, keyed by their start address. Adjacent free ranges are merged.
+    free: BTreeMap<usize, usize>,
+    granularity: usize,
+    memory_backend: M,
+}
+
+impl<M> RangeAllocator<M>
+where
+    M: MemoryBackend,
+{
+    /// Creates an empty range allocator. The granularity must be a power of two.
+    pub const fn new(memory_backend: M, granularity: usize) -> Self {
+        RangeAllocator {
+            free: BTreeMap::new(),
+            granularity,
+            memory_backend,
+        }
+    }
+
+    /// Initializes the allocator with the given bounds.
+    ///
+    /// # Safety
+    ///
+    /// This method is unsafe because the caller must ensure that the given
+    /// memory range is unused. Also, this method must be called only once.
+    pub unsafe fn init(&mut self, start: *mut u8, size: usize) {
+        self.free.insert(start as usize, size);
+    }
+
+    /// The amount of bytes that are not allocated.
+    pub fn free_bytes(&self) -> usize {
+        self.free.values().sum()
+    }
+
+    fn granules(&self, size: usize) -> usize {
+        align_up(size, self.granularity)
+    }
+
+    /// Removes a range with the given size and alignment from the free ranges, and
+    /// returns its start address.
+    fn take_range(&mut self, size: usize, align: usize) -> Option<usize> {
+        let (start, len, aligned) = self.free.iter().find_map(|(&start, &len)| {
+            let aligned = align_up(start, align);
+            let end = aligned.checked_add(size)?;
+            (end <= start + len).then_some((start, len, aligned))
+        })?;
+        self.free.remove(&start);
+        if aligned > start {
+            self.free.insert(start, aligned - start);
+        }
+        let end = aligned + size;
+        if end < start + len {
+            self.free.insert(end, start + len - end);
+        }
+        Some(aligned)
+    }
+
+    /// Adds a range to the free ranges and merges it with its neighbours.
+    fn release_range(&mut self, mut start: usize, mut size: usize) {
+        if let Some((&prev_start, &prev_len)) = self.free.range(..start).next_back() {
+            debug_assert!(
+                prev_start + prev_len <= start,
+                "double free at {:#x}",
+                start
+            );
+            if prev_start + prev_len == start {
+                self.free.remove(&prev_start);
+                start = prev_start;
+                size += prev_len;
+            }
+        }
+        if let Some(next_len) = self.free.remove(&(start + size)) {
+            size += next_len;
+        }
+        self.free.insert(start, size);
+    }
+}
+
+unsafe impl<M> GlobalAlloc for Locked<RangeAllocator<M>>
+where
+    M: MemoryBackend,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            // nothing is reserved, but the pointer has to be non-null and aligned
+            return layout.align() as *mut u8;
+        }
+
+        let mut allocator = self.lock();
+        let size = allocator.granules(layout.size());
+        let align = layout.align().max(allocator.granularity);
+        let start = match allocator.take_range(size, align) {
+            Some(start) => start,
+            None => return ptr::null_mut(), // out of memory
+        };
+        if allocator
+            .memory_backend
+            .memory_allocated(start as *const u8, size)
+            .is_err()
+        {
+            allocator.release_range(start, size);
+            return ptr::null_mut();
+        }
+        start as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+
+        let mut allocator = self.lock();
+        let size = allocator.granules(layout.size());
+        allocator
+            .memory_backend
+            .memory_deallocated(ptr as *const u8, size)
+            .expect("memory deallocation via backend failed");
+        allocator.release_range(ptr as usize, size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::allocator::backend::already_mapped::MemoryAlreadyMappedBackend;
+
+    use super::*;
+
+    const START: usize = 0x1000_0000;
+    const SIZE: usize = 0x10_0000;
+
+    fn allocator() -> Locked<RangeAllocator<MemoryAlreadyMappedBackend>> {
+        let allocator = Locked::new(RangeAllocator::new(MemoryAlreadyMappedBackend, 4096));
+        // the allocator never touches the memory that it manages
+        unsafe { allocator.lock().init(START as *mut u8, SIZE) };
+        allocator
+    }
+
+    #[test_case]
+    fn test_allocations_are_page_granular() {
+        let allocator = allocator();
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        let a = unsafe { allocator.alloc(layout) } as usize;
+        let b = unsafe { allocator.alloc(layout) } as usize;
+        assert_eq!(START, a);
+        assert_eq!(START + 4096, b);
+        assert_eq!(SIZE - 2 * 4096, allocator.lock().free_bytes());
+    }
+
+    #[test_case]
+    fn test_freed_ranges_are_reused_and_merged() {
+        let allocator = allocator();
+        let layout = Layout::from_size_align(3 * 4096, 4096).unwrap();
+        let a = unsafe { allocator.alloc(layout) };
+        let b = unsafe { allocator.alloc(layout) };
+        unsafe {
+            allocator.dealloc(a, layout);
+            assert_eq!(a, allocator.alloc(layout));
+            allocator.dealloc(b, layout);
+            allocator.dealloc(a, layout);
+        }
+        assert_eq!(SIZE, allocator.lock().free_bytes());
+        assert_eq!(1, allocator.lock().free.len());
+    }
+
+    #[test_case]
+    fn test_alignment_and_exhaustion() {
+        let allocator = allocator();
+        let small = Layout::from_size_align(1, 1).unwrap();
+        let aligned = Layout::from_size_align(4096, 0x4_0000).unwrap();
+        unsafe {
+            allocator.alloc(small);
+            let ptr = allocator.alloc(aligned) as usize;
+            assert_eq!(0, ptr % 0x4_0000);
+            assert!(allocator
+                .alloc(Layout::from_size_align(SIZE, 4096).unwrap())
+                .is_null());
+        }
+    }
+}
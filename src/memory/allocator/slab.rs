@@ -0,0 +1,425 @@
+//! A slab allocator in the style of Bonwick's, with one [`SlabCache`] per kind of
+//! object.
+//!
+//! A cache hands out objects of a single size. It takes slabs of one or more pages from
+//! the [`KBUFFER`](crate::memory::span::KBUFFER) span, and carves every slab into
+//! objects. Objects are initialized by the constructor of the cache once, when their
+//! slab is created, and keep their state while they are free, so a user that returns
+//! objects in their constructed state doesn't have to construct them again. The
+//! metadata of a slab, including the free list, is kept at the start of the slab, so
+//! free objects are never written to.
+//!
+//! A cache keeps at most one empty slab around. Further slabs are unmapped and their
+//! frames are freed as soon as they become empty, and [`SlabCache::reclaim`] does so
+//! for all of them.
+//!
+//! Caches implement [`Allocator`], so objects can be allocated with `Box::new_in`.
+//! Note that this overwrites the constructed state of the object. Caches are usually
+//! statics, and can be passed to [`register`] to show up in [`stats`].
+
+use alloc::vec::Vec;
+use core::alloc::{AllocError, Allocator, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr::NonNull;
+
+use kstd::sync::Mutex;
+use x86_64::structures::paging::{PageSize, Size4KiB};
+
+use crate::memory::allocator::align_up;
+use crate::memory::kbuffer;
+
+/// Slabs are made large enough to hold at least this many objects.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// The largest slab size. Caches for larger objects hold fewer objects per slab.
+const MAX_SLAB_SIZE: usize = 16 * Size4KiB::SIZE as usize;
+
+static CACHES: Mutex<Vec<&'static SlabCache>> = Mutex::new(Vec::new());
+
+/// Adds the given cache to the caches that are listed in [`stats`] and reclaimed by
+/// [`reclaim_all`]. Registering a cache more than once has no effect.
+pub fn register(cache: &'static SlabCache) {
+    let mut caches = CACHES.lock();
+    if !caches.iter().any(|c| core::ptr::eq(*c, cache)) {
+        caches.push(cache);
+    }
+}
+
+/// Returns the statistics of all registered caches.
+pub fn stats() -> Vec<SlabCacheStats> {
+    let caches = CACHES.lock().clone();
+    caches.iter().map(|c| c.stats()).collect()
+}
+
+/// Frees the empty slabs of all registered caches, and returns the amount of slabs that
+/// were freed.
+pub fn reclaim_all() -> usize {
+    let caches = CACHES.lock().clone();
+    caches.iter().map(|c| c.reclaim()).sum()
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SlabCacheStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub slab_size: usize,
+    pub slabs: usize,
+    /// Objects that are handed out.
+    pub objects_in_use: usize,
+    /// Objects in all slabs, whether they are handed out or not.
+    pub objects_total: usize,
+    /// Slabs that were freed.
+    pub reclaimed_slabs: usize,
+}
+
+/// The metadata at the start of every slab. It is followed by the stack of the
+/// indices of the free objects, and then by the objects.
+struct Slab {
+    /// The amount of indices on the free stack.
+    free_count: usize,
+}
+
+impl Slab {
+    fn free_stack(slab: NonNull<Slab>) -> *mut u16 {
+        unsafe { (slab.as_ptr() as *mut u8).add(size_of::<Slab>()) as *mut u16 }
+    }
+
+    fn in_use(slab: NonNull<Slab>, capacity: usize) -> usize {
+        capacity - unsafe { slab.as_ref() }.free_count
+    }
+}
+
+/// The geometry of the slabs of a cache, computed when the first slab is created.
+#[derive(Copy, Clone)]
+struct Geometry {
+    slab_size: usize,
+    /// The distance between two objects.
+    stride: usize,
+    /// The offset of the first object from the start of the slab.
+    objects_offset: usize,
+    capacity: usize,
+}
+
+impl Geometry {
+    fn new(object_size: usize, align: usize) -> Self {
+        let stride = align_up(object_size.max(1), align);
+        let mut slab_size = Size4KiB::SIZE as usize;
+        loop {
+            let geometry = Self::with_slab_size(slab_size, stride, align);
+            if geometry.capacity >= MIN_OBJECTS_PER_SLAB || slab_size >= MAX_SLAB_SIZE {
+                assert!(geometry.capacity > 0, "object too large for a slab");
+                return geometry;
+            }
+            slab_size *= 2;
+        }
+    }
+
+    fn with_slab_size(slab_size: usize, stride: usize, align: usize) -> Self {
+        // every object needs its size plus one entry on the free stack
+        let header = size_of::<Slab>() + align;
+        let capacity =
+            (slab_size.saturating_sub(header) / (stride + size_of::<u16>())).min(u16::MAX as usize);
+        let objects_offset = align_up(size_of::<Slab>() + capacity * size_of::<u16>(), align);
+        Self {
+            slab_size,
+            stride,
+            objects_offset,
+            capacity,
+        }
+    }
+
+    fn layout(&self) -> Layout {
+        // slabs are aligned to their size, so that the slab of an object can be found
+        // by rounding down its address
+        Layout::from_size_align(self.slab_size, self.slab_size).unwrap()
+    }
+}
+
+struct Slabs {
+    geometry: Option<Geometry>,
+    /// All slabs of the cache, whether they are full, partially used or empty.
+    slabs: Vec<NonNull<Slab>>,
+    reclaimed_slabs: usize,
+}
+
+// the slabs are only accessed while the cache is locked
+unsafe impl Send for Slabs {}
+
+/// A cache of objects of one size, see the [module documentation](self).
+pub struct SlabCache {
+    name: &'static str,
+    layout: Layout,
+    constructor: Option<fn(NonNull<u8>)>,
+    inner: Mutex<Slabs>,
+}
+
+impl SlabCache {
+    /// Creates a cache for objects with the given layout. If a constructor is given,
+    /// it is called for every object when its slab is created.
+    pub const fn new(
+        name: &'static str,
+        layout: Layout,
+        constructor: Option<fn(NonNull<u8>)>,
+    ) -> Self {
+        Self {
+            name,
+            layout,
+            constructor,
+            inner: Mutex::new(Slabs {
+                geometry: None,
+                slabs: Vec::new(),
+                reclaimed_slabs: 0,
+            }),
+        }
+    }
+
+    /// Creates a cache for objects of type `T`, without a constructor.
+    pub const fn for_type<T>(name: &'static str) -> Self {
+        Self::new(name, Layout::new::<T>(), None)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The layout of the objects of this cache.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns a free object, or `None` if no slab could be allocated.
+    pub fn alloc(&self) -> Option<NonNull<u8>> {
+        let mut inner = self.inner.lock();
+        let geometry = *inner
+            .geometry
+            .get_or_insert_with(|| Geometry::new(self.layout.size(), self.layout.align()));
+
+        // prefer partially used slabs, so that empty ones can be given back
+        let slab = match inner
+            .slabs
+            .iter()
+            .copied()
+            .filter(|&s| unsafe { s.as_ref() }.free_count > 0)
+            .min_by_key(|&s| unsafe { s.as_ref() }.free_count)
+        {
+            Some(slab) => slab,
+            None => {
+                let slab = self.create_slab(&geometry)?;
+                inner.slabs.push(slab);
+                slab
+            }
+        };
+
+        unsafe {
+            let header = &mut *slab.as_ptr();
+            header.free_count -= 1;
+            let index = *Slab::free_stack(slab).add(header.free_count) as usize;
+            let object =
+                (slab.as_ptr() as *mut u8).add(geometry.objects_offset + index * geometry.stride);
+            Some(NonNull::new_unchecked(object))
+        }
+    }
+
+    /// Returns an object to the cache.
+    ///
+    /// # Safety
+    ///
+    /// The object must have been returned by [`SlabCache::alloc`] of this cache, and
+    /// must not be used afterwards.
+    pub unsafe fn free(&self, object: NonNull<u8>) {
+        let mut inner = self.inner.lock();
+        let geometry = inner.geometry.expect("free on a cache without slabs");
+        let slab_addr = object.as_ptr() as usize & !(geometry.slab_size - 1);
+        let position = inner
+            .slabs
+            .iter()
+            .position(|s| s.as_ptr() as usize == slab_addr)
+            .expect("object does not belong to this cache");
+        let slab = inner.slabs[position];
+
+        let index =
+            (object.as_ptr() as usize - slab_addr - geometry.objects_offset) / geometry.stride;
+        let header = &mut *slab.as_ptr();
+        *Slab::free_stack(slab).add(header.free_count) = index as u16;
+        header.free_count += 1;
+
+        // keep one empty slab, so that a cache that is used for a single object
+        // doesn't allocate and free a slab all the time
+        if header.free_count == geometry.capacity {
+            let other_empty_slab = inner
+                .slabs
+                .iter()
+                .any(|&s| s != slab && Slab::in_use(s, geometry.capacity) == 0);
+            if other_empty_slab {
+                inner.slabs.swap_remove(position);
+                inner.reclaimed_slabs += 1;
+                kbuffer::deallocate_pages(slab.cast(), geometry.layout());
+            }
+        }
+    }
+
+    /// Frees all empty slabs, and returns how many there were.
+    pub fn reclaim(&self) -> usize {
+        let mut inner = self.inner.lock();
+        let geometry = match inner.geometry {
+            Some(g) => g,
+            None => return 0,
+        };
+        let (empty, used): (Vec<_>, Vec<_>) = inner
+            .slabs
+            .iter()
+            .partition(|&&s| Slab::in_use(s, geometry.capacity) == 0);
+        inner.slabs = used;
+        inner.reclaimed_slabs += empty.len();
+        for slab in &empty {
+            unsafe { kbuffer::deallocate_pages(slab.cast(), geometry.layout()) };
+        }
+        empty.len()
+    }
+
+    pub fn stats(&self) -> SlabCacheStats {
+        let inner = self.inner.lock();
+        let (slab_size, capacity) = inner.geometry.map_or((0, 0), |g| (g.slab_size, g.capacity));
+        SlabCacheStats {
+            name: self.name,
+            object_size: self.layout.size(),
+            slab_size,
+            slabs: inner.slabs.len(),
+            objects_in_use: inner.slabs.iter().map(|&s| Slab::in_use(s, capacity)).sum(),
+            objects_total: inner.slabs.len() * capacity,
+            reclaimed_slabs: inner.reclaimed_slabs,
+        }
+    }
+
+    /// Allocates a new slab, and constructs all of its objects.
+    fn create_slab(&self, geometry: &Geometry) -> Option<NonNull<Slab>> {
+        debug_assert!(align_of::<Slab>() <= geometry.slab_size);
+        let slab = kbuffer::allocate_pages(geometry.layout())?.cast::<Slab>();
+        unsafe {
+            slab.as_ptr().write(Slab {
+                free_count: geometry.capacity,
+            });
+            let free_stack = Slab::free_stack(slab);
+            for index in 0..geometry.capacity {
+                // the lowest index is on top of the stack
+                *free_stack.add(index) = (geometry.capacity - 1 - index) as u16;
+                if let Some(constructor) = self.constructor {
+                    let object = (slab.as_ptr() as *mut u8)
+                        .add(geometry.objects_offset + index * geometry.stride);
+                    constructor(NonNull::new_unchecked(object));
+                }
+            }
+        }
+        Some(slab)
+    }
+}
+
+unsafe impl Allocator for SlabCache {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() > self.layout.size() || layout.align() > self.layout.align() {
+            return Err(AllocError);
+        }
+        let object = self.alloc().ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(object, self.layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _: Layout) {
+        self.free(ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use x86_64::VirtAddr;
+
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct Object {
+        a: u64,
+        b: [u8; 40],
+    }
+
+    #[test_case]
+    fn test_alloc_free() {
+        static CACHE: SlabCache = SlabCache::for_type::<Object>("test-object");
+        let a = CACHE.alloc().unwrap();
+        let b = CACHE.alloc().unwrap();
+        assert_ne!(a, b);
+        assert_eq!(0, a.as_ptr() as usize % align_of::<Object>());
+
+        let stats = CACHE.stats();
+        assert_eq!(1, stats.slabs);
+        assert_eq!(2, stats.objects_in_use);
+        assert!(stats.objects_total >= MIN_OBJECTS_PER_SLAB);
+
+        unsafe {
+            CACHE.free(b);
+            CACHE.free(a);
+        }
+        assert_eq!(0, CACHE.stats().objects_in_use);
+        // freed objects are reused
+        assert_eq!(a, CACHE.alloc().unwrap());
+    }
+
+    #[test_case]
+    fn test_constructor() {
+        fn construct(object: NonNull<u8>) {
+            unsafe { object.cast::<u64>().as_ptr().write(0xC0FFEE) };
+        }
+        static CACHE: SlabCache =
+            SlabCache::new("test-constructed", Layout::new::<u64>(), Some(construct));
+
+        let object = CACHE.alloc().unwrap().cast::<u64>();
+        assert_eq!(0xC0FFEE, unsafe { *object.as_ptr() });
+        // objects keep their state while they are free
+        unsafe {
+            *object.as_ptr() = 7;
+            CACHE.free(object.cast());
+        }
+        assert_eq!(7, unsafe { *CACHE.alloc().unwrap().cast::<u64>().as_ptr() });
+    }
+
+    #[test_case]
+    fn test_empty_slabs_are_reclaimed() {
+        static CACHE: SlabCache = SlabCache::for_type::<[u8; 512]>("test-reclaim");
+        let objects = (0..64).map(|_| CACHE.alloc().unwrap()).collect::<Vec<_>>();
+        let slabs = CACHE.stats().slabs;
+        assert!(slabs > 2);
+
+        objects.into_iter().for_each(|o| unsafe { CACHE.free(o) });
+        let stats = CACHE.stats();
+        assert_eq!(1, stats.slabs);
+        assert_eq!(slabs - 1, stats.reclaimed_slabs);
+        assert_eq!(1, CACHE.reclaim());
+        assert_eq!(0, CACHE.stats().slabs);
+    }
+
+    #[test_case]
+    fn test_reclaimed_slabs_are_unmapped() {
+        static CACHE: SlabCache = SlabCache::for_type::<Object>("test-unmap");
+        let object = CACHE.alloc().unwrap();
+        let addr = VirtAddr::from_ptr(object.as_ptr());
+        assert!(crate::memory::is_mapped(addr));
+
+        unsafe { CACHE.free(object) };
+        assert_eq!(1, CACHE.reclaim());
+        assert!(!crate::memory::is_mapped(addr));
+    }
+
+    #[test_case]
+    fn test_box_in_cache() {
+        static CACHE: SlabCache = SlabCache::for_type::<Object>("test-box");
+        register(&CACHE);
+        let boxed = Box::new_in(Object { a: 1, b: [2; 40] }, &CACHE);
+        assert_eq!(1, boxed.a);
+        assert!(stats()
+            .iter()
+            .any(|s| s.name == "test-box" && s.objects_in_use == 1));
+        drop(boxed);
+        assert_eq!(0, CACHE.stats().objects_in_use);
+        assert!(Box::try_new_in([0_u8; 128], &CACHE).is_err());
+    }
+}
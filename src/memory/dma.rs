@@ -0,0 +1,235 @@
+//! Buffers for direct memory access by devices. A [`DmaBuffer`] is backed by physically
+//! contiguous frames, so that a device can access all of it through its physical
+//! address.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+use x86_64::structures::paging::{Page, PageSize, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::allocator::backend::already_mapped::MemoryAlreadyMappedBackend;
+use crate::memory::allocator::range::RangeAllocator;
+use crate::memory::heap::Locked;
+use crate::memory::manager::{CacheMode, MemoryManager};
+use crate::memory::size::Size;
+use crate::memory::span::DMA;
+use crate::memory::{DefaultPageSize, Error, Result};
+
+/// The virtual addresses of the buffers. The buffers map their frames themselves, so
+/// the allocator only hands out address ranges.
+static DMA_SPACE: Locked<RangeAllocator<MemoryAlreadyMappedBackend>> = Locked::new(
+    RangeAllocator::new(MemoryAlreadyMappedBackend, DefaultPageSize::SIZE as usize),
+);
+
+pub(in crate::memory) fn init_dma_space() {
+    // the allocator never touches the memory that it manages
+    unsafe { DMA_SPACE.lock().init(DMA.as_mut_ptr::<u8>(), DMA.len()) };
+}
+
+/// The requirements of a device for the memory of a [`DmaBuffer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaOptions {
+    /// The alignment of the physical address in bytes, which is a power of two. Buffers
+    /// are always aligned to a page.
+    pub align: u64,
+    /// The end of the physical memory that the device can address.
+    pub limit: u64,
+    pub cache_mode: CacheMode,
+}
+
+impl Default for DmaOptions {
+    /// Uncached memory below 4 GiB, which devices with 32 bit addresses can access.
+    fn default() -> Self {
+        Self {
+            align: DefaultPageSize::SIZE,
+            limit: Size::GiB(4).bytes() as u64,
+            cache_mode: CacheMode::Uncached,
+        }
+    }
+}
+
+/// A zeroed buffer of physically contiguous memory. Its frames are unmapped and
+/// returned to the frame allocator when the buffer is dropped.
+///
+/// While the buffer exists, its frames have its cache mode in the mapping of the
+/// complete physical memory as well.
+///
+/// Contiguous frames are only taken from memory that was never allocated, see
+/// [`PhysicalFrameAllocator::allocate_contiguous_below`], so buffers below a limit
+/// should be allocated once and kept, for example when a driver is probed.
+///
+/// [`PhysicalFrameAllocator::allocate_contiguous_below`]: crate::memory::physical::PhysicalFrameAllocator::allocate_contiguous_below
+pub struct DmaBuffer {
+    start: NonNull<u8>,
+    phys_addr: PhysAddr,
+    len: usize,
+    allocation_layout: Layout, // used for deallocation
+}
+
+// the buffer owns its memory, which is not accessed through any other address
+unsafe impl Send for DmaBuffer {}
+
+impl DmaBuffer {
+    /// Allocates a buffer of the given size with [`DmaOptions::default`].
+    pub fn allocate(size: usize) -> Result<Self> {
+        Self::allocate_with_options(size, DmaOptions::default())
+    }
+
+    /// Allocates a buffer of the given size, which satisfies the given options.
+    pub fn allocate_with_options(size: usize, options: DmaOptions) -> Result<Self> {
+        if size == 0 || !options.align.is_power_of_two() {
+            return Err(Error::InvalidRange);
+        }
+        let align = options.align.max(DefaultPageSize::SIZE);
+        let layout = Layout::from_size_align(size, DefaultPageSize::SIZE as usize)
+            .map_err(|_| Error::InvalidRange)?;
+        // the virtual range is allocated before the memory manager is locked, since the
+        // range allocator uses the heap
+        let start = NonNull::new(unsafe { DMA_SPACE.alloc(layout) }).ok_or(Error::OutOfMemory)?;
+        let start_addr = VirtAddr::from_ptr(start.as_ptr());
+        let pages = Page::range(
+            Page::containing_address(start_addr),
+            Page::containing_address(start_addr + (size - 1)) + 1,
+        );
+
+        let phys_addr = match MemoryManager::lock().allocate_and_map_contiguous(
+            pages,
+            align,
+            options.limit,
+            options.cache_mode,
+        ) {
+            Ok(phys_addr) => phys_addr,
+            Err(e) => {
+                unsafe { DMA_SPACE.dealloc(start.as_ptr(), layout) };
+                return Err(e);
+            }
+        };
+        unsafe { start.as_ptr().write_bytes(0, size) };
+        Ok(Self {
+            start,
+            phys_addr,
+            len: size,
+            allocation_layout: layout,
+        })
+    }
+
+    pub fn virt_addr(&self) -> VirtAddr {
+        VirtAddr::from_ptr(self.start.as_ptr())
+    }
+
+    /// The physical address of the buffer, which a device uses to access it.
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys_addr
+    }
+
+    pub fn as_mut_ptr<T>(&self) -> *mut T {
+        self.start.cast::<T>().as_ptr()
+    }
+
+    pub fn as_ptr<T>(&self) -> *const T {
+        self.start.cast::<T>().as_ptr()
+    }
+
+    #[allow(clippy::len_without_is_empty)] // buffers are never empty
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        let start = self.virt_addr();
+        let pages = Page::range(
+            Page::containing_address(start),
+            Page::containing_address(start + (self.len - 1)) + 1,
+        );
+        let first_frame = PhysFrame::containing_address(self.phys_addr);
+        let frames = PhysFrame::range(first_frame, first_frame + pages.count() as u64);
+        let mut mm = MemoryManager::lock();
+        mm.set_physical_cache_mode(frames, CacheMode::WriteBack)
+            .expect("resetting the cache mode of a dma buffer failed");
+        mm.deallocate_and_unmap_page_range(pages)
+            .expect("unmapping a dma buffer failed");
+        unsafe { DMA_SPACE.dealloc(self.start.as_ptr(), self.allocation_layout) };
+    }
+}
+
+impl AsRef<[u8]> for DmaBuffer {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.start.as_ptr(), self.len) }
+    }
+}
+
+impl AsMut<[u8]> for DmaBuffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.start.as_ptr(), self.len) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x86_64::structures::paging::PageTableFlags;
+
+    use super::*;
+
+    #[test_case]
+    fn test_buffer_is_physically_contiguous() {
+        let size = 3 * DefaultPageSize::SIZE as usize + 100;
+        let mut buffer = DmaBuffer::allocate(size).unwrap();
+        assert!(buffer.as_ref().iter().all(|&b| b == 0));
+        buffer.as_mut()[size - 1] = 42;
+
+        let mm = MemoryManager::lock();
+        for offset in (0..size).step_by(DefaultPageSize::SIZE as usize) {
+            assert_eq!(
+                Some(buffer.phys_addr() + offset),
+                mm.translate(buffer.virt_addr() + offset)
+            );
+        }
+        assert!(buffer.phys_addr().as_u64() + (size as u64) <= Size::GiB(4).bytes() as u64);
+    }
+
+    #[test_case]
+    fn test_options_are_respected() {
+        let options = DmaOptions {
+            align: 0x1_0000,
+            cache_mode: CacheMode::WriteCombining,
+            ..Default::default()
+        };
+        let buffer = DmaBuffer::allocate_with_options(100, options).unwrap();
+        assert!(buffer.phys_addr().is_aligned(0x1_0000_u64));
+        assert_eq!(Err(Error::InvalidRange), DmaBuffer::allocate(0).map(|_| ()));
+    }
+
+    #[test_case]
+    fn test_physical_memory_mapping_has_the_cache_mode() {
+        let physical_cache_flags = |phys_addr: PhysAddr| {
+            let addr = crate::memory::physical_to_virtual(phys_addr);
+            let mut flags = PageTableFlags::empty();
+            MemoryManager::lock().walk_mappings(addr, 1, |_, _, _, f| {
+                flags = f;
+                false
+            });
+            flags & (PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH)
+        };
+
+        let buffer = DmaBuffer::allocate(DefaultPageSize::SIZE as usize).unwrap();
+        let phys_addr = buffer.phys_addr();
+        assert_eq!(
+            CacheMode::Uncached.page_table_flags(),
+            physical_cache_flags(phys_addr)
+        );
+        drop(buffer);
+        // the frame may be used as normal memory again
+        assert_eq!(PageTableFlags::empty(), physical_cache_flags(phys_addr));
+    }
+
+    #[test_case]
+    fn test_frames_are_freed_on_drop() {
+        let buffer = DmaBuffer::allocate(DefaultPageSize::SIZE as usize).unwrap();
+        let addr = buffer.virt_addr();
+        drop(buffer);
+        assert_eq!(None, MemoryManager::lock().translate(addr));
+    }
+}
@@ -1,31 +1,43 @@
-use crate::memory::allocator::backend::already_mapped::MemoryAlreadyMappedBackend;
-use crate::memory::allocator::fixed_size_block::FixedSizeBlockAllocator;
-use crate::memory::manager::{MemoryKind, MemoryManager, UserAccessible};
-use crate::memory::{span, Error};
+use crate::memory::allocator::backend::lazy_page::{LazyPageMappingBackend, NoUnmap};
+use crate::memory::allocator::fixed_size_block::{FixedSizeBlockAllocator, HeapStats};
+use crate::memory::size::Size;
+use crate::memory::{span, DefaultPageSize, Error};
 use kstd::sync::{Mutex, MutexGuard};
-use x86_64::structures::paging::Size4KiB;
 
-#[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator<MemoryAlreadyMappedBackend>> =
-    Locked::new(FixedSizeBlockAllocator::new(MemoryAlreadyMappedBackend));
+/// The size of the heap after initialization. Its pages are mapped right away.
+pub const INITIAL_HEAP_SIZE: Size = Size::MiB(1);
 
-pub(in crate::memory) fn init_heap() -> Result<(), Error> {
-    const HEAP_START: *mut u8 = span::HEAP.as_mut_ptr::<u8>();
-    const HEAP_SIZE: usize = span::HEAP.len();
+/// The size up to which the heap grows by default, see [`set_size_limit`].
+pub const DEFAULT_HEAP_SIZE_LIMIT: Size = Size::MiB(256);
 
-    let page_range = span::HEAP.as_page_range::<Size4KiB>();
-    // allocate writable and zeroed kernel memory
-    MemoryManager::lock().allocate_and_map_page_range(
-        page_range,
-        MemoryKind::Writable,
-        UserAccessible::No,
-    )?;
+// pages of the heap are mapped when the heap grows, so the memory manager must never
+// allocate heap memory while it is locked
+#[global_allocator]
+static ALLOCATOR: Locked<
+    FixedSizeBlockAllocator<LazyPageMappingBackend<DefaultPageSize, NoUnmap>>,
+> = Locked::new(FixedSizeBlockAllocator::new(LazyPageMappingBackend::new()));
 
+pub(in crate::memory) fn init_heap() -> Result<(), Error> {
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(
+            span::HEAP.as_mut_ptr::<u8>(),
+            INITIAL_HEAP_SIZE.bytes(),
+            DEFAULT_HEAP_SIZE_LIMIT.bytes(),
+        )
     }
+}
+
+/// Sets the size up to which the heap can grow. The limit is capped at the size of
+/// [`span::HEAP`], and can't be lower than the current size of the heap.
+pub fn set_size_limit(limit: Size) {
+    let limit = limit.bytes().min(span::HEAP.len());
+    // the whole heap span is reserved for the heap
+    unsafe { ALLOCATOR.lock().set_size_limit(limit) }
+}
 
-    Ok(())
+/// Returns statistics about the usage of the heap.
+pub fn stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
 }
 
 /// A wrapper around spin::Mutex to permit trait implementations.
@@ -44,3 +56,35 @@ impl<A> Locked<A> {
         self.inner.lock()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec;
+
+    use super::*;
+
+    #[test_case]
+    fn test_heap_grows() {
+        let before = stats();
+        let large = vec![1_u8; INITIAL_HEAP_SIZE.bytes() * 2];
+        let after = stats();
+        assert!(after.size > INITIAL_HEAP_SIZE.bytes() * 2);
+        assert!(after.size <= after.size_limit);
+        assert!(after.used >= before.used + large.len());
+        assert_eq!(after.size, after.used + after.free);
+        drop(large);
+        assert!(stats().used < after.used);
+    }
+
+    #[test_case]
+    fn test_size_class_stats() {
+        let before = stats().size_classes[0];
+        assert_eq!(8, before.block_size);
+        let value = Box::new(7_u64);
+        let after = stats().size_classes[0];
+        assert_eq!(before.in_use + 1, after.in_use);
+        drop(value);
+        assert_eq!(before.in_use, stats().size_classes[0].in_use);
+    }
+}
@@ -0,0 +1,210 @@
+//! Information about the memory of the kernel: the physical memory map, the usage of
+//! the frame allocator and of the virtual memory spans, and the mappings of the active
+//! page table. [`meminfo`] formats the most important numbers like `/proc/meminfo`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use bootloader::boot_info::MemoryRegion;
+use x86_64::structures::paging::{PageSize, PageTableFlags};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::manager::MemoryManager;
+use crate::memory::span::{self, MemorySpan};
+use crate::memory::vma::user_address_space;
+use crate::memory::{heap, kbuffer, DefaultPageSize};
+
+/// The physical memory map that the bootloader passed to the kernel.
+pub fn memory_regions() -> &'static [MemoryRegion] {
+    MemoryManager::lock()
+        .physical_frame_allocator()
+        .memory_regions()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub frame_size: u64,
+    /// The number of frames that the frame allocator can hand out.
+    pub usable: usize,
+    pub allocated: usize,
+    pub free: usize,
+}
+
+/// Returns statistics about the physical frame allocator.
+pub fn frame_stats() -> FrameStats {
+    let mm = MemoryManager::lock();
+    let frames = mm.physical_frame_allocator();
+    let usable = frames.usable_frame_count();
+    let allocated = frames.allocated_frames();
+    FrameStats {
+        frame_size: DefaultPageSize::SIZE,
+        usable,
+        allocated,
+        free: usable - allocated,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanUsage {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    pub len: usize,
+    /// The amount of bytes of the span that are in use.
+    pub used: usize,
+}
+
+impl SpanUsage {
+    fn new(name: &'static str, span: &MemorySpan, used: usize) -> Self {
+        Self {
+            name,
+            start: span.start(),
+            len: span.len(),
+            used,
+        }
+    }
+}
+
+/// Returns how much of the virtual memory spans is in use: the mapped areas of
+/// userspace, the size of the heap, and the allocated kernel buffers.
+pub fn span_usage() -> [SpanUsage; 3] {
+    let userland = user_address_space().vmas().map(|vma| vma.len()).sum();
+    [
+        SpanUsage::new("userland", &span::USERLAND, userland),
+        SpanUsage::new("heap", &span::HEAP, heap::stats().size),
+        SpanUsage::new("kbuffer", &span::KBUFFER, kbuffer::allocated_bytes()),
+    ]
+}
+
+/// A run of pages with the same size and flags, which map contiguous physical memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub start: VirtAddr,
+    pub frame: PhysAddr,
+    pub len: u64,
+    pub page_size: u64,
+    pub flags: PageTableFlags,
+}
+
+impl Mapping {
+    /// Whether the page directly follows this mapping, so that it can be merged into it.
+    fn is_continued_by(
+        &self,
+        start: VirtAddr,
+        frame: PhysAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> bool {
+        // the raw addresses don't panic at the end of the lower half of the address space
+        self.start.as_u64().checked_add(self.len) == Some(start.as_u64())
+            && self.frame.as_u64().checked_add(self.len) == Some(frame.as_u64())
+            && self.page_size == size
+            && self.flags == flags
+    }
+}
+
+/// Returns the mappings of the active page table in the `len` bytes from `start`.
+/// Adjacent pages are merged into one mapping if possible, and at most `limit`
+/// mappings are returned.
+pub fn mappings(start: VirtAddr, len: u64, limit: usize) -> Vec<Mapping> {
+    // the memory manager must not allocate while it is locked
+    let mut mappings: Vec<Mapping> = Vec::with_capacity(limit);
+    MemoryManager::lock().walk_mappings(start, len, |start, frame, size, flags| {
+        match mappings.last_mut() {
+            Some(last) if last.is_continued_by(start, frame, size, flags) => {
+                last.len += size;
+            }
+            _ if mappings.len() == limit => return false,
+            _ => mappings.push(Mapping {
+                start,
+                frame,
+                len: size,
+                page_size: size,
+                flags,
+            }),
+        }
+        true
+    });
+    mappings
+}
+
+/// Formats the memory statistics like `/proc/meminfo`, one `Name: value` pair per line.
+pub fn meminfo() -> String {
+    let frames = frame_stats();
+    let frame_kib = frames.frame_size / 1024;
+    let heap = heap::stats();
+    let [userland, _, kbuffer] = span_usage();
+    let lines = [
+        ("MemTotal", frames.usable as u64 * frame_kib),
+        ("MemFree", frames.free as u64 * frame_kib),
+        ("MemUsed", frames.allocated as u64 * frame_kib),
+        ("HeapTotal", heap.size as u64 / 1024),
+        ("HeapUsed", heap.used as u64 / 1024),
+        ("HeapLimit", heap.size_limit as u64 / 1024),
+        ("UserlandMapped", userland.used as u64 / 1024),
+        ("KBufferUsed", kbuffer.used as u64 / 1024),
+    ];
+    let mut out = String::new();
+    for (name, kib) in lines {
+        let _ = writeln!(out, "{}:{:>width$} kB", name, kib, width = 24 - name.len());
+    }
+    out
+}
+
+/// Formats the physical memory map, one region per line.
+pub fn memmap() -> String {
+    let mut out = String::new();
+    for region in memory_regions() {
+        let _ = writeln!(
+            out,
+            "{:#014x}-{:#014x} {:?}",
+            region.start, region.end, region.kind
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_frame_stats_add_up() {
+        let stats = frame_stats();
+        assert!(stats.allocated > 0);
+        assert_eq!(stats.usable, stats.allocated + stats.free);
+    }
+
+    #[test_case]
+    fn test_mappings_of_the_heap_are_merged() {
+        let size = heap::stats().size as u64;
+        let pages = (size / DefaultPageSize::SIZE) as usize;
+        let mappings = mappings(span::HEAP.start(), size, pages);
+        assert_eq!(span::HEAP.start(), mappings[0].start);
+        assert!(mappings[0].flags.contains(PageTableFlags::WRITABLE));
+        assert_eq!(size, mappings.iter().map(|m| m.len).sum::<u64>());
+        // most frames of the heap are contiguous
+        assert!(mappings.len() < pages);
+    }
+
+    #[test_case]
+    fn test_mapping_at_the_end_of_the_lower_half() {
+        let flags = PageTableFlags::PRESENT;
+        let mapping = Mapping {
+            start: VirtAddr::new(0x7fff_ffff_f000),
+            frame: PhysAddr::new(0x1000),
+            len: 0x1000,
+            page_size: 0x1000,
+            flags,
+        };
+        let upper_half = VirtAddr::new(0xffff_8000_0000_0000);
+        assert!(!mapping.is_continued_by(upper_half, PhysAddr::new(0x2000), 0x1000, flags));
+    }
+
+    #[test_case]
+    fn test_meminfo() {
+        let info = meminfo();
+        assert!(info.starts_with("MemTotal:"));
+        assert!(info.lines().all(|line| line.ends_with(" kB")));
+    }
+}
@@ -1,13 +1,20 @@
-use crate::memory::allocator::backend::lazy_page::{LazyPageMappingBackend, NoUnmap};
-use crate::memory::allocator::bump::BumpAllocator;
+use crate::memory::allocator::backend::lazy_page::{LazyPageMappingBackend, WithUnmap};
+use crate::memory::allocator::range::RangeAllocator;
 use crate::memory::heap::Locked;
 use crate::memory::span::KBUFFER;
-use crate::memory::{DefaultPageSize, Result};
+use crate::memory::{DefaultPageSize, Error, Result};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
+use x86_64::structures::paging::PageSize;
 
-static mut KBUFFER_HEAP: Locked<BumpAllocator<LazyPageMappingBackend<DefaultPageSize, NoUnmap>>> =
-    Locked::new(BumpAllocator::new(LazyPageMappingBackend::new()));
+/// KBuffers are made of whole pages, which are mapped on allocation, and unmapped
+/// and returned to the frame allocator when the buffer is dropped.
+static mut KBUFFER_HEAP: Locked<
+    RangeAllocator<LazyPageMappingBackend<DefaultPageSize, WithUnmap>>,
+> = Locked::new(RangeAllocator::new(
+    LazyPageMappingBackend::new(),
+    DefaultPageSize::SIZE as usize,
+));
 
 pub(in crate::memory) fn init_kbuffer_heap() -> Result<()> {
     /*
@@ -23,6 +30,27 @@ pub(in crate::memory) fn init_kbuffer_heap() -> Result<()> {
     Ok(())
 }
 
+/// The amount of bytes in the [`KBUFFER`] span that are allocated.
+pub fn allocated_bytes() -> usize {
+    KBUFFER.len() - unsafe { KBUFFER_HEAP.lock().free_bytes() }
+}
+
+/// Allocates whole pages for the given layout, which are mapped when they are allocated.
+/// Returns `None` if the [`KBUFFER`] span is exhausted.
+pub(in crate::memory) fn allocate_pages(layout: Layout) -> Option<NonNull<u8>> {
+    NonNull::new(unsafe { KBUFFER_HEAP.alloc(layout) })
+}
+
+/// Unmaps the pages of an allocation of [`allocate_pages`], and frees their frames.
+///
+/// # Safety
+///
+/// The pages must have been allocated by [`allocate_pages`] with the same layout, and
+/// must not be used afterwards.
+pub(in crate::memory) unsafe fn deallocate_pages(ptr: NonNull<u8>, layout: Layout) {
+    KBUFFER_HEAP.dealloc(ptr.as_ptr(), layout)
+}
+
 pub struct KBuffer {
     start: NonNull<u8>,
     len: usize,
@@ -36,20 +64,36 @@ impl KBuffer {
     }
 
     /// Allocates a buffer with the given size and an alignment of 64 bytes.
+    ///
+    /// Panics if the [`KBUFFER`] span is exhausted, see [`KBuffer::try_allocate`].
     pub fn allocate(size: usize) -> Self {
-        let layout = Layout::from_size_align(size, 64).unwrap();
-        Self::allocate_from_layout(layout)
+        Self::try_allocate(size).expect("did KBUFFER_HEAP run out of memory?")
+    }
+
+    /// Allocates a buffer with the given size and an alignment of 64 bytes, or returns
+    /// [`Error::OutOfMemory`] if the [`KBUFFER`] span is exhausted.
+    pub fn try_allocate(size: usize) -> Result<Self> {
+        let layout = Layout::from_size_align(size, 64).map_err(|_| Error::OutOfMemory)?;
+        Self::try_allocate_from_layout(layout)
     }
 
     /// Allocates a buffer with the given layout.
+    ///
+    /// Panics if the [`KBUFFER`] span is exhausted, see
+    /// [`KBuffer::try_allocate_from_layout`].
     pub fn allocate_from_layout(layout: Layout) -> Self {
+        Self::try_allocate_from_layout(layout).expect("did KBUFFER_HEAP run out of memory?")
+    }
+
+    /// Allocates a buffer with the given layout, or returns [`Error::OutOfMemory`] if the
+    /// [`KBUFFER`] span is exhausted.
+    pub fn try_allocate_from_layout(layout: Layout) -> Result<Self> {
         let start = unsafe { KBUFFER_HEAP.alloc_zeroed(layout) };
-        KBuffer {
-            start: NonNull::new(start)
-                .expect("did KBUFFER_HEAP run out of memory? (alloc returned 0)"),
+        Ok(KBuffer {
+            start: NonNull::new(start).ok_or(Error::OutOfMemory)?,
             len: layout.size(),
             allocation_layout: layout,
-        }
+        })
     }
 
     pub fn as_mut_ptr<T>(&self) -> *mut T {
@@ -0,0 +1,153 @@
+//! The kernel image as the bootloader loaded it. [`protect`] maps the segments of the
+//! image with the permissions of their ELF program headers, so that code is not
+//! writable and data is not executable.
+
+use goblin::elf::header::{ELFMAG, SELFMAG};
+use goblin::elf::program_header::{PF_W, PF_X, PT_LOAD};
+use goblin::elf64::header::{Header, SIZEOF_EHDR};
+use goblin::elf64::program_header::{ProgramHeader, SIZEOF_PHDR};
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::Page;
+use x86_64::VirtAddr;
+
+use crate::memory::manager::{MemoryKind, MemoryManager, UserAccessible};
+use crate::memory::{DefaultPageSize, Error, Result};
+
+extern "C" {
+    /// The first byte of the ELF header of the kernel, defined by the linker.
+    static __ehdr_start: u8;
+}
+
+/// A loadable segment of the kernel image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub start: VirtAddr,
+    pub len: u64,
+    pub kind: MemoryKind,
+}
+
+impl Segment {
+    fn from_program_header(header: &ProgramHeader) -> Result<Self> {
+        let kind = match (header.p_flags & PF_W != 0, header.p_flags & PF_X != 0) {
+            (true, true) => return Err(Error::WritableAndExecutable),
+            (true, false) => MemoryKind::Writable,
+            (false, true) => MemoryKind::Executable,
+            (false, false) => MemoryKind::ReadOnly,
+        };
+        Ok(Self {
+            start: VirtAddr::new(header.p_vaddr),
+            len: header.p_memsz,
+            kind,
+        })
+    }
+
+    fn pages(&self) -> PageRange<DefaultPageSize> {
+        let first = Page::containing_address(self.start);
+        let last = Page::containing_address(self.start + (self.len - 1));
+        Page::range(first, last + 1)
+    }
+}
+
+/// The program headers of the kernel, or `None` if the ELF header or the program
+/// headers are not mapped.
+fn program_headers() -> Option<&'static [ProgramHeader]> {
+    let start = unsafe { &__ehdr_start as *const u8 };
+    let header_addr = VirtAddr::from_ptr(start);
+    let is_mapped = |addr: VirtAddr, len: u64| {
+        let mm = MemoryManager::lock();
+        mm.translate(addr).is_some() && mm.translate(addr + (len - 1)).is_some()
+    };
+    if !is_mapped(header_addr, SIZEOF_EHDR as u64) {
+        return None;
+    }
+    let bytes = unsafe { &*(start as *const [u8; SIZEOF_EHDR]) };
+    if bytes[..SELFMAG] != ELFMAG[..] {
+        return None;
+    }
+    let header = Header::from_bytes(bytes);
+    if header.e_phentsize as usize != SIZEOF_PHDR || header.e_phnum == 0 {
+        return None;
+    }
+    // the program headers are part of the first segment, just like the ELF header
+    let headers_addr = header_addr + header.e_phoff;
+    if !is_mapped(headers_addr, header.e_phnum as u64 * SIZEOF_PHDR as u64) {
+        return None;
+    }
+    Some(unsafe { ProgramHeader::from_raw_parts(headers_addr.as_ptr(), header.e_phnum as usize) })
+}
+
+/// The loadable segments of the kernel, or `None` if the program headers of the kernel
+/// are not mapped.
+pub fn segments() -> Option<impl Iterator<Item = Result<Segment>>> {
+    let headers = program_headers()?;
+    Some(
+        headers
+            .iter()
+            .filter(|header| header.p_type == PT_LOAD && header.p_memsz > 0)
+            .map(Segment::from_program_header),
+    )
+}
+
+/// Maps the pages of every loadable segment of the kernel with the permissions of the
+/// segment. Returns the number of segments, or [`Error::NotMapped`] if the program
+/// headers of the kernel are not mapped.
+pub(in crate::memory) fn protect() -> Result<usize> {
+    let mut count = 0;
+    for segment in segments().ok_or(Error::NotMapped)? {
+        let segment = segment?;
+        MemoryManager::lock().update_flags(segment.pages(), segment.kind, UserAccessible::No)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use x86_64::structures::paging::{PageSize, PageTableFlags};
+
+    use super::*;
+
+    #[test_case]
+    fn test_code_is_not_writable_and_data_is_not_executable() {
+        let segments = match segments() {
+            Some(segments) => segments,
+            None => return,
+        };
+        for segment in segments {
+            let segment = segment.unwrap();
+            let mut flags = None;
+            MemoryManager::lock().walk_mappings(segment.start, 1, |_, _, _, f| {
+                flags = Some(f);
+                false
+            });
+            let flags = flags.unwrap();
+            match segment.kind {
+                MemoryKind::Executable => {
+                    assert!(!flags.contains(PageTableFlags::WRITABLE));
+                    assert!(!flags.contains(PageTableFlags::NO_EXECUTE));
+                }
+                _ => assert!(flags.contains(PageTableFlags::NO_EXECUTE)),
+            }
+        }
+    }
+
+    #[test_case]
+    fn test_segments_are_page_aligned_apart() {
+        let segments = match segments() {
+            Some(segments) => segments,
+            None => return,
+        };
+        let mut previous_end: Option<VirtAddr> = None;
+        for segment in segments {
+            let segment = segment.unwrap();
+            if let Some(end) = previous_end {
+                // segments with different permissions must not share a page
+                assert!(
+                    end.align_up(DefaultPageSize::SIZE)
+                        <= segment.start.align_down(DefaultPageSize::SIZE)
+                );
+            }
+            previous_end = Some(segment.start + segment.len);
+        }
+    }
+}
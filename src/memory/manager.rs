@@ -1,18 +1,39 @@
-use crate::memory::physical::PhysicalFrameAllocator;
+use crate::memory::physical::{FrameReferences, PhysicalFrameAllocator};
+use crate::memory::span::{SPANS, USERLAND};
 use crate::memory::Error;
 use crate::memory::Result;
+use core::arch::x86_64::_mm_clflush;
 use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut, Range, RangeInclusive};
+use core::sync::atomic::{AtomicBool, Ordering};
 use kstd::sync::{Mutex, MutexGuard};
+use x86_64::instructions::{interrupts, tlb};
+use x86_64::registers::control::Cr3;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateError, TranslateResult};
 use x86_64::structures::paging::page::PageRange;
 use x86_64::structures::paging::{
-    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags,
-    PhysFrame, Size4KiB,
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableEntry, PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
 };
-use x86_64::VirtAddr;
+use x86_64::{PhysAddr, VirtAddr};
 
-static mut MEMORY_MANAGER: Option<
-    Mutex<MemoryManager<Size4KiB, OffsetPageTable<'static>, PhysicalFrameAllocator<Size4KiB>>>,
-> = None;
+/// Marks pages that are mapped read-only because their frame is shared, but that may
+/// be written to. The first write copies the frame, see [`resolve_page_fault`].
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+type KernelMemoryManager =
+    MemoryManager<Size4KiB, OffsetPageTable<'static>, PhysicalFrameAllocator<Size4KiB>>;
+
+static mut MEMORY_MANAGER: Option<Mutex<KernelMemoryManager>> = None;
+
+/// Whether a [`MemoryManagerGuard`] exists, so that the page fault handler can tell
+/// that locking the memory manager would never return. It is only changed while the
+/// memory manager is locked.
+static MEMORY_MANAGER_LOCKED: AtomicBool = AtomicBool::new(false);
 
 pub(in crate::memory) fn init_memory_manager(
     page_table: OffsetPageTable<'static>,
@@ -46,6 +67,41 @@ pub enum MemoryKind {
     Executable,
 }
 
+/// How the processor caches memory that devices access as well.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CacheMode {
+    /// Accesses are cached, which is the default for normal memory.
+    WriteBack,
+    /// Every access goes to memory, in order.
+    Uncached,
+    /// Writes are collected in a buffer and written to memory in bursts, reads are
+    /// not cached. Fits buffers that are mostly written by the processor.
+    WriteCombining,
+}
+
+impl CacheMode {
+    pub(in crate::memory) fn page_table_flags(self) -> PageTableFlags {
+        match self {
+            CacheMode::WriteBack => PageTableFlags::empty(),
+            CacheMode::Uncached => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+            // selects entry 1 of the page attribute table, see `PAGE_ATTRIBUTES`
+            CacheMode::WriteCombining => PageTableFlags::WRITE_THROUGH,
+        }
+    }
+}
+
+/// The page attribute table, which defines the memory types that the cache flags of
+/// page table entries select. It is the default of the processor, except for entry 1,
+/// which is write-combining instead of write-through.
+const PAGE_ATTRIBUTES: u64 = 0x0007_0406_0007_0106;
+
+/// Loads [`PAGE_ATTRIBUTES`], which every x86_64 processor supports. Must be called
+/// before memory is mapped with [`CacheMode::WriteCombining`].
+pub(in crate::memory) fn init_page_attribute_table() {
+    unsafe { Msr::new(0x277).write(PAGE_ATTRIBUTES) };
+    tlb::flush_all();
+}
+
 pub struct MemoryManager<S, M, A>
 where
     S: PageSize,
@@ -55,12 +111,647 @@ where
     _page_size: PhantomData<S>,
 }
 
+/// Resolves a page fault if it was caused by writing to a copy-on-write page, and
+/// returns whether it did. The memory manager is locked with interrupts disabled, so
+/// it is only locked during a fault if the faulting code locked it itself. Such faults
+/// are never resolved, since the lock would never be released.
+pub fn resolve_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    let write_to_present_page =
+        PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if !error_code.contains(write_to_present_page) {
+        return false;
+    }
+    match MemoryManager::try_lock() {
+        Some(mut mm) => mm.resolve_copy_on_write(addr).unwrap_or(false),
+        None => false,
+    }
+}
+
+/// The locked memory manager, see [`MemoryManager::lock`]. Interrupts are disabled
+/// while the guard exists.
+pub struct MemoryManagerGuard {
+    guard: ManuallyDrop<MutexGuard<'static, KernelMemoryManager>>,
+    /// Whether interrupts were enabled before the memory manager was locked.
+    interrupts_enabled: bool,
+}
+
+impl Deref for MemoryManagerGuard {
+    type Target = KernelMemoryManager;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for MemoryManagerGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl Drop for MemoryManagerGuard {
+    fn drop(&mut self) {
+        MEMORY_MANAGER_LOCKED.store(false, Ordering::SeqCst);
+        // the lock has to be released before interrupts are enabled again
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.interrupts_enabled {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Returns [`Error::WritableAndExecutable`] if the given flags map memory that is both
+/// writable and executable.
+fn check_write_xor_execute(flags: PageTableFlags) -> Result<()> {
+    if flags.contains(PageTableFlags::WRITABLE) && !flags.contains(PageTableFlags::NO_EXECUTE) {
+        Err(Error::WritableAndExecutable)
+    } else {
+        Ok(())
+    }
+}
+
+/// The bits of a virtual address that are translated by the page table.
+const ADDRESS_MASK: u64 = (1 << 48) - 1;
+
+/// The indices of the level 4 entries that cover the range from `start` to `end`.
+fn level_4_indices(start: VirtAddr, end: VirtAddr) -> RangeInclusive<usize> {
+    usize::from(start.p4_index())..=usize::from((end - 1_u64).p4_index())
+}
+
+impl KernelMemoryManager {
+    /// Locks the memory manager, which then operates on the active page table.
+    /// Interrupts are disabled until the guard is dropped, so that the lock is never
+    /// held by a preempted task.
+    pub fn lock() -> MemoryManagerGuard {
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+        let mut mm = unsafe { MEMORY_MANAGER.as_ref().unwrap().lock() };
+        MEMORY_MANAGER_LOCKED.store(true, Ordering::SeqCst);
+        let (active, _) = Cr3::read();
+        if mm.level_4_frame() != active {
+            mm.page_table = unsafe { mm.page_table_at(active) };
+        }
+        MemoryManagerGuard {
+            guard: ManuallyDrop::new(mm),
+            interrupts_enabled,
+        }
+    }
+
+    /// Locks the memory manager like [`MemoryManager::lock`], or returns `None` if it
+    /// is already locked, which can only be the case if the caller locked it itself,
+    /// for example in a page fault caused while the memory manager was locked.
+    pub fn try_lock() -> Option<MemoryManagerGuard> {
+        if MEMORY_MANAGER_LOCKED.load(Ordering::SeqCst) {
+            None
+        } else {
+            Some(Self::lock())
+        }
+    }
+
+    /// The frame of the level 4 table that the memory manager operates on.
+    pub fn level_4_frame(&mut self) -> PhysFrame {
+        let offset = self.page_table.phys_offset();
+        let table = self.page_table.level_4_table() as *mut PageTable as u64;
+        PhysFrame::containing_address(PhysAddr::new(table - offset.as_u64()))
+    }
+
+    unsafe fn page_table_at(&self, level_4_frame: PhysFrame) -> OffsetPageTable<'static> {
+        OffsetPageTable::new(self.table_mut(level_4_frame), self.page_table.phys_offset())
+    }
+
+    /// Calls `f` with the memory manager operating on the page table in the given
+    /// frame instead of the active one.
+    ///
+    /// # Safety
+    ///
+    /// The frame must contain a level 4 table that shares the kernel mappings of the
+    /// active one.
+    pub unsafe fn with_page_table<F, R>(&mut self, level_4_frame: PhysFrame, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let table = self.page_table_at(level_4_frame);
+        let active = core::mem::replace(&mut self.page_table, table);
+        let result = f(self);
+        self.page_table = active;
+        result
+    }
+
+    /// Creates a new level 4 table that shares all mappings of the active one, except
+    /// for the mappings between `start` and `end`, which are private to the table.
+    /// Kernel mappings that are created later are shared as well, since the level 3
+    /// tables of the kernel spans are allocated up front, see
+    /// [`MemoryManager::allocate_kernel_tables`].
+    pub fn create_page_table(&mut self, start: VirtAddr, end: VirtAddr) -> Result<PhysFrame> {
+        let frame = self.allocate_frame()?;
+        let private = level_4_indices(start, end);
+        let table = unsafe { self.table_mut(frame) };
+        table.zero();
+        let active = self.page_table.level_4_table();
+        for (index, entry) in active.iter().enumerate() {
+            if !private.contains(&index) {
+                table[index] = entry.clone();
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Allocates the level 3 tables of all spans except [`USERLAND`], so that every
+    /// level 4 table that is copied from the active one shares them, and kernel
+    /// mappings appear in all address spaces. Returns the number of allocated tables.
+    pub fn allocate_kernel_tables(&mut self) -> Result<usize> {
+        let mut allocated = 0;
+        for span in SPANS.iter().filter(|span| span.name() != USERLAND.name()) {
+            let end = span.start() + span.len();
+            for index in level_4_indices(span.start(), end) {
+                if !self.page_table.level_4_table()[index].is_unused() {
+                    continue;
+                }
+                let frame = self.allocate_frame()?;
+                unsafe { self.table_mut(frame) }.zero();
+                self.page_table.level_4_table()[index]
+                    .set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+                allocated += 1;
+            }
+        }
+        Ok(allocated)
+    }
+
+    /// Deallocates a level 4 table that was created with
+    /// [`MemoryManager::create_page_table`], together with the tables of its private
+    /// mappings.
+    ///
+    /// # Safety
+    ///
+    /// The table must not be active, and there must be no pages left in the private
+    /// range.
+    pub unsafe fn free_page_table(
+        &mut self,
+        level_4_frame: PhysFrame,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) {
+        let table = self.table_mut(level_4_frame);
+        for index in level_4_indices(start, end) {
+            self.free_table(&table[index], 3);
+        }
+        self.physical_frame_allocator
+            .deallocate_frame(level_4_frame);
+    }
+
+    /// Deallocates the table that the entry points to, which is a table of the given
+    /// level, and all tables below it.
+    unsafe fn free_table(&mut self, entry: &PageTableEntry, level: usize) {
+        let frame = match entry.frame() {
+            Ok(frame) => frame,
+            Err(_) => return, // unused, or a huge page
+        };
+        if level > 1 {
+            let table = self.table_mut(frame);
+            for entry in table.iter() {
+                self.free_table(entry, level - 1);
+            }
+        }
+        self.physical_frame_allocator.deallocate_frame(frame);
+    }
+
+    unsafe fn table_mut(&self, frame: PhysFrame) -> &'static mut PageTable {
+        let offset = self.page_table.phys_offset();
+        &mut *(offset + frame.start_address().as_u64()).as_mut_ptr::<PageTable>()
+    }
+
+    /// Shares the frame of the given page with another mapping. A writable page is
+    /// mapped read-only and marked as copy-on-write. Returns the frame and the flags
+    /// that the other mapping has to use, or `None` if the page is not mapped.
+    pub fn share_page(&mut self, page: Page) -> Result<Option<(PhysFrame, PageTableFlags)>> {
+        while self.split_huge_page(page.start_address())? {}
+        let (frame, mut flags) = match self.page_table.translate(page.start_address()) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags,
+                ..
+            } => (frame, flags),
+            _ => return Ok(None),
+        };
+        if flags.contains(PageTableFlags::WRITABLE) {
+            flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+            unsafe { self.page_table.update_flags(page, flags) }?.flush();
+        }
+        self.physical_frame_allocator.add_reference(frame);
+        Ok(Some((frame, flags)))
+    }
+
+    /// Maps a frame that was shared with [`MemoryManager::share_page`].
+    pub fn map_shared_page(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<()> {
+        check_write_xor_execute(flags)?;
+        // the parent tables have to be writable, so that copy-on-write pages can be
+        // made writable later
+        let table_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | (flags & PageTableFlags::USER_ACCESSIBLE);
+        let pt = &mut self.page_table;
+        let fa = &mut self.physical_frame_allocator;
+        unsafe { pt.map_to_with_table_flags(page, frame, flags, table_flags, fa) }?.flush();
+        Ok(())
+    }
+
+    /// Allocates physically contiguous frames for the given pages, the first of which
+    /// is aligned to `align` bytes and all of which end at or below the physical address
+    /// `limit`, and maps them writable with the given cache mode. Returns the address of
+    /// the first frame.
+    pub fn allocate_and_map_contiguous(
+        &mut self,
+        pages: PageRange,
+        align: u64,
+        limit: u64,
+        cache_mode: CacheMode,
+    ) -> Result<PhysAddr> {
+        let frames = self
+            .physical_frame_allocator
+            .allocate_contiguous_below(pages.count(), align, limit)
+            .ok_or(Error::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE
+            | cache_mode.page_table_flags();
+        for (frame, page) in frames.zip(pages) {
+            if let Err(e) = self.map_frame_to_page(frame, page, flags) {
+                // the frames of the mapped pages are deallocated with the pages
+                self.deallocate_and_unmap_page_range(Page::range(pages.start, page))?;
+                for frame in PhysFrame::range(frame, frames.end) {
+                    unsafe { self.physical_frame_allocator.deallocate_frame(frame) };
+                }
+                return Err(e);
+            }
+        }
+        if let Err(e) = self.set_physical_cache_mode(frames, cache_mode) {
+            self.set_physical_cache_mode(frames, CacheMode::WriteBack)?;
+            self.deallocate_and_unmap_page_range(pages)?;
+            return Err(e);
+        }
+        Ok(frames.start.start_address())
+    }
+
+    /// Sets the cache mode of the given frames in the mapping of the complete physical
+    /// memory. Frames that are mapped with another cache mode elsewhere must have the
+    /// same mode there, since the processor doesn't keep aliases with different memory
+    /// types coherent. Frames must be set back to [`CacheMode::WriteBack`] before they
+    /// are deallocated.
+    pub fn set_physical_cache_mode(
+        &mut self,
+        frames: PhysFrameRange,
+        cache_mode: CacheMode,
+    ) -> Result<()> {
+        let offset = self.page_table.phys_offset();
+        let cache_flags = PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+        for frame in frames {
+            let addr = offset + frame.start_address().as_u64();
+            while self.split_huge_page(addr)? {}
+            if cache_mode != CacheMode::WriteBack {
+                // lines that are still cached would be written back over the data of
+                // the device later
+                for line in (0..Size4KiB::SIZE).step_by(64) {
+                    unsafe { _mm_clflush((addr + line).as_ptr()) };
+                }
+            }
+            let entry = unsafe { self.entry(addr, 1) };
+            entry.set_flags((entry.flags() - cache_flags) | cache_mode.page_table_flags());
+            tlb::flush(addr);
+        }
+        Ok(())
+    }
+
+    /// Makes the copy-on-write page that contains the given address writable. If its
+    /// frame is still shared, the page gets a copy of the frame. Returns whether the
+    /// page was a copy-on-write page.
+    pub fn resolve_copy_on_write(&mut self, addr: VirtAddr) -> Result<bool> {
+        let page = Page::<Size4KiB>::containing_address(addr);
+        let (frame, flags) = match self.page_table.translate(addr) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags,
+                ..
+            } if flags.contains(COPY_ON_WRITE) => (frame, flags),
+            _ => return Ok(false),
+        };
+        let flags = (flags | PageTableFlags::WRITABLE) - COPY_ON_WRITE;
+
+        if self.physical_frame_allocator.references(frame) == 1 {
+            unsafe { self.page_table.update_flags(page, flags) }?.flush();
+            return Ok(true);
+        }
+        let copy = self.allocate_frame()?;
+        let offset = self.page_table.phys_offset();
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                (offset + frame.start_address().as_u64()).as_ptr::<u8>(),
+                (offset + copy.start_address().as_u64()).as_mut_ptr::<u8>(),
+                Size4KiB::SIZE as usize,
+            );
+        }
+        self.page_table.unmap(page)?.1.flush();
+        self.map_frame_to_page(copy, page, flags)?;
+        self.physical_frame_allocator.remove_reference(frame);
+        Ok(true)
+    }
+}
+
+/// Whether the processor supports 1 GiB pages.
+fn supports_1gib_pages() -> bool {
+    #[allow(unused_unsafe)] // the intrinsic is safe on newer compilers
+    let features = unsafe { core::arch::x86_64::__cpuid(0x8000_0001) };
+    features.edx & (1 << 26) != 0
+}
+
+/// Mapping with huge pages. Huge pages are backed by physically contiguous 4 KiB
+/// frames, which are deallocated one by one, so a huge page can be split into smaller
+/// pages at any time.
 impl MemoryManager<Size4KiB, OffsetPageTable<'static>, PhysicalFrameAllocator<Size4KiB>> {
-    pub fn lock() -> MutexGuard<
-        'static,
-        MemoryManager<Size4KiB, OffsetPageTable<'static>, PhysicalFrameAllocator<Size4KiB>>,
-    > {
-        unsafe { MEMORY_MANAGER.as_ref().unwrap().lock() }
+    /// Maps the pages of the given range that are not mapped yet. Parts of the range
+    /// that cover a whole 2 MiB or 1 GiB page which is not mapped at all are mapped
+    /// with a huge page, if enough contiguous frames are available.
+    pub fn map_with_huge_pages(
+        &mut self,
+        range: PageRange,
+        memory_kind: MemoryKind,
+        user_accessible: UserAccessible,
+    ) -> Result<()> {
+        let flags = Self::translate_page_table_flags(memory_kind, user_accessible);
+        let mut page = range.start;
+        while page < range.end {
+            let remaining = range.end - page;
+            if supports_1gib_pages() {
+                if let Some(count) = self.try_map_huge_page::<Size1GiB>(page, remaining, flags)? {
+                    page += count;
+                    continue;
+                }
+            }
+            if let Some(count) = self.try_map_huge_page::<Size2MiB>(page, remaining, flags)? {
+                page += count;
+                continue;
+            }
+            if matches!(
+                self.page_table.translate_page(page),
+                Err(TranslateError::PageNotMapped)
+            ) {
+                let frame = self.allocate_frame()?;
+                self.map_frame_to_page(frame, page, flags)?;
+            }
+            page += 1;
+        }
+        Ok(())
+    }
+
+    /// Maps a huge page of size `H` at the given page, if it is aligned, the remaining
+    /// `count` pages cover it and nothing is mapped there yet. Returns the number of
+    /// 4 KiB pages that were mapped.
+    fn try_map_huge_page<H: PageSize>(
+        &mut self,
+        page: Page,
+        count: u64,
+        flags: PageTableFlags,
+    ) -> Result<Option<u64>>
+    where
+        OffsetPageTable<'static>: Mapper<H>,
+    {
+        check_write_xor_execute(flags)?;
+        let pages = H::SIZE / Size4KiB::SIZE;
+        let huge_page = match Page::<H>::from_start_address(page.start_address()) {
+            Ok(huge_page) if count >= pages => huge_page,
+            _ => return Ok(None),
+        };
+        if !matches!(
+            self.page_table.translate_page(huge_page),
+            Err(TranslateError::PageNotMapped)
+        ) {
+            return Ok(None);
+        }
+        let frames = match self
+            .physical_frame_allocator
+            .allocate_contiguous(pages as usize, H::SIZE)
+        {
+            Some(frames) => frames,
+            None => return Ok(None),
+        };
+
+        let frame = PhysFrame::<H>::containing_address(frames.start.start_address());
+        let table_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | (flags & PageTableFlags::USER_ACCESSIBLE);
+        let pt = &mut self.page_table;
+        let fa = &mut self.physical_frame_allocator;
+        match unsafe { pt.map_to_with_table_flags(huge_page, frame, flags, table_flags, fa) } {
+            Ok(flush) => flush.flush(),
+            Err(e) => {
+                frames.for_each(|frame| unsafe { fa.deallocate_frame(frame) });
+                return Err(e.into());
+            }
+        }
+        Ok(Some(pages))
+    }
+
+    /// The size of the page that maps the given address, or `None` if the address is
+    /// not mapped.
+    pub fn mapped_page_size(&self, addr: VirtAddr) -> Option<u64> {
+        match self.page_table.translate(addr) {
+            TranslateResult::Mapped { frame, .. } => Some(frame.size()),
+            _ => None,
+        }
+    }
+
+    /// Calls `f` with the address, frame, size and flags of every page that is
+    /// mapped in the `len` bytes from `start`, in order, until `f` returns false. The
+    /// flags are the ones of the entry that maps the page.
+    pub fn walk_mappings<F>(&mut self, start: VirtAddr, len: u64, mut f: F)
+    where
+        F: FnMut(VirtAddr, PhysAddr, u64, PageTableFlags) -> bool,
+    {
+        self.walk_entries(start, len, |addr, entry, size| {
+            f(addr, entry.addr(), size, entry.flags())
+        });
+    }
+
+    /// Adds the no-execute flag to every writable page of the active page table, and
+    /// returns the number of entries that were changed. The bootloader maps for example
+    /// the physical memory writable and executable.
+    pub fn enforce_write_xor_execute(&mut self) -> usize {
+        let mut count = 0;
+        self.walk_entries(VirtAddr::zero(), ADDRESS_MASK + 1, |_, entry, _| {
+            let flags = entry.flags();
+            if check_write_xor_execute(flags).is_err() {
+                entry.set_flags(flags | PageTableFlags::NO_EXECUTE);
+                count += 1;
+            }
+            true
+        });
+        tlb::flush_all();
+        count
+    }
+
+    /// Calls `f` with the address, entry and page size of every page that is mapped in
+    /// the `len` bytes from `start`, in order, until `f` returns false.
+    fn walk_entries<F>(&mut self, start: VirtAddr, len: u64, mut f: F)
+    where
+        F: FnMut(VirtAddr, &mut PageTableEntry, u64) -> bool,
+    {
+        // compare addresses without the sign extension, so that they follow the order
+        // of the table entries
+        let start = start.as_u64() & ADDRESS_MASK;
+        let end = start.saturating_add(len).min(ADDRESS_MASK + 1);
+        let level_4 = self.page_table.level_4_table() as *mut PageTable;
+        unsafe { self.walk_table(&mut *level_4, 4, 0, start..end, &mut f) };
+    }
+
+    /// Walks the entries of a table of the given level, whose first entry maps the
+    /// address `base`. Returns false if the walk was stopped.
+    unsafe fn walk_table<F>(
+        &self,
+        table: &mut PageTable,
+        level: u32,
+        base: u64,
+        range: Range<u64>,
+        f: &mut F,
+    ) -> bool
+    where
+        F: FnMut(VirtAddr, &mut PageTableEntry, u64) -> bool,
+    {
+        let entry_size = Size4KiB::SIZE << (9 * (level - 1));
+        for (index, entry) in table.iter_mut().enumerate() {
+            let entry_start = base + index as u64 * entry_size;
+            let flags = entry.flags();
+            if entry_start + entry_size <= range.start
+                || entry_start >= range.end
+                || !flags.contains(PageTableFlags::PRESENT)
+            {
+                continue;
+            }
+            let walked = if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+                f(VirtAddr::new_truncate(entry_start), entry, entry_size)
+            } else {
+                let next = self.table_mut(PhysFrame::containing_address(entry.addr()));
+                self.walk_table(next, level - 1, entry_start, range.clone(), f)
+            };
+            if !walked {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Splits the huge page that contains the given address into pages of the next
+    /// smaller size, which map the same frames with the same flags. Returns whether
+    /// there was a huge page.
+    ///
+    /// The new table replaces the huge page in one step, so the memory stays
+    /// accessible, even if it is the stack of the caller.
+    pub fn split_huge_page(&mut self, addr: VirtAddr) -> Result<bool> {
+        let (level, small_size) = match self.page_table.translate(addr) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size2MiB(_),
+                ..
+            } => (2, Size4KiB::SIZE),
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size1GiB(_),
+                ..
+            } => (3, Size2MiB::SIZE),
+            _ => return Ok(false),
+        };
+        let table_frame = self.allocate_frame()?;
+        let entry = unsafe { self.entry(addr, level) };
+        let table = unsafe { self.table_mut(table_frame) };
+
+        let flags = entry.flags();
+        let small_flags = if small_size == Size4KiB::SIZE {
+            // the bit means something else in the lowest level
+            flags - PageTableFlags::HUGE_PAGE
+        } else {
+            flags
+        };
+        for (index, small) in table.iter_mut().enumerate() {
+            small.set_addr(entry.addr() + index as u64 * small_size, small_flags);
+        }
+        let table_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | (flags & PageTableFlags::USER_ACCESSIBLE);
+        entry.set_frame(table_frame, table_flags);
+        tlb::flush_all();
+        Ok(true)
+    }
+
+    /// The entry of the table of the given level that maps the given address. The
+    /// tables above it must exist and must not map huge pages.
+    unsafe fn entry(&mut self, addr: VirtAddr, level: usize) -> &'static mut PageTableEntry {
+        let indices = [
+            addr.p4_index(),
+            addr.p3_index(),
+            addr.p2_index(),
+            addr.p1_index(),
+        ];
+        let mut table = &mut *(self.page_table.level_4_table() as *mut PageTable);
+        for &index in &indices[..4 - level] {
+            let frame = table[index].frame().expect("page table walk failed");
+            table = self.table_mut(frame);
+        }
+        &mut table[indices[4 - level]]
+    }
+
+    /// Unmaps the given page, and deallocates its frame unless it is still mapped
+    /// elsewhere. A huge page that contains the page is split first, and the tables that
+    /// don't map anything anymore afterwards are deallocated.
+    pub fn deallocate_and_unmap_page(&mut self, addr: VirtAddr) -> Result<()> {
+        while self.split_huge_page(addr)? {}
+        let page = Page::<Size4KiB>::containing_address(addr);
+        let (frame, flush) = self.page_table.unmap(page)?;
+        flush.flush();
+        if self.physical_frame_allocator.remove_reference(frame) {
+            unsafe { self.physical_frame_allocator.deallocate_frame(frame) };
+        }
+        self.free_empty_tables(addr);
+        Ok(())
+    }
+
+    /// Deallocates the level 1 and then the level 2 table that maps the given address,
+    /// if it is empty, and clears the entry that points to it, like
+    /// [`MemoryManager::free_page_table`] does. Level 3 tables are kept, since they may
+    /// be shared with other page tables, and so are tables of the bootloader.
+    fn free_empty_tables(&mut self, addr: VirtAddr) {
+        for level in 1..=2 {
+            let parent = unsafe { self.entry(addr, level + 1) };
+            let frame = match parent.frame() {
+                Ok(frame) => frame,
+                Err(_) => return,
+            };
+            let table = unsafe { self.table_mut(frame) };
+            if !table.iter().all(PageTableEntry::is_unused)
+                || !self.physical_frame_allocator.is_allocated(frame)
+            {
+                return;
+            }
+            parent.set_unused();
+            // also invalidates the cached entries of the table
+            tlb::flush(addr);
+            unsafe { self.physical_frame_allocator.deallocate_frame(frame) };
+        }
+    }
+
+    /// Unmaps the given pages and returns their frames to the frame allocator. Pages
+    /// that are not mapped are skipped.
+    pub fn deallocate_and_unmap_page_range(&mut self, range: PageRange) -> Result<()> {
+        for page in range {
+            if self
+                .page_table
+                .translate_addr(page.start_address())
+                .is_some()
+            {
+                self.deallocate_and_unmap_page(page.start_address())?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -79,11 +770,20 @@ where
     }
 }
 
+impl<S, M, A> MemoryManager<S, M, A>
+where
+    S: PageSize,
+{
+    pub fn physical_frame_allocator(&self) -> &A {
+        &self.physical_frame_allocator
+    }
+}
+
 impl<S, M, A> MemoryManager<S, M, A>
 where
     S: PageSize,
     M: Mapper<S>,
-    A: FrameAllocator<S> + FrameAllocator<Size4KiB>, // 4KiB required since page table mapping pages are 4KiB
+    A: FrameAllocator<S> + FrameAllocator<Size4KiB> + FrameReferences<S>, // 4KiB required since page table mapping pages are 4KiB
 {
     pub fn ensure_is_mapped(
         &mut self,
@@ -95,7 +795,7 @@ where
 
         for page in range {
             let translate_result = self.page_table.translate_page(page);
-            if matches!(translate_result, Err(_)) {
+            if matches!(translate_result, Err(TranslateError::PageNotMapped)) {
                 let frame = self.allocate_frame()?;
                 self.map_frame_to_page(frame, page, page_table_flags)?;
             }
@@ -103,6 +803,28 @@ where
         Ok(())
     }
 
+    /// Changes the flags of the given pages, which must be mapped. Writable pages
+    /// whose frame is shared become copy-on-write pages.
+    pub fn update_flags(
+        &mut self,
+        range: PageRange<S>,
+        memory_kind: MemoryKind,
+        user_accessible: UserAccessible,
+    ) -> Result<()> {
+        let page_table_flags = Self::translate_page_table_flags(memory_kind, user_accessible);
+        for page in range {
+            let frame = self.page_table.translate_page(page)?;
+            let mut flags = page_table_flags;
+            if flags.contains(PageTableFlags::WRITABLE)
+                && self.physical_frame_allocator.references(frame) > 1
+            {
+                flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+            }
+            unsafe { self.page_table.update_flags(page, flags) }?.flush();
+        }
+        Ok(())
+    }
+
     fn translate_page_table_flags(
         memory_kind: MemoryKind,
         user_accessible: UserAccessible,
@@ -142,6 +864,29 @@ where
         Ok(())
     }
 
+    /// Maps the given frames to the given pages as uncacheable memory, for example
+    /// for memory mapped device registers. The frames are not taken from the frame
+    /// allocator, and both ranges must have the same length.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the frames are not used as regular memory,
+    /// and that the pages are not in use.
+    pub unsafe fn map_device_memory(
+        &mut self,
+        frames: PhysFrameRange<S>,
+        pages: PageRange<S>,
+    ) -> Result<()> {
+        let flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE
+            | CacheMode::Uncached.page_table_flags();
+        for (frame, page) in frames.zip(pages) {
+            self.map_frame_to_page(frame, page, flags)?;
+        }
+        Ok(())
+    }
+
     fn allocate_frame(&mut self) -> Result<PhysFrame<S>> {
         self.physical_frame_allocator
             .allocate_frame()
@@ -154,6 +899,7 @@ where
         page: Page<S>,
         flags: PageTableFlags,
     ) -> Result<()> {
+        check_write_xor_execute(flags)?;
         let pt = &mut self.page_table;
         let fa = &mut self.physical_frame_allocator;
         unsafe { pt.map_to::<A>(page, frame, flags, fa) }?.flush();
@@ -164,14 +910,149 @@ where
 impl<S, M, A> MemoryManager<S, M, A>
 where
     S: PageSize,
-    M: Mapper<S>,
-    A: FrameDeallocator<S>,
+    M: Translate,
 {
-    pub fn deallocate_and_unmap_page(&mut self, addr: VirtAddr) -> Result<()> {
-        let page = Page::<S>::containing_address(addr);
-        let (frame, flush) = self.page_table.unmap(page)?;
-        flush.flush();
-        unsafe { self.physical_frame_allocator.deallocate_frame(frame) };
-        Ok(())
+    /// Translates the given virtual address into the physical address that it is mapped
+    /// to, or returns `None` if the address is not mapped.
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.page_table.translate_addr(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::span::{self, USERLAND};
+
+    use super::*;
+
+    #[test_case]
+    fn test_huge_page_is_split_when_partially_unmapped() {
+        // a part of the userland span that no other test uses
+        let start = VirtAddr::new(x86_64::align_up(
+            USERLAND.start().as_u64() + 0x1000_0000,
+            Size2MiB::SIZE,
+        ));
+        let pages = Page::range(
+            Page::containing_address(start),
+            Page::containing_address(start + Size2MiB::SIZE),
+        );
+        let mut mm = MemoryManager::lock();
+        mm.map_with_huge_pages(pages, MemoryKind::Writable, UserAccessible::No)
+            .unwrap();
+        assert_eq!(Some(Size2MiB::SIZE), mm.mapped_page_size(start));
+        unsafe { start.as_mut_ptr::<u64>().write_volatile(42) };
+
+        let second = start + Size4KiB::SIZE;
+        mm.deallocate_and_unmap_page(second).unwrap();
+        assert_eq!(Some(Size4KiB::SIZE), mm.mapped_page_size(start));
+        assert_eq!(None, mm.mapped_page_size(second));
+        assert_eq!(42, unsafe { start.as_ptr::<u64>().read_volatile() });
+
+        mm.deallocate_and_unmap_page_range(pages).unwrap();
+        assert_eq!(None, mm.mapped_page_size(start));
+    }
+
+    #[test_case]
+    fn test_frames_of_a_split_huge_page_are_returned() {
+        // a part of the userland span that no other test uses
+        let start = VirtAddr::new(x86_64::align_up(
+            USERLAND.start().as_u64() + 0x3000_0000,
+            Size2MiB::SIZE,
+        ));
+        let pages = Page::range(
+            Page::containing_address(start),
+            Page::containing_address(start + Size2MiB::SIZE),
+        );
+        let mut mm = MemoryManager::lock();
+        // map the level 3 table, which is kept, before counting
+        mm.map_with_huge_pages(pages, MemoryKind::Writable, UserAccessible::No)
+            .unwrap();
+        mm.deallocate_and_unmap_page_range(pages).unwrap();
+
+        let before = mm.physical_frame_allocator().allocated_frames();
+        mm.map_with_huge_pages(pages, MemoryKind::Writable, UserAccessible::No)
+            .unwrap();
+        assert_eq!(Some(Size2MiB::SIZE), mm.mapped_page_size(start));
+        mm.deallocate_and_unmap_page(start + Size4KiB::SIZE)
+            .unwrap();
+        mm.deallocate_and_unmap_page_range(pages).unwrap();
+        assert_eq!(
+            before,
+            mm.physical_frame_allocator().allocated_frames(),
+            "the frames and the table of the split page must be returned"
+        );
+    }
+
+    #[test_case]
+    fn test_no_mapping_is_writable_and_executable() {
+        let mut mm = MemoryManager::lock();
+        mm.walk_mappings(VirtAddr::zero(), ADDRESS_MASK + 1, |addr, _, _, flags| {
+            assert!(
+                check_write_xor_execute(flags).is_ok(),
+                "{:#x} is writable and executable",
+                addr
+            );
+            true
+        });
+    }
+
+    #[test_case]
+    fn test_walk_is_clamped_to_the_address_space() {
+        let mut mm = MemoryManager::lock();
+        let mut found = false;
+        mm.walk_mappings(span::HEAP.start(), u64::MAX, |_, _, _, _| {
+            found = true;
+            false
+        });
+        assert!(found);
+    }
+
+    #[test_case]
+    fn test_writable_and_executable_mapping_is_rejected() {
+        let page = Page::containing_address(USERLAND.start() + 0x2000_0000_u64);
+        let mut mm = MemoryManager::lock();
+        let frame = mm.allocate_frame().unwrap();
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        assert_eq!(
+            Err(Error::WritableAndExecutable),
+            mm.map_frame_to_page(frame, page, flags)
+        );
+        assert_eq!(None, mm.translate(page.start_address()));
+        unsafe { mm.physical_frame_allocator.deallocate_frame(frame) };
+    }
+
+    #[test_case]
+    fn test_try_lock_fails_while_locked() {
+        let mm = MemoryManager::lock();
+        assert!(MemoryManager::try_lock().is_none());
+        drop(mm);
+        assert!(MemoryManager::try_lock().is_some());
+    }
+
+    #[test_case]
+    fn test_interrupts_are_disabled_while_locked() {
+        let enabled = x86_64::instructions::interrupts::are_enabled();
+        let mm = MemoryManager::lock();
+        assert!(!x86_64::instructions::interrupts::are_enabled());
+        drop(mm);
+        assert_eq!(enabled, x86_64::instructions::interrupts::are_enabled());
+    }
+
+    #[test_case]
+    fn test_kernel_tables_are_shared_with_new_page_tables() {
+        let mut mm = MemoryManager::lock();
+        assert_eq!(Ok(0), mm.allocate_kernel_tables());
+        let frame = mm
+            .create_page_table(USERLAND.start(), USERLAND.start() + USERLAND.len())
+            .unwrap();
+        let table = unsafe { mm.table_mut(frame) };
+        let active = mm.page_table.level_4_table();
+        for span in SPANS.iter().filter(|span| span.name() != USERLAND.name()) {
+            for index in level_4_indices(span.start(), span.start() + span.len()) {
+                assert!(!table[index].is_unused());
+                assert_eq!(active[index].addr(), table[index].addr());
+            }
+        }
+        unsafe { mm.free_page_table(frame, USERLAND.start(), USERLAND.start() + USERLAND.len()) };
     }
 }
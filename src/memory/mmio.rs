@@ -0,0 +1,42 @@
+//! Mapping of physical memory that is not managed by the frame allocator,
+//! like memory mapped device registers or firmware tables.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::structures::paging::{Page, PageSize, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::manager::MemoryManager;
+use crate::memory::span::MMIO;
+use crate::memory::{DefaultPageSize, Error, Result};
+
+/// The offset of the next unused page within the [`MMIO`] span.
+static NEXT_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Maps `len` bytes of physical memory starting at `address` as uncacheable memory into
+/// the [`MMIO`] span, and returns the virtual address that corresponds to `address`.
+/// Mappings are never removed.
+///
+/// # Safety
+///
+/// The caller must ensure that the physical memory is not usable RAM, since the frames
+/// are not taken from the frame allocator and could be handed out to someone else.
+pub unsafe fn map_physical(address: PhysAddr, len: usize) -> Result<VirtAddr> {
+    let page_size = DefaultPageSize::SIZE;
+    let first_frame = PhysFrame::<DefaultPageSize>::containing_address(address);
+    let last_frame = PhysFrame::containing_address(address + len.max(1) as u64 - 1u64);
+    let frame_count = (last_frame - first_frame) + 1;
+
+    let offset = NEXT_OFFSET.fetch_add(frame_count * page_size, Ordering::SeqCst);
+    if offset + frame_count * page_size > MMIO.len() as u64 {
+        return Err(Error::OutOfMemory);
+    }
+
+    let first_page = Page::<DefaultPageSize>::containing_address(MMIO.start() + offset);
+    MemoryManager::lock().map_device_memory(
+        PhysFrame::range(first_frame, last_frame + 1),
+        Page::range(first_page, first_page + frame_count),
+    )?;
+
+    Ok(first_page.start_address() + (address - first_frame.start_address()))
+}
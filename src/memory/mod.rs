@@ -1,22 +1,36 @@
 use crate::memory::physical::PhysicalFrameAllocator;
 use bootloader::boot_info::Optional;
 use bootloader::BootInfo;
+use core::arch::x86_64::{__cpuid_count, _rdseed64_step, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
 use derive_more::Display;
-use x86_64::structures::paging::mapper::{MapToError, UnmapError};
-use x86_64::structures::paging::{OffsetPageTable, PageSize, PageTable, Size4KiB};
-use x86_64::VirtAddr;
+use x86_64::instructions::random::RdRand;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::mapper::{FlagUpdateError, MapToError, TranslateError, UnmapError};
+use x86_64::structures::paging::{OffsetPageTable, PageSize, PageTable, PageTableFlags, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
 
 #[cfg(test)]
 use crate::serial_println;
-use crate::vga_buffer;
+use crate::{error, info, vga_buffer};
 
 pub mod allocator;
+pub mod dma;
 pub mod heap;
+pub mod info;
 pub mod kbuffer;
+pub mod kernel_image;
 pub mod manager;
+pub mod mmio;
 pub mod physical;
 pub mod size;
 pub mod span;
+pub mod vma;
+
+/// The virtual address at which the complete physical memory is mapped, or 0 before
+/// the memory is initialized.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
 
 /// The default page size used by this kernel.
 pub type DefaultPageSize = Size4KiB;
@@ -33,6 +47,18 @@ pub enum Error {
     FrameAllocationFailed,
     #[display(fmt = "out of memory")]
     OutOfMemory,
+    #[display(fmt = "invalid address range")]
+    InvalidRange,
+    #[display(fmt = "address range is already in use")]
+    RangeInUse,
+    #[display(fmt = "address range is not mapped")]
+    NotMapped,
+    #[display(fmt = "reading the backing file failed")]
+    BackingFile,
+    #[display(fmt = "address space is not active")]
+    InactiveAddressSpace,
+    #[display(fmt = "memory must not be both writable and executable")]
+    WritableAndExecutable,
 }
 
 impl<S: PageSize> From<MapToError<S>> for Error {
@@ -47,6 +73,18 @@ impl From<UnmapError> for Error {
     }
 }
 
+impl From<FlagUpdateError> for Error {
+    fn from(_: FlagUpdateError) -> Self {
+        Self::PageMappingFailed
+    }
+}
+
+impl From<TranslateError> for Error {
+    fn from(_: TranslateError) -> Self {
+        Self::NotMapped
+    }
+}
+
 pub fn init_memory(boot_info: &'static mut BootInfo) {
     if let Some(framebuffer) = boot_info.framebuffer.as_mut() {
         vga_buffer::init_vga_buffer(framebuffer);
@@ -61,12 +99,105 @@ pub fn init_memory(boot_info: &'static mut BootInfo) {
         Optional::Some(addr) => addr,
         Optional::None => panic!("no boot info physical memory offset given"),
     };
+    PHYSICAL_MEMORY_OFFSET.store(addr, Ordering::Relaxed);
+    // writes of the kernel to read-only pages have to fault as well, otherwise
+    // copy-on-write pages would be modified in place
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+    manager::init_page_attribute_table();
+    // no span is in use before the heap is initialized
+    unsafe { span::randomize(boot_entropy) };
     manager::init_memory_manager(
         unsafe { create_offset_page_table(VirtAddr::new(addr)) },
-        unsafe { PhysicalFrameAllocator::init(&boot_info.memory_regions) },
+        unsafe { PhysicalFrameAllocator::init(&boot_info.memory_regions, VirtAddr::new(addr)) },
     );
+    match kernel_image::protect() {
+        Ok(segments) => info!("protected {} segments of the kernel image", segments),
+        Err(e) => error!("could not protect the kernel image: {}", e),
+    }
+    let fixed = manager::MemoryManager::lock().enforce_write_xor_execute();
+    info!("made {} writable mappings non-executable", fixed);
+    // address spaces are copied from the kernel's level 4 table, which must not change
+    // afterwards, see `MemoryManager::create_page_table`
+    manager::MemoryManager::lock()
+        .allocate_kernel_tables()
+        .expect("could not allocate the page tables of the kernel");
     heap::init_heap().expect("kernel heap initialization failed");
     kbuffer::init_kbuffer_heap().expect("kbuffer heap initialization failed");
+    dma::init_dma_space();
+}
+
+/// Returns the address of the given physical address in the mapping of the complete
+/// physical memory, which is mapped write-back by the bootloader.
+pub fn physical_to_virtual(addr: PhysAddr) -> VirtAddr {
+    let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed);
+    assert_ne!(0, offset, "memory is not initialized");
+    VirtAddr::new(offset + addr.as_u64())
+}
+
+/// Whether the given address is mapped in the active page table. In contrast to
+/// [`MemoryManager::translate`](manager::MemoryManager::translate), this doesn't lock
+/// the memory manager, so that it can be used after a panic.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed);
+    if offset == 0 {
+        return false;
+    }
+    let indices = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut table = Cr3::read().0.start_address();
+    for (level, &index) in indices.iter().enumerate() {
+        let table_ptr = (offset + table.as_u64()) as *const PageTable;
+        let flags = unsafe { (*table_ptr)[index].flags() };
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return false;
+        }
+        // level 3 and 2 tables may map 1 GiB and 2 MiB pages
+        if (level == 1 || level == 2) && flags.contains(PageTableFlags::HUGE_PAGE) {
+            return true;
+        }
+        table = unsafe { (*table_ptr)[index].addr() };
+    }
+    true
+}
+
+/// Returns 64 random bits for the layout of the address space. They are taken from
+/// RDSEED or RDRAND if the processor supports them, or else from the time stamp counter.
+fn boot_entropy() -> u64 {
+    const RETRIES: usize = 10;
+    let seed = (0..RETRIES).find_map(|_| rdseed());
+    let random = || RdRand::new().and_then(|rng| (0..RETRIES).find_map(|_| rng.get_u64()));
+    seed.or_else(random).unwrap_or_else(|| {
+        // the time stamp counter is barely random, so at least spread its low bits,
+        // which differ the most between boots
+        let mut z = unsafe { _rdtsc() };
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+/// Returns a random seed from RDSEED, or `None` if the processor doesn't support it or
+/// has no entropy available right now.
+fn rdseed() -> Option<u64> {
+    #[target_feature(enable = "rdseed")]
+    unsafe fn step() -> Option<u64> {
+        let mut seed = 0;
+        (_rdseed64_step(&mut seed) == 1).then_some(seed)
+    }
+
+    // RDSEED support is indicated by CPUID leaf 07h, ebx bit 18
+    #[allow(unused_unsafe)] // the intrinsic is safe on newer compilers
+    let features = unsafe { __cpuid_count(0x7, 0) };
+    if features.ebx & (1 << 18) != 0 {
+        unsafe { step() }
+    } else {
+        None
+    }
 }
 
 /// Initialize a new OffsetPageTable.
@@ -91,8 +222,6 @@ pub unsafe fn create_offset_page_table(
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
-    use x86_64::registers::control::Cr3;
-
     let (level_4_table_frame, _) = Cr3::read();
 
     let phys = level_4_table_frame.start_address();
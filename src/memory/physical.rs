@@ -1,12 +1,31 @@
-use bootloader::boot_info::{MemoryRegionKind, MemoryRegions};
+use bootloader::boot_info::{MemoryRegion, MemoryRegionKind, MemoryRegions};
 use core::marker::PhantomData;
-use x86_64::structures::paging::{FrameAllocator, Page, PageSize, PhysFrame};
-use x86_64::PhysAddr;
+use core::ops::Range;
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Page, PageSize, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
 
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
+///
+/// Deallocated frames are kept in a free list and are handed out again before any
+/// new frames. The free list is stored in the free frames themselves, which are
+/// accessed through the mapping of the complete physical memory.
+///
+/// Every usable frame has a reference count, which is the number of times that it is
+/// mapped, so that frames can be shared between address spaces. The counts are kept
+/// in a table at the start of the first usable region that is large enough.
 pub struct PhysicalFrameAllocator<S: PageSize> {
     memory_regions: &'static MemoryRegions,
+    physical_memory_offset: VirtAddr,
+    /// The reference counts, indexed by the frame number.
+    reference_counts: &'static mut [u16],
+    /// The physical memory that holds the reference counts.
+    reserved: Range<u64>,
     next: usize,
+    /// The most recently deallocated frame, which contains the address of the
+    /// next free frame, or 0 at the end of the list.
+    free_list: Option<PhysFrame<S>>,
+    free_count: usize,
     _marker: PhantomData<S>,
 }
 
@@ -17,34 +36,222 @@ impl<S: PageSize> PhysicalFrameAllocator<S> {
     ///
     /// This function is unsafe because the caller must guarantee that the passed
     /// memory map is valid. The main requirement is that all frames that are marked
-    /// as `USABLE` in it are really unused.
-    pub unsafe fn init(memory_regions: &'static MemoryRegions) -> Self {
+    /// as `USABLE` in it are really unused, and that the complete physical memory is
+    /// mapped at the given offset.
+    pub unsafe fn init(
+        memory_regions: &'static MemoryRegions,
+        physical_memory_offset: VirtAddr,
+    ) -> Self {
+        let frame_count = usable_regions(memory_regions)
+            .map(|r| r.end / S::SIZE)
+            .max()
+            .unwrap_or(0);
+        let table_size = x86_64::align_up(frame_count * 2, S::SIZE);
+        let table_start = usable_regions(memory_regions)
+            .map(|r| (x86_64::align_up(r.start, S::SIZE), r.end))
+            .find(|&(start, end)| start + table_size <= end)
+            .map(|(start, _)| start)
+            .expect("no memory for the frame reference counts");
+        let reference_counts = core::slice::from_raw_parts_mut(
+            (physical_memory_offset + table_start).as_mut_ptr::<u16>(),
+            frame_count as usize,
+        );
+        reference_counts.fill(0);
+
         PhysicalFrameAllocator {
             memory_regions,
+            physical_memory_offset,
+            reference_counts,
+            reserved: table_start..table_start + table_size,
             next: 0,
+            free_list: None,
+            free_count: 0,
             _marker: PhantomData,
         }
     }
 
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame<S>> {
+    /// The amount of frames that are currently allocated.
+    pub fn allocated_frames(&self) -> usize {
+        self.next - self.free_count
+    }
+
+    /// Whether the given frame was allocated by this allocator and is still in use, as
+    /// opposed to free frames and frames that the bootloader allocated.
+    pub fn is_allocated(&mut self, frame: PhysFrame<S>) -> bool {
+        self.reference_count(frame)
+            .map_or(false, |count| *count > 0)
+    }
+
+    /// The memory map that the frames are taken from.
+    pub fn memory_regions(&self) -> &'static MemoryRegions {
         self.memory_regions
-            .iter()
-            // get usable regions from memory map
-            .filter(|r| r.kind == MemoryRegionKind::Usable)
+    }
+
+    /// The amount of usable frames in the memory map.
+    pub fn usable_frame_count(&self) -> usize {
+        self.usable_frames().count()
+    }
+
+    /// Allocates `count` physically contiguous frames, the first of which is aligned to
+    /// `align` bytes. Only frames that were never allocated are considered, and the
+    /// frames that are skipped to find a suitable range are added to the free list.
+    pub fn allocate_contiguous(&mut self, count: usize, align: u64) -> Option<PhysFrameRange<S>> {
+        self.allocate_contiguous_below(count, align, u64::MAX)
+    }
+
+    /// Like [`PhysicalFrameAllocator::allocate_contiguous`], but all frames end at or
+    /// below the physical address `limit`, for devices that can't address all memory.
+    ///
+    /// Freed frames are not considered, since the free list is not ordered. Once all
+    /// frames below `limit` were allocated, this fails even if some of them were freed
+    /// again.
+    pub fn allocate_contiguous_below(
+        &mut self,
+        count: usize,
+        align: u64,
+        limit: u64,
+    ) -> Option<PhysFrameRange<S>> {
+        let mut run: Option<(usize, PhysFrame<S>)> = None;
+        let mut previous: Option<PhysFrame<S>> = None;
+        let mut found = None;
+        for (index, frame) in self.usable_frames().enumerate().skip(self.next) {
+            if frame.start_address().as_u64() + S::SIZE > limit {
+                run = None;
+                continue;
+            }
+            let continues = run.is_some() && previous.map_or(false, |p| p + 1 == frame);
+            if !continues {
+                run = frame
+                    .start_address()
+                    .is_aligned(align)
+                    .then_some((index, frame));
+            }
+            previous = Some(frame);
+            if let Some((start_index, start)) = run {
+                if index + 1 - start_index == count {
+                    found = Some((start_index, start));
+                    break;
+                }
+            }
+        }
+        let (start_index, start) = found?;
+
+        for frame in self
+            .usable_frames()
+            .skip(self.next)
+            .take(start_index - self.next)
+        {
+            unsafe { self.deallocate_frame(frame) };
+        }
+        self.next = start_index + count;
+        let range = PhysFrame::range(start, start + count as u64);
+        range.for_each(|frame| self.set_reference_count(frame, 1));
+        Some(range)
+    }
+
+    /// Returns an iterator over the usable frames specified in the memory map, except
+    /// for the frames that hold the reference counts.
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame<S>> {
+        let reserved = self.reserved.clone();
+        usable_regions(self.memory_regions)
             // map each region to its address range
             .map(|r| r.start..r.end)
             // transform to an iterator of frame start addresses
             .flat_map(|r| r.step_by(Page::<S>::SIZE as usize))
+            .filter(move |addr| !reserved.contains(addr))
             // create `PhysFrame` types from the start addresses
             .map(|addr| PhysFrame::<S>::containing_address(PhysAddr::new(addr)))
     }
+
+    /// The reference count of the given frame, or `None` for frames that are not
+    /// usable memory, such as memory mapped device registers.
+    fn reference_count(&mut self, frame: PhysFrame<S>) -> Option<&mut u16> {
+        let index = frame.start_address().as_u64() / S::SIZE;
+        self.reference_counts.get_mut(index as usize)
+    }
+
+    fn set_reference_count(&mut self, frame: PhysFrame<S>, value: u16) {
+        if let Some(count) = self.reference_count(frame) {
+            *count = value;
+        }
+    }
+
+    /// The location of the free list link in the given free frame.
+    fn link(&self, frame: PhysFrame<S>) -> *mut u64 {
+        (self.physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
 }
 
 unsafe impl<S: PageSize> FrameAllocator<S> for PhysicalFrameAllocator<S> {
     fn allocate_frame(&mut self) -> Option<PhysFrame<S>> {
-        let frame = self.usable_frames().nth(self.next);
+        if let Some(frame) = self.free_list {
+            let next = unsafe { self.link(frame).read() };
+            self.free_list =
+                (next != 0).then(|| PhysFrame::containing_address(PhysAddr::new(next)));
+            self.free_count -= 1;
+            self.set_reference_count(frame, 1);
+            return Some(frame);
+        }
+
+        let frame = self.usable_frames().nth(self.next)?;
         self.next += 1;
-        frame
+        self.set_reference_count(frame, 1);
+        Some(frame)
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for PhysicalFrameAllocator<S> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        let next = self.free_list.map_or(0, |f| f.start_address().as_u64());
+        self.link(frame).write(next);
+        self.free_list = Some(frame);
+        self.free_count += 1;
+        self.set_reference_count(frame, 0);
     }
 }
+
+/// Counts how often frames are mapped, so that they can be shared.
+pub trait FrameReferences<S: PageSize> {
+    /// The number of mappings of the given frame. Frames that are not counted, such
+    /// as device memory, count as mapped once.
+    fn references(&mut self, frame: PhysFrame<S>) -> usize;
+
+    /// Records another mapping of the given frame.
+    fn add_reference(&mut self, frame: PhysFrame<S>);
+
+    /// Removes a mapping of the given frame, and returns whether it was the last one,
+    /// in which case the frame can be deallocated.
+    fn remove_reference(&mut self, frame: PhysFrame<S>) -> bool;
+}
+
+impl<S: PageSize> FrameReferences<S> for PhysicalFrameAllocator<S> {
+    fn references(&mut self, frame: PhysFrame<S>) -> usize {
+        self.reference_count(frame)
+            .map_or(1, |count| (*count).max(1) as usize)
+    }
+
+    fn add_reference(&mut self, frame: PhysFrame<S>) {
+        if let Some(count) = self.reference_count(frame) {
+            *count = (*count)
+                .max(1)
+                .checked_add(1)
+                .expect("too many references to a frame");
+        }
+    }
+
+    fn remove_reference(&mut self, frame: PhysFrame<S>) -> bool {
+        match self.reference_count(frame) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+fn usable_regions(memory_regions: &MemoryRegions) -> impl Iterator<Item = &MemoryRegion> {
+    memory_regions
+        .iter()
+        .filter(|r| r.kind == MemoryRegionKind::Usable)
+}
@@ -1,9 +1,28 @@
+//! The spans of virtual memory that the kernel reserves for specific purposes.
+//!
+//! Every span has a default base address, which is moved by a random slide at boot,
+//! see [`randomize`]. The slide is smaller than the gap between two spans, so the
+//! spans never overlap, which is checked at compile time for the default base
+//! addresses and at runtime for the randomized ones, see [`check_layout`].
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use x86_64::structures::paging::page::PageRange;
 use x86_64::structures::paging::{Page, PageSize};
 use x86_64::VirtAddr;
 
 use crate::memory::size::Size;
 
+/// The largest distance by which a span is moved from its default base address.
+pub const MAX_SLIDE: Size = Size::TiB(4);
+
+/// The alignment of the slide, so that spans keep the alignment of their default base
+/// address up to the size of a huge page.
+pub const SLIDE_ALIGNMENT: Size = Size::MiB(2);
+
+/// The end of the lower half of the canonical address space, in which all spans lie.
+const LOWER_HALF_END: u64 = 0x8000_0000_0000;
+
 macro_rules! checks {
     (
         ($name1:ident, $addr1:expr, $size1:expr),
@@ -18,7 +37,7 @@ macro_rules! checks {
             concat!("keep the list sorted, address of ", stringify!($name1), " is larger than ", stringify!($name2))
         );
         assert!(
-            !$name1.overlaps(&$name2),
+            !overlaps_with_any_slide($addr1, $size1.bytes(), $addr2, $size2.bytes()),
             concat!("Overlap between ", stringify!($name1), " and ", stringify!($name2))
         );
     };
@@ -34,13 +53,16 @@ macro_rules! checks {
 macro_rules! declare_spans {
     ($(($name:ident, $addr:expr, $size:expr)),+) => {
         $(
-            pub const $name: MemorySpan = {
+            pub static $name: MemorySpan = {
                 let sz: $crate::memory::size::Size = $size;
                 let addr: u64 = $addr;
-                let virt_addr: x86_64::VirtAddr = x86_64::VirtAddr::new_truncate(addr);
-                $crate::memory::span::MemorySpan::new(virt_addr, sz.bytes())
+                $crate::memory::span::MemorySpan::new(stringify!($name), addr, sz.bytes())
             };
         )+
+
+        /// All spans, sorted by their base address.
+        pub static SPANS: &[&MemorySpan] = &[$(&$name),+];
+
         const _CHECK: () = {
             checks!($(($name, $addr, $size)),+);
         };
@@ -50,30 +72,47 @@ macro_rules! declare_spans {
 declare_spans! {
     // 32 TiB as potential user memory should be sufficient
     (USERLAND, 0x1111_1111_0000, Size::TiB(32)),
-    // heap is mapped on kernel initialization, make it only as big as necessary
-    (HEAP, 0x4444_4444_0000, Size::MiB(1)),
-    (KBUFFER, 0x5555_5555_0000, Size::TiB(1))
+    // the heap grows on demand within this span, up to its configurable size limit
+    (HEAP, 0x4444_4444_0000, Size::GiB(16)),
+    (KBUFFER, 0x5555_5555_0000, Size::TiB(1)),
+    // virtual addresses for memory mapped device registers and firmware tables
+    (MMIO, 0x6666_6666_0000, Size::GiB(64)),
+    // virtual addresses for buffers that devices access with DMA
+    (DMA, 0x7777_7777_0000, Size::GiB(64))
 }
 
 pub struct MemorySpan {
-    #[allow(dead_code)] // needed for the compile-time check of the address, never read at runtime
-    numeric_address: u64,
-    start: VirtAddr,
+    name: &'static str,
+    base: u64,
+    slide: AtomicU64,
     len: usize,
 }
 
 impl MemorySpan {
-    const fn new(start: VirtAddr, len: usize) -> Self {
+    const fn new(name: &'static str, base: u64, len: usize) -> Self {
         assert!(len > 0, "length size must be greater than 0");
+        assert!(
+            base + len as u64 + MAX_SLIDE.bytes() as u64 <= LOWER_HALF_END,
+            "span must lie in the lower half of the address space"
+        );
         Self {
-            numeric_address: start.as_u64(),
-            start,
+            name,
+            base,
+            slide: AtomicU64::new(0),
             len,
         }
     }
 
-    pub const fn as_mut_ptr<T>(&self) -> *mut T {
-        self.start.as_mut_ptr::<T>()
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn start(&self) -> VirtAddr {
+        VirtAddr::new(self.base + self.slide.load(Ordering::Relaxed))
+    }
+
+    pub fn as_mut_ptr<T>(&self) -> *mut T {
+        self.start().as_mut_ptr::<T>()
     }
 
     #[allow(clippy::len_without_is_empty)] // since a span should never be empty, is_empty doesn't make sense
@@ -83,26 +122,70 @@ impl MemorySpan {
 
     pub fn as_page_range<S: PageSize>(&self) -> PageRange<S> {
         Page::range(
-            Page::<S>::containing_address(self.start),
-            Page::<S>::containing_address(self.start + self.len),
+            Page::<S>::containing_address(self.start()),
+            Page::<S>::containing_address(self.start() + self.len),
         )
     }
 
-    #[allow(dead_code)] // needed for the compile-time check of the address, never read at runtime
-    const fn numeric_address(&self) -> u64 {
-        self.numeric_address
+    /// Whether this span overlaps the given span at their current addresses.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        let self_start = self.start().as_u64();
+        let self_end = self_start + self.len as u64;
+        let other_start = other.start().as_u64();
+        let other_end = other_start + other.len as u64;
+        self_start.max(other_start) < self_end.min(other_end)
     }
+}
+
+/// Moves every span by a random slide, which is derived from the given entropy.
+///
+/// # Safety
+///
+/// No span must be in use, so this has to be called before the heap is initialized.
+pub(in crate::memory) unsafe fn randomize(mut entropy: impl FnMut() -> u64) {
+    let positions = (MAX_SLIDE.bytes() / SLIDE_ALIGNMENT.bytes()) as u64;
+    for span in SPANS {
+        let slide = entropy() % positions * SLIDE_ALIGNMENT.bytes() as u64;
+        span.slide.store(slide, Ordering::Relaxed);
+    }
+    check_layout();
+}
 
-    #[allow(dead_code)] // needed for the compile-time check of the address, never read at runtime
-    const fn overlaps(&self, other: &Self) -> bool {
-        let self_start = self.numeric_address();
-        let self_end = self.numeric_address() + self.len() as u64;
-        let other_start = other.numeric_address();
-        let other_end = other.numeric_address() + other.len() as u64;
-        const_max(self_start, other_start) < const_min(self_end, other_end)
+/// Panics if the spans are not sorted, overlap, or leave the lower half of the
+/// address space.
+pub fn check_layout() {
+    for pair in SPANS.windows(2) {
+        assert!(
+            pair[0].start() < pair[1].start(),
+            "keep the list sorted, address of {} is larger than {}",
+            pair[0].name,
+            pair[1].name
+        );
+        assert!(
+            !pair[0].overlaps(pair[1]),
+            "Overlap between {} and {}",
+            pair[0].name,
+            pair[1].name
+        );
+    }
+    for span in SPANS {
+        assert!(
+            span.start().as_u64() + span.len as u64 <= LOWER_HALF_END,
+            "{} is not in the lower half of the address space",
+            span.name
+        );
     }
 }
 
+/// Whether two spans could overlap, if both are moved by any slide.
+#[allow(dead_code)] // needed for the compile-time check of the address, never read at runtime
+const fn overlaps_with_any_slide(addr1: u64, len1: usize, addr2: u64, len2: usize) -> bool {
+    let slide = MAX_SLIDE.bytes() as u64;
+    let end1 = addr1 + len1 as u64 + slide;
+    let end2 = addr2 + len2 as u64 + slide;
+    const_max(addr1, addr2) < const_min(end1, end2)
+}
+
 #[allow(dead_code)] // needed for the compile-time check of the address, never read at runtime
 const fn const_max(a: u64, b: u64) -> u64 {
     if a > b {
@@ -120,3 +203,22 @@ const fn const_min(a: u64, b: u64) -> u64 {
         b
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_layout_is_valid() {
+        check_layout();
+    }
+
+    #[test_case]
+    fn test_slides_are_aligned_and_bounded() {
+        for span in SPANS {
+            let slide = span.start().as_u64() - span.base;
+            assert_eq!(0, slide % SLIDE_ALIGNMENT.bytes() as u64);
+            assert!(slide < MAX_SLIDE.bytes() as u64);
+        }
+    }
+}
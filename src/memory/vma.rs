@@ -0,0 +1,645 @@
+//! Virtual memory areas (VMAs), which record which ranges of an address space are
+//! mapped, how they may be accessed, and where their content comes from.
+//!
+//! An [`AddressSpace`] manages a span of virtual memory, and hands out ranges of it
+//! with [`AddressSpace::mmap`]. Mappings are either anonymous, which means that they
+//! are zeroed, or backed by a file, in which case they are filled with the content
+//! of the file. All pages of a mapping are mapped right away.
+//!
+//! The address space of userspace is [`user_address_space`], which manages
+//! [`span::USERLAND`]. [`AddressSpace::fork`] creates a copy of an address space with
+//! its own page table, which shares all frames copy-on-write.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+
+use kstd::sync::{Mutex, MutexGuard, Once};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Page, PageSize, PhysFrame};
+use x86_64::VirtAddr;
+
+use crate::io::fs::{IFileHandle, INodeBase};
+use crate::memory::manager::{MemoryKind, MemoryManager, UserAccessible};
+use crate::memory::span::{self, MemorySpan};
+use crate::memory::{DefaultPageSize, Error, Result};
+
+const PAGE_SIZE: u64 = DefaultPageSize::SIZE;
+
+static mut USER_ADDRESS_SPACE: Option<Mutex<AddressSpace>> = None;
+static USER_ADDRESS_SPACE_INIT: Once = Once::new();
+
+/// The address space of userspace.
+pub fn user_address_space() -> MutexGuard<'static, AddressSpace> {
+    USER_ADDRESS_SPACE_INIT.call_once(|| unsafe {
+        USER_ADDRESS_SPACE = Some(Mutex::new(AddressSpace::new(&span::USERLAND)));
+    });
+    unsafe { USER_ADDRESS_SPACE.as_ref().unwrap().lock() }
+}
+
+/// Where the content of a mapping comes from.
+#[derive(Clone)]
+pub enum Backing {
+    /// The mapping is zeroed.
+    Anonymous,
+    /// The mapping starts with the content of the file at the given offset. The rest
+    /// of the mapping after the end of the file is zeroed.
+    File { file: IFileHandle, offset: u64 },
+}
+
+impl Debug for Backing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Backing::Anonymous => write!(f, "anonymous"),
+            Backing::File { file, offset } => {
+                write!(f, "file {} at {}", file.read().name(), offset)
+            }
+        }
+    }
+}
+
+impl Backing {
+    /// The backing of the part of a mapping that starts `offset` bytes later.
+    fn advanced_by(&self, offset: u64) -> Self {
+        match self {
+            Backing::Anonymous => Backing::Anonymous,
+            Backing::File { file, offset: o } => Backing::File {
+                file: file.clone(),
+                offset: o + offset,
+            },
+        }
+    }
+}
+
+/// A mapped range of virtual memory.
+#[derive(Clone, Debug)]
+pub struct Vma {
+    start: VirtAddr,
+    end: VirtAddr,
+    kind: MemoryKind,
+    user_accessible: UserAccessible,
+    backing: Backing,
+}
+
+impl Vma {
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    /// The first address after the area.
+    pub fn end(&self) -> VirtAddr {
+        self.end
+    }
+
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn kind(&self) -> MemoryKind {
+        self.kind
+    }
+
+    pub fn user_accessible(&self) -> UserAccessible {
+        self.user_accessible
+    }
+
+    pub fn backing(&self) -> &Backing {
+        &self.backing
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    fn pages(&self) -> PageRange<DefaultPageSize> {
+        page_range(self.start, self.end)
+    }
+}
+
+fn page_range(start: VirtAddr, end: VirtAddr) -> PageRange<DefaultPageSize> {
+    Page::range(
+        Page::containing_address(start),
+        Page::containing_address(end),
+    )
+}
+
+/// A span of virtual memory and the areas that are mapped in it.
+///
+/// Dropping an address space unmaps all of its areas.
+pub struct AddressSpace {
+    start: VirtAddr,
+    end: VirtAddr,
+    /// The level 4 table that the areas are mapped in.
+    page_table: PhysFrame,
+    /// Whether the page table was created for this address space, and has to be
+    /// deallocated with it.
+    owns_page_table: bool,
+    /// The mapped areas, keyed by their start address. Areas never overlap.
+    vmas: BTreeMap<VirtAddr, Vma>,
+}
+
+impl AddressSpace {
+    /// Creates an address space in the active page table.
+    pub fn new(span: &MemorySpan) -> Self {
+        Self {
+            start: span.start(),
+            end: span.start() + span.len(),
+            page_table: Cr3::read().0,
+            owns_page_table: false,
+            vmas: BTreeMap::new(),
+        }
+    }
+
+    /// The frame of the level 4 table that the areas are mapped in.
+    pub fn page_table(&self) -> PhysFrame {
+        self.page_table
+    }
+
+    /// Whether the page table of this address space is the active one.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.page_table
+    }
+
+    /// Loads the page table of this address space.
+    ///
+    /// # Safety
+    ///
+    /// The caller has to ensure that no references into the private span of the
+    /// previously active address space are used afterwards.
+    pub unsafe fn activate(&self) {
+        let (_, flags) = Cr3::read();
+        Cr3::write(self.page_table, flags);
+    }
+
+    /// Creates a copy of this address space with its own page table. No memory is
+    /// copied: both address spaces map the same frames, and writable pages are
+    /// mapped read-only as copy-on-write pages in both of them. The first write to
+    /// such a page gives the writing address space its own copy of the frame.
+    pub fn fork(&self) -> Result<AddressSpace> {
+        let page_table = unsafe {
+            MemoryManager::lock().with_page_table(self.page_table, |mm| {
+                mm.create_page_table(self.start, self.end)
+            })
+        }?;
+        // clone the areas before locking the memory manager, since that allocates
+        let child = AddressSpace {
+            start: self.start,
+            end: self.end,
+            page_table,
+            owns_page_table: true,
+            vmas: self.vmas.clone(),
+        };
+
+        let result = unsafe {
+            MemoryManager::lock().with_page_table(self.page_table, |mm| -> Result<()> {
+                for page in self.vmas().flat_map(Vma::pages) {
+                    if let Some((frame, flags)) = mm.share_page(page)? {
+                        mm.with_page_table(page_table, |mm| {
+                            mm.map_shared_page(page, frame, flags)
+                        })?;
+                    }
+                }
+                Ok(())
+            })
+        };
+        // on failure, dropping the child releases the frames that it already maps
+        result.map(|_| child)
+    }
+
+    /// Returns all areas, sorted by their start address.
+    pub fn vmas(&self) -> impl Iterator<Item = &Vma> {
+        self.vmas.values()
+    }
+
+    /// Returns the area that contains the given address.
+    pub fn find(&self, addr: VirtAddr) -> Option<&Vma> {
+        self.vmas
+            .range(..=addr)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    /// Maps `len` bytes, rounded up to whole pages, and returns the start of the
+    /// mapping. If an address is given, the mapping starts there, and it fails with
+    /// [`Error::RangeInUse`] if the range overlaps an existing mapping. Otherwise,
+    /// the lowest free range is used. The address space has to be active, so that
+    /// the mapping can be filled.
+    pub fn mmap(
+        &mut self,
+        addr: Option<VirtAddr>,
+        len: usize,
+        kind: MemoryKind,
+        user_accessible: UserAccessible,
+        backing: Backing,
+    ) -> Result<VirtAddr> {
+        if len == 0 {
+            return Err(Error::InvalidRange);
+        }
+        if !self.is_active() {
+            return Err(Error::InactiveAddressSpace);
+        }
+        let len = x86_64::align_up(len as u64, PAGE_SIZE);
+        let start = match addr {
+            Some(addr) => {
+                self.check_range(addr, len)?;
+                if self.overlaps(addr, addr + len) {
+                    return Err(Error::RangeInUse);
+                }
+                addr
+            }
+            None => self.find_free_range(len).ok_or(Error::OutOfMemory)?,
+        };
+
+        let vma = Vma {
+            start,
+            end: start + len,
+            kind,
+            user_accessible,
+            backing,
+        };
+        populate(&vma)?;
+        self.vmas.insert(start, vma);
+        Ok(start)
+    }
+
+    /// Unmaps all pages in the given range and returns their frames. Parts of the
+    /// range that are not mapped are ignored.
+    pub fn munmap(&mut self, addr: VirtAddr, len: usize) -> Result<()> {
+        let end = self.page_aligned_end(addr, len)?;
+        self.split_at(addr);
+        self.split_at(end);
+
+        let starts = self
+            .vmas
+            .range(addr..end)
+            .map(|(&start, _)| start)
+            .collect::<Vec<_>>();
+        for start in starts {
+            let vma = self.vmas.remove(&start).unwrap();
+            unsafe {
+                MemoryManager::lock().with_page_table(self.page_table, |mm| {
+                    mm.deallocate_and_unmap_page_range(vma.pages())
+                })
+            }?;
+        }
+        Ok(())
+    }
+
+    /// Changes how the pages in the given range may be accessed. The whole range has
+    /// to be mapped, otherwise [`Error::NotMapped`] is returned and nothing is changed.
+    pub fn mprotect(
+        &mut self,
+        addr: VirtAddr,
+        len: usize,
+        kind: MemoryKind,
+        user_accessible: UserAccessible,
+    ) -> Result<()> {
+        let end = self.page_aligned_end(addr, len)?;
+        if !self.is_mapped(addr, end) {
+            return Err(Error::NotMapped);
+        }
+        self.split_at(addr);
+        self.split_at(end);
+
+        let page_table = self.page_table;
+        for (_, vma) in self.vmas.range_mut(addr..end) {
+            vma.kind = kind;
+            vma.user_accessible = user_accessible;
+            unsafe {
+                MemoryManager::lock().with_page_table(page_table, |mm| {
+                    mm.update_flags(vma.pages(), kind, user_accessible)
+                })
+            }?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the range is page aligned and inside of the address space, and
+    /// returns its end, rounded up to whole pages.
+    fn page_aligned_end(&self, addr: VirtAddr, len: usize) -> Result<VirtAddr> {
+        if len == 0 {
+            return Err(Error::InvalidRange);
+        }
+        let len = x86_64::align_up(len as u64, PAGE_SIZE);
+        self.check_range(addr, len)?;
+        Ok(addr + len)
+    }
+
+    fn check_range(&self, addr: VirtAddr, len: u64) -> Result<()> {
+        let in_bounds = addr >= self.start
+            && addr
+                .as_u64()
+                .checked_add(len)
+                .map_or(false, |end| end <= self.end.as_u64());
+        if !addr.is_aligned(PAGE_SIZE) || !in_bounds {
+            return Err(Error::InvalidRange);
+        }
+        Ok(())
+    }
+
+    fn overlaps(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        // the last area that starts before the end is the only candidate
+        self.vmas
+            .range(..end)
+            .next_back()
+            .map_or(false, |(_, vma)| vma.end > start)
+    }
+
+    /// Whether every address in the range belongs to an area.
+    fn is_mapped(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        let mut next = start;
+        while next < end {
+            match self.find(next) {
+                Some(vma) => next = vma.end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    fn find_free_range(&self, len: u64) -> Option<VirtAddr> {
+        let mut candidate = self.start;
+        for vma in self.vmas.values() {
+            if vma.start - candidate >= len {
+                break;
+            }
+            candidate = vma.end;
+        }
+        (self.end - candidate >= len).then_some(candidate)
+    }
+
+    /// Splits the area that contains the given address, so that one area ends and
+    /// the next one starts at the address.
+    fn split_at(&mut self, addr: VirtAddr) {
+        let vma = match self.find(addr) {
+            Some(vma) if vma.start != addr => vma,
+            _ => return,
+        };
+        let start = vma.start;
+        let second = Vma {
+            start: addr,
+            backing: vma.backing.advanced_by(addr - start),
+            ..vma.clone()
+        };
+        self.vmas.get_mut(&start).unwrap().end = addr;
+        self.vmas.insert(addr, second);
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let vmas = core::mem::take(&mut self.vmas);
+        let mut mm = MemoryManager::lock();
+        unsafe {
+            mm.with_page_table(self.page_table, |mm| {
+                for vma in vmas.values() {
+                    let _ = mm.deallocate_and_unmap_page_range(vma.pages());
+                }
+            });
+            if self.owns_page_table {
+                assert!(!self.is_active(), "dropping the active address space");
+                mm.free_page_table(self.page_table, self.start, self.end);
+            }
+        }
+        // the areas are deallocated after unlocking the memory manager
+        drop(mm);
+    }
+}
+
+/// Maps the pages of a new area and fills them with their content.
+fn populate(vma: &Vma) -> Result<()> {
+    // the pages have to be writable to be filled, the memory manager must not be
+    // locked while reading the file, since that may allocate
+    MemoryManager::lock().allocate_and_map_page_range(
+        vma.pages(),
+        MemoryKind::Writable,
+        vma.user_accessible,
+    )?;
+    let fill = || -> Result<()> {
+        let memory = unsafe { core::slice::from_raw_parts_mut(vma.start.as_mut_ptr(), vma.len()) };
+        memory.fill(0);
+        if let Backing::File { file, offset } = &vma.backing {
+            let mut filled = 0;
+            while filled < memory.len() {
+                let mut rest = &mut memory[filled..];
+                let read = file
+                    .read()
+                    .read_at(*offset + filled as u64, &mut rest)
+                    .map_err(|_| Error::BackingFile)?;
+                if read == 0 {
+                    break;
+                }
+                filled += read;
+            }
+        }
+        if vma.kind != MemoryKind::Writable {
+            MemoryManager::lock().update_flags(vma.pages(), vma.kind, vma.user_accessible)?;
+        }
+        Ok(())
+    };
+    fill().map_err(|e| {
+        let _ = MemoryManager::lock().deallocate_and_unmap_page_range(vma.pages());
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use crate::io::fs::perm::Permission;
+    use crate::io::fs::tmpfs::TmpFs;
+    use crate::io::fs::{CreateNodeType, Fs};
+
+    use super::*;
+
+    fn address_space() -> AddressSpace {
+        // every test uses its own part of the userland span
+        AddressSpace::new(&span::USERLAND)
+    }
+
+    fn is_mapped(addr: VirtAddr) -> bool {
+        MemoryManager::lock().translate(addr).is_some()
+    }
+
+    #[test_case]
+    fn test_anonymous_mapping() {
+        let mut space = address_space();
+        let addr = space
+            .mmap(
+                Some(span::USERLAND.start() + 0x10_0000_u64),
+                5000,
+                MemoryKind::Writable,
+                UserAccessible::No,
+                Backing::Anonymous,
+            )
+            .unwrap();
+        let vma = space.find(addr + 4096_u64).unwrap();
+        assert_eq!(2 * 4096, vma.len());
+        let memory = unsafe { core::slice::from_raw_parts_mut(addr.as_mut_ptr::<u8>(), 8192) };
+        assert!(memory.iter().all(|&b| b == 0));
+        memory.fill(1);
+
+        space.munmap(addr, 8192).unwrap();
+        assert!(space.find(addr).is_none());
+        assert!(!is_mapped(addr));
+    }
+
+    #[test_case]
+    fn test_fixed_mapping_overlap() {
+        let mut space = address_space();
+        let addr = span::USERLAND.start() + 0x20_0000_u64;
+        space
+            .mmap(
+                Some(addr),
+                4096,
+                MemoryKind::ReadOnly,
+                UserAccessible::No,
+                Backing::Anonymous,
+            )
+            .unwrap();
+        assert_eq!(
+            Err(Error::RangeInUse),
+            space.mmap(
+                Some(addr),
+                4096,
+                MemoryKind::ReadOnly,
+                UserAccessible::No,
+                Backing::Anonymous
+            )
+        );
+        assert_eq!(
+            Err(Error::InvalidRange),
+            space.mmap(
+                Some(addr + 1_u64),
+                4096,
+                MemoryKind::ReadOnly,
+                UserAccessible::No,
+                Backing::Anonymous
+            )
+        );
+        space.munmap(addr, 4096).unwrap();
+    }
+
+    #[test_case]
+    fn test_munmap_splits() {
+        let mut space = address_space();
+        let addr = span::USERLAND.start() + 0x30_0000_u64;
+        space
+            .mmap(
+                Some(addr),
+                3 * 4096,
+                MemoryKind::Writable,
+                UserAccessible::No,
+                Backing::Anonymous,
+            )
+            .unwrap();
+        space.munmap(addr + 4096_u64, 4096).unwrap();
+
+        assert_eq!(2, space.vmas().count());
+        assert!(is_mapped(addr));
+        assert!(!is_mapped(addr + 4096_u64));
+        assert!(is_mapped(addr + 2 * 4096_u64));
+        // the lowest free range is reused
+        let free = space.find_free_range(4096).unwrap();
+        assert_eq!(span::USERLAND.start(), free);
+
+        space.munmap(addr, 3 * 4096).unwrap();
+        assert_eq!(0, space.vmas().count());
+    }
+
+    #[test_case]
+    fn test_mprotect() {
+        let mut space = address_space();
+        let addr = span::USERLAND.start() + 0x40_0000_u64;
+        space
+            .mmap(
+                Some(addr),
+                2 * 4096,
+                MemoryKind::Writable,
+                UserAccessible::No,
+                Backing::Anonymous,
+            )
+            .unwrap();
+        space
+            .mprotect(addr, 4096, MemoryKind::ReadOnly, UserAccessible::No)
+            .unwrap();
+        let kinds = space.vmas().map(|vma| vma.kind()).collect::<Vec<_>>();
+        assert_eq!(vec![MemoryKind::ReadOnly, MemoryKind::Writable], kinds);
+        assert_eq!(
+            Err(Error::NotMapped),
+            space.mprotect(addr, 3 * 4096, MemoryKind::ReadOnly, UserAccessible::No)
+        );
+        space.munmap(addr, 2 * 4096).unwrap();
+    }
+
+    #[test_case]
+    fn test_file_mapping() {
+        let fs = TmpFs::new("tmp".to_string(), 0);
+        let node = fs
+            .root_inode()
+            .as_dir()
+            .unwrap()
+            .write()
+            .create(&"data", CreateNodeType::File, Permission::user_rwx())
+            .unwrap();
+        let file = node.as_file().unwrap();
+        file.write().write_at(0, &b"0123456789").unwrap();
+
+        let mut space = address_space();
+        let addr = space
+            .mmap(
+                Some(span::USERLAND.start() + 0x50_0000_u64),
+                4096,
+                MemoryKind::ReadOnly,
+                UserAccessible::No,
+                Backing::File { file, offset: 2 },
+            )
+            .unwrap();
+        let memory = unsafe { core::slice::from_raw_parts(addr.as_ptr::<u8>(), 4096) };
+        assert_eq!(b"23456789", &memory[..8]);
+        assert!(memory[8..].iter().all(|&b| b == 0));
+        space.munmap(addr, 4096).unwrap();
+    }
+
+    #[test_case]
+    fn test_fork_copies_on_write() {
+        let mut parent = address_space();
+        let addr = parent
+            .mmap(
+                Some(span::USERLAND.start() + 0x60_0000_u64),
+                4096,
+                MemoryKind::Writable,
+                UserAccessible::No,
+                Backing::Anonymous,
+            )
+            .unwrap();
+        let memory = addr.as_mut_ptr::<u8>();
+        unsafe { memory.write_volatile(1) };
+        let frame = MemoryManager::lock().translate(addr).unwrap();
+
+        let child = parent.fork().unwrap();
+        x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+            child.activate();
+            assert_eq!(1, memory.read_volatile());
+            memory.write_volatile(2);
+            assert_ne!(Some(frame), MemoryManager::lock().translate(addr));
+            parent.activate();
+        });
+        assert_eq!(1, unsafe { memory.read_volatile() });
+
+        // the parent is the only user of the frame now, so it keeps it
+        unsafe { memory.write_volatile(3) };
+        assert_eq!(Some(frame), MemoryManager::lock().translate(addr));
+
+        drop(child);
+        parent.munmap(addr, 4096).unwrap();
+    }
+}
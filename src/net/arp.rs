@@ -0,0 +1,108 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::net::{Error, Ipv4Address, MacAddress, Result};
+
+const PACKET_LEN: usize = 28;
+
+const HARDWARE_TYPE_ETHERNET: u16 = 1;
+const PROTOCOL_TYPE_IPV4: u16 = 0x0800;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Operation {
+    Request,
+    Reply,
+}
+
+/// An ARP packet for IPv4 over Ethernet.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ArpPacket {
+    pub operation: Operation,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Address,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Address,
+}
+
+impl ArpPacket {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < PACKET_LEN
+            || u16::from_be_bytes([data[0], data[1]]) != HARDWARE_TYPE_ETHERNET
+            || u16::from_be_bytes([data[2], data[3]]) != PROTOCOL_TYPE_IPV4
+            || data[4] != 6
+            || data[5] != 4
+        {
+            return Err(Error::Malformed);
+        }
+        let operation = match u16::from_be_bytes([data[6], data[7]]) {
+            1 => Operation::Request,
+            2 => Operation::Reply,
+            _ => return Err(Error::Malformed),
+        };
+        Ok(Self {
+            operation,
+            sender_mac: MacAddress(data[8..14].try_into().unwrap()),
+            sender_ip: Ipv4Address(data[14..18].try_into().unwrap()),
+            target_mac: MacAddress(data[18..24].try_into().unwrap()),
+            target_ip: Ipv4Address(data[24..28].try_into().unwrap()),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(PACKET_LEN);
+        data.extend_from_slice(&HARDWARE_TYPE_ETHERNET.to_be_bytes());
+        data.extend_from_slice(&PROTOCOL_TYPE_IPV4.to_be_bytes());
+        data.extend_from_slice(&[6, 4]);
+        let operation: u16 = match self.operation {
+            Operation::Request => 1,
+            Operation::Reply => 2,
+        };
+        data.extend_from_slice(&operation.to_be_bytes());
+        data.extend_from_slice(&self.sender_mac.0);
+        data.extend_from_slice(&self.sender_ip.0);
+        data.extend_from_slice(&self.target_mac.0);
+        data.extend_from_slice(&self.target_ip.0);
+        data
+    }
+}
+
+/// Maps IPv4 addresses to the MAC addresses that they were last seen with.
+/// Entries never expire.
+#[derive(Default)]
+pub struct ArpCache {
+    entries: BTreeMap<Ipv4Address, MacAddress>,
+}
+
+impl ArpCache {
+    pub fn lookup(&self, ip: Ipv4Address) -> Option<MacAddress> {
+        self.entries.get(&ip).copied()
+    }
+
+    pub fn insert(&mut self, ip: Ipv4Address, mac: MacAddress) {
+        self.entries.insert(ip, mac);
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&Ipv4Address, &MacAddress)> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_parse_serialize() {
+        let packet = ArpPacket {
+            operation: Operation::Request,
+            sender_mac: MacAddress([0x52, 0x54, 0, 0x12, 0x34, 0x56]),
+            sender_ip: Ipv4Address::new(10, 0, 2, 15),
+            target_mac: MacAddress::ZERO,
+            target_ip: Ipv4Address::new(10, 0, 2, 2),
+        };
+        let data = packet.serialize();
+        assert_eq!(PACKET_LEN, data.len());
+        assert_eq!(packet, ArpPacket::parse(&data).unwrap());
+        assert_eq!(Err(Error::Malformed), ArpPacket::parse(&data[..27]));
+    }
+}
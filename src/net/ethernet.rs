@@ -0,0 +1,93 @@
+use alloc::vec::Vec;
+
+use crate::net::{Error, MacAddress, Result};
+
+pub const HEADER_LEN: usize = 14;
+
+/// The largest payload that is sent in a single frame.
+pub const MTU: usize = 1500;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EtherType {
+    Ipv4,
+    Arp,
+    Other(u16),
+}
+
+impl From<u16> for EtherType {
+    fn from(v: u16) -> Self {
+        match v {
+            0x0800 => Self::Ipv4,
+            0x0806 => Self::Arp,
+            _ => Self::Other(v),
+        }
+    }
+}
+
+impl From<EtherType> for u16 {
+    fn from(t: EtherType) -> Self {
+        match t {
+            EtherType::Ipv4 => 0x0800,
+            EtherType::Arp => 0x0806,
+            EtherType::Other(v) => v,
+        }
+    }
+}
+
+/// An Ethernet II frame, without the frame check sequence, which is handled by the device.
+#[derive(Debug)]
+pub struct EthernetFrame<'a> {
+    pub destination: MacAddress,
+    pub source: MacAddress,
+    pub ether_type: EtherType,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < HEADER_LEN {
+            return Err(Error::Malformed);
+        }
+        Ok(Self {
+            destination: MacAddress(data[0..6].try_into().unwrap()),
+            source: MacAddress(data[6..12].try_into().unwrap()),
+            ether_type: u16::from_be_bytes([data[12], data[13]]).into(),
+            payload: &data[HEADER_LEN..],
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        frame.extend_from_slice(&self.destination.0);
+        frame.extend_from_slice(&self.source.0);
+        frame.extend_from_slice(&u16::from(self.ether_type).to_be_bytes());
+        frame.extend_from_slice(self.payload);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_parse_serialize() {
+        let frame = EthernetFrame {
+            destination: MacAddress::BROADCAST,
+            source: MacAddress([0x52, 0x54, 0, 0x12, 0x34, 0x56]),
+            ether_type: EtherType::Arp,
+            payload: &[1, 2, 3],
+        };
+        let data = frame.serialize();
+        assert_eq!(HEADER_LEN + 3, data.len());
+        assert_eq!([0x08, 0x06], data[12..14]);
+
+        let parsed = EthernetFrame::parse(&data).unwrap();
+        assert_eq!(frame.destination, parsed.destination);
+        assert_eq!(frame.source, parsed.source);
+        assert_eq!(EtherType::Arp, parsed.ether_type);
+        assert_eq!(&[1, 2, 3], parsed.payload);
+
+        assert!(EthernetFrame::parse(&data[..13]).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use crate::net::ipv4::{checksum, Ipv4Packet, Protocol};
+use crate::net::stack::Stack;
+use crate::net::{lock_stack, wait_for, Error, Ipv4Address, Result};
+use crate::time;
+
+const HEADER_LEN: usize = 8;
+
+/// The maximum number of echo replies that are kept until someone waits for them.
+const MAX_ECHO_REPLIES: usize = 16;
+
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_ECHO_REQUEST: u8 = 8;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EchoKind {
+    Request,
+    Reply,
+}
+
+/// An ICMP echo request or reply. All other ICMP messages are ignored.
+#[derive(Debug)]
+pub struct EchoPacket<'a> {
+    pub kind: EchoKind,
+    pub identifier: u16,
+    pub sequence: u16,
+    pub data: &'a [u8],
+}
+
+impl<'a> EchoPacket<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < HEADER_LEN || data[1] != 0 || checksum(&[data]) != 0 {
+            return Err(Error::Malformed);
+        }
+        let kind = match data[0] {
+            TYPE_ECHO_REQUEST => EchoKind::Request,
+            TYPE_ECHO_REPLY => EchoKind::Reply,
+            _ => return Err(Error::Malformed),
+        };
+        Ok(Self {
+            kind,
+            identifier: u16::from_be_bytes([data[4], data[5]]),
+            sequence: u16::from_be_bytes([data[6], data[7]]),
+            data: &data[HEADER_LEN..],
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LEN + self.data.len());
+        data.push(match self.kind {
+            EchoKind::Request => TYPE_ECHO_REQUEST,
+            EchoKind::Reply => TYPE_ECHO_REPLY,
+        });
+        data.push(0); // code
+        data.extend_from_slice(&[0, 0]); // checksum
+        data.extend_from_slice(&self.identifier.to_be_bytes());
+        data.extend_from_slice(&self.sequence.to_be_bytes());
+        data.extend_from_slice(self.data);
+        let sum = checksum(&[&data]);
+        data[2..4].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct EchoReply {
+    source: Ipv4Address,
+    identifier: u16,
+    sequence: u16,
+}
+
+#[derive(Default)]
+pub(in crate::net) struct IcmpState {
+    replies: VecDeque<EchoReply>,
+}
+
+pub(in crate::net) fn handle(stack: &mut Stack, packet: &Ipv4Packet) {
+    let echo = match EchoPacket::parse(packet.payload) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    match echo.kind {
+        EchoKind::Request => {
+            let reply = EchoPacket {
+                kind: EchoKind::Reply,
+                ..echo
+            };
+            let _ = stack.send_ipv4(packet.source, Protocol::Icmp, &reply.serialize());
+        }
+        EchoKind::Reply => {
+            let replies = &mut stack.icmp.replies;
+            if replies.len() >= MAX_ECHO_REPLIES {
+                replies.pop_front();
+            }
+            replies.push_back(EchoReply {
+                source: packet.source,
+                identifier: echo.identifier,
+                sequence: echo.sequence,
+            });
+        }
+    }
+}
+
+/// Sends an echo request to the given address and waits for the reply. Returns
+/// the round trip time in microseconds.
+pub fn ping(destination: Ipv4Address, timeout_ms: u64) -> Result<u64> {
+    static NEXT_SEQUENCE: AtomicU16 = AtomicU16::new(0);
+    const IDENTIFIER: u16 = 0x4D54;
+
+    let expected = EchoReply {
+        source: destination,
+        identifier: IDENTIFIER,
+        sequence: NEXT_SEQUENCE.fetch_add(1, Ordering::SeqCst),
+    };
+    let request = EchoPacket {
+        kind: EchoKind::Request,
+        identifier: expected.identifier,
+        sequence: expected.sequence,
+        data: b"martim ping",
+    };
+
+    let start = time::microseconds_monotonic();
+    lock_stack().send_ipv4(destination, Protocol::Icmp, &request.serialize())?;
+    wait_for(Some(timeout_ms), |stack| {
+        let replies = &mut stack.icmp.replies;
+        let index = replies.iter().position(|r| *r == expected)?;
+        replies.remove(index);
+        Some(Ok(time::microseconds_monotonic() - start))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_parse_serialize() {
+        let request = EchoPacket {
+            kind: EchoKind::Request,
+            identifier: 1,
+            sequence: 2,
+            data: &[0xAB; 5],
+        };
+        let data = request.serialize();
+        assert_eq!(0, checksum(&[&data]));
+
+        let parsed = EchoPacket::parse(&data).unwrap();
+        assert_eq!(EchoKind::Request, parsed.kind);
+        assert_eq!(1, parsed.identifier);
+        assert_eq!(2, parsed.sequence);
+        assert_eq!(&[0xAB; 5], parsed.data);
+    }
+}
@@ -0,0 +1,95 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::net::{Ipv4Address, MacAddress, Result};
+
+/// The address configuration of the guest in QEMU's user mode networking.
+pub const QEMU_USER_NETWORK: Ipv4Config = Ipv4Config {
+    address: Ipv4Address::new(10, 0, 2, 15),
+    netmask: Ipv4Address::new(255, 255, 255, 0),
+    gateway: Some(Ipv4Address::new(10, 0, 2, 2)),
+};
+
+/// A device that can send and receive Ethernet frames.
+pub trait NetworkDevice: Send {
+    fn mac_address(&self) -> MacAddress;
+
+    /// Sends a single frame, which doesn't include the frame check sequence.
+    fn transmit(&mut self, frame: &[u8]) -> Result<()>;
+
+    /// Returns the next received frame, or `None` if no frame is available.
+    fn receive(&mut self) -> Option<Vec<u8>>;
+
+    /// Whether frames that are transmitted by this device are received by the device
+    /// itself. Such devices don't need address resolution.
+    fn is_loopback(&self) -> bool {
+        false
+    }
+
+    /// Whether the stack is notified when this device received frames, so that it
+    /// doesn't have to be polled continuously. See [`crate::net::notify_received`].
+    fn notifies_receive(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Ipv4Config {
+    pub address: Ipv4Address,
+    pub netmask: Ipv4Address,
+    pub gateway: Option<Ipv4Address>,
+}
+
+pub struct Interface {
+    name: String,
+    device: Box<dyn NetworkDevice>,
+    config: Option<Ipv4Config>,
+}
+
+impl Interface {
+    pub fn new(name: String, device: Box<dyn NetworkDevice>, config: Option<Ipv4Config>) -> Self {
+        Self {
+            name,
+            device,
+            config,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn config(&self) -> Option<Ipv4Config> {
+        self.config
+    }
+
+    pub fn mac_address(&self) -> MacAddress {
+        self.device.mac_address()
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.device.is_loopback()
+    }
+
+    pub fn notifies_receive(&self) -> bool {
+        self.device.notifies_receive()
+    }
+
+    pub fn device(&mut self) -> &mut dyn NetworkDevice {
+        self.device.as_mut()
+    }
+
+    /// Returns the next hop for a packet to the given destination, if the destination
+    /// is reachable through this interface.
+    pub fn next_hop(&self, destination: Ipv4Address) -> Option<Ipv4Address> {
+        let config = self.config?;
+        if destination == Ipv4Address::BROADCAST
+            || destination.same_subnet(config.address, config.netmask)
+        {
+            Some(destination)
+        } else {
+            config.gateway
+        }
+    }
+}
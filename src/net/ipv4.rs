@@ -0,0 +1,209 @@
+use alloc::vec::Vec;
+
+use crate::net::{Error, Ipv4Address, Result};
+
+pub const HEADER_LEN: usize = 20;
+
+const DEFAULT_TTL: u8 = 64;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Protocol {
+    Icmp,
+    Tcp,
+    Udp,
+    Other(u8),
+}
+
+impl From<u8> for Protocol {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => Self::Icmp,
+            6 => Self::Tcp,
+            17 => Self::Udp,
+            _ => Self::Other(v),
+        }
+    }
+}
+
+impl From<Protocol> for u8 {
+    fn from(p: Protocol) -> Self {
+        match p {
+            Protocol::Icmp => 1,
+            Protocol::Tcp => 6,
+            Protocol::Udp => 17,
+            Protocol::Other(v) => v,
+        }
+    }
+}
+
+/// An IPv4 packet. Options are skipped when parsing and never sent, and
+/// fragmented packets are not supported.
+#[derive(Debug)]
+pub struct Ipv4Packet<'a> {
+    pub identification: u16,
+    pub ttl: u8,
+    pub protocol: Protocol,
+    pub source: Ipv4Address,
+    pub destination: Ipv4Address,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    pub fn new(
+        identification: u16,
+        protocol: Protocol,
+        source: Ipv4Address,
+        destination: Ipv4Address,
+        payload: &'a [u8],
+    ) -> Self {
+        Self {
+            identification,
+            ttl: DEFAULT_TTL,
+            protocol,
+            source,
+            destination,
+            payload,
+        }
+    }
+
+    /// Parses an IPv4 packet and verifies its header checksum. Fragments are
+    /// rejected with [`Error::Malformed`].
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < HEADER_LEN || data[0] >> 4 != 4 {
+            return Err(Error::Malformed);
+        }
+        let header_len = (data[0] & 0x0F) as usize * 4;
+        let total_len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if header_len < HEADER_LEN || total_len < header_len || total_len > data.len() {
+            return Err(Error::Malformed);
+        }
+        if checksum(&[&data[..header_len]]) != 0 {
+            return Err(Error::Malformed);
+        }
+        let flags_fragment_offset = u16::from_be_bytes([data[6], data[7]]);
+        let more_fragments = flags_fragment_offset & (1 << 13) != 0;
+        if more_fragments || flags_fragment_offset & 0x1FFF != 0 {
+            return Err(Error::Malformed);
+        }
+
+        Ok(Self {
+            identification: u16::from_be_bytes([data[4], data[5]]),
+            ttl: data[8],
+            protocol: data[9].into(),
+            source: Ipv4Address(data[12..16].try_into().unwrap()),
+            destination: Ipv4Address(data[16..20].try_into().unwrap()),
+            // ethernet frames may be padded, so only use the length from the header
+            payload: &data[header_len..total_len],
+        })
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let total_len = HEADER_LEN + self.payload.len();
+        if total_len > u16::MAX as usize {
+            return Err(Error::PacketTooLarge);
+        }
+
+        let mut data = Vec::with_capacity(total_len);
+        data.push(0x45); // version 4, 5 words of header
+        data.push(0); // DSCP and ECN
+        data.extend_from_slice(&(total_len as u16).to_be_bytes());
+        data.extend_from_slice(&self.identification.to_be_bytes());
+        data.extend_from_slice(&(1_u16 << 14).to_be_bytes()); // don't fragment
+        data.push(self.ttl);
+        data.push(self.protocol.into());
+        data.extend_from_slice(&[0, 0]); // checksum
+        data.extend_from_slice(&self.source.0);
+        data.extend_from_slice(&self.destination.0);
+        let header_checksum = checksum(&[&data]);
+        data[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+        data.extend_from_slice(self.payload);
+        Ok(data)
+    }
+}
+
+/// Computes the internet checksum (RFC 1071) over the concatenation of the given
+/// chunks. Every chunk except the last must have an even length.
+pub fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in chunks {
+        let mut words = chunk.chunks_exact(2);
+        for word in &mut words {
+            sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+        }
+        if let [last] = words.remainder() {
+            sum += (*last as u32) << 8;
+        }
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Computes the checksum of a TCP or UDP segment, including the IPv4 pseudo header.
+pub fn pseudo_header_checksum(
+    source: Ipv4Address,
+    destination: Ipv4Address,
+    protocol: Protocol,
+    segment: &[u8],
+) -> u16 {
+    let len = (segment.len() as u16).to_be_bytes();
+    let pseudo_header = [
+        source.0[0],
+        source.0[1],
+        source.0[2],
+        source.0[3],
+        destination.0[0],
+        destination.0[1],
+        destination.0[2],
+        destination.0[3],
+        0,
+        protocol.into(),
+        len[0],
+        len[1],
+    ];
+    checksum(&[&pseudo_header, segment])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_checksum() {
+        // example header from https://en.wikipedia.org/wiki/Internet_checksum
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(0xb861, checksum(&[&header]));
+        assert_eq!(
+            checksum(&[&header]),
+            checksum(&[&header[..10], &header[10..]])
+        );
+    }
+
+    #[test_case]
+    fn test_parse_serialize() {
+        let packet = Ipv4Packet::new(
+            7,
+            Protocol::Udp,
+            Ipv4Address::new(10, 0, 2, 15),
+            Ipv4Address::new(10, 0, 2, 2),
+            &[1, 2, 3, 4, 5],
+        );
+        let mut data = packet.serialize().unwrap();
+        assert_eq!(HEADER_LEN + 5, data.len());
+
+        data.extend_from_slice(&[0; 10]); // padding must be ignored
+        let parsed = Ipv4Packet::parse(&data).unwrap();
+        assert_eq!(7, parsed.identification);
+        assert_eq!(Protocol::Udp, parsed.protocol);
+        assert_eq!(packet.source, parsed.source);
+        assert_eq!(packet.destination, parsed.destination);
+        assert_eq!(&[1, 2, 3, 4, 5], parsed.payload);
+
+        data[8] = 1; // changing the ttl invalidates the checksum
+        assert!(Ipv4Packet::parse(&data).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! The loopback interface `lo`, through which the kernel talks to itself. It doesn't
+//! need any hardware, so sockets work even if no network card was found.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::net::interface::{Ipv4Config, NetworkDevice};
+use crate::net::{Ipv4Address, MacAddress, Result};
+
+pub const LOOPBACK_NAME: &str = "lo";
+
+pub const LOOPBACK_CONFIG: Ipv4Config = Ipv4Config {
+    address: Ipv4Address::LOCALHOST,
+    netmask: Ipv4Address::new(255, 0, 0, 0),
+    gateway: None,
+};
+
+/// A device that receives every frame that it transmits.
+#[derive(Default)]
+pub struct Loopback {
+    queue: VecDeque<Vec<u8>>,
+}
+
+impl NetworkDevice for Loopback {
+    fn mac_address(&self) -> MacAddress {
+        MacAddress::ZERO
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        self.queue.push_back(frame.into());
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.queue.pop_front()
+    }
+
+    fn is_loopback(&self) -> bool {
+        true
+    }
+
+    // the stack wakes the waiting futures whenever it transmits a frame on this device
+    fn notifies_receive(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::net::tcp::{TcpListener, TcpStream};
+    use crate::net::udp::UdpSocket;
+    use crate::net::{interfaces, Error, Ipv4Address};
+    use crate::task::executor::Executor;
+    use crate::task::Task;
+
+    use super::*;
+
+    #[test_case]
+    fn test_loopback_interface_exists() {
+        assert!(interfaces()
+            .iter()
+            .any(|(name, config)| name == LOOPBACK_NAME && *config == Some(LOOPBACK_CONFIG)));
+    }
+
+    #[test_case]
+    fn test_udp_loopback() {
+        let mut receiver = UdpSocket::bind(0).unwrap();
+        receiver.set_read_timeout(Some(1000));
+        let sender = UdpSocket::bind(0).unwrap();
+
+        assert_eq!(
+            Ok(5),
+            sender.send_to(b"hello", Ipv4Address::LOCALHOST, receiver.local_port())
+        );
+        let mut buf = [0_u8; 16];
+        assert_eq!(
+            Ok((5, Ipv4Address::LOCALHOST, sender.local_port())),
+            receiver.recv_from(&mut buf)
+        );
+        assert_eq!(b"hello", &buf[..5]);
+        assert_eq!(Err(Error::Timeout), receiver.recv_from(&mut buf));
+    }
+
+    #[test_case]
+    fn test_tcp_loopback() {
+        let listener = TcpListener::bind(7000).unwrap();
+        let client = TcpStream::connect(Ipv4Address::LOCALHOST, 7000).unwrap();
+        let server = listener.accept().unwrap();
+        assert_eq!((Ipv4Address::LOCALHOST, client.local_port()), server.peer());
+
+        let mut buf = [0_u8; 16];
+        assert_eq!(Ok(4), client.send(b"ping"));
+        assert_eq!(Ok(4), server.recv(&mut buf));
+        assert_eq!(b"ping", &buf[..4]);
+        assert_eq!(Ok(4), server.send(b"pong"));
+        assert_eq!(Ok(4), client.recv(&mut buf));
+        assert_eq!(b"pong", &buf[..4]);
+
+        drop(client);
+        assert_eq!(Ok(0), server.recv(&mut buf));
+    }
+
+    #[test_case]
+    fn test_tcp_loopback_connection_refused() {
+        assert_eq!(
+            Err(Error::ConnectionRefused),
+            TcpStream::connect(Ipv4Address::LOCALHOST, 7001).map(|_| ())
+        );
+    }
+
+    #[test_case]
+    fn test_async_sockets() {
+        let listener = TcpListener::bind(7002).unwrap();
+        let received = Arc::new(AtomicBool::new(false));
+
+        let mut executor = Executor::default();
+        let server_received = received.clone();
+        executor.spawn(Task::new(async move {
+            let stream = listener.accept_async().await.unwrap();
+            let mut buf = [0_u8; 16];
+            let len = stream.recv_async(&mut buf).await.unwrap();
+            assert_eq!(b"async", &buf[..len]);
+            server_received.store(true, Ordering::SeqCst);
+        }));
+        executor.spawn(Task::new(async {
+            let stream = TcpStream::connect_async(Ipv4Address::LOCALHOST, 7002)
+                .await
+                .unwrap();
+            assert_eq!(Ok(5), stream.send_async(b"async").await);
+        }));
+        // the loopback interface has to wake the waiting tasks, or they never complete
+        assert_eq!(0, executor.run_until_stalled());
+
+        assert!(received.load(Ordering::SeqCst));
+    }
+
+    #[test_case]
+    fn test_async_udp() {
+        let receiver = UdpSocket::bind(0).unwrap();
+        let port = receiver.local_port();
+        let mut executor = Executor::default();
+        executor.spawn(Task::new(async move {
+            let mut buf = [0_u8; 16];
+            let (len, _, _) = receiver.recv_from_async(&mut buf).await.unwrap();
+            assert_eq!(b"datagram", &buf[..len]);
+        }));
+        executor.spawn(Task::new(async move {
+            let sender = UdpSocket::bind(0).unwrap();
+            sender
+                .send_to(b"datagram", Ipv4Address::LOCALHOST, port)
+                .unwrap();
+        }));
+        assert_eq!(0, executor.run_until_stalled());
+    }
+
+    #[test_case]
+    fn test_loopback_device() {
+        let mut device: Box<dyn NetworkDevice> = Box::new(Loopback::default());
+        assert!(device.is_loopback());
+        device.transmit(&[1, 2, 3]).unwrap();
+        assert_eq!(Some(alloc::vec![1, 2, 3]), device.receive());
+        assert_eq!(None, device.receive());
+    }
+}
@@ -0,0 +1,220 @@
+//! A minimal network stack with support for Ethernet, ARP, IPv4, ICMP echo, UDP and TCP.
+//!
+//! Network devices are registered as [`Interface`]s, and all received frames are
+//! processed when [`poll`] is called. Operations that wait for the network, like
+//! [`icmp::ping`] or receiving from a socket, poll the interfaces while waiting.
+//! Blocking operations yield to other scheduler tasks between polls, and sockets
+//! also have `async` variants that can be awaited in the task executor.
+//!
+//! The loopback interface `lo` is always present, so that the kernel can talk to
+//! itself through 127.0.0.1 without any network hardware.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use core::future::Future;
+use core::task::Poll;
+
+use derive_more::Display;
+use kstd::sync::{Mutex, MutexGuard, Once};
+
+use crate::net::interface::{Interface, Ipv4Config, NetworkDevice};
+use crate::net::stack::Stack;
+use crate::scheduler::Scheduler;
+use crate::time;
+
+pub mod arp;
+pub mod ethernet;
+pub mod icmp;
+pub mod interface;
+pub mod ipv4;
+pub mod loopback;
+pub mod socket;
+mod stack;
+pub mod tcp;
+pub mod udp;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Display)]
+pub enum Error {
+    #[display(fmt = "packet is malformed")]
+    Malformed,
+    #[display(fmt = "packet is too large")]
+    PacketTooLarge,
+    #[display(fmt = "no route to {_0}")]
+    NoRoute(Ipv4Address),
+    #[display(fmt = "port {_0} is already in use")]
+    PortInUse(u16),
+    #[display(fmt = "no free port")]
+    NoFreePort,
+    #[display(fmt = "operation timed out")]
+    Timeout,
+    #[display(fmt = "connection refused")]
+    ConnectionRefused,
+    #[display(fmt = "connection reset")]
+    ConnectionReset,
+    #[display(fmt = "not connected")]
+    NotConnected,
+    #[display(fmt = "device error")]
+    Device,
+}
+
+impl core::error::Error for Error {}
+
+impl From<Error> for kstd::io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Malformed => Self::DecodeError,
+            Error::PacketTooLarge | Error::PortInUse(_) | Error::NoFreePort => {
+                Self::InvalidArgument
+            }
+            Error::NoRoute(_) | Error::ConnectionRefused | Error::NotConnected => Self::NotFound,
+            Error::Timeout | Error::ConnectionReset => Self::PrematureEndOfInput,
+            Error::Device => Self::IncoherentData,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xFF; 6]);
+    pub const ZERO: MacAddress = MacAddress([0; 6]);
+}
+
+impl Display for MacAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Ipv4Address = Ipv4Address([0; 4]);
+    pub const BROADCAST: Ipv4Address = Ipv4Address([0xFF; 4]);
+    pub const LOCALHOST: Ipv4Address = Ipv4Address([127, 0, 0, 1]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+
+    /// Whether this and the other address are in the same subnet with the given mask.
+    pub fn same_subnet(&self, other: Ipv4Address, netmask: Ipv4Address) -> bool {
+        let mask = u32::from(netmask);
+        u32::from(*self) & mask == u32::from(other) & mask
+    }
+}
+
+impl From<Ipv4Address> for u32 {
+    fn from(a: Ipv4Address) -> Self {
+        u32::from_be_bytes(a.0)
+    }
+}
+
+impl From<u32> for Ipv4Address {
+    fn from(v: u32) -> Self {
+        Self(v.to_be_bytes())
+    }
+}
+
+impl Display for Ipv4Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+static mut STACK: Option<Mutex<Stack>> = None;
+static STACK_INIT: Once = Once::new();
+
+fn lock_stack() -> MutexGuard<'static, Stack> {
+    STACK_INIT.call_once(|| unsafe {
+        STACK = Some(Mutex::new(Stack::new()));
+    });
+    unsafe { STACK.as_ref().unwrap().lock() }
+}
+
+/// Adds a network device as a new interface with the given name. If a config is given,
+/// the interface can immediately be used to send and receive IPv4 packets.
+pub fn add_interface(name: String, device: Box<dyn NetworkDevice>, config: Option<Ipv4Config>) {
+    lock_stack()
+        .interfaces
+        .push(Interface::new(name, device, config));
+}
+
+/// Returns the names of all interfaces together with their config.
+pub fn interfaces() -> Vec<(String, Option<Ipv4Config>)> {
+    lock_stack()
+        .interfaces
+        .iter()
+        .map(|i| (i.name().into(), i.config()))
+        .collect()
+}
+
+/// Processes all frames that the interfaces received since the last call, and
+/// retransmits unacknowledged TCP segments.
+pub fn poll() {
+    lock_stack().poll();
+}
+
+/// Wakes the futures that wait for the network. Drivers call this from the interrupt
+/// handler of a device that received frames, which are processed by the woken futures.
+pub fn notify_received() {
+    stack::wake_from_interrupt();
+}
+
+/// Polls the interfaces until `f` returns `Some`, or until `timeout_ms` milliseconds
+/// have passed. Without a timeout, this waits forever. Other scheduler tasks run
+/// between the polls.
+fn wait_for<T, F>(timeout_ms: Option<u64>, mut f: F) -> Result<T>
+where
+    F: FnMut(&mut Stack) -> Option<Result<T>>,
+{
+    let start = time::microseconds_monotonic();
+    loop {
+        {
+            let mut stack = lock_stack();
+            stack.poll();
+            if let Some(result) = f(&mut stack) {
+                return result;
+            }
+        }
+        if let Some(timeout) = timeout_ms {
+            if time::microseconds_monotonic() - start > timeout * 1000 {
+                return Err(Error::Timeout);
+            }
+        }
+        Scheduler::reschedule();
+    }
+}
+
+/// Like [`wait_for`], but returns a future that completes once `f` returns `Some`.
+/// The future is woken when the stack received frames, and immediately if the stack
+/// has to be polled continuously.
+fn poll_for<T, F>(mut f: F) -> impl Future<Output = Result<T>>
+where
+    F: FnMut(&mut Stack) -> Option<Result<T>>,
+{
+    core::future::poll_fn(move |cx| {
+        let mut stack = lock_stack();
+        stack.poll();
+        if let Some(result) = f(&mut stack) {
+            return Poll::Ready(result);
+        }
+        stack.register_waker(cx.waker());
+        if stack.needs_polling() {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    })
+}
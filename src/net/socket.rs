@@ -0,0 +1,174 @@
+//! Sockets as character device files, so that they can be read and written like
+//! any other node in the VFS. The offset of reads and writes is ignored.
+//!
+//! Sockets are mounted at `/dev` (e.g. `/dev/udp:49152`) until they are closed.
+
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use kstd::io::Result;
+
+use crate::io::fs::{vfs, ICharacterDeviceFile, INode, INodeBase, INodeNum, Stat};
+use crate::net::tcp::TcpStream;
+use crate::net::udp::UdpSocket;
+use crate::net::Ipv4Address;
+
+/// Inode numbers of sockets, which don't belong to any file system.
+static NEXT_INODE: AtomicU64 = AtomicU64::new(1 << 32);
+
+pub enum Socket {
+    /// A UDP socket that sends all data to a fixed peer and receives from anyone.
+    Udp {
+        socket: UdpSocket,
+        peer: (Ipv4Address, u16),
+    },
+    Tcp(TcpStream),
+}
+
+pub struct SocketFile {
+    name: String,
+    stat: Stat,
+    socket: Socket,
+}
+
+impl SocketFile {
+    pub fn new(name: String, socket: Socket) -> Self {
+        Self {
+            name,
+            stat: Stat {
+                inode: NEXT_INODE.fetch_add(1, Ordering::SeqCst).into(),
+                ..Default::default()
+            },
+            socket,
+        }
+    }
+
+    pub fn socket(&self) -> &Socket {
+        &self.socket
+    }
+}
+
+impl INodeBase for SocketFile {
+    fn num(&self) -> INodeNum {
+        self.stat.inode
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn stat(&self) -> Stat {
+        self.stat
+    }
+}
+
+impl ICharacterDeviceFile for SocketFile {
+    fn read_at(&self, _: u64, buf: &mut dyn AsMut<[u8]>) -> Result<usize> {
+        let buffer = buf.as_mut();
+        Ok(match &self.socket {
+            Socket::Udp { socket, .. } => socket.recv_from(buffer)?.0,
+            Socket::Tcp(stream) => stream.recv(buffer)?,
+        })
+    }
+
+    fn write_at(&self, _: u64, buf: &dyn AsRef<[u8]>) -> Result<usize> {
+        let buffer = buf.as_ref();
+        Ok(match &self.socket {
+            Socket::Udp { socket, peer } => socket.send_to(buffer, peer.0, peer.1)?,
+            Socket::Tcp(stream) => stream.send(buffer)?,
+        })
+    }
+}
+
+/// Binds a UDP socket to the given local port (0 for any port) and mounts it at
+/// `/dev/udp:<port>`. Everything that is written to the node is sent to the given peer.
+pub fn udp(local_port: u16, peer: (Ipv4Address, u16)) -> Result<INode> {
+    let socket = UdpSocket::bind(local_port)?;
+    let name = format!("udp:{}", socket.local_port());
+    mount(SocketFile::new(name, Socket::Udp { socket, peer }))
+}
+
+/// Connects to the given peer and mounts the connection at `/dev/tcp:<port>`, named
+/// after the local port.
+pub fn tcp(peer: (Ipv4Address, u16)) -> Result<INode> {
+    let stream = TcpStream::connect(peer.0, peer.1)?;
+    let name = format!("tcp:{}", stream.local_port());
+    mount(SocketFile::new(name, Socket::Tcp(stream)))
+}
+
+/// Unmounts the socket with the given name from `/dev`. The socket is closed once
+/// the last handle to its node is dropped.
+pub fn close(name: &str) -> Result<()> {
+    vfs::unmount(&"/dev", &name).map(|_| ())
+}
+
+fn mount(file: SocketFile) -> Result<INode> {
+    let node = INode::new_character_device_file(file);
+    vfs::mount(&"/dev", node.clone())?;
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicBool, AtomicU16};
+    use core::time::Duration;
+
+    use crate::io::fs::memfs::MemFs;
+    use crate::io::fs::Fs;
+    use crate::net::tcp::TcpListener;
+    use crate::scheduler::Scheduler;
+    use crate::time;
+
+    use super::*;
+
+    static LOCAL_PORT: AtomicU16 = AtomicU16::new(0);
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    fn open(name: &str) -> crate::io::fs::ICharacterDeviceHandle {
+        vfs::find_inode(&format!("/dev/{}", name).as_str())
+            .unwrap()
+            .as_character_device_file()
+            .unwrap()
+    }
+
+    extern "C" fn reader() {
+        let socket = open(&format!("tcp:{}", LOCAL_PORT.load(Ordering::SeqCst)));
+        let mut buf = [0_u8; 4];
+        // waits for the reply to the write of the other task
+        assert_eq!(Ok(4), socket.read().read_at(0, &mut buf));
+        assert_eq!(b"pong", &buf);
+        RECEIVED.store(true, Ordering::SeqCst);
+        Scheduler::exit();
+    }
+
+    #[test_case]
+    fn test_concurrent_read_and_write() {
+        if vfs::find_inode(&"/dev").is_err() {
+            vfs::mount(&"/", MemFs::new("dev".into()).root_inode()).unwrap();
+        }
+        let listener = TcpListener::bind(7010).unwrap();
+        let node = tcp((Ipv4Address::LOCALHOST, 7010)).unwrap();
+        let server = listener.accept().unwrap();
+        LOCAL_PORT.store(server.peer().1, Ordering::SeqCst);
+
+        Scheduler::spawn_from_c_fn(reader).unwrap();
+        Scheduler::sleep(Duration::from_millis(10));
+        let socket = open(&node.name());
+        assert_eq!(Ok(4), socket.read().write_at(0, &b"ping"));
+        let mut buf = [0_u8; 4];
+        assert_eq!(Ok(4), server.recv(&mut buf));
+        assert_eq!(b"ping", &buf);
+        assert_eq!(Ok(4), server.send(b"pong"));
+
+        let start = time::microseconds_monotonic();
+        while !RECEIVED.load(Ordering::SeqCst) {
+            assert!(
+                time::microseconds_monotonic() - start < 1_000_000,
+                "the reader didn't receive the reply"
+            );
+            Scheduler::reschedule();
+        }
+        close(&node.name()).unwrap();
+    }
+}
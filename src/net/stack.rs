@@ -0,0 +1,348 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::task::Waker;
+
+use kstd::sync::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::net::arp::{ArpCache, ArpPacket, Operation};
+use crate::net::ethernet::{EtherType, EthernetFrame};
+use crate::net::icmp::IcmpState;
+use crate::net::interface::Interface;
+use crate::net::ipv4::{Ipv4Packet, Protocol};
+use crate::net::loopback::{Loopback, LOOPBACK_CONFIG, LOOPBACK_NAME};
+use crate::net::tcp::TcpState;
+use crate::net::udp::UdpState;
+use crate::net::{icmp, tcp, udp, Error, Ipv4Address, MacAddress, Result};
+
+/// The maximum number of packets that wait for an ARP reply.
+const MAX_PENDING_PACKETS: usize = 16;
+
+/// Ephemeral ports as suggested by IANA.
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// An IPv4 packet that can't be sent until the MAC address of the next hop is known.
+struct PendingPacket {
+    interface: usize,
+    next_hop: Ipv4Address,
+    packet: Vec<u8>,
+}
+
+/// Wakers of futures that wait for the stack to change. They are kept outside of the
+/// stack, so that interrupt handlers can wake them without locking the stack. Every
+/// lock holder except the interrupt handlers disables interrupts, so this can't deadlock.
+static WAKERS: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+
+/// Wakes all registered futures without removing them, so that no memory is freed.
+/// This is called by interrupt handlers of devices that received frames; the woken
+/// futures poll the stack, which removes the wakers.
+pub(in crate::net) fn wake_from_interrupt() {
+    WAKERS.lock().iter().for_each(Waker::wake_by_ref);
+}
+
+/// A route to a destination.
+#[derive(Copy, Clone, Debug)]
+pub(in crate::net) struct Route {
+    pub interface: usize,
+    pub next_hop: Ipv4Address,
+    pub source: Ipv4Address,
+}
+
+/// All state of the network stack. Protocol handlers get mutable access to the
+/// whole stack, so that they can send replies while processing a packet.
+pub(in crate::net) struct Stack {
+    pub interfaces: Vec<Interface>,
+    pub arp_cache: ArpCache,
+    pub icmp: IcmpState,
+    pub udp: UdpState,
+    pub tcp: TcpState,
+    pending: Vec<PendingPacket>,
+    next_identification: u16,
+    next_ephemeral_port: u16,
+}
+
+impl Stack {
+    pub fn new() -> Self {
+        Self {
+            interfaces: vec![Interface::new(
+                LOOPBACK_NAME.into(),
+                Box::new(Loopback::default()),
+                Some(LOOPBACK_CONFIG),
+            )],
+            arp_cache: ArpCache::default(),
+            icmp: IcmpState::default(),
+            udp: UdpState::default(),
+            tcp: TcpState::default(),
+            pending: Vec::new(),
+            next_identification: 0,
+            next_ephemeral_port: *EPHEMERAL_PORTS.start(),
+        }
+    }
+
+    pub fn poll(&mut self) {
+        let mut received = false;
+        for interface in 0..self.interfaces.len() {
+            while let Some(frame) = self.interfaces[interface].device().receive() {
+                self.handle_frame(interface, &frame);
+                received = true;
+            }
+        }
+        tcp::on_tick(self);
+        if received {
+            self.wake_all();
+        }
+    }
+
+    /// Registers a waker that is woken once the stack received frames.
+    pub fn register_waker(&mut self, waker: &Waker) {
+        without_interrupts(|| {
+            let mut wakers = WAKERS.lock();
+            if !wakers.iter().any(|w| w.will_wake(waker)) {
+                wakers.push(waker.clone());
+            }
+        });
+    }
+
+    fn wake_all(&mut self) {
+        let wakers = without_interrupts(|| core::mem::take(&mut *WAKERS.lock()));
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Whether the stack has to be polled continuously, because an interface doesn't
+    /// notify the stack about received frames, or because TCP segments may have to be
+    /// retransmitted.
+    pub fn needs_polling(&self) -> bool {
+        self.interfaces.iter().any(|i| !i.notifies_receive())
+            || self.tcp.has_pending_retransmissions()
+    }
+
+    /// Whether the given address is assigned to one of the interfaces.
+    pub fn is_local_address(&self, address: Ipv4Address) -> bool {
+        self.interfaces
+            .iter()
+            .filter_map(Interface::config)
+            .any(|config| config.address == address)
+    }
+
+    fn handle_frame(&mut self, interface: usize, data: &[u8]) {
+        let frame = match EthernetFrame::parse(data) {
+            Ok(f) => f,
+            Err(_) => return,
+        };
+        let mac = self.interfaces[interface].mac_address();
+        if frame.destination != mac && frame.destination != MacAddress::BROADCAST {
+            return;
+        }
+
+        match frame.ether_type {
+            EtherType::Arp => self.handle_arp(interface, frame.payload),
+            EtherType::Ipv4 => self.handle_ipv4(interface, frame.payload),
+            EtherType::Other(_) => {}
+        }
+    }
+
+    fn handle_arp(&mut self, interface: usize, data: &[u8]) {
+        let packet = match ArpPacket::parse(data) {
+            Ok(p) => p,
+            Err(_) => return,
+        };
+        let config = match self.interfaces[interface].config() {
+            Some(c) => c,
+            None => return,
+        };
+
+        let known = self.arp_cache.lookup(packet.sender_ip).is_some();
+        let for_us = packet.target_ip == config.address;
+        if known || for_us {
+            self.arp_cache.insert(packet.sender_ip, packet.sender_mac);
+            self.send_pending(packet.sender_ip, packet.sender_mac);
+        }
+
+        if for_us && packet.operation == Operation::Request {
+            let mac = self.interfaces[interface].mac_address();
+            let reply = ArpPacket {
+                operation: Operation::Reply,
+                sender_mac: mac,
+                sender_ip: config.address,
+                target_mac: packet.sender_mac,
+                target_ip: packet.sender_ip,
+            };
+            let _ = self.transmit(
+                interface,
+                packet.sender_mac,
+                EtherType::Arp,
+                &reply.serialize(),
+            );
+        }
+    }
+
+    fn handle_ipv4(&mut self, interface: usize, data: &[u8]) {
+        let packet = match Ipv4Packet::parse(data) {
+            Ok(p) => p,
+            Err(_) => return,
+        };
+        let config = match self.interfaces[interface].config() {
+            Some(c) => c,
+            None => return,
+        };
+        // everything on the loopback interface was sent by ourselves to ourselves
+        if !self.interfaces[interface].is_loopback()
+            && packet.destination != config.address
+            && packet.destination != Ipv4Address::BROADCAST
+        {
+            return;
+        }
+
+        match packet.protocol {
+            Protocol::Icmp => icmp::handle(self, &packet),
+            Protocol::Udp => udp::handle(self, &packet),
+            Protocol::Tcp => tcp::handle(self, &packet),
+            Protocol::Other(_) => {}
+        }
+    }
+
+    /// Finds the interface through which the given destination is reachable. Local
+    /// addresses are reached through the loopback interface, and interfaces that reach
+    /// the destination directly are preferred over those that need a gateway.
+    pub fn route(&self, destination: Ipv4Address) -> Result<Route> {
+        if self.is_local_address(destination) {
+            if let Some(index) = self.interfaces.iter().position(Interface::is_loopback) {
+                return Ok(Route {
+                    interface: index,
+                    next_hop: destination,
+                    source: destination,
+                });
+            }
+        }
+
+        let find = |direct: bool| {
+            self.interfaces
+                .iter()
+                .enumerate()
+                .find_map(|(index, interface)| {
+                    let next_hop = interface.next_hop(destination)?;
+                    if direct && next_hop != destination {
+                        return None;
+                    }
+                    Some(Route {
+                        interface: index,
+                        next_hop,
+                        source: interface.config()?.address,
+                    })
+                })
+        };
+        find(true)
+            .or_else(|| find(false))
+            .ok_or(Error::NoRoute(destination))
+    }
+
+    /// Sends an IPv4 packet with the given payload. If the MAC address of the next hop
+    /// is unknown, the packet is queued and sent once the address was resolved.
+    pub fn send_ipv4(
+        &mut self,
+        destination: Ipv4Address,
+        protocol: Protocol,
+        payload: &[u8],
+    ) -> Result<()> {
+        let route = self.route(destination)?;
+        let identification = self.next_identification;
+        self.next_identification = self.next_identification.wrapping_add(1);
+        let packet = Ipv4Packet::new(identification, protocol, route.source, destination, payload)
+            .serialize()?;
+
+        let mac = if self.interfaces[route.interface].is_loopback() {
+            Some(MacAddress::ZERO)
+        } else if route.next_hop == Ipv4Address::BROADCAST {
+            Some(MacAddress::BROADCAST)
+        } else {
+            self.arp_cache.lookup(route.next_hop)
+        };
+        match mac {
+            Some(mac) => self.transmit(route.interface, mac, EtherType::Ipv4, &packet),
+            None => {
+                if self.pending.len() >= MAX_PENDING_PACKETS {
+                    self.pending.remove(0);
+                }
+                self.pending.push(PendingPacket {
+                    interface: route.interface,
+                    next_hop: route.next_hop,
+                    packet,
+                });
+                self.send_arp_request(route.interface, route.source, route.next_hop)
+            }
+        }
+    }
+
+    fn send_arp_request(
+        &mut self,
+        interface: usize,
+        source: Ipv4Address,
+        target: Ipv4Address,
+    ) -> Result<()> {
+        let request = ArpPacket {
+            operation: Operation::Request,
+            sender_mac: self.interfaces[interface].mac_address(),
+            sender_ip: source,
+            target_mac: MacAddress::ZERO,
+            target_ip: target,
+        };
+        self.transmit(
+            interface,
+            MacAddress::BROADCAST,
+            EtherType::Arp,
+            &request.serialize(),
+        )
+    }
+
+    fn send_pending(&mut self, next_hop: Ipv4Address, mac: MacAddress) {
+        let (ready, pending) = core::mem::take(&mut self.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|p| p.next_hop == next_hop);
+        self.pending = pending;
+        for packet in ready {
+            let _ = self.transmit(packet.interface, mac, EtherType::Ipv4, &packet.packet);
+        }
+    }
+
+    fn transmit(
+        &mut self,
+        interface: usize,
+        destination: MacAddress,
+        ether_type: EtherType,
+        payload: &[u8],
+    ) -> Result<()> {
+        let interface = &mut self.interfaces[interface];
+        let frame = EthernetFrame {
+            destination,
+            source: interface.mac_address(),
+            ether_type,
+            payload,
+        };
+        interface.device().transmit(&frame.serialize())?;
+        if interface.is_loopback() {
+            // the frame is received with the next poll, which waiting futures have to do
+            self.wake_all();
+        }
+        Ok(())
+    }
+
+    /// Returns an unused ephemeral port, according to the given predicate.
+    pub fn allocate_port<F>(&mut self, is_used: F) -> Result<u16>
+    where
+        F: Fn(u16) -> bool,
+    {
+        for _ in EPHEMERAL_PORTS {
+            let port = self.next_ephemeral_port;
+            self.next_ephemeral_port = if port == *EPHEMERAL_PORTS.end() {
+                *EPHEMERAL_PORTS.start()
+            } else {
+                port + 1
+            };
+            if !is_used(port) {
+                return Ok(port);
+            }
+        }
+        Err(Error::NoFreePort)
+    }
+}
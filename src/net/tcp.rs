@@ -0,0 +1,860 @@
+//! A small TCP implementation. It supports active and passive opens, in-order
+//! delivery with go-back-N retransmission and the usual connection teardown.
+//! Out-of-order segments are dropped and re-acknowledged, there is no congestion
+//! control, and `TIME-WAIT` is skipped.
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::vec::Vec;
+
+use bitflags::bitflags;
+
+use crate::net::ipv4::{pseudo_header_checksum, Ipv4Packet, Protocol};
+use crate::net::stack::Stack;
+use crate::net::{lock_stack, poll_for, wait_for, Error, Ipv4Address, Result};
+use crate::time;
+
+const HEADER_LEN: usize = 20;
+
+/// The maximum segment size that is advertised to the peer.
+const OUR_MSS: u16 = 1460;
+/// The maximum segment size that is assumed if the peer doesn't advertise one.
+const DEFAULT_MSS: u16 = 536;
+
+const RECEIVE_BUFFER_SIZE: usize = 16 * 1024;
+const SEND_BUFFER_SIZE: usize = 16 * 1024;
+
+const RETRANSMISSION_TIMEOUT_MS: u64 = 1000;
+const MAX_RETRIES: u32 = 5;
+const CONNECT_TIMEOUT_MS: u64 = 10_000;
+
+bitflags! {
+    pub struct TcpFlags: u8 {
+        const FIN = 1 << 0;
+        const SYN = 1 << 1;
+        const RST = 1 << 2;
+        const PSH = 1 << 3;
+        const ACK = 1 << 4;
+        const URG = 1 << 5;
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpSegment<'a> {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub sequence: u32,
+    pub acknowledgement: u32,
+    pub flags: TcpFlags,
+    pub window: u16,
+    /// The maximum segment size option, which is only sent with `SYN`.
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    pub fn parse(data: &'a [u8], source: Ipv4Address, destination: Ipv4Address) -> Result<Self> {
+        if data.len() < HEADER_LEN {
+            return Err(Error::Malformed);
+        }
+        let header_len = (data[12] >> 4) as usize * 4;
+        if header_len < HEADER_LEN || header_len > data.len() {
+            return Err(Error::Malformed);
+        }
+        if pseudo_header_checksum(source, destination, Protocol::Tcp, data) != 0 {
+            return Err(Error::Malformed);
+        }
+
+        Ok(Self {
+            source_port: u16::from_be_bytes([data[0], data[1]]),
+            destination_port: u16::from_be_bytes([data[2], data[3]]),
+            sequence: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            acknowledgement: u32::from_be_bytes(data[8..12].try_into().unwrap()),
+            flags: TcpFlags::from_bits_truncate(data[13]),
+            window: u16::from_be_bytes([data[14], data[15]]),
+            mss: Self::parse_mss(&data[HEADER_LEN..header_len]),
+            payload: &data[header_len..],
+        })
+    }
+
+    fn parse_mss(mut options: &[u8]) -> Option<u16> {
+        while let [kind, rest @ ..] = options {
+            match kind {
+                0 => return None, // end of options
+                1 => options = rest,
+                _ => {
+                    let len = *rest.first()? as usize;
+                    if len < 2 || len > options.len() {
+                        return None;
+                    }
+                    if *kind == 2 && len == 4 {
+                        return Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        None
+    }
+
+    /// The length of this segment in sequence space.
+    pub fn sequence_len(&self) -> u32 {
+        let mut len = self.payload.len() as u32;
+        if self.flags.contains(TcpFlags::SYN) {
+            len += 1;
+        }
+        if self.flags.contains(TcpFlags::FIN) {
+            len += 1;
+        }
+        len
+    }
+
+    pub fn serialize(&self, source: Ipv4Address, destination: Ipv4Address) -> Vec<u8> {
+        let header_len = if self.mss.is_some() {
+            HEADER_LEN + 4
+        } else {
+            HEADER_LEN
+        };
+        let mut data = Vec::with_capacity(header_len + self.payload.len());
+        data.extend_from_slice(&self.source_port.to_be_bytes());
+        data.extend_from_slice(&self.destination_port.to_be_bytes());
+        data.extend_from_slice(&self.sequence.to_be_bytes());
+        data.extend_from_slice(&self.acknowledgement.to_be_bytes());
+        data.push(((header_len / 4) as u8) << 4);
+        data.push(self.flags.bits());
+        data.extend_from_slice(&self.window.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0]); // checksum and urgent pointer
+        if let Some(mss) = self.mss {
+            data.extend_from_slice(&[2, 4]);
+            data.extend_from_slice(&mss.to_be_bytes());
+        }
+        data.extend_from_slice(self.payload);
+        let sum = pseudo_header_checksum(source, destination, Protocol::Tcp, &data);
+        data[16..18].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    Closed,
+}
+
+/// Whether `a` comes before `b` in sequence space.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// The transmission control block of a connection.
+struct Tcb {
+    local_address: Ipv4Address,
+    local_port: u16,
+    remote_address: Ipv4Address,
+    remote_port: u16,
+    state: State,
+
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u16,
+    mss: u16,
+    rcv_nxt: u32,
+
+    /// All data from `snd_una` on, of which the first `sent` bytes are in flight.
+    send_buffer: VecDeque<u8>,
+    sent: usize,
+    /// Whether a `FIN` should be sent after the send buffer has been sent.
+    fin_queued: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    receive_buffer: VecDeque<u8>,
+
+    retransmit_at: Option<u64>,
+    retries: u32,
+    error: Option<Error>,
+    /// The port of the listener that owns this connection until it is accepted.
+    listener: Option<u16>,
+    /// Whether no [`TcpStream`] references this connection anymore, so that it can
+    /// be removed once it is closed.
+    detached: bool,
+}
+
+impl Tcb {
+    fn new(local: (Ipv4Address, u16), remote: (Ipv4Address, u16), state: State, iss: u32) -> Self {
+        Self {
+            local_address: local.0,
+            local_port: local.1,
+            remote_address: remote.0,
+            remote_port: remote.1,
+            state,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1), // the SYN
+            snd_wnd: 0,
+            mss: DEFAULT_MSS,
+            rcv_nxt: 0,
+            send_buffer: VecDeque::new(),
+            sent: 0,
+            fin_queued: false,
+            fin_sent: false,
+            fin_received: false,
+            receive_buffer: VecDeque::new(),
+            retransmit_at: None,
+            retries: 0,
+            error: None,
+            listener: None,
+            detached: false,
+        }
+    }
+
+    fn matches(&self, packet: &Ipv4Packet, segment: &TcpSegment) -> bool {
+        self.local_address == packet.destination
+            && self.local_port == segment.destination_port
+            && self.remote_address == packet.source
+            && self.remote_port == segment.source_port
+    }
+
+    fn window(&self) -> u16 {
+        (RECEIVE_BUFFER_SIZE - self.receive_buffer.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn start_timer(&mut self) {
+        if self.retransmit_at.is_none() {
+            let backoff = 1 << self.retries;
+            self.retransmit_at =
+                Some(time::microseconds_monotonic() + RETRANSMISSION_TIMEOUT_MS * 1000 * backoff);
+        }
+    }
+
+    fn close_with(&mut self, error: Option<Error>) {
+        self.state = State::Closed;
+        self.error = self.error.or(error);
+        self.retransmit_at = None;
+    }
+
+    /// Removes acknowledged data from the send buffer.
+    fn acknowledge(&mut self, acknowledgement: u32) {
+        let mut acked = acknowledgement.wrapping_sub(self.snd_una) as usize;
+        if self.state == State::SynReceived {
+            acked -= 1; // our SYN
+        }
+        let data = acked.min(self.sent);
+        self.send_buffer.drain(..data);
+        self.sent -= data;
+        self.snd_una = acknowledgement;
+
+        self.retries = 0;
+        self.retransmit_at = None;
+        if self.snd_una != self.snd_nxt {
+            self.start_timer();
+        }
+    }
+
+    fn everything_acked(&self) -> bool {
+        self.fin_sent && self.snd_una == self.snd_nxt
+    }
+}
+
+#[derive(Default)]
+pub(in crate::net) struct TcpState {
+    connections: BTreeMap<u32, Tcb>,
+    listeners: BTreeSet<u16>,
+    next_id: u32,
+}
+
+impl TcpState {
+    /// Whether any connection waits for a retransmission timeout.
+    pub(in crate::net) fn has_pending_retransmissions(&self) -> bool {
+        self.connections
+            .values()
+            .any(|tcb| tcb.retransmit_at.is_some())
+    }
+
+    fn insert(&mut self, tcb: Tcb) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.connections.insert(id, tcb);
+        id
+    }
+
+    fn is_port_used(&self, port: u16) -> bool {
+        self.listeners.contains(&port) || self.connections.values().any(|c| c.local_port == port)
+    }
+}
+
+/// Temporarily removes the connection from the stack, so that it can be modified
+/// while segments are sent through the stack.
+fn with_tcb<T, F>(stack: &mut Stack, id: u32, f: F) -> Option<T>
+where
+    F: FnOnce(&mut Stack, &mut Tcb) -> T,
+{
+    let mut tcb = stack.tcp.connections.remove(&id)?;
+    let result = f(stack, &mut tcb);
+    stack.tcp.connections.insert(id, tcb);
+    Some(result)
+}
+
+fn initial_sequence_number() -> u32 {
+    // roughly the 4 microsecond clock from RFC 793
+    (time::microseconds_monotonic() / 4) as u32
+}
+
+fn send_segment(stack: &mut Stack, tcb: &Tcb, flags: TcpFlags, sequence: u32, payload: &[u8]) {
+    let segment = TcpSegment {
+        source_port: tcb.local_port,
+        destination_port: tcb.remote_port,
+        sequence,
+        acknowledgement: if flags.contains(TcpFlags::ACK) {
+            tcb.rcv_nxt
+        } else {
+            0
+        },
+        flags,
+        window: tcb.window(),
+        mss: flags.contains(TcpFlags::SYN).then_some(OUR_MSS),
+        payload,
+    };
+    let data = segment.serialize(tcb.local_address, tcb.remote_address);
+    let _ = stack.send_ipv4(tcb.remote_address, Protocol::Tcp, &data);
+}
+
+fn send_syn(stack: &mut Stack, tcb: &mut Tcb) {
+    let flags = match tcb.state {
+        State::SynReceived => TcpFlags::SYN | TcpFlags::ACK,
+        _ => TcpFlags::SYN,
+    };
+    send_segment(stack, tcb, flags, tcb.snd_una, &[]);
+    tcb.start_timer();
+}
+
+fn send_ack(stack: &mut Stack, tcb: &Tcb) {
+    send_segment(stack, tcb, TcpFlags::ACK, tcb.snd_nxt, &[]);
+}
+
+/// Sends as much of the send buffer as the peer's window allows, followed by a
+/// `FIN` if the connection is being closed.
+fn transmit(stack: &mut Stack, tcb: &mut Tcb) {
+    if matches!(
+        tcb.state,
+        State::SynSent | State::SynReceived | State::Closed
+    ) {
+        return;
+    }
+
+    while tcb.sent < tcb.send_buffer.len() && tcb.sent < tcb.snd_wnd as usize {
+        let len = (tcb.send_buffer.len() - tcb.sent)
+            .min(tcb.snd_wnd as usize - tcb.sent)
+            .min(tcb.mss as usize);
+        let payload = tcb
+            .send_buffer
+            .range(tcb.sent..tcb.sent + len)
+            .copied()
+            .collect::<Vec<u8>>();
+        send_segment(
+            stack,
+            tcb,
+            TcpFlags::ACK | TcpFlags::PSH,
+            tcb.snd_nxt,
+            &payload,
+        );
+        tcb.snd_nxt = tcb.snd_nxt.wrapping_add(len as u32);
+        tcb.sent += len;
+        tcb.start_timer();
+    }
+
+    if tcb.fin_queued && !tcb.fin_sent && tcb.sent == tcb.send_buffer.len() {
+        send_segment(stack, tcb, TcpFlags::FIN | TcpFlags::ACK, tcb.snd_nxt, &[]);
+        tcb.snd_nxt = tcb.snd_nxt.wrapping_add(1);
+        tcb.fin_sent = true;
+        tcb.start_timer();
+    }
+}
+
+/// Answers a segment that doesn't belong to any connection with a reset.
+fn send_reset(stack: &mut Stack, packet: &Ipv4Packet, segment: &TcpSegment) {
+    let (sequence, acknowledgement, flags) = if segment.flags.contains(TcpFlags::ACK) {
+        (segment.acknowledgement, 0, TcpFlags::RST)
+    } else {
+        (
+            0,
+            segment.sequence.wrapping_add(segment.sequence_len()),
+            TcpFlags::RST | TcpFlags::ACK,
+        )
+    };
+    let reset = TcpSegment {
+        source_port: segment.destination_port,
+        destination_port: segment.source_port,
+        sequence,
+        acknowledgement,
+        flags,
+        window: 0,
+        mss: None,
+        payload: &[],
+    };
+    let data = reset.serialize(packet.destination, packet.source);
+    let _ = stack.send_ipv4(packet.source, Protocol::Tcp, &data);
+}
+
+pub(in crate::net) fn handle(stack: &mut Stack, packet: &Ipv4Packet) {
+    let segment = match TcpSegment::parse(packet.payload, packet.source, packet.destination) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+
+    let id = stack
+        .tcp
+        .connections
+        .iter()
+        .find(|(_, tcb)| tcb.matches(packet, &segment))
+        .map(|(&id, _)| id);
+    match id {
+        Some(id) => {
+            with_tcb(stack, id, |stack, tcb| process(stack, tcb, &segment));
+        }
+        None if segment.flags & (TcpFlags::SYN | TcpFlags::ACK | TcpFlags::RST)
+            == TcpFlags::SYN
+            && stack.tcp.listeners.contains(&segment.destination_port) =>
+        {
+            let mut tcb = Tcb::new(
+                (packet.destination, segment.destination_port),
+                (packet.source, segment.source_port),
+                State::SynReceived,
+                initial_sequence_number(),
+            );
+            tcb.rcv_nxt = segment.sequence.wrapping_add(1);
+            tcb.snd_wnd = segment.window;
+            tcb.mss = segment.mss.unwrap_or(DEFAULT_MSS);
+            tcb.listener = Some(segment.destination_port);
+            send_syn(stack, &mut tcb);
+            stack.tcp.insert(tcb);
+        }
+        None if !segment.flags.contains(TcpFlags::RST) => send_reset(stack, packet, &segment),
+        None => {}
+    }
+}
+
+fn process(stack: &mut Stack, tcb: &mut Tcb, segment: &TcpSegment) {
+    let flags = segment.flags;
+
+    if tcb.state == State::SynSent {
+        let acceptable_ack =
+            flags.contains(TcpFlags::ACK) && segment.acknowledgement == tcb.snd_nxt;
+        if flags.contains(TcpFlags::RST) {
+            if acceptable_ack {
+                tcb.close_with(Some(Error::ConnectionRefused));
+            }
+        } else if flags.contains(TcpFlags::SYN) && acceptable_ack {
+            tcb.rcv_nxt = segment.sequence.wrapping_add(1);
+            tcb.snd_una = segment.acknowledgement;
+            tcb.snd_wnd = segment.window;
+            tcb.mss = segment.mss.unwrap_or(DEFAULT_MSS);
+            tcb.state = State::Established;
+            tcb.retries = 0;
+            tcb.retransmit_at = None;
+            send_ack(stack, tcb);
+        }
+        return;
+    }
+
+    if flags.contains(TcpFlags::RST) {
+        if segment.sequence == tcb.rcv_nxt {
+            tcb.close_with(Some(Error::ConnectionReset));
+        }
+        return;
+    }
+    if flags.contains(TcpFlags::SYN) {
+        // most likely a retransmission because our SYN-ACK or ACK was lost
+        if tcb.state == State::SynReceived {
+            send_syn(stack, tcb);
+        } else {
+            send_ack(stack, tcb);
+        }
+        return;
+    }
+    if !flags.contains(TcpFlags::ACK) {
+        return;
+    }
+
+    let ack = segment.acknowledgement;
+    if seq_lt(tcb.snd_una, ack) && !seq_lt(tcb.snd_nxt, ack) {
+        tcb.acknowledge(ack);
+    }
+    tcb.snd_wnd = segment.window;
+
+    match tcb.state {
+        State::SynReceived if tcb.snd_una == tcb.snd_nxt => tcb.state = State::Established,
+        State::FinWait1 if tcb.everything_acked() => tcb.state = State::FinWait2,
+        State::Closing | State::LastAck if tcb.everything_acked() => tcb.close_with(None),
+        _ => {}
+    }
+
+    let mut needs_ack = false;
+    let receiving = matches!(
+        tcb.state,
+        State::Established | State::FinWait1 | State::FinWait2
+    );
+    if segment.sequence == tcb.rcv_nxt && receiving {
+        let free = RECEIVE_BUFFER_SIZE - tcb.receive_buffer.len();
+        let accepted = segment.payload.len().min(free);
+        tcb.receive_buffer
+            .extend(segment.payload[..accepted].iter().copied());
+        tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(accepted as u32);
+        needs_ack = !segment.payload.is_empty();
+
+        if flags.contains(TcpFlags::FIN) && accepted == segment.payload.len() {
+            tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(1);
+            tcb.fin_received = true;
+            needs_ack = true;
+            match tcb.state {
+                State::Established => tcb.state = State::CloseWait,
+                State::FinWait1 => tcb.state = State::Closing,
+                State::FinWait2 => tcb.close_with(None), // skip TIME-WAIT
+                _ => {}
+            }
+        }
+    } else if segment.sequence_len() > 0 {
+        // out of order or duplicate, tell the peer what we expect
+        needs_ack = true;
+    }
+
+    if needs_ack {
+        send_ack(stack, tcb);
+    }
+    transmit(stack, tcb);
+}
+
+/// Retransmits timed out segments and removes closed connections that are no
+/// longer referenced by a stream or were never accepted.
+pub(in crate::net) fn on_tick(stack: &mut Stack) {
+    let now = time::microseconds_monotonic();
+    let expired = stack
+        .tcp
+        .connections
+        .iter()
+        .filter(|(_, tcb)| tcb.retransmit_at.map_or(false, |t| t <= now))
+        .map(|(&id, _)| id)
+        .collect::<Vec<u32>>();
+
+    for id in expired {
+        with_tcb(stack, id, |stack, tcb| {
+            tcb.retransmit_at = None;
+            tcb.retries += 1;
+            if tcb.retries > MAX_RETRIES {
+                tcb.close_with(Some(Error::Timeout));
+                return;
+            }
+            match tcb.state {
+                State::SynSent | State::SynReceived => send_syn(stack, tcb),
+                _ => {
+                    // go back n
+                    tcb.snd_nxt = tcb.snd_una;
+                    tcb.sent = 0;
+                    tcb.fin_sent = false;
+                    transmit(stack, tcb);
+                }
+            }
+        });
+    }
+
+    stack
+        .tcp
+        .connections
+        .retain(|_, tcb| tcb.state != State::Closed || !(tcb.detached || tcb.listener.is_some()));
+}
+
+/// A TCP connection. The connection is closed when the stream is dropped.
+pub struct TcpStream {
+    id: u32,
+    read_timeout: Option<u64>,
+}
+
+impl TcpStream {
+    /// Opens a connection to the given address and port, and waits until it is established.
+    pub fn connect(address: Ipv4Address, port: u16) -> Result<Self> {
+        let stream = Self::open(address, port)?;
+        wait_for(Some(CONNECT_TIMEOUT_MS), |stack| stream.try_connect(stack))?;
+        Ok(stream)
+    }
+
+    /// Like [`TcpStream::connect`], but without a timeout. The connection still fails
+    /// once the SYN was retransmitted too often.
+    pub async fn connect_async(address: Ipv4Address, port: u16) -> Result<Self> {
+        let stream = Self::open(address, port)?;
+        poll_for(|stack| stream.try_connect(stack)).await?;
+        Ok(stream)
+    }
+
+    /// Sends a SYN to the given address and port.
+    fn open(address: Ipv4Address, port: u16) -> Result<Self> {
+        let id = {
+            let mut stack = lock_stack();
+            let source = stack.route(address)?.source;
+            let used = stack
+                .tcp
+                .connections
+                .values()
+                .map(|c| c.local_port)
+                .chain(stack.tcp.listeners.iter().copied())
+                .collect::<Vec<u16>>();
+            let local_port = stack.allocate_port(|p| used.contains(&p))?;
+
+            let mut tcb = Tcb::new(
+                (source, local_port),
+                (address, port),
+                State::SynSent,
+                initial_sequence_number(),
+            );
+            send_syn(&mut stack, &mut tcb);
+            stack.tcp.insert(tcb)
+        };
+        Ok(Self {
+            id,
+            read_timeout: None,
+        })
+    }
+
+    fn try_connect(&self, stack: &mut Stack) -> Option<Result<()>> {
+        let tcb = stack.tcp.connections.get(&self.id)?;
+        match tcb.state {
+            State::SynSent => None,
+            State::Closed => Some(Err(tcb.error.unwrap_or(Error::ConnectionRefused))),
+            _ => Some(Ok(())),
+        }
+    }
+
+    /// Sets the time in milliseconds that [`TcpStream::recv`] waits for data.
+    /// `None` waits forever.
+    pub fn set_read_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.read_timeout = timeout_ms;
+    }
+
+    pub fn state(&self) -> State {
+        lock_stack()
+            .tcp
+            .connections
+            .get(&self.id)
+            .map_or(State::Closed, |tcb| tcb.state)
+    }
+
+    pub fn local_port(&self) -> u16 {
+        lock_stack().tcp.connections[&self.id].local_port
+    }
+
+    pub fn peer(&self) -> (Ipv4Address, u16) {
+        let stack = lock_stack();
+        let tcb = &stack.tcp.connections[&self.id];
+        (tcb.remote_address, tcb.remote_port)
+    }
+
+    /// Queues the given data for sending, and waits until there is space in the send
+    /// buffer. Returns the number of bytes that were queued, which may be less than
+    /// the length of the data.
+    pub fn send(&self, data: &[u8]) -> Result<usize> {
+        wait_for(None, |stack| self.try_send(stack, data))
+    }
+
+    /// Like [`TcpStream::send`], but waits asynchronously for space in the send buffer.
+    pub async fn send_async(&self, data: &[u8]) -> Result<usize> {
+        poll_for(|stack| self.try_send(stack, data)).await
+    }
+
+    fn try_send(&self, stack: &mut Stack, data: &[u8]) -> Option<Result<usize>> {
+        with_tcb(stack, self.id, |stack, tcb| {
+            if let Some(e) = tcb.error {
+                return Some(Err(e));
+            }
+            if !matches!(tcb.state, State::Established | State::CloseWait) || tcb.fin_queued {
+                return Some(Err(Error::NotConnected));
+            }
+            let len = data.len().min(SEND_BUFFER_SIZE - tcb.send_buffer.len());
+            if len == 0 && !data.is_empty() {
+                return None;
+            }
+            tcb.send_buffer.extend(data[..len].iter().copied());
+            transmit(stack, tcb);
+            Some(Ok(len))
+        })
+        .unwrap_or(Some(Err(Error::NotConnected)))
+    }
+
+    /// Waits for data and reads it into the given buffer. Returns 0 if the peer
+    /// closed the connection.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        wait_for(self.read_timeout, |stack| self.try_recv(stack, buf))
+    }
+
+    /// Like [`TcpStream::recv`], but without a timeout.
+    pub async fn recv_async(&self, buf: &mut [u8]) -> Result<usize> {
+        poll_for(|stack| self.try_recv(stack, buf)).await
+    }
+
+    fn try_recv(&self, stack: &mut Stack, buf: &mut [u8]) -> Option<Result<usize>> {
+        with_tcb(stack, self.id, |stack, tcb| {
+            if !tcb.receive_buffer.is_empty() {
+                let was_full = tcb.window() < tcb.mss;
+                let len = buf.len().min(tcb.receive_buffer.len());
+                for (dst, src) in buf.iter_mut().zip(tcb.receive_buffer.drain(..len)) {
+                    *dst = src;
+                }
+                if was_full && tcb.state != State::Closed {
+                    send_ack(stack, tcb); // window update
+                }
+                return Some(Ok(len));
+            }
+            if let Some(e) = tcb.error {
+                return Some(Err(e));
+            }
+            if tcb.fin_received {
+                return Some(Ok(0));
+            }
+            None
+        })
+        .unwrap_or(Some(Err(Error::NotConnected)))
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let mut stack = lock_stack();
+        with_tcb(&mut stack, self.id, |stack, tcb| {
+            tcb.detached = true;
+            match tcb.state {
+                State::SynSent => tcb.close_with(None),
+                State::SynReceived => {
+                    send_segment(stack, tcb, TcpFlags::RST, tcb.snd_nxt, &[]);
+                    tcb.close_with(None);
+                }
+                State::Established => tcb.state = State::FinWait1,
+                State::CloseWait => tcb.state = State::LastAck,
+                _ => return,
+            }
+            tcb.fin_queued = true;
+            transmit(stack, tcb);
+        });
+    }
+}
+
+/// Accepts incoming connections on a local port.
+pub struct TcpListener {
+    port: u16,
+    accept_timeout: Option<u64>,
+}
+
+impl TcpListener {
+    pub fn bind(port: u16) -> Result<Self> {
+        let mut stack = lock_stack();
+        if stack.tcp.is_port_used(port) {
+            return Err(Error::PortInUse(port));
+        }
+        stack.tcp.listeners.insert(port);
+        Ok(Self {
+            port,
+            accept_timeout: None,
+        })
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Sets the time in milliseconds that [`TcpListener::accept`] waits for a
+    /// connection. `None` waits forever.
+    pub fn set_accept_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.accept_timeout = timeout_ms;
+    }
+
+    /// Waits for an established connection. Connections are accepted in the order
+    /// in which they were initiated.
+    pub fn accept(&self) -> Result<TcpStream> {
+        wait_for(self.accept_timeout, |stack| self.try_accept(stack))
+    }
+
+    /// Like [`TcpListener::accept`], but without a timeout.
+    pub async fn accept_async(&self) -> Result<TcpStream> {
+        poll_for(|stack| self.try_accept(stack)).await
+    }
+
+    fn try_accept(&self, stack: &mut Stack) -> Option<Result<TcpStream>> {
+        let id = stack
+            .tcp
+            .connections
+            .iter_mut()
+            .find(|(_, tcb)| tcb.listener == Some(self.port) && tcb.state != State::SynReceived)
+            .map(|(&id, tcb)| {
+                tcb.listener = None;
+                id
+            })?;
+        Some(Ok(TcpStream {
+            id,
+            read_timeout: None,
+        }))
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let ids = {
+            let mut stack = lock_stack();
+            stack.tcp.listeners.remove(&self.port);
+            stack
+                .tcp
+                .connections
+                .iter()
+                .filter(|(_, tcb)| tcb.listener == Some(self.port))
+                .map(|(&id, _)| id)
+                .collect::<Vec<u32>>()
+        };
+        // connections that were not accepted yet are closed like dropped streams
+        for id in ids {
+            drop(TcpStream {
+                id,
+                read_timeout: None,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_parse_serialize() {
+        let source = Ipv4Address::new(10, 0, 2, 15);
+        let destination = Ipv4Address::new(10, 0, 2, 2);
+        let segment = TcpSegment {
+            source_port: 49152,
+            destination_port: 80,
+            sequence: 0xFFFF_FFF0,
+            acknowledgement: 0,
+            flags: TcpFlags::SYN,
+            window: 1024,
+            mss: Some(OUR_MSS),
+            payload: &[],
+        };
+        let data = segment.serialize(source, destination);
+        assert_eq!(HEADER_LEN + 4, data.len());
+
+        let parsed = TcpSegment::parse(&data, source, destination).unwrap();
+        assert_eq!(segment.sequence, parsed.sequence);
+        assert_eq!(TcpFlags::SYN, parsed.flags);
+        assert_eq!(Some(OUR_MSS), parsed.mss);
+        assert_eq!(1, parsed.sequence_len());
+
+        // the checksum covers the addresses
+        assert!(TcpSegment::parse(&data, destination, source).is_err());
+    }
+
+    #[test_case]
+    fn test_sequence_comparison() {
+        assert!(seq_lt(1, 2));
+        assert!(!seq_lt(2, 1));
+        assert!(seq_lt(0xFFFF_FFFF, 0));
+        assert!(!seq_lt(5, 5));
+    }
+}
@@ -0,0 +1,169 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+use crate::net::ipv4::{pseudo_header_checksum, Ipv4Packet, Protocol};
+use crate::net::stack::Stack;
+use crate::net::{lock_stack, poll_for, wait_for, Error, Ipv4Address, Result};
+
+const HEADER_LEN: usize = 8;
+
+/// The maximum number of datagrams that are queued per socket. Further datagrams
+/// are dropped until the socket is read from.
+const MAX_QUEUED_DATAGRAMS: usize = 64;
+
+#[derive(Debug)]
+pub struct UdpDatagram<'a> {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    /// Parses a datagram and verifies its checksum, if the sender computed one.
+    pub fn parse(data: &'a [u8], source: Ipv4Address, destination: Ipv4Address) -> Result<Self> {
+        if data.len() < HEADER_LEN {
+            return Err(Error::Malformed);
+        }
+        let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+        if len < HEADER_LEN || len > data.len() {
+            return Err(Error::Malformed);
+        }
+        let has_checksum = data[6] != 0 || data[7] != 0;
+        if has_checksum
+            && pseudo_header_checksum(source, destination, Protocol::Udp, &data[..len]) != 0
+        {
+            return Err(Error::Malformed);
+        }
+        Ok(Self {
+            source_port: u16::from_be_bytes([data[0], data[1]]),
+            destination_port: u16::from_be_bytes([data[2], data[3]]),
+            payload: &data[HEADER_LEN..len],
+        })
+    }
+
+    pub fn serialize(&self, source: Ipv4Address, destination: Ipv4Address) -> Result<Vec<u8>> {
+        let len = HEADER_LEN + self.payload.len();
+        if len > u16::MAX as usize {
+            return Err(Error::PacketTooLarge);
+        }
+        let mut data = Vec::with_capacity(len);
+        data.extend_from_slice(&self.source_port.to_be_bytes());
+        data.extend_from_slice(&self.destination_port.to_be_bytes());
+        data.extend_from_slice(&(len as u16).to_be_bytes());
+        data.extend_from_slice(&[0, 0]); // checksum
+        data.extend_from_slice(self.payload);
+        let sum = match pseudo_header_checksum(source, destination, Protocol::Udp, &data) {
+            0 => 0xFFFF, // 0 means that no checksum was computed
+            sum => sum,
+        };
+        data[6..8].copy_from_slice(&sum.to_be_bytes());
+        Ok(data)
+    }
+}
+
+struct ReceivedDatagram {
+    source: Ipv4Address,
+    source_port: u16,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+pub(in crate::net) struct UdpState {
+    sockets: BTreeMap<u16, VecDeque<ReceivedDatagram>>,
+}
+
+pub(in crate::net) fn handle(stack: &mut Stack, packet: &Ipv4Packet) {
+    let datagram = match UdpDatagram::parse(packet.payload, packet.source, packet.destination) {
+        Ok(d) => d,
+        Err(_) => return,
+    };
+    if let Some(queue) = stack.udp.sockets.get_mut(&datagram.destination_port) {
+        if queue.len() < MAX_QUEUED_DATAGRAMS {
+            queue.push_back(ReceivedDatagram {
+                source: packet.source,
+                source_port: datagram.source_port,
+                data: datagram.payload.into(),
+            });
+        }
+    }
+}
+
+/// A UDP socket that is bound to a local port. The port is released when the
+/// socket is dropped.
+pub struct UdpSocket {
+    port: u16,
+    read_timeout: Option<u64>,
+}
+
+impl UdpSocket {
+    /// Binds a socket to the given port. If the port is 0, an unused ephemeral
+    /// port is chosen.
+    pub fn bind(port: u16) -> Result<Self> {
+        let mut stack = lock_stack();
+        let port = match port {
+            0 => {
+                let used = stack.udp.sockets.keys().copied().collect::<Vec<_>>();
+                stack.allocate_port(|p| used.contains(&p))?
+            }
+            port if stack.udp.sockets.contains_key(&port) => return Err(Error::PortInUse(port)),
+            port => port,
+        };
+        stack.udp.sockets.insert(port, VecDeque::new());
+        Ok(Self {
+            port,
+            read_timeout: None,
+        })
+    }
+
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Sets the time in milliseconds that [`UdpSocket::recv_from`] waits for a
+    /// datagram. `None` waits forever.
+    pub fn set_read_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.read_timeout = timeout_ms;
+    }
+
+    pub fn send_to(&self, data: &[u8], destination: Ipv4Address, port: u16) -> Result<usize> {
+        let mut stack = lock_stack();
+        let source = stack.route(destination)?.source;
+        let datagram = UdpDatagram {
+            source_port: self.port,
+            destination_port: port,
+            payload: data,
+        }
+        .serialize(source, destination)?;
+        stack.send_ipv4(destination, Protocol::Udp, &datagram)?;
+        Ok(data.len())
+    }
+
+    /// Receives the next datagram into the given buffer, and returns the number of
+    /// bytes read together with the sender. Bytes that don't fit into the buffer
+    /// are discarded.
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Ipv4Address, u16)> {
+        wait_for(self.read_timeout, |stack| self.try_recv_from(stack, buf))
+    }
+
+    /// Like [`UdpSocket::recv_from`], but without a timeout.
+    pub async fn recv_from_async(&self, buf: &mut [u8]) -> Result<(usize, Ipv4Address, u16)> {
+        poll_for(|stack| self.try_recv_from(stack, buf)).await
+    }
+
+    fn try_recv_from(
+        &self,
+        stack: &mut Stack,
+        buf: &mut [u8],
+    ) -> Option<Result<(usize, Ipv4Address, u16)>> {
+        let datagram = stack.udp.sockets.get_mut(&self.port)?.pop_front()?;
+        let len = buf.len().min(datagram.data.len());
+        buf[..len].copy_from_slice(&datagram.data[..len]);
+        Some(Ok((len, datagram.source, datagram.source_port)))
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        lock_stack().udp.sockets.remove(&self.port);
+    }
+}
@@ -1,9 +1,11 @@
+use alloc::vec::Vec;
 use core::ptr::NonNull;
 use core::time::Duration;
 
 use kstd::sync::Once;
 
 use crate::memory::size::Size;
+use crate::scheduler::task::TaskInfo;
 use crate::{scheduler::tid::Tid, Result};
 
 pub mod round_robin;
@@ -89,4 +91,14 @@ impl Scheduler {
     pub fn total_ticks() -> u64 {
         unsafe { SCHEDULER.as_ref().unwrap().total_ticks() }
     }
+
+    /// Returns the running and the ready tasks.
+    pub fn tasks() -> Vec<TaskInfo> {
+        unsafe { SCHEDULER.as_ref().unwrap().tasks() }
+    }
+
+    /// The amount of tasks that are running, ready or sleeping.
+    pub fn task_count() -> u32 {
+        unsafe { SCHEDULER.as_ref().unwrap().task_count() }
+    }
 }
@@ -1,4 +1,6 @@
+use alloc::boxed::Box;
 use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 use core::mem::swap;
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicU32, Ordering};
@@ -6,20 +8,26 @@ use core::time::Duration;
 
 use x86_64::instructions::interrupts::without_interrupts;
 
+use crate::memory::allocator::slab::{self, SlabCache};
 use crate::scheduler::switch::switch;
-use crate::scheduler::task::{ProcessStatus, Task};
+use crate::scheduler::task::{ProcessStatus, Task, TaskInfo};
 use crate::scheduler::tid::Tid;
 use crate::syscall::Result;
 use crate::{debug, hlt_loop};
 use kstd::collections::deltaq::DeltaQueue;
 
+/// The control blocks of all tasks.
+static TASK_CACHE: SlabCache = SlabCache::for_type::<Task>("task");
+
+type TaskBox = Box<Task, &'static SlabCache>;
+
 pub struct RoundRobin {
-    current_task: Task,
+    current_task: TaskBox,
     /// Tasks that are ready to be scheduled.
-    ready_queue: VecDeque<Task>,
+    ready_queue: VecDeque<TaskBox>,
     /// Finished tasks waiting for cleanup.
-    finished_tasks: VecDeque<Task>,
-    sleeping_tasks: DeltaQueue<Task>,
+    finished_tasks: VecDeque<TaskBox>,
+    sleeping_tasks: DeltaQueue<TaskBox>,
     /// The amount of running or ready tasks.
     /// Finished tasks are not included.
     task_count: AtomicU32,
@@ -30,7 +38,8 @@ impl !Default for RoundRobin {}
 
 impl RoundRobin {
     pub fn new() -> Self {
-        let current_task = Task::new_for_current(Tid::new());
+        slab::register(&TASK_CACHE);
+        let current_task = Box::new_in(Task::new_for_current(Tid::new()), &TASK_CACHE);
 
         Self {
             current_task,
@@ -46,7 +55,7 @@ impl RoundRobin {
         without_interrupts(|| {
             // Create the new task.
             let tid = Tid::new();
-            let mut task = Task::new(tid, ProcessStatus::Ready);
+            let mut task = Box::new_in(Task::new(tid, ProcessStatus::Ready), &TASK_CACHE);
 
             task.allocate_stack(func);
 
@@ -96,6 +105,25 @@ impl RoundRobin {
         self.ticks
     }
 
+    /// Returns the running and the ready tasks. Sleeping tasks are not included.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        without_interrupts(|| {
+            core::iter::once(&*self.current_task)
+                .chain(self.ready_queue.iter().map(|task| &**task))
+                .map(|task| TaskInfo {
+                    tid: task.tid,
+                    status: task.status,
+                    ticks: task.ticks,
+                })
+                .collect()
+        })
+    }
+
+    /// The amount of tasks that are running, ready or sleeping.
+    pub fn task_count(&self) -> u32 {
+        self.task_count.load(Ordering::SeqCst)
+    }
+
     pub fn timer_tick(&mut self) {
         self.ticks += 1
     }
@@ -181,7 +209,7 @@ impl RoundRobin {
     }
 
     /// Replaces the current task with the given new task and returns the old one.
-    fn exchange_current_task(&mut self, new_task: Task) -> Task {
+    fn exchange_current_task(&mut self, new_task: TaskBox) -> TaskBox {
         let mut tmp = new_task;
         swap(&mut tmp, &mut self.current_task);
         tmp
@@ -14,6 +14,14 @@ pub enum ProcessStatus {
     Finished,
 }
 
+/// A snapshot of the state of a task, as returned by [`Scheduler::tasks`].
+#[derive(Copy, Clone, Debug)]
+pub struct TaskInfo {
+    pub tid: Tid,
+    pub status: ProcessStatus,
+    pub ticks: u64,
+}
+
 #[repr(align(64))]
 #[repr(C)]
 pub struct Stack {
@@ -1,27 +1,338 @@
+//! The first serial port (COM1).
+//!
+//! Once [`init_interrupts`] was called, the port is interrupt driven: received bytes
+//! are queued by the interrupt handler and passed to [`tty::serial`] by
+//! [`process_serial_input`], which reads them from a [`SerialStream`], and output is
+//! queued and sent by the interrupt handler whenever the port can take more data.
+//! Before that, and while interrupts are disabled, all output is sent synchronously.
+
+use core::fmt;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
 use kstd::sync::Mutex;
 use lazy_static::lazy_static;
 use uart_16550::SerialPort;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::tty;
+
+/// The I/O port base of the first serial port.
+const COM1: u16 = 0x3F8;
+
+/// The legacy IRQ of the first serial port.
+pub const COM1_IRQ: u8 = 4;
+
+/// Offset of the interrupt enable register.
+const INTERRUPT_ENABLE: u16 = 1;
+/// Offset of the interrupt identification register, whose lowest bit is set if no
+/// interrupt is pending.
+const INTERRUPT_IDENTIFICATION: u16 = 2;
+/// Offset of the line status register.
+const LINE_STATUS: u16 = 5;
+
+const INTERRUPT_RECEIVED_DATA: u8 = 1 << 0;
+const INTERRUPT_TRANSMITTER_EMPTY: u8 = 1 << 1;
+
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_TRANSMITTER_EMPTY: u8 = 1 << 5;
+
+/// The number of bytes that fit into the transmit FIFO of a 16550.
+const FIFO_SIZE: usize = 16;
+
+const RX_QUEUE_SIZE: usize = 1024;
+const TX_QUEUE_SIZE: usize = 4096;
+
+static RX_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static TX_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static RX_WAKER: AtomicWaker = AtomicWaker::new();
+static INTERRUPTS_ENABLED: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        let mut serial_port = unsafe { SerialPort::new(COM1) };
         serial_port.init();
         Mutex::new(serial_port)
     };
 }
 
+/// Allocates the receive and transmit queues and enables the interrupt of the serial
+/// port. Requires the heap and the interrupt handlers to be initialized.
+pub fn init_interrupts() {
+    RX_QUEUE.init_once(|| ArrayQueue::new(RX_QUEUE_SIZE));
+    TX_QUEUE.init_once(|| ArrayQueue::new(TX_QUEUE_SIZE));
+
+    interrupts::without_interrupts(|| {
+        let _guard = SERIAL1.lock();
+        unsafe { Port::<u8>::new(COM1 + INTERRUPT_ENABLE).write(INTERRUPT_RECEIVED_DATA) };
+        INTERRUPTS_ENABLED.store(true, Ordering::SeqCst);
+    });
+    crate::interrupts::unmask_legacy_irq(COM1_IRQ);
+}
+
+/// Called by the serial interrupt handler. Moves received bytes into the receive
+/// queue and refills the transmit FIFO.
+///
+/// Must not block or allocate.
+pub(crate) fn handle_interrupt() {
+    // every other lock holder disables interrupts, so this can't deadlock
+    let _guard = SERIAL1.lock();
+
+    let mut identification = Port::<u8>::new(COM1 + INTERRUPT_IDENTIFICATION);
+    let mut received = false;
+    // the irq is edge triggered, so handle everything that is pending before returning
+    while unsafe { identification.read() } & 1 == 0 {
+        while line_status() & LINE_STATUS_DATA_READY != 0 {
+            let byte = unsafe { Port::<u8>::new(COM1).read() };
+            // bytes are dropped if nobody reads them
+            if let Some(queue) = RX_QUEUE.get() {
+                let _ = queue.push(byte);
+                received = true;
+            }
+        }
+        if line_status() & LINE_STATUS_TRANSMITTER_EMPTY != 0 {
+            send_queued(FIFO_SIZE);
+        }
+    }
+    if received {
+        RX_WAKER.wake();
+    }
+}
+
+fn line_status() -> u8 {
+    unsafe { Port::<u8>::new(COM1 + LINE_STATUS).read() }
+}
+
+/// Sends up to `max` bytes from the transmit queue, and disables the transmitter
+/// interrupt once the queue is empty. The caller must hold the lock on [`SERIAL1`].
+fn send_queued(max: usize) {
+    let queue = match TX_QUEUE.get() {
+        Some(q) => q,
+        None => return,
+    };
+    let mut data = Port::<u8>::new(COM1);
+    for _ in 0..max {
+        match queue.pop() {
+            Some(byte) => unsafe { data.write(byte) },
+            None => break,
+        }
+    }
+    if queue.is_empty() {
+        set_transmitter_interrupt(false);
+    }
+}
+
+fn set_transmitter_interrupt(enabled: bool) {
+    let mut register = Port::<u8>::new(COM1 + INTERRUPT_ENABLE);
+    unsafe {
+        let value = register.read();
+        register.write(if enabled {
+            value | INTERRUPT_TRANSMITTER_EMPTY
+        } else {
+            value & !INTERRUPT_TRANSMITTER_EMPTY
+        });
+    }
+}
+
+/// Sends everything in the transmit queue synchronously. The caller must hold the
+/// lock on [`SERIAL1`].
+fn drain_queued(serial: &mut SerialPort) {
+    if let Some(queue) = TX_QUEUE.get() {
+        while let Some(byte) = queue.pop() {
+            serial.send_raw(byte);
+        }
+    }
+}
+
+/// Writes bytes to the serial port. They are queued if the port is interrupt driven
+/// and interrupts were enabled by the caller, and sent synchronously otherwise.
+struct Writer<'a> {
+    serial: &'a mut SerialPort,
+    buffered: bool,
+}
+
+impl<'a> Writer<'a> {
+    fn write_byte(&mut self, byte: u8) {
+        match TX_QUEUE.get() {
+            Some(queue) if self.buffered => {
+                while queue.push(byte).is_err() {
+                    // the queue is full, so make space without waiting for interrupts
+                    drain_queued(self.serial);
+                }
+            }
+            _ => {
+                // keep the order of the output
+                drain_queued(self.serial);
+                self.serial.send_raw(byte);
+            }
+        }
+    }
+
+    fn finish(self) {
+        if self.buffered {
+            set_transmitter_interrupt(true);
+        }
+    }
+}
+
+impl<'a> fmt::Write for Writer<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|b| self.write_byte(b));
+        Ok(())
+    }
+}
+
+/// Disables interrupts and calls `f` with a writer to the serial port.
+fn with_writer<F>(f: F)
+where
+    F: FnOnce(&mut Writer),
+{
+    let buffered = interrupts::are_enabled() && INTERRUPTS_ENABLED.load(Ordering::SeqCst);
+    interrupts::without_interrupts(|| {
+        let mut serial = SERIAL1.lock();
+        let mut writer = Writer {
+            serial: &mut *serial,
+            buffered,
+        };
+        f(&mut writer);
+        writer.finish();
+    });
+}
+
+/// Sends all queued output synchronously, for example before shutting down.
+pub fn flush() {
+    interrupts::without_interrupts(|| drain_queued(&mut *SERIAL1.lock()));
+}
+
+/// Returns the next byte that the first serial port received, or `None` if there is
+/// none. In contrast to [`SerialPort::receive`], this doesn't wait for data, so that
+/// the lock on [`SERIAL1`] is only held briefly.
+pub fn try_receive() -> Option<u8> {
+    if let Some(queue) = RX_QUEUE.get() {
+        return queue.pop();
+    }
+    interrupts::without_interrupts(|| {
+        let _guard = SERIAL1.lock();
+        if line_status() & LINE_STATUS_DATA_READY == 0 {
+            return None;
+        }
+        Some(unsafe { Port::<u8>::new(COM1).read() })
+    })
+}
+
+/// The bytes that the first serial port receives. All streams share one queue, so
+/// there should only be one reader.
+#[derive(Default)]
+pub struct SerialStream {
+    _private: (), // avoid initialization from outside of the module
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        assert!(
+            RX_QUEUE.is_initialized(),
+            "serial interrupts are not initialized"
+        );
+        SerialStream { _private: () }
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let queue = RX_QUEUE.try_get().expect("not initialized");
+
+        // fast path
+        if let Some(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        RX_WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(byte) => {
+                RX_WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Passes the bytes that the first serial port receives as input to [`tty::serial`].
+pub async fn process_serial_input() {
+    let mut bytes = SerialStream::new();
+    while let Some(byte) = bytes.next().await {
+        // terminals send a carriage return for the enter key
+        let byte = if byte == b'\r' { b'\n' } else { byte };
+        tty::serial().input(&[byte]);
+    }
+}
+
+/// Writes raw bytes to the first serial port. Newlines are sent as `\r\n`, so that
+/// terminals return to the start of the line.
+pub fn _write_bytes(data: &[u8]) {
+    with_writer(|writer| {
+        for &b in data {
+            if b == b'\n' {
+                writer.write_byte(b'\r');
+            }
+            writer.write_byte(b);
+        }
+    });
+}
+
 #[doc(hidden)]
-pub fn _print(args: core::fmt::Arguments) {
+pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
-    // disable interrupts while holding a lock on the WRITER
-    // so that no deadlock can occur when we want to print
-    // something in an interrupt handler
-    interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
+    // interrupts are disabled while holding the lock on SERIAL1, so that
+    // no deadlock can occur when we want to print something in an interrupt handler
+    with_writer(|writer| {
+        writer.write_fmt(args).expect("Printing to serial failed");
     });
 }
+
+/// Prints to the serial port without taking the lock on [`SERIAL1`], for reports after
+/// a panic, when the lock may be held forever. Queued output is sent first.
+///
+/// # Safety
+///
+/// Nothing else may use the serial port at the same time, so the caller must have
+/// disabled interrupts and must be the only processor that runs.
+pub unsafe fn _print_unlocked(args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let mut serial = SerialPort::new(COM1);
+    let mut writer = Writer {
+        serial: &mut serial,
+        buffered: false,
+    };
+    let _ = writer.write_fmt(args);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_flush_sends_queued_output() {
+        crate::serial_print!("queued output ");
+        flush();
+        assert!(TX_QUEUE.get().unwrap().is_empty());
+    }
+
+    #[test_case]
+    fn test_output_without_interrupts_is_sent_immediately() {
+        interrupts::without_interrupts(|| {
+            crate::serial_print!("synchronous output ");
+            assert!(TX_QUEUE.get().unwrap().is_empty());
+        });
+    }
+}
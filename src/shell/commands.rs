@@ -0,0 +1,368 @@
+//! The built-in commands of the shell.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt::Write;
+
+use x86_64::VirtAddr;
+
+use crate::driver::{pci, registry, Peripherals};
+use crate::io::fs::memfs::MemFs;
+use crate::io::fs::tmpfs::TmpFs;
+use crate::io::fs::{vfs, Fs, INode, INodeBase};
+use crate::memory::allocator::slab;
+use crate::memory::{heap, info};
+use crate::scheduler::Scheduler;
+use crate::shell::{Error, Result};
+use crate::vfs_setup::{self, TMPFS_SIZE_LIMIT};
+use crate::{elf, time};
+
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: fn(&mut dyn Write, &[&str]) -> Result<()>,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        usage: "help",
+        help: "list all commands",
+        run: help,
+    },
+    Command {
+        name: "echo",
+        usage: "echo [words...]",
+        help: "print the arguments",
+        run: echo,
+    },
+    Command {
+        name: "ls",
+        usage: "ls [path]",
+        help: "list the children of a directory",
+        run: ls,
+    },
+    Command {
+        name: "cat",
+        usage: "cat <path>",
+        help: "print the content of a file",
+        run: cat,
+    },
+    Command {
+        name: "stat",
+        usage: "stat <path>",
+        help: "print the metadata of a node",
+        run: stat,
+    },
+    Command {
+        name: "mount",
+        usage: MOUNT_USAGE,
+        help: "mount a new file system, or the ext2 file system in an image file at /mnt",
+        run: mount,
+    },
+    Command {
+        name: "umount",
+        usage: "umount <dir> <name>",
+        help: "unmount a node",
+        run: umount,
+    },
+    Command {
+        name: "lspci",
+        usage: "lspci",
+        help: "list all pci devices and their drivers",
+        run: lspci,
+    },
+    Command {
+        name: "ps",
+        usage: "ps",
+        help: "list the running and ready tasks",
+        run: ps,
+    },
+    Command {
+        name: "date",
+        usage: "date",
+        help: "print the boot time and the current unix time",
+        run: date,
+    },
+    Command {
+        name: "mem",
+        usage: "mem",
+        help: "print memory usage",
+        run: mem,
+    },
+    Command {
+        name: "pagemap",
+        usage: "pagemap <address> <length>",
+        help: "print the page mappings of a virtual memory range",
+        run: pagemap,
+    },
+    Command {
+        name: "exec",
+        usage: "exec <path>",
+        help: "load an elf executable",
+        run: exec,
+    },
+];
+
+/// Returns the command with the given name.
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Returns the only argument, or a usage error if there is not exactly one.
+fn single_arg<'a>(args: &[&'a str], usage: &'static str) -> Result<&'a str> {
+    match args {
+        [arg] => Ok(*arg),
+        _ => Err(Error::Usage(usage)),
+    }
+}
+
+fn help(out: &mut dyn Write, _: &[&str]) -> Result<()> {
+    for command in COMMANDS {
+        writeln!(out, "{:<36} {}", command.usage, command.help)?;
+    }
+    Ok(())
+}
+
+fn echo(out: &mut dyn Write, args: &[&str]) -> Result<()> {
+    writeln!(out, "{}", args.join(" "))?;
+    Ok(())
+}
+
+fn ls(out: &mut dyn Write, args: &[&str]) -> Result<()> {
+    let path = match args {
+        [] => "/",
+        [path] => *path,
+        _ => return Err(Error::Usage("ls [path]")),
+    };
+    let dir = vfs::find_inode(&path)?
+        .as_dir()
+        .ok_or(kstd::io::Error::IsFile)?;
+    let mut children = dir.read().children()?;
+    children.sort_by_key(|node| node.name());
+    for child in children {
+        let suffix = match child {
+            INode::Dir(_) => "/",
+            INode::Symlink(_) => "@",
+            INode::Pipe(_) => "|",
+            _ => "",
+        };
+        writeln!(out, "{}{}", child.name(), suffix)?;
+    }
+    Ok(())
+}
+
+fn cat(out: &mut dyn Write, args: &[&str]) -> Result<()> {
+    let path = single_arg(args, "cat <path>")?;
+    let content = vfs::read_file_node(&path)?;
+    out.write_str(&String::from_utf8_lossy(&content))?;
+    Ok(())
+}
+
+fn stat(out: &mut dyn Write, args: &[&str]) -> Result<()> {
+    let path = single_arg(args, "stat <path>")?;
+    let node = vfs::find_inode(&path)?;
+    let stat = node.stat();
+    let kind = match node {
+        INode::File(_) => "file",
+        INode::Dir(_) => "dir",
+        INode::BlockDevice(_) => "block device",
+        INode::CharacterDevice(_) => "character device",
+        INode::Pipe(_) => "fifo",
+        INode::Symlink(_) => "symlink",
+    };
+    writeln!(out, "  name: {}", node.name())?;
+    writeln!(out, "  type: {}", kind)?;
+    writeln!(out, " inode: {}", stat.inode)?;
+    writeln!(out, "  size: {}", stat.size)?;
+    writeln!(out, " links: {}", stat.nlink)?;
+    writeln!(out, "   uid: {}, gid: {}", stat.uid, stat.gid)?;
+    Ok(())
+}
+
+const MOUNT_USAGE: &str = "mount <tmpfs|memfs> <dir> <name> | mount ext2 <image>";
+
+fn mount(out: &mut dyn Write, args: &[&str]) -> Result<()> {
+    let (fs, dir, name) = match args {
+        ["ext2", image] => {
+            let path = vfs_setup::mount_ext2_image(image)?;
+            writeln!(out, "mounted {} at {}", image, path)?;
+            return Ok(());
+        }
+        [fs, dir, name] => (*fs, *dir, name.to_string()),
+        _ => return Err(Error::Usage(MOUNT_USAGE)),
+    };
+    let root = match fs {
+        "tmpfs" => TmpFs::new(name, TMPFS_SIZE_LIMIT.bytes() as u64).root_inode(),
+        "memfs" => MemFs::new(name).root_inode(),
+        _ => return Err(Error::Usage(MOUNT_USAGE)),
+    };
+    vfs::mount(&dir, root)?;
+    Ok(())
+}
+
+fn umount(_: &mut dyn Write, args: &[&str]) -> Result<()> {
+    match args {
+        [dir, name] => {
+            vfs::unmount(dir, name)?;
+            Ok(())
+        }
+        _ => Err(Error::Usage("umount <dir> <name>")),
+    }
+}
+
+fn lspci(out: &mut dyn Write, _: &[&str]) -> Result<()> {
+    for device in pci::devices() {
+        writeln!(
+            out,
+            "{} {:?} ({})",
+            device,
+            device.class(),
+            registry::bound_driver(device).unwrap_or("no driver")
+        )?;
+    }
+    Ok(())
+}
+
+fn ps(out: &mut dyn Write, _: &[&str]) -> Result<()> {
+    writeln!(out, "{:>5} {:<10} {:>10}", "TID", "STATUS", "TICKS")?;
+    for task in Scheduler::tasks() {
+        writeln!(
+            out,
+            "{:>5} {:<10} {:>10}",
+            task.tid.as_usize(),
+            format!("{:?}", task.status),
+            task.ticks
+        )?;
+    }
+    writeln!(out, "{} tasks in total", Scheduler::task_count())?;
+    Ok(())
+}
+
+fn date(out: &mut dyn Write, _: &[&str]) -> Result<()> {
+    let boot_time = Peripherals::boot_time();
+    writeln!(
+        out,
+        "booted at {:04}-{:02}-{:02}, {:02}:{:02}:{:02}",
+        boot_time.full_year(),
+        boot_time.month,
+        boot_time.day_of_month,
+        boot_time.hours,
+        boot_time.minutes,
+        boot_time.seconds,
+    )?;
+    writeln!(out, "unix time: {}", time::unix_timestamp())?;
+    Ok(())
+}
+
+fn mem(out: &mut dyn Write, _: &[&str]) -> Result<()> {
+    let frames = info::frame_stats();
+    writeln!(
+        out,
+        "physical frames: {} of {} allocated ({} KiB of {} KiB)",
+        frames.allocated,
+        frames.usable,
+        frames.allocated as u64 * frames.frame_size / 1024,
+        frames.usable as u64 * frames.frame_size / 1024
+    )?;
+    let heap = heap::stats();
+    writeln!(
+        out,
+        "heap: {} KiB used, {} KiB free, {} KiB limit",
+        heap.used / 1024,
+        heap.free / 1024,
+        heap.size_limit / 1024
+    )?;
+    for class in heap.size_classes {
+        writeln!(
+            out,
+            "  {:>5} byte blocks: {} in use, {} free",
+            class.block_size, class.in_use, class.free
+        )?;
+    }
+    for cache in slab::stats() {
+        writeln!(
+            out,
+            "slab cache {}: {} of {} objects of {} bytes in use, {} slabs",
+            cache.name, cache.objects_in_use, cache.objects_total, cache.object_size, cache.slabs
+        )?;
+    }
+    Ok(())
+}
+
+/// The maximum number of mappings that `pagemap` prints.
+const PAGEMAP_LIMIT: usize = 64;
+
+fn pagemap(out: &mut dyn Write, args: &[&str]) -> Result<()> {
+    const USAGE: &str = "pagemap <address> <length>";
+    let parse = |arg: &str| {
+        let digits = arg.strip_prefix("0x").unwrap_or(arg);
+        u64::from_str_radix(digits, 16).map_err(|_| Error::Usage(USAGE))
+    };
+    let (start, len) = match args {
+        [start, len] => (parse(start)?, parse(len)?),
+        _ => return Err(Error::Usage(USAGE)),
+    };
+    let start = VirtAddr::try_new(start).map_err(|_| Error::Usage(USAGE))?;
+    let mappings = info::mappings(start, len, PAGEMAP_LIMIT);
+    for mapping in &mappings {
+        writeln!(
+            out,
+            "{:#014x}-{:#014x} -> {:#x} ({} KiB pages) {:?}",
+            mapping.start,
+            mapping.start + mapping.len,
+            mapping.frame,
+            mapping.page_size / 1024,
+            mapping.flags
+        )?;
+    }
+    if mappings.len() == PAGEMAP_LIMIT {
+        writeln!(out, "(only the first {} mappings are shown)", PAGEMAP_LIMIT)?;
+    }
+    Ok(())
+}
+
+fn exec(out: &mut dyn Write, args: &[&str]) -> Result<()> {
+    let path = single_arg(args, "exec <path>")?;
+    let content = vfs::read_file_node(&path)?;
+    let loaded = elf::load(&content)?;
+    writeln!(
+        out,
+        "loaded {} at base address {:#x}",
+        path, loaded.base_address
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::execute;
+
+    use super::*;
+
+    #[test_case]
+    fn test_echo() {
+        let mut out = String::new();
+        execute(&mut out, "echo hello   \"big world\"").unwrap();
+        assert_eq!("hello big world\n", out);
+    }
+
+    #[test_case]
+    fn test_help_lists_all_commands() {
+        let mut out = String::new();
+        execute(&mut out, "help").unwrap();
+        for command in COMMANDS {
+            assert!(out.contains(command.usage));
+        }
+    }
+
+    #[test_case]
+    fn test_usage_error() {
+        let mut out = String::new();
+        assert_eq!(
+            Err(Error::Usage("cat <path>")),
+            execute(&mut out, "cat a b")
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! A small interactive shell for inspecting the kernel. It runs as a scheduler task
+//! on a [`Tty`], reads one command per line and writes the output back to the
+//! terminal. The available commands are listed in [`commands::COMMANDS`].
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use derive_more::Display;
+
+use crate::tty::{self, Tty};
+
+pub mod commands;
+
+pub const PROMPT: &str = "martim> ";
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Eq, PartialEq, Clone, Display)]
+pub enum Error {
+    #[display(fmt = "{_0}: command not found")]
+    UnknownCommand(String),
+    #[display(fmt = "usage: {_0}")]
+    Usage(&'static str),
+    #[display(fmt = "{_0}")]
+    Io(kstd::io::Error),
+    #[display(fmt = "{_0}")]
+    Elf(crate::elf::Error),
+    #[display(fmt = "output failed")]
+    Output,
+}
+
+impl core::error::Error for Error {}
+
+impl From<kstd::io::Error> for Error {
+    fn from(e: kstd::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<crate::elf::Error> for Error {
+    fn from(e: crate::elf::Error) -> Self {
+        Self::Elf(e)
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Self {
+        Self::Output
+    }
+}
+
+/// Splits a command line into words. Words are separated by whitespace, and can be
+/// quoted with `"` to contain whitespace.
+pub fn tokenize(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(core::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// Executes a single command line and writes its output to `out`. Empty lines are
+/// ignored.
+pub fn execute(out: &mut dyn fmt::Write, line: &str) -> Result<()> {
+    let words = tokenize(line);
+    let args: Vec<&str> = words.iter().map(String::as_str).collect();
+    let (name, args) = match args.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+    let command = commands::find(name).ok_or_else(|| Error::UnknownCommand((*name).into()))?;
+    (command.run)(out, args)
+}
+
+/// Writes formatted output to a terminal.
+struct TtyWriter(&'static Tty);
+
+impl fmt::Write for TtyWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Runs the shell on the given terminal until the input ends.
+pub fn run(tty: &'static Tty) {
+    let mut out = TtyWriter(tty);
+    loop {
+        tty.write(PROMPT.as_bytes());
+        let line = match tty.read_line() {
+            Ok(line) if line.is_empty() => break,
+            Ok(line) => line,
+            Err(e) => {
+                crate::error!("shell on {} failed to read: {}", tty.name(), e);
+                break;
+            }
+        };
+        // a Ctrl-C only discards the current line
+        tty.take_interrupt();
+        if let Err(e) = execute(&mut out, &line) {
+            let _ = fmt::write(&mut out, format_args!("{e}\n"));
+        }
+    }
+}
+
+/// Runs a shell on the console, see [`tty::console`].
+pub extern "C" fn console_shell() {
+    run(tty::console());
+}
+
+/// Runs a shell on the first serial port, see [`tty::serial`].
+pub extern "C" fn serial_shell() {
+    run(tty::serial());
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test_case]
+    fn test_tokenize() {
+        assert_eq!(Vec::<String>::new(), tokenize("  \n"));
+        assert_eq!(vec!["ls", "/dev"], tokenize("  ls   /dev\n"));
+        assert_eq!(
+            vec!["echo", "hello world", ""],
+            tokenize("echo \"hello world\" \"\"")
+        );
+    }
+
+    #[test_case]
+    fn test_execute_unknown_command() {
+        let mut out = String::new();
+        assert_eq!(
+            Err(Error::UnknownCommand("frobnicate".into())),
+            execute(&mut out, "frobnicate now")
+        );
+        assert!(out.is_empty());
+    }
+
+    #[test_case]
+    fn test_execute_empty_line() {
+        let mut out = String::new();
+        assert_eq!(Ok(()), execute(&mut out, "\n"));
+        assert!(out.is_empty());
+    }
+}
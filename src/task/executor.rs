@@ -57,6 +57,14 @@ impl Executor {
         }
     }
 
+    /// Runs the tasks until none of them is ready anymore, and returns the number of
+    /// tasks that didn't complete. These wait for a wakeup, which may come from an
+    /// interrupt.
+    pub fn run_until_stalled(&mut self) -> usize {
+        self.run_ready_tasks();
+        self.tasks.len()
+    }
+
     fn sleep_if_idle(&self) {
         use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
@@ -11,7 +11,7 @@ use futures_util::{
 };
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 
-use crate::{vga_print, vga_println};
+use crate::{tty, vga_println};
 
 const SCANCODE_QUEUE_SIZE: usize = 100;
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
@@ -32,17 +32,22 @@ pub(crate) fn add_scancode(scancode: u8) {
     }
 }
 
-pub async fn print_keypresses() {
+/// Decodes keypresses and passes them as input to the [`tty::console`]. Ctrl is
+/// mapped, so that for example Ctrl-C arrives as `0x03`. Keys without a character,
+/// like the arrow keys, are ignored.
+pub async fn process_keypresses() {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
+    let mut keyboard = Keyboard::new(
+        layouts::Us104Key,
+        ScancodeSet1,
+        HandleControl::MapLettersToUnicode,
+    );
 
     while let Some(scancode) = scancodes.next().await {
         if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => vga_print!("{}", character),
-                    DecodedKey::RawKey(key) => vga_print!("{:?}", key),
-                }
+            if let Some(DecodedKey::Unicode(character)) = keyboard.process_keyevent(key_event) {
+                let mut buf = [0_u8; 4];
+                tty::console().input(character.encode_utf8(&mut buf).as_bytes());
             }
         }
     }
@@ -1,3 +1,4 @@
+use crate::driver::Peripherals;
 use core::arch::x86_64::_rdtsc;
 use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering::Relaxed;
@@ -58,9 +59,18 @@ pub fn microseconds_monotonic() -> u64 {
     //((pit * tsc_per_pit + tsc) * 878807) / (1024*1024 * tsc_per_pit)
 
     const SCALED_TSC_RATE: u64 = 16;
-    let scaled_tsc = (tsc * SCALED_TSC_RATE) / tsc_per_pit;
+    // the TSC is not calibrated before the first PIT interrupt
+    let scaled_tsc = (tsc * SCALED_TSC_RATE)
+        .checked_div(tsc_per_pit)
+        .unwrap_or(0);
 
     // Factorize 878807 = 437 * 2011
     // This will overflow in about 142 years : 2**64 / 4096 microseconds
     ((((pit * SCALED_TSC_RATE + scaled_tsc) * 2011) / 4096) * 437) / (256 * SCALED_TSC_RATE)
 }
+
+/// Seconds since the unix epoch, derived from the CMOS time at boot
+/// and the monotonic time since then.
+pub fn unix_timestamp() -> u64 {
+    Peripherals::boot_time().unix_timestamp() + microseconds_monotonic() / 1_000_000
+}
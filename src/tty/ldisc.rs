@@ -0,0 +1,237 @@
+//! The line discipline, which turns the raw input of a terminal into what readers of
+//! the terminal see, and decides what is echoed back.
+
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Input beyond this length is dropped until the line is completed.
+pub const MAX_LINE_LEN: usize = 4096;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const BACKSPACE: u8 = 0x08;
+const CTRL_U: u8 = 0x15;
+const DELETE: u8 = 0x7F;
+
+/// Echoed for every erased character.
+const ERASE: &[u8] = b"\x08 \x08";
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Settings {
+    /// In canonical mode, input is edited line by line and only complete lines can
+    /// be read. In raw mode, every byte can be read as soon as it arrives.
+    pub canonical: bool,
+    /// Whether input is echoed back to the terminal.
+    pub echo: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            canonical: true,
+            echo: true,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LineDiscipline {
+    settings: Settings,
+    /// The line that is currently being edited.
+    line: Vec<u8>,
+    /// Input that can be read. In canonical mode, every entry is a line, and an
+    /// empty entry marks an end of file.
+    ready: VecDeque<Vec<u8>>,
+    interrupted: bool,
+}
+
+impl LineDiscipline {
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    /// Changes the settings. When switching to raw mode, the line that is being
+    /// edited becomes readable.
+    pub fn set_settings(&mut self, settings: Settings) {
+        if !settings.canonical && !self.line.is_empty() {
+            self.ready.push_back(core::mem::take(&mut self.line));
+        }
+        self.settings = settings;
+    }
+
+    /// Processes a byte of input and appends what has to be echoed to `echo`.
+    pub fn input(&mut self, byte: u8, echo: &mut Vec<u8>) {
+        if !self.settings.canonical {
+            self.ready.push_back(vec![byte]);
+            if self.settings.echo {
+                echo.push(byte);
+            }
+            return;
+        }
+
+        let mut output = Vec::new();
+        match byte {
+            b'\r' | b'\n' => {
+                self.line.push(b'\n');
+                self.ready.push_back(core::mem::take(&mut self.line));
+                output.push(b'\n');
+            }
+            BACKSPACE | DELETE => {
+                if self.line.pop().is_some() {
+                    output.extend_from_slice(ERASE);
+                }
+            }
+            CTRL_U => {
+                for _ in self.line.drain(..) {
+                    output.extend_from_slice(ERASE);
+                }
+            }
+            CTRL_C => {
+                // discard the line, and let readers see an empty one
+                self.line.clear();
+                self.ready.push_back(vec![b'\n']);
+                self.interrupted = true;
+                output.extend_from_slice(b"^C\n");
+            }
+            CTRL_D => {
+                // an empty line is an end of file
+                self.ready.push_back(core::mem::take(&mut self.line));
+            }
+            _ if self.line.len() < MAX_LINE_LEN - 1 => {
+                self.line.push(byte);
+                output.push(byte);
+            }
+            _ => {}
+        }
+        if self.settings.echo {
+            echo.append(&mut output);
+        }
+    }
+
+    /// Reads input into the given buffer, or returns `None` if nothing can be read.
+    /// In canonical mode, a read returns at most one line, and 0 at an end of file.
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if buf.is_empty() {
+            return Some(0);
+        }
+
+        let mut len = 0;
+        while len < buf.len() {
+            let chunk = match self.ready.front_mut() {
+                Some(c) => c,
+                None => break,
+            };
+            if chunk.is_empty() {
+                // end of file, which is only reported if nothing was read before
+                if len == 0 {
+                    self.ready.pop_front();
+                    return Some(0);
+                }
+                break;
+            }
+            let n = chunk.len().min(buf.len() - len);
+            buf[len..len + n].copy_from_slice(&chunk[..n]);
+            chunk.drain(..n);
+            len += n;
+            if chunk.is_empty() {
+                self.ready.pop_front();
+                if self.settings.canonical {
+                    break;
+                }
+            }
+        }
+        (len > 0).then_some(len)
+    }
+
+    /// Whether input can be read without waiting.
+    pub fn has_input(&self) -> bool {
+        !self.ready.is_empty()
+    }
+
+    /// Returns whether Ctrl-C was pressed since the last call.
+    pub fn take_interrupt(&mut self) -> bool {
+        core::mem::take(&mut self.interrupted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(ldisc: &mut LineDiscipline, input: &[u8]) -> Vec<u8> {
+        let mut echo = Vec::new();
+        input.iter().for_each(|&b| ldisc.input(b, &mut echo));
+        echo
+    }
+
+    #[test_case]
+    fn test_canonical_line() {
+        let mut ldisc = LineDiscipline::default();
+        let mut buf = [0_u8; 16];
+
+        assert_eq!(b"ls", feed(&mut ldisc, b"ls").as_slice());
+        assert_eq!(None, ldisc.read(&mut buf));
+        feed(&mut ldisc, b"\r");
+        assert_eq!(Some(3), ldisc.read(&mut buf));
+        assert_eq!(b"ls\n", &buf[..3]);
+    }
+
+    #[test_case]
+    fn test_canonical_reads_one_line() {
+        let mut ldisc = LineDiscipline::default();
+        feed(&mut ldisc, b"a\nb\n");
+        let mut buf = [0_u8; 16];
+        assert_eq!(Some(2), ldisc.read(&mut buf));
+        assert_eq!(Some(2), ldisc.read(&mut buf));
+        assert_eq!(b"b\n", &buf[..2]);
+    }
+
+    #[test_case]
+    fn test_erase() {
+        let mut ldisc = LineDiscipline::default();
+        let echo = feed(&mut ldisc, b"ab\x08c\x7f\x7fd\n");
+        assert_eq!(b"ab\x08 \x08c\x08 \x08\x08 \x08d\n", echo.as_slice());
+        let mut buf = [0_u8; 16];
+        assert_eq!(Some(2), ldisc.read(&mut buf));
+        assert_eq!(b"d\n", &buf[..2]);
+    }
+
+    #[test_case]
+    fn test_ctrl_c() {
+        let mut ldisc = LineDiscipline::default();
+        let echo = feed(&mut ldisc, b"abc\x03");
+        assert_eq!(b"abc^C\n", echo.as_slice());
+        assert!(ldisc.take_interrupt());
+        assert!(!ldisc.take_interrupt());
+        let mut buf = [0_u8; 16];
+        assert_eq!(Some(1), ldisc.read(&mut buf));
+        assert_eq!(b'\n', buf[0]);
+    }
+
+    #[test_case]
+    fn test_ctrl_d() {
+        let mut ldisc = LineDiscipline::default();
+        feed(&mut ldisc, b"abc\x04\x04");
+        let mut buf = [0_u8; 16];
+        assert_eq!(Some(3), ldisc.read(&mut buf));
+        assert_eq!(Some(0), ldisc.read(&mut buf));
+        assert_eq!(None, ldisc.read(&mut buf));
+    }
+
+    #[test_case]
+    fn test_raw_mode() {
+        let mut ldisc = LineDiscipline::default();
+        feed(&mut ldisc, b"ab");
+        ldisc.set_settings(Settings {
+            canonical: false,
+            echo: false,
+        });
+        assert!(feed(&mut ldisc, b"c\x08\x03").is_empty());
+        assert!(!ldisc.take_interrupt());
+
+        let mut buf = [0_u8; 16];
+        assert_eq!(Some(5), ldisc.read(&mut buf));
+        assert_eq!(b"abc\x08\x03", &buf[..5]);
+    }
+}
@@ -0,0 +1,232 @@
+//! Terminals. A [`Tty`] connects a [`TtyDriver`], which displays output and may
+//! provide input, with a [`LineDiscipline`], which edits the input before readers
+//! see it.
+//!
+//! There are two terminals: [`console`] (`/dev/tty0`), which reads from the keyboard
+//! and writes to the screen, and [`serial`] (`/dev/ttyS0`), which reads from and
+//! writes to the first serial port.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::task::{Poll, Waker};
+
+use kstd::io::Result;
+use kstd::sync::Mutex;
+use lazy_static::lazy_static;
+
+use crate::scheduler::Scheduler;
+use crate::tty::ldisc::{LineDiscipline, Settings};
+
+pub mod ldisc;
+
+/// The hardware side of a terminal. Input is passed to [`Tty::input`] by whoever
+/// receives it, like [`process_keypresses`](crate::task::keyboard::process_keypresses).
+pub trait TtyDriver: Send {
+    fn write(&mut self, data: &[u8]);
+}
+
+struct VgaConsole;
+
+impl TtyDriver for VgaConsole {
+    fn write(&mut self, data: &[u8]) {
+        crate::vga_buffer::_write_bytes(data);
+    }
+}
+
+struct SerialConsole;
+
+impl TtyDriver for SerialConsole {
+    fn write(&mut self, data: &[u8]) {
+        crate::serial::_write_bytes(data);
+    }
+}
+
+lazy_static! {
+    static ref CONSOLE: Tty = Tty::new("tty0", Box::new(VgaConsole));
+    static ref SERIAL: Tty = Tty::new("ttyS0", Box::new(SerialConsole));
+}
+
+/// The terminal of the keyboard and screen.
+pub fn console() -> &'static Tty {
+    &CONSOLE
+}
+
+/// The terminal of the first serial port.
+pub fn serial() -> &'static Tty {
+    &SERIAL
+}
+
+struct State {
+    ldisc: LineDiscipline,
+    /// Wakers of futures that wait for input.
+    wakers: Vec<Waker>,
+}
+
+pub struct Tty {
+    name: &'static str,
+    state: Mutex<State>,
+    driver: Mutex<Box<dyn TtyDriver>>,
+}
+
+impl Tty {
+    pub fn new(name: &'static str, driver: Box<dyn TtyDriver>) -> Self {
+        Self {
+            name,
+            state: Mutex::new(State {
+                ldisc: LineDiscipline::default(),
+                wakers: Vec::new(),
+            }),
+            driver: Mutex::new(driver),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.state.lock().ldisc.settings()
+    }
+
+    pub fn set_settings(&self, settings: Settings) {
+        let mut state = self.state.lock();
+        state.ldisc.set_settings(settings);
+        if state.ldisc.has_input() {
+            state.wakers.drain(..).for_each(Waker::wake);
+        }
+    }
+
+    /// Passes input to the line discipline and echoes it.
+    pub fn input(&self, data: &[u8]) {
+        let mut echo = Vec::new();
+        {
+            let mut state = self.state.lock();
+            data.iter().for_each(|&b| state.ldisc.input(b, &mut echo));
+            if state.ldisc.has_input() {
+                state.wakers.drain(..).for_each(Waker::wake);
+            }
+        }
+        if !echo.is_empty() {
+            self.driver.lock().write(&echo);
+        }
+    }
+
+    pub fn write(&self, data: &[u8]) -> usize {
+        self.driver.lock().write(data);
+        data.len()
+    }
+
+    /// Returns whether Ctrl-C was pressed since the last call.
+    pub fn take_interrupt(&self) -> bool {
+        self.state.lock().ldisc.take_interrupt()
+    }
+
+    /// Waits for input and reads it into the given buffer. In canonical mode, this
+    /// reads at most one line, and returns 0 if Ctrl-D was pressed on an empty line.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if let Some(len) = self.try_read(buf) {
+                return Ok(len);
+            }
+            Scheduler::reschedule();
+        }
+    }
+
+    /// Like [`Tty::read`], but waits asynchronously.
+    pub async fn read_async(&self, buf: &mut [u8]) -> Result<usize> {
+        core::future::poll_fn(|cx| {
+            if let Some(len) = self.try_read(buf) {
+                return Poll::Ready(Ok(len));
+            }
+            let mut state = self.state.lock();
+            if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                state.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Reads a line including the trailing newline. Returns an empty string at the
+    /// end of the input.
+    pub fn read_line(&self) -> Result<String> {
+        let mut line = Vec::new();
+        let mut buf = vec![0_u8; 128];
+        loop {
+            let len = self.read(&mut buf)?;
+            line.extend_from_slice(&buf[..len]);
+            if len == 0 || line.ends_with(b"\n") {
+                return Ok(String::from_utf8_lossy(&line).into_owned());
+            }
+        }
+    }
+
+    /// Like [`Tty::read_line`], but waits asynchronously.
+    pub async fn read_line_async(&self) -> Result<String> {
+        let mut line = Vec::new();
+        let mut buf = vec![0_u8; 128];
+        loop {
+            let len = self.read_async(&mut buf).await?;
+            line.extend_from_slice(&buf[..len]);
+            if len == 0 || line.ends_with(b"\n") {
+                return Ok(String::from_utf8_lossy(&line).into_owned());
+            }
+        }
+    }
+
+    fn try_read(&self, buf: &mut [u8]) -> Option<usize> {
+        self.state.lock().ldisc.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+
+    use crate::task::simple_executor::SimpleExecutor;
+    use crate::task::Task;
+
+    use super::*;
+
+    /// Records all output in a shared buffer.
+    struct Recorder(Arc<Mutex<Vec<u8>>>);
+
+    impl TtyDriver for Recorder {
+        fn write(&mut self, data: &[u8]) {
+            self.0.lock().extend_from_slice(data);
+        }
+    }
+
+    #[test_case]
+    fn test_tty_echo_and_read_line() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let tty = Tty::new("test", Box::new(Recorder(output.clone())));
+
+        tty.input(b"hellp\x08o\n");
+        assert_eq!(b"hellp\x08 \x08o\n", output.lock().as_slice());
+        assert_eq!(Ok("hello\n".into()), tty.read_line());
+
+        tty.write(b"prompt> ");
+        assert!(output.lock().ends_with(b"prompt> "));
+    }
+
+    #[test_case]
+    fn test_tty_async_read() {
+        let tty: &'static Tty = Box::leak(Box::new(Tty::new(
+            "test",
+            Box::new(Recorder(Arc::new(Mutex::new(Vec::new())))),
+        )));
+
+        let mut executor = SimpleExecutor::default();
+        executor.spawn(Task::new(async move {
+            assert_eq!(Ok("first\n".into()), tty.read_line_async().await);
+            assert_eq!(Ok(String::new()), tty.read_line_async().await);
+        }));
+        executor.spawn(Task::new(async move {
+            tty.input(b"first\n\x04");
+        }));
+        executor.run();
+    }
+}
@@ -1,18 +1,35 @@
-use crate::driver::Peripherals;
+use crate::driver;
+use crate::driver::registry;
 use crate::io::fs::devfs::DevFs;
 use crate::io::fs::device::block::BlockDeviceFile;
+use crate::io::fs::device::cache::CachedBlockDevice;
+use crate::io::fs::device::loop_device;
+use crate::io::fs::device::ramdisk::RamDisk;
 use crate::io::fs::device::FileBlockDevice;
 use crate::io::fs::ext2::Ext2Fs;
 use crate::io::fs::memfs::MemFs;
+use crate::io::fs::procfs::ProcFs;
+use crate::io::fs::tmpfs::TmpFs;
 use crate::io::fs::INodeBase;
 use crate::io::fs::{vfs, Fs, INode};
+use crate::memory::size::Size;
 use crate::{error, info, serial_println};
 use alloc::format;
-use alloc::string::ToString;
+use alloc::string::{String, ToString};
+use kstd::io::{Error, Result};
+use kstd::path::Path;
+
+/// The size of the ram disk that is mounted at `/dev/ram0`.
+pub const RAM_DISK_SIZE: Size = Size::MiB(4);
+
+/// The maximum amount of file data that can be stored in `/tmp`.
+pub const TMPFS_SIZE_LIMIT: Size = Size::MiB(16);
 
 pub extern "C" fn init_vfs() {
     setup_vfs_base_structuce();
-    mount_ide_drive_files();
+    driver::register_builtin_drivers();
+    registry::probe_all();
+    mount_ram_disk();
 
     mount_ext2();
 
@@ -26,6 +43,7 @@ pub extern "C" fn init_vfs() {
                 INode::Dir(_) => "dir".to_string(),
                 INode::BlockDevice(_) => "block device".to_string(),
                 INode::CharacterDevice(_) => "character device".to_string(),
+                INode::Pipe(_) => "fifo".to_string(),
                 INode::Symlink(link) => format!("-> {}", link.read().target().unwrap()),
             }
         );
@@ -40,28 +58,26 @@ fn setup_vfs_base_structuce() {
     let devfs = DevFs::new("dev".to_string());
     vfs::mount(&"/", devfs.root_inode()).unwrap();
 
+    let procfs = ProcFs::new("proc".to_string());
+    vfs::mount(&"/", procfs.root_inode()).unwrap();
+
     let mntfs = MemFs::new("mnt".to_string());
     vfs::mount(&"/", mntfs.root_inode()).unwrap();
+
+    let tmpfs = TmpFs::new("tmp".to_string(), TMPFS_SIZE_LIMIT.bytes() as u64);
+    vfs::mount(&"/", tmpfs.root_inode()).unwrap();
 }
 
-fn mount_ide_drive_files() {
-    let drives = Peripherals::ide_drives();
-
-    for (i, &drive) in drives.iter().enumerate() {
-        let display_string = format!("{drive}");
-        let block_device_file = BlockDeviceFile::new(drive, 0_u64.into(), format!("ide{i}"));
-        let block_device_node = INode::new_block_device_file(block_device_file);
-        info!(
-            "mount {} at /dev/{}",
-            display_string,
-            block_device_node.name()
-        );
-        vfs::mount(&"/dev", block_device_node).expect("mount failed");
-    }
+fn mount_ram_disk() {
+    const BLOCK_SIZE: usize = 512;
+
+    let ram_disk = RamDisk::new(RAM_DISK_SIZE.bytes() / BLOCK_SIZE, BLOCK_SIZE);
+    let block_device_file = BlockDeviceFile::new(ram_disk, 0_u64.into(), "ram0".to_string());
+    vfs::mount(&"/dev", INode::new_block_device_file(block_device_file)).expect("mount failed");
 }
 
 fn mount_ext2() {
-    // all devices are mounted at /dev, so check all block device files for
+    // all devices and partitions are mounted at /dev, so check all block device files for
     // the ext2 magic number 0x53 0xEF at position 1080 - 1081
 
     vfs::find_inode(&"/dev")
@@ -81,7 +97,7 @@ fn mount_ext2() {
         .enumerate()
         .map(|(num, file)| {
             Ext2Fs::new_with_named_root(
-                FileBlockDevice::new(file),
+                CachedBlockDevice::new(FileBlockDevice::new(file)),
                 format!("block_device{num}").as_str(),
             )
         })
@@ -93,3 +109,21 @@ fn mount_ext2() {
                 .unwrap_or_else(|_| panic!("mount of {} at /mnt failed", root_name));
         });
 }
+
+/// Attaches the ext2 image file at the given path to a new loop device, and mounts
+/// the file system in it at `/mnt`, named after the loop device (e.g. `/mnt/loop0`).
+/// Returns the path of the mounted file system.
+pub fn mount_ext2_image(image: &dyn AsRef<Path>) -> Result<String> {
+    let file = vfs::find_inode(image)?.as_file().ok_or(Error::IsDir)?;
+    let loop_node = loop_device::attach(file, 1024)?;
+    let loop_file = loop_node
+        .as_block_device_file()
+        .expect("loop device must be a block device");
+
+    let fs = Ext2Fs::new_with_named_root(
+        CachedBlockDevice::new(FileBlockDevice::new(loop_file)),
+        loop_node.name().as_str(),
+    )?;
+    vfs::mount(&"/mnt", fs.root_inode())?;
+    Ok(format!("/mnt/{}", loop_node.name()))
+}
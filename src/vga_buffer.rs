@@ -6,10 +6,34 @@ use noto_sans_mono_bitmap::{get_bitmap, BitmapHeight, FontWeight};
 
 static WRITER: Mutex<Writer> = Mutex::new(Writer::new());
 
+/// The framebuffer of [`WRITER`], for [`panic_writer`].
+static mut FRAMEBUFFER: Option<(*mut u8, usize, FrameBufferInfo)> = None;
+
 pub fn init_vga_buffer(buffer: &'static mut FrameBuffer) {
+    let info = buffer.info();
+    let bytes = buffer.buffer_mut();
+    unsafe { FRAMEBUFFER = Some((bytes.as_mut_ptr(), bytes.len(), info)) };
     WRITER.lock().init(buffer);
 }
 
+/// Returns a writer to the framebuffer that starts at the top left corner and doesn't
+/// take the lock on the writer of [`vga_print`], for reports after a panic, when the
+/// lock may be held forever. Returns `None` if there is no framebuffer.
+///
+/// # Safety
+///
+/// Nothing else may use the framebuffer while the writer exists, so the caller must
+/// have disabled interrupts and must be the only processor that runs.
+pub unsafe fn panic_writer() -> Option<Writer<'static>> {
+    let (buffer, len, info) = FRAMEBUFFER?;
+    Some(Writer {
+        x_pos: 0,
+        y_pos: 0,
+        buffer: core::slice::from_raw_parts_mut(buffer, len),
+        info: Some(info),
+    })
+}
+
 #[derive(Default)]
 pub struct Writer<'a> {
     x_pos: usize,
@@ -45,10 +69,14 @@ impl<'a> Writer<'a> {
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
+        self.write_bytes(s.as_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
             match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' | b'\t' => self.write_byte(byte),
+                // printable ASCII byte, newline, tab or backspace
+                0x20..=0x7e | b'\n' | b'\t' | 0x08 => self.write_byte(byte),
                 // not part of printable ASCII range
                 _ => self.write_byte(0xfe),
             }
@@ -69,7 +97,16 @@ impl<'a> Writer<'a> {
         match byte {
             b'\n' => self.new_line(),
             b'\xFE' => {
-                // for some reason, this is a backspace
+                // characters that can't be displayed are skipped
+            }
+            0x08 => {
+                // move back one character and clear it, but not beyond the start of the line
+                self.x_pos = self.x_pos.saturating_sub(m_width);
+                for y in self.y_pos..self.y_pos + 14 {
+                    for x in self.x_pos..self.x_pos + m_width {
+                        self.write_pixel(x, y, 0);
+                    }
+                }
             }
             b'\t' => {
                 for _ in 0..4 {
@@ -164,6 +201,15 @@ pub fn _print(args: fmt::Arguments) {
     });
 }
 
+#[doc(hidden)]
+pub fn _write_bytes(bytes: &[u8]) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        WRITER.lock().write_bytes(bytes);
+    });
+}
+
 #[doc(hidden)]
 pub fn _clear() {
     use x86_64::instructions::interrupts;
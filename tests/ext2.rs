@@ -13,7 +13,11 @@ use core::panic::PanicInfo;
 
 use bootloader::{entry_point, BootInfo};
 
-use martim::io::fs::{vfs, IDirHandle};
+use martim::io::fs::device::block::BlockDeviceFile;
+use martim::io::fs::device::cache::CachedBlockDevice;
+use martim::io::fs::device::loop_device::LoopDevice;
+use martim::io::fs::perm::Permission;
+use martim::io::fs::{vfs, CreateNodeType, IDirHandle, INode};
 use martim::{kernel_init, vfs_setup};
 
 entry_point!(main);
@@ -174,6 +178,40 @@ fn test_filecontent_256kib() {
     }
 }
 
+#[test_case]
+fn test_filecontent_sparse() {
+    let content = filecontent_read_file("sparse.dat").unwrap();
+    assert_eq!(5120, content.len());
+    assert_eq!(&[b'a'; 1024], &content[..1024]);
+    assert_eq!(
+        &[0_u8; 3072],
+        &content[1024..4096],
+        "holes must read as zeros"
+    );
+    assert_eq!(&[b'b'; 1024], &content[4096..]);
+}
+
+#[test_case]
+fn test_read_is_clamped_to_file_size() {
+    let file = vfs::find_inode(&"/mnt/block_device0/filecontent/hello_world.txt")
+        .expect("not found")
+        .as_file()
+        .expect("not a file");
+    let guard = file.read();
+
+    let mut buf = [0xFF_u8; 32];
+    assert_eq!(6, guard.read_at(8, &mut buf).unwrap());
+    assert_eq!(b"orld!\n", &buf[..6]);
+    assert_eq!(
+        &[0xFF_u8; 26],
+        &buf[6..],
+        "bytes after the end must not be touched"
+    );
+
+    assert_eq!(0, guard.read_at(14, &mut buf).unwrap());
+    assert_eq!(0, guard.read_at(u64::MAX, &mut buf).unwrap());
+}
+
 #[test_case]
 fn test_symlinks_same_level() {
     let root = root_node();
@@ -203,3 +241,54 @@ fn test_symlinks_same_level() {
     assert_symlink_points_to!("target_folder", "symlink_folder");
     assert_symlink_points_to!("target_folder/cough.txt", "symlink_folder_file");
 }
+
+#[test_case]
+fn test_mount_image() {
+    let path = vfs_setup::mount_ext2_image(&"/mnt/block_device0/images/nested.img")
+        .expect("mounting the image failed");
+    assert!(path.starts_with("/mnt/loop"));
+
+    let hello = format!("{}/hello.txt", path);
+    let file = vfs::find_inode(&hello.as_str())
+        .expect("not found")
+        .as_file()
+        .expect("not a file");
+    let mut buf = [0_u8; 26];
+    assert_eq!(buf.len(), file.read().read_at(0, &mut buf).unwrap());
+    assert_eq!(b"hello from a nested image\n", &buf);
+}
+
+#[test_case]
+fn test_flush_through_dev_reaches_cached_device() {
+    let tmp = vfs::find_inode(&"/tmp").unwrap().as_dir().unwrap();
+    let image = tmp
+        .write()
+        .create(&"flush.img", CreateNodeType::File, Permission::user_rwx())
+        .unwrap()
+        .as_file()
+        .unwrap();
+    image.write().truncate(4096).unwrap();
+
+    let device = CachedBlockDevice::new(LoopDevice::new(image.clone(), 512));
+    let node = INode::new_block_device_file(BlockDeviceFile::new(
+        device,
+        0_u64.into(),
+        String::from("cached_flush"),
+    ));
+    vfs::mount(&"/dev", node).unwrap();
+
+    let file = vfs::find_inode(&"/dev/cached_flush")
+        .unwrap()
+        .as_block_device_file()
+        .unwrap();
+    file.write().write_at(512, &[0xAB_u8; 512]).unwrap();
+    let mut buf = [0_u8; 512];
+    image.read().read_at(512, &mut buf).unwrap();
+    assert_eq!([0_u8; 512], buf, "the write should only be cached");
+
+    file.write().flush().unwrap();
+    image.read().read_at(512, &mut buf).unwrap();
+    assert_eq!([0xAB_u8; 512], buf);
+
+    vfs::unmount(&"/dev", &"cached_flush").unwrap();
+}
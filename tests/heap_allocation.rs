@@ -29,7 +29,7 @@ fn panic(info: &PanicInfo) -> ! {
 #[cfg(test)]
 mod tests {
     use alloc::{boxed::Box, collections::LinkedList, vec, vec::Vec};
-    use martim::memory::span::HEAP;
+    use martim::memory::heap::INITIAL_HEAP_SIZE;
 
     #[test_case]
     fn simple_allocation() {
@@ -61,7 +61,7 @@ mod tests {
     /// whether the allocator can re-use previous, freed allocations
     #[test_case]
     fn many_boxes() {
-        for i in 0..HEAP.len() as u64 {
+        for i in 0..INITIAL_HEAP_SIZE.bytes() as u64 {
             let x = Box::new(i);
             assert_eq!(*x, i);
         }
@@ -70,16 +70,24 @@ mod tests {
     #[test_case]
     fn many_boxes_long_lived() {
         let long_lived = Box::new(1);
-        for i in 0..HEAP.len() as u64 {
+        for i in 0..INITIAL_HEAP_SIZE.bytes() as u64 {
             let x = Box::new(i);
             assert_eq!(*x, i);
         }
         assert_eq!(*long_lived, 1);
     }
 
+    /// Allocations that are larger than the initial heap make it grow
+    #[test_case]
+    fn larger_than_initial_heap() {
+        let size: usize = INITIAL_HEAP_SIZE.bytes() * 4;
+        let large = vec![1_u8; size];
+        assert_eq!(large.iter().map(|&b| b as usize).sum::<usize>(), size);
+    }
+
     #[test_case]
     fn large_allocation() {
-        let size: usize = HEAP.len() / 2;
+        let size: usize = INITIAL_HEAP_SIZE.bytes() / 2;
         for _ in 0..1000 {
             let large = vec![0_u8; size];
             assert_eq!(large.len(), size);
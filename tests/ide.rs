@@ -16,7 +16,11 @@ use bootloader::{entry_point, BootInfo};
 use kstd::io::block::BlockDevice;
 use kstd::io::ReadAt;
 use martim::driver::ide::drive::IDEDrive;
+use martim::driver::ide::IDE_DRIVER;
+use martim::driver::registry;
 use martim::driver::Peripherals;
+use martim::io::fs::device::block::BlockDeviceFile;
+use martim::io::fs::IBlockDeviceFile;
 use martim::kernel_init;
 
 entry_point!(main);
@@ -24,6 +28,8 @@ entry_point!(main);
 #[allow(clippy::empty_loop)]
 fn main(boot_info: &'static mut BootInfo) -> ! {
     kernel_init(boot_info);
+    registry::register(&IDE_DRIVER);
+    registry::probe_all();
 
     test_main();
     loop {}
@@ -107,3 +113,27 @@ fn test_write_first_block() {
     drive.read_block(0, &mut original_read_back).unwrap();
     assert_eq!(original_block, original_read_back); // if this fails, writing back the original block failed
 }
+
+#[test_case]
+fn test_block_device_file_unaligned_write() {
+    let mut file = BlockDeviceFile::new(get_ide_drive(1), 0_u64.into(), String::from("ide1"));
+
+    let mut original = vec![0_u8; 1024];
+    file.read_at(0, &mut original).unwrap();
+
+    // spans the end of block 0 and the start of block 1
+    let write_data = [0xAB_u8; 20];
+    let written = file.write_at(500, &write_data).unwrap();
+    assert_eq!(write_data.len(), written);
+
+    let mut read_back = vec![0_u8; 1024];
+    file.read_at(0, &mut read_back).unwrap();
+    assert_eq!(original[..500], read_back[..500]);
+    assert_eq!(write_data, read_back[500..520]);
+    assert_eq!(original[520..], read_back[520..]);
+
+    // write back original data
+    file.write_at(0, &original).unwrap();
+    file.read_at(0, &mut read_back).unwrap();
+    assert_eq!(original, read_back);
+}
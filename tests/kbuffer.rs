@@ -29,6 +29,7 @@ fn panic(info: &PanicInfo) -> ! {
 #[cfg(test)]
 mod tests {
     use martim::memory::kbuffer::KBuffer;
+    use martim::memory::manager::MemoryManager;
 
     #[test_case]
     fn test_kbuffer_allocation() {
@@ -63,4 +64,36 @@ mod tests {
             assert_eq!((i % 255) as u8, *x);
         });
     }
+
+    #[test_case]
+    fn test_freed_memory_is_reused() {
+        let first = KBuffer::allocate(3 * 4096);
+        let addr = first.as_ptr::<u8>();
+        drop(first);
+        let second = KBuffer::allocate(3 * 4096);
+        assert_eq!(addr, second.as_ptr::<u8>());
+    }
+
+    #[test_case]
+    fn test_frames_are_returned() {
+        let allocated_frames = || {
+            MemoryManager::lock()
+                .physical_frame_allocator()
+                .allocated_frames()
+        };
+        // grow the heap for the bookkeeping of the buffer before counting
+        drop(KBuffer::allocate(64 * 4096));
+
+        let before = allocated_frames();
+        let mut buf = KBuffer::allocate(64 * 4096);
+        buf.as_mut().fill(0xAB);
+        // the frames of the buffer, and the page tables that map them if there were none
+        assert!(allocated_frames() >= before + 64);
+        drop(buf);
+        assert_eq!(
+            before,
+            allocated_frames(),
+            "frames and emptied page tables must be returned"
+        );
+    }
 }
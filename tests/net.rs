@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(martim::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+
+use martim::driver::e1000::E1000_DRIVER;
+use martim::driver::registry;
+use martim::kernel_init;
+use martim::net::interface::QEMU_USER_NETWORK;
+use martim::net::tcp::TcpStream;
+use martim::net::udp::UdpSocket;
+use martim::net::{icmp, Error, Ipv4Address};
+
+/// The gateway of QEMU's user mode networking, which answers pings.
+const GATEWAY: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
+
+entry_point!(main);
+
+#[allow(clippy::empty_loop)]
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info);
+    registry::register(&E1000_DRIVER);
+    registry::probe_all();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    martim::test_panic_handler(info);
+}
+
+#[test_case]
+fn test_interface_configured() {
+    let interfaces = martim::net::interfaces();
+    assert_eq!(2, interfaces.len());
+    let (_, config) = interfaces
+        .iter()
+        .find(|(name, _)| name == "eth0")
+        .expect("eth0 is missing");
+    assert_eq!(Some(QEMU_USER_NETWORK), *config);
+}
+
+#[test_case]
+fn test_ping_gateway() {
+    // the first ping also has to resolve the gateway's mac address
+    icmp::ping(GATEWAY, 5000).expect("gateway didn't answer the first ping");
+    icmp::ping(GATEWAY, 5000).expect("gateway didn't answer the second ping");
+}
+
+#[test_case]
+fn test_udp_bind() {
+    let socket = UdpSocket::bind(0).unwrap();
+    assert_ne!(0, socket.local_port());
+    assert_eq!(
+        Err(Error::PortInUse(socket.local_port())),
+        UdpSocket::bind(socket.local_port()).map(|_| ())
+    );
+    assert_eq!(Ok(4), socket.send_to(b"test", GATEWAY, 9));
+}
+
+#[test_case]
+fn test_ping_own_address() {
+    // packets to our own address are routed through the loopback interface
+    icmp::ping(QEMU_USER_NETWORK.address, 1000).expect("no reply from our own address");
+}
+
+#[test_case]
+fn test_tcp_connection_refused() {
+    // nothing listens on the host's port 1, so qemu resets the connection
+    assert_eq!(
+        Err(Error::ConnectionRefused),
+        TcpStream::connect(GATEWAY, 1).map(|_| ())
+    );
+}
@@ -44,3 +44,50 @@ fn test_pci_devices_exist() {
         assert_eq!(count, pci::devices().filter(|d| d.class() == class).count());
     }
 }
+
+#[test_case]
+fn test_network_controller_bar0() {
+    use martim::driver::pci::classes::NetworkSubClass::EthernetController;
+    use martim::driver::pci::classes::PCIDeviceClass::NetworkController;
+    use martim::driver::pci::header::{Bar, PCIStandardHeaderDevice};
+
+    let device = pci::devices()
+        .find(|d| d.class() == NetworkController(EthernetController))
+        .expect("no ethernet controller found");
+    let device = PCIStandardHeaderDevice::new(device.clone()).unwrap();
+
+    // qemu's default network card is an e1000, which has 128KiB of registers in BAR0
+    match device.bar(0) {
+        Some(Bar::Memory32 { address, size, .. }) => {
+            assert_ne!(0, address);
+            assert_eq!(0x20000, size);
+        }
+        bar => panic!("expected a 32-bit memory bar, but got {:?}", bar),
+    }
+}
+
+#[test_case]
+fn test_enable_bus_master() {
+    use martim::driver::pci::device::Command;
+
+    let device = pci::devices()
+        .find(|d| d.vendor() == 0x8086 && d.device() == 0x100E)
+        .expect("no e1000 found");
+    device.enable_bus_master();
+    assert!(device.command().contains(Command::BUS_MASTER));
+}
+
+#[test_case]
+fn test_upper_half_of_64_bit_bar() {
+    use martim::driver::pci::header::{Bar, PCIStandardHeaderDevice};
+
+    // the modern interface of a virtio device is in a 64-bit memory bar
+    let device = pci::devices()
+        .find(|d| d.vendor() == 0x1AF4 && d.device() == 0x1005)
+        .expect("no virtio rng device found");
+    let device = PCIStandardHeaderDevice::new(device.clone()).unwrap();
+    let index = (0..5)
+        .find(|&i| matches!(device.bar(i), Some(Bar::Memory64 { .. })))
+        .expect("no 64-bit bar found");
+    assert_eq!(None, device.bar(index + 1));
+}
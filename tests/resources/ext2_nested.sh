@@ -0,0 +1,15 @@
+root_dir=ext2_nested
+img_file=ext2_fs/images/nested.img
+
+rm -f "$img_file"
+/opt/homebrew/opt/e2fsprogs/sbin/mke2fs \
+  -L '' \
+  -N 16 \
+  -O ^64bit \
+  -d "$root_dir" \
+  -m 5 \
+  -r 1 \
+  -t ext2 \
+  "$img_file" \
+  64K \
+;